@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+/// The counters of the broker's `/api/v1/metrics` endpoint used by the benchmark.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+struct BrokerMetrics {
+    clients_connected: usize,
+    messages_inflight: usize,
+    publish_messages_dropped: usize,
+    publish_messages_received: usize,
+    publish_messages_sent: usize,
+    store_messages_count: usize,
+    subscriptions_count: usize,
+}
+
+/// The broker metrics at one point of the test, the message counters are relative to the
+/// start of the test.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrokerSample {
+    /// Seconds since the start of the test.
+    pub elapsed: f64,
+    pub clients_connected: usize,
+    pub messages_inflight: usize,
+    pub store_messages_count: usize,
+    pub subscriptions_count: usize,
+    pub publish_messages_dropped: usize,
+    pub publish_messages_received: usize,
+    pub publish_messages_sent: usize,
+}
+
+/// Fetches the metrics with a plain HTTP/1.0 request, the url must have the form
+/// `http://host[:port]/path`.
+async fn fetch(url: &str) -> Result<BrokerMetrics> {
+    let rest = url
+        .strip_prefix("http://")
+        .with_context(|| format!("unsupported metrics url: {}", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, "/"),
+    };
+    let addr = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let mut stream = TcpStream::connect(addr).await?;
+    stream
+        .write_all(format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, authority).as_bytes())
+        .await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    let idx = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .context("invalid http response")?;
+    let (head, body) = response.split_at(idx + 4);
+    let status_line = head.split(|c| *c == b'\r').next().unwrap_or_default();
+    if !status_line.windows(5).any(|w| w == b" 200 ") {
+        anyhow::bail!(
+            "metrics request failed: {}",
+            String::from_utf8_lossy(status_line)
+        );
+    }
+    Ok(serde_json::from_slice(body)?)
+}
+
+/// Polls the metrics every `interval` until `duration` has elapsed.
+///
+/// Returns an error if the first request fails, later failures skip the sample.
+pub async fn sample_loop(
+    url: String,
+    interval: Duration,
+    duration: Duration,
+) -> Result<Vec<BrokerSample>> {
+    let start = Instant::now();
+    let initial = fetch(&url).await?;
+    let mut samples = Vec::new();
+    let mut next = start;
+
+    while next < start + duration {
+        next = (next + interval).min(start + duration);
+        tokio::time::sleep_until(next).await;
+        let metrics = match fetch(&url).await {
+            Ok(metrics) => metrics,
+            Err(err) => {
+                println!("failed to fetch broker metrics: {}", err);
+                continue;
+            }
+        };
+        samples.push(BrokerSample {
+            elapsed: start.elapsed().as_secs_f64(),
+            clients_connected: metrics.clients_connected,
+            messages_inflight: metrics.messages_inflight,
+            store_messages_count: metrics.store_messages_count,
+            subscriptions_count: metrics.subscriptions_count,
+            publish_messages_dropped: metrics
+                .publish_messages_dropped
+                .saturating_sub(initial.publish_messages_dropped),
+            publish_messages_received: metrics
+                .publish_messages_received
+                .saturating_sub(initial.publish_messages_received),
+            publish_messages_sent: metrics
+                .publish_messages_sent
+                .saturating_sub(initial.publish_messages_sent),
+        });
+    }
+
+    Ok(samples)
+}
+
+/// Prints the peak values and the totals of the last sample.
+pub fn print_summary(samples: &[BrokerSample]) {
+    let last = match samples.last() {
+        Some(last) => last,
+        None => {
+            println!("Broker: no samples");
+            return;
+        }
+    };
+    let peak = |f: fn(&BrokerSample) -> usize| samples.iter().map(f).max().unwrap_or_default();
+    println!(
+        "Broker: {} connections max, {} inflight messages max, {} stored messages max, {} subscriptions max",
+        peak(|s| s.clients_connected),
+        peak(|s| s.messages_inflight),
+        peak(|s| s.store_messages_count),
+        peak(|s| s.subscriptions_count),
+    );
+    println!(
+        "Broker: {} publish received, {} publish sent, {} publish dropped",
+        last.publish_messages_received, last.publish_messages_sent, last.publish_messages_dropped,
+    );
+}
@@ -1,18 +1,31 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::default_trait_access)]
 
-use std::sync::atomic::{AtomicUsize, Ordering};
+mod broker;
+mod report;
+mod sample;
+mod stats;
+mod topic;
+
+use std::convert::TryFrom;
+use std::future;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Result;
 use bytes::Bytes;
 use bytesize::ByteSize;
-use bytestring::ByteString;
-use client::{Client, FilterBuilder, Qos};
+use client::{Client, FilterBuilder, Message, Packet, ProtocolLevel, Qos};
 use structopt::StructOpt;
-use tokio::sync::Barrier;
-use tokio_stream::StreamExt;
+use tokio::sync::{mpsc, Barrier};
+use tokio::time::Instant;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::report::Report;
+use crate::sample::LiveCounters;
+use crate::stats::{Stats, TIMESTAMP_SIZE};
+use crate::topic::TopicTemplate;
 
 #[derive(StructOpt)]
 struct Options {
@@ -24,55 +37,197 @@ struct Options {
     #[structopt(default_value = "1883", short)]
     pub port: u16,
 
+    /// broker url, overrides the host and port.
+    ///
+    /// Supported schemes are `mqtt`, `mqtts`, `ws` and `wss`, e.g. `wss://localhost/mqtt`.
+    #[structopt(long)]
+    pub url: Option<String>,
+
+    /// mqtt protocol version, `v3` (3.1), `v4` (3.1.1) or `v5`.
+    #[structopt(long, default_value = "v5", parse(try_from_str = parse_protocol))]
+    pub protocol: ProtocolLevel,
+
+    /// accept invalid server certificates when connecting with TLS.
+    #[structopt(long)]
+    pub insecure: bool,
+
     /// number of threads to use.
     #[structopt(name = "threads", default_value = "32", short = "t")]
     pub num_threads: usize,
 
-    /// payload size to publish.
+    /// number of publishing clients, defaults to the number of threads.
+    #[structopt(long)]
+    pub publishers: Option<usize>,
+
+    /// number of subscribing clients, defaults to the number of threads.
+    #[structopt(long)]
+    pub subscribers: Option<usize>,
+
+    /// number of topics the publishers and subscribers are spread over, defaults to the number
+    /// of threads.
+    #[structopt(long)]
+    pub topics: Option<usize>,
+
+    /// topic name template, supports the `{topic}`, `{client}`, `{seq}` and `{rand}`
+    /// placeholders.
+    #[structopt(long = "topic", default_value = "bench/{topic}")]
+    pub topic_template: TopicTemplate,
+
+    /// subscribe through the `$share/<group>/` shared subscription so that each message is
+    /// delivered to only one subscriber of the topic.
+    #[structopt(long = "share")]
+    pub share_group: Option<String>,
+
+    /// qos level of published messages (0, 1 or 2).
+    #[structopt(long, default_value = "2", parse(try_from_str = parse_qos))]
+    pub qos: Qos,
+
+    /// percentage of published messages with the retain flag set.
+    #[structopt(long = "retain", default_value = "0")]
+    pub retain_percent: u8,
+
+    /// payload size to publish, at least 8 bytes for the send timestamp.
     #[structopt(name = "payload_size", default_value = "256", short = "s")]
     pub payload_size: usize,
 
     /// duration of test
     #[structopt(default_value = "10", short = "d")]
     pub duration: usize,
+
+    /// publish at a fixed rate per client (msgs/sec) instead of as fast as possible.
+    #[structopt(long)]
+    pub rate: Option<f64>,
+
+    /// number of new connections per second while ramping up, all clients connect at once if
+    /// not specified.
+    #[structopt(long)]
+    pub connect_rate: Option<f64>,
+
+    /// number of additional clients, as a percentage of the benchmark clients, that keep
+    /// disconnecting and reconnecting during the test.
+    #[structopt(long = "churn", default_value = "0")]
+    pub churn_percent: usize,
+
+    /// seconds a churning client stays connected.
+    #[structopt(long, default_value = "1")]
+    pub churn_interval: f64,
+
+    /// seconds between two time series samples of the throughput and latency.
+    #[structopt(long, default_value = "1")]
+    pub sample_interval: f64,
+
+    /// url of the broker's metrics endpoint to poll during the test, e.g.
+    /// `http://localhost:8080/api/v1/metrics`.
+    #[structopt(long)]
+    pub broker_metrics: Option<String>,
+
+    /// write the results and time series samples as JSON to this file.
+    #[structopt(long, parse(from_os_str))]
+    pub json: Option<PathBuf>,
+
+    /// write the time series samples as CSV to this file.
+    #[structopt(long, parse(from_os_str))]
+    pub csv: Option<PathBuf>,
+
+    /// compare the results with a JSON file written by a previous run and exit with an error
+    /// if any metric regressed.
+    #[structopt(long, parse(from_os_str))]
+    pub compare: Option<PathBuf>,
+
+    /// percentage by which a metric must be worse than the baseline to count as a regression.
+    #[structopt(long, default_value = "10")]
+    pub regression_threshold: f64,
+}
+
+fn parse_qos(s: &str) -> Result<Qos, String> {
+    let value: u8 = s.parse().map_err(|_| format!("invalid qos: {}", s))?;
+    Qos::try_from(value).map_err(|_| format!("invalid qos: {}", s))
+}
+
+fn parse_protocol(s: &str) -> Result<ProtocolLevel, String> {
+    match s {
+        "v3" | "3.1" => Ok(ProtocolLevel::V3),
+        "v4" | "3.1.1" => Ok(ProtocolLevel::V4),
+        "v5" | "5" => Ok(ProtocolLevel::V5),
+        _ => Err(format!("invalid protocol: {}", s)),
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let options: Options = Options::from_args();
+    let mut options: Options = Options::from_args();
+    options.payload_size = options.payload_size.max(TIMESTAMP_SIZE);
     let payload: Bytes = b"123456789"
         .iter()
         .copied()
         .cycle()
         .take(options.payload_size)
         .collect();
-    let barrier = Arc::new(Barrier::new(options.num_threads + 1));
+    let num_publishers = options.publishers.unwrap_or(options.num_threads);
+    let num_subscribers = options.subscribers.unwrap_or(options.num_threads);
+    let num_topics = options.topics.unwrap_or(options.num_threads).max(1);
+    let num_clients = num_publishers.max(num_subscribers);
+    let options = Arc::new(options);
+    let barrier = Arc::new(Barrier::new(num_clients + 1));
+    let counters = Arc::new(LiveCounters::default());
     let mut handles = Vec::new();
 
-    for i in 0..options.num_threads {
+    // Client `i` publishes and/or subscribes to topic `i % topics`, so the number of
+    // publishers, subscribers and topics select N→1, 1→N or N→M topologies.
+    for i in 0..num_clients {
         let handle = tokio::spawn(client_loop(
-            i,
+            ClientOptions {
+                id: i,
+                topic: i % num_topics,
+                publish: i < num_publishers,
+                subscribe: i < num_subscribers,
+                subscribers_on_topic: match (0..num_subscribers)
+                    .filter(|j| j % num_topics == i % num_topics)
+                    .count()
+                {
+                    // a shared subscription group receives each message once
+                    n if options.share_group.is_some() => n.min(1),
+                    n => n,
+                },
+                payload: payload.clone(),
+            },
+            options.clone(),
+            counters.clone(),
             barrier.clone(),
-            (options.host.clone(), options.port),
-            payload.clone(),
-            options.duration,
         ));
         handles.push(handle);
+
+        if let Some(connect_rate) = options.connect_rate {
+            tokio::time::sleep(Duration::from_secs_f64(1.0 / connect_rate)).await;
+        }
     }
 
     barrier.wait().await;
 
     println!("connected");
 
-    let mut send_count = 0;
-    let mut recv_count = 0;
+    let sampler = tokio::spawn(sample::sample_loop(
+        counters,
+        Duration::from_secs_f64(options.sample_interval),
+        Duration::from_secs(options.duration as u64),
+    ));
+    let broker_sampler = options.broker_metrics.clone().map(|url| {
+        tokio::spawn(broker::sample_loop(
+            url,
+            Duration::from_secs_f64(options.sample_interval),
+            Duration::from_secs(options.duration as u64),
+        ))
+    });
+
+    for i in 0..num_clients * options.churn_percent / 100 {
+        handles.push(tokio::spawn(churn_loop(num_clients + i, options.clone())));
+    }
+
+    let mut stats = Stats::default();
 
     for handle in handles {
         match handle.await.unwrap() {
-            Ok(res) => {
-                send_count += res.0;
-                recv_count += res.1;
-            }
+            Ok(res) => stats.merge(&res),
             Err(err) => {
                 println!("error: {}", err);
                 break;
@@ -82,77 +237,249 @@ async fn main() {
 
     println!(
         "Send TPS: {:.3}",
-        send_count as f64 / options.duration as f64
+        stats.send_count as f64 / options.duration as f64
     );
     println!(
         "Receive TPS: {:.3}",
-        recv_count as f64 / options.duration as f64
+        stats.recv_count as f64 / options.duration as f64
     );
     println!(
         "Transferred Bytes: {}",
-        ByteSize::b(((send_count + recv_count) * options.payload_size) as u64)
+        ByteSize::b(((stats.send_count + stats.recv_count) * options.payload_size) as u64)
     );
+    if let Some(rate) = options.rate {
+        stats.print_rate(rate, num_publishers, options.duration);
+    }
+    if options.share_group.is_some() {
+        stats.print_consumer_distribution();
+    }
+    stats.print_latency();
+    stats.print_connect_latency();
+
+    let mut report = Report::new(
+        &stats,
+        options.duration,
+        options.payload_size,
+        options.rate.is_some(),
+        sampler.await.unwrap(),
+    );
+    if let Some(broker_sampler) = broker_sampler {
+        match broker_sampler.await.unwrap() {
+            Ok(samples) => {
+                broker::print_summary(&samples);
+                report.broker_samples = samples;
+            }
+            Err(err) => println!("failed to fetch broker metrics: {}", err),
+        }
+    }
+    if let Some(path) = &options.json {
+        report.write_json(path).unwrap();
+    }
+    if let Some(path) = &options.csv {
+        report.write_csv(path).unwrap();
+    }
+    if let Some(path) = &options.compare {
+        let baseline = Report::load(path).unwrap();
+        let regressions = report.regressions(&baseline, options.regression_threshold);
+        if regressions.is_empty() {
+            println!("No regressions compared to {}", path.display());
+        } else {
+            println!("Regressions compared to {}:", path.display());
+            for regression in &regressions {
+                println!("  {}", regression);
+            }
+            std::process::exit(1);
+        }
+    }
 }
 
-async fn client_loop(
+/// Connects a client and waits for the CONNACK packet.
+///
+/// Returns the client, its message stream and the CONNECT to CONNACK latency.
+async fn connect(
+    options: &Options,
     id: usize,
-    barrier: Arc<Barrier>,
-    addr: (String, u16),
-    payload: Bytes,
-    duration: usize,
-) -> Result<(usize, usize)> {
-    let (client, mut receiver) = Client::new(addr)
+) -> Result<(Client, impl Stream<Item = Message>, Duration)> {
+    let (tx_connack, mut rx_connack) = mpsc::unbounded_channel();
+    let start = Instant::now();
+    let url = match &options.url {
+        Some(url) => url.clone(),
+        None if options.host.contains(':') => format!("mqtt://[{}]:{}", options.host, options.port),
+        None => format!("mqtt://{}:{}", options.host, options.port),
+    };
+    let mut builder = Client::from_url(&url)?;
+    if options.insecure {
+        builder = builder.danger_accept_invalid_certs();
+    }
+    let (client, receiver) = builder
+        .protocol_level(options.protocol)
         .client_id(format!("client{}", id))
         .clean_start()
+        .on_packet_received(move |packet| {
+            if let Packet::ConnAck(_) = packet {
+                tx_connack.send(Instant::now()).ok();
+            }
+        })
         .build()
+        .await?;
+    let connected_at = tokio::time::timeout(Duration::from_secs(10), rx_connack.recv())
         .await
-        .unwrap();
-    let topic: ByteString = format!("client{}", id).into();
-    client
-        .subscribe()
-        .filter(FilterBuilder::new(topic.clone()))
-        .send()
-        .await
-        .unwrap();
+        .ok()
+        .flatten()
+        .ok_or_else(|| anyhow::anyhow!("client{}: connect timeout", id))?;
+    Ok((client, receiver, connected_at - start))
+}
+
+/// Keeps connecting and disconnecting a client until the end of the test.
+async fn churn_loop(id: usize, options: Arc<Options>) -> Result<Stats> {
+    let mut stats = Stats::default();
+    let deadline = Instant::now() + Duration::from_secs(options.duration as u64);
+
+    while Instant::now() < deadline {
+        let (client, receiver, latency) = connect(&options, id).await?;
+        stats
+            .connect_latency
+            .saturating_record(latency.as_micros() as u64);
+        stats.reconnect_count += 1;
+        tokio::time::sleep_until(
+            deadline.min(Instant::now() + Duration::from_secs_f64(options.churn_interval)),
+        )
+        .await;
+        drop((client, receiver));
+    }
+
+    Ok(stats)
+}
+
+struct ClientOptions {
+    id: usize,
+    topic: usize,
+    publish: bool,
+    subscribe: bool,
+    subscribers_on_topic: usize,
+    payload: Bytes,
+}
+
+async fn client_loop(
+    client_options: ClientOptions,
+    options: Arc<Options>,
+    counters: Arc<LiveCounters>,
+    barrier: Arc<Barrier>,
+) -> Result<Stats> {
+    let (client, receiver, connect_latency) = connect(&options, client_options.id).await.unwrap();
+    tokio::pin!(receiver);
+    if client_options.subscribe {
+        let filter = options
+            .topic_template
+            .subscribe_filter(client_options.topic);
+        let filter = match &options.share_group {
+            Some(group) => format!("$share/{}/{}", group, filter).into(),
+            None => filter,
+        };
+        client
+            .subscribe()
+            .filter(FilterBuilder::new(filter).qos(options.qos))
+            .send()
+            .await
+            .unwrap();
+    }
 
     barrier.wait().await;
 
-    let send_count = Arc::new(AtomicUsize::default());
-    let recv_count = Arc::new(AtomicUsize::default());
-
-    let timeout = tokio::time::sleep(Duration::from_secs(duration as u64));
-    let publish_task = {
-        let send_count = send_count.clone();
-        async move {
-            loop {
-                client
-                    .publish(topic.clone())
-                    .qos(Qos::ExactlyOnce)
-                    .payload(payload.clone())
-                    .send()
-                    .await
-                    .unwrap();
-                send_count.fetch_add(1, Ordering::SeqCst);
+    let mut stats = Stats::default();
+    stats
+        .connect_latency
+        .saturating_record(connect_latency.as_micros() as u64);
+    let Stats {
+        send_count,
+        recv_count,
+        max_backlog,
+        latency,
+        ..
+    } = &mut stats;
+    let payload = &client_options.payload;
+    let duration = Duration::from_secs(options.duration as u64);
+    let start = Instant::now();
+    let timeout = tokio::time::sleep(duration);
+    let publish_task = async {
+        if !client_options.publish {
+            return future::pending().await;
+        }
+        let static_topic = if options.topic_template.is_dynamic() {
+            None
+        } else {
+            Some(
+                options
+                    .topic_template
+                    .publish_topic(client_options.topic, client_options.id, 0),
+            )
+        };
+        let mut seq = 0;
+        loop {
+            if let Some(rate) = options.rate {
+                tokio::time::sleep_until(start + Duration::from_secs_f64(seq as f64 / rate)).await;
+                let scheduled = (start.elapsed().as_secs_f64() * rate) as usize + 1;
+                *max_backlog = (*max_backlog).max(scheduled.saturating_sub(seq as usize));
             }
+
+            let topic = match &static_topic {
+                Some(topic) => topic.clone(),
+                None => options.topic_template.publish_topic(
+                    client_options.topic,
+                    client_options.id,
+                    seq,
+                ),
+            };
+            let mut builder = client
+                .publish(topic)
+                .qos(options.qos)
+                .payload(stats::create_payload(payload, payload.len()));
+            if fastrand::u8(..100) < options.retain_percent {
+                builder = builder.retain();
+            }
+            builder.send().await.unwrap();
+            *send_count += 1;
+            counters.record_send();
+            seq += 1;
         }
     };
-    let receive_task = {
-        let recv_count = recv_count.clone();
-        async move {
-            while let Some(_) = receiver.next().await {
-                recv_count.fetch_add(1, Ordering::SeqCst);
+    let receive_task = async {
+        while let Some(msg) = receiver.next().await {
+            *recv_count += 1;
+            let value = stats::payload_latency(msg.payload());
+            if let Some(value) = value {
+                latency.saturating_record(value);
             }
+            counters.record_recv(value);
         }
     };
 
+    tokio::pin!(receive_task);
+
     tokio::select! {
         _ = timeout => {}
         _ = publish_task => {}
-        _ = receive_task => {}
+        _ = &mut receive_task => {}
     }
 
-    Ok((
-        send_count.load(Ordering::SeqCst),
-        recv_count.load(Ordering::SeqCst),
-    ))
+    // in target-rate mode, wait for the messages still in flight to measure the loss
+    if options.rate.is_some() {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            _ = receive_task => {}
+        }
+    }
+
+    if let Some(rate) = options.rate {
+        if client_options.publish {
+            stats.scheduled_count = (rate * duration.as_secs_f64()) as usize;
+        }
+    }
+    stats.expected_recv_count = stats.send_count * client_options.subscribers_on_topic;
+    if client_options.subscribe {
+        stats
+            .consumer_counts
+            .push((client_options.id, stats.recv_count));
+    }
+    Ok(stats)
 }
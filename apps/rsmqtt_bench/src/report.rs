@@ -0,0 +1,165 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+
+use crate::broker::BrokerSample;
+use crate::sample::Sample;
+use crate::stats::Stats;
+
+/// Percentiles of a latency histogram in milliseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencySummary {
+    fn new(histogram: &Histogram<u64>) -> Option<Self> {
+        if histogram.is_empty() {
+            return None;
+        }
+        let ms = |value: u64| value as f64 / 1000.0;
+        Some(Self {
+            p50_ms: ms(histogram.value_at_quantile(0.5)),
+            p90_ms: ms(histogram.value_at_quantile(0.9)),
+            p99_ms: ms(histogram.value_at_quantile(0.99)),
+            max_ms: ms(histogram.max()),
+        })
+    }
+}
+
+/// The results of a benchmark run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub send_tps: f64,
+    pub recv_tps: f64,
+    pub transferred_bytes: u64,
+    pub latency: Option<LatencySummary>,
+    pub connect_latency: Option<LatencySummary>,
+
+    /// Only measured in target-rate mode.
+    #[serde(default)]
+    pub message_loss_percent: Option<f64>,
+
+    #[serde(default)]
+    pub samples: Vec<Sample>,
+
+    /// Only collected if the broker metrics endpoint is given.
+    #[serde(default)]
+    pub broker_samples: Vec<BrokerSample>,
+}
+
+impl Report {
+    pub fn new(
+        stats: &Stats,
+        duration: usize,
+        payload_size: usize,
+        rate_mode: bool,
+        samples: Vec<Sample>,
+    ) -> Self {
+        Self {
+            send_tps: stats.send_count as f64 / duration as f64,
+            recv_tps: stats.recv_count as f64 / duration as f64,
+            transferred_bytes: ((stats.send_count + stats.recv_count) * payload_size) as u64,
+            latency: LatencySummary::new(&stats.latency),
+            connect_latency: LatencySummary::new(&stats.connect_latency),
+            message_loss_percent: if rate_mode {
+                Some(stats.message_loss_percent())
+            } else {
+                None
+            },
+            samples,
+            broker_samples: Vec::new(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_slice(&data).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn write_json(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Writes the time series samples, one row per sampling interval.
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut data = String::new();
+        data.push_str(Sample::CSV_HEADER);
+        data.push('\n');
+        for sample in &self.samples {
+            data.push_str(&sample.to_csv());
+            data.push('\n');
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Returns a description of every metric that is worse than the baseline by more than
+    /// `threshold` percent.
+    pub fn regressions(&self, baseline: &Report, threshold: f64) -> Vec<String> {
+        let mut regressions = Vec::new();
+        let mut check = |name: &str, value: f64, baseline: f64, higher_is_better: bool| {
+            if baseline <= 0.0 {
+                return;
+            }
+            let change = (value - baseline) * 100.0 / baseline;
+            let regressed = if higher_is_better {
+                change < -threshold
+            } else {
+                change > threshold
+            };
+            if regressed {
+                regressions.push(format!(
+                    "{}: {:.3} -> {:.3} ({:+.1}%)",
+                    name, baseline, value, change
+                ));
+            }
+        };
+
+        check("send_tps", self.send_tps, baseline.send_tps, true);
+        check("recv_tps", self.recv_tps, baseline.recv_tps, true);
+        for (name, summary, baseline) in [
+            ("latency", &self.latency, &baseline.latency),
+            (
+                "connect_latency",
+                &self.connect_latency,
+                &baseline.connect_latency,
+            ),
+        ] {
+            if let (Some(summary), Some(baseline)) = (summary, baseline) {
+                check(
+                    &format!("{}.p50_ms", name),
+                    summary.p50_ms,
+                    baseline.p50_ms,
+                    false,
+                );
+                check(
+                    &format!("{}.p99_ms", name),
+                    summary.p99_ms,
+                    baseline.p99_ms,
+                    false,
+                );
+            }
+        }
+        if let (Some(loss), Some(baseline_loss)) =
+            (self.message_loss_percent, baseline.message_loss_percent)
+        {
+            if loss > baseline_loss + threshold {
+                regressions.push(format!(
+                    "message_loss_percent: {:.3} -> {:.3}",
+                    baseline_loss, loss
+                ));
+            }
+        }
+
+        regressions
+    }
+}
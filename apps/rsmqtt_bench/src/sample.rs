@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// Counters shared by all clients, read periodically to build the time series.
+#[derive(Default)]
+pub struct LiveCounters {
+    send_count: AtomicU64,
+    recv_count: AtomicU64,
+    latency_sum: AtomicU64,
+    latency_count: AtomicU64,
+    latency_max: AtomicU64,
+}
+
+impl LiveCounters {
+    pub fn record_send(&self) {
+        self.send_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a received message and its delivery latency in microseconds.
+    pub fn record_recv(&self, latency: Option<u64>) {
+        self.recv_count.fetch_add(1, Ordering::Relaxed);
+        if let Some(latency) = latency {
+            self.latency_sum.fetch_add(latency, Ordering::Relaxed);
+            self.latency_count.fetch_add(1, Ordering::Relaxed);
+            self.latency_max.fetch_max(latency, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The throughput and latency over one sampling interval.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    /// Seconds since the start of the test at the end of the interval.
+    pub elapsed: f64,
+    pub send_tps: f64,
+    pub recv_tps: f64,
+    pub latency_avg_ms: f64,
+    pub latency_max_ms: f64,
+}
+
+impl Sample {
+    pub const CSV_HEADER: &'static str = "elapsed,send_tps,recv_tps,latency_avg_ms,latency_max_ms";
+
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{:.3},{:.3},{:.3},{:.3},{:.3}",
+            self.elapsed, self.send_tps, self.recv_tps, self.latency_avg_ms, self.latency_max_ms
+        )
+    }
+}
+
+/// Samples the counters every `interval` until `duration` has elapsed.
+pub async fn sample_loop(
+    counters: Arc<LiveCounters>,
+    interval: Duration,
+    duration: Duration,
+) -> Vec<Sample> {
+    let start = Instant::now();
+    let mut last = start;
+    let mut last_send_count = 0;
+    let mut last_recv_count = 0;
+    let mut samples = Vec::new();
+
+    while last < start + duration {
+        tokio::time::sleep_until((last + interval).min(start + duration)).await;
+        let now = Instant::now();
+        let secs = (now - last).as_secs_f64();
+
+        let send_count = counters.send_count.load(Ordering::Relaxed);
+        let recv_count = counters.recv_count.load(Ordering::Relaxed);
+        let latency_sum = counters.latency_sum.swap(0, Ordering::Relaxed);
+        let latency_count = counters.latency_count.swap(0, Ordering::Relaxed);
+        let latency_max = counters.latency_max.swap(0, Ordering::Relaxed);
+
+        samples.push(Sample {
+            elapsed: (now - start).as_secs_f64(),
+            send_tps: (send_count - last_send_count) as f64 / secs,
+            recv_tps: (recv_count - last_recv_count) as f64 / secs,
+            latency_avg_ms: if latency_count > 0 {
+                latency_sum as f64 / latency_count as f64 / 1000.0
+            } else {
+                0.0
+            },
+            latency_max_ms: latency_max as f64 / 1000.0,
+        });
+
+        last = now;
+        last_send_count = send_count;
+        last_recv_count = recv_count;
+    }
+
+    samples
+}
@@ -0,0 +1,203 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use hdrhistogram::Histogram;
+
+/// Size of the send timestamp embedded at the beginning of each payload.
+pub const TIMESTAMP_SIZE: usize = 8;
+
+/// Returns the number of microseconds since the unix epoch.
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// Creates a payload of `size` bytes that starts with the current time.
+pub fn create_payload(padding: &Bytes, size: usize) -> Bytes {
+    let mut payload = BytesMut::with_capacity(size.max(TIMESTAMP_SIZE));
+    payload.put_u64_le(now_micros());
+    payload.extend_from_slice(&padding[..size.saturating_sub(TIMESTAMP_SIZE)]);
+    payload.freeze()
+}
+
+/// Returns the delivery latency in microseconds of a payload created by [`create_payload`].
+pub fn payload_latency(payload: &[u8]) -> Option<u64> {
+    let mut timestamp = [0; TIMESTAMP_SIZE];
+    timestamp.copy_from_slice(payload.get(..TIMESTAMP_SIZE)?);
+    Some(now_micros().saturating_sub(u64::from_le_bytes(timestamp)))
+}
+
+pub struct Stats {
+    pub send_count: usize,
+    pub recv_count: usize,
+
+    /// Number of messages that should have been published in target-rate mode.
+    pub scheduled_count: usize,
+
+    /// Largest number of messages a publisher was behind its schedule.
+    pub max_backlog: usize,
+
+    /// Number of deliveries expected for the published messages.
+    pub expected_recv_count: usize,
+
+    /// Delivery latency in microseconds.
+    pub latency: Histogram<u64>,
+
+    /// CONNECT to CONNACK latency in microseconds.
+    pub connect_latency: Histogram<u64>,
+
+    /// Number of connections made by churning clients.
+    pub reconnect_count: usize,
+
+    /// Number of messages received by each subscribing client, by client id.
+    pub consumer_counts: Vec<(usize, usize)>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            send_count: 0,
+            recv_count: 0,
+            scheduled_count: 0,
+            max_backlog: 0,
+            expected_recv_count: 0,
+            latency: Histogram::new(3).unwrap(),
+            connect_latency: Histogram::new(3).unwrap(),
+            reconnect_count: 0,
+            consumer_counts: Vec::new(),
+        }
+    }
+}
+
+impl Stats {
+    pub fn merge(&mut self, other: &Stats) {
+        self.send_count += other.send_count;
+        self.recv_count += other.recv_count;
+        self.scheduled_count += other.scheduled_count;
+        self.max_backlog = self.max_backlog.max(other.max_backlog);
+        self.expected_recv_count += other.expected_recv_count;
+        self.latency.add(&other.latency).unwrap();
+        self.connect_latency.add(&other.connect_latency).unwrap();
+        self.reconnect_count += other.reconnect_count;
+        self.consumer_counts
+            .extend_from_slice(&other.consumer_counts);
+    }
+
+    pub fn print_rate(&self, rate: f64, num_publishers: usize, duration: usize) {
+        println!(
+            "Target rate: {:.3} msg/s, achieved: {:.3} msg/s",
+            rate * num_publishers as f64,
+            self.send_count as f64 / duration as f64
+        );
+        println!(
+            "Backlog: {} messages at the end, {} messages max per publisher",
+            self.scheduled_count.saturating_sub(self.send_count),
+            self.max_backlog
+        );
+        println!(
+            "Message loss: {} of {} ({:.3}%)",
+            self.expected_recv_count.saturating_sub(self.recv_count),
+            self.expected_recv_count,
+            self.message_loss_percent()
+        );
+    }
+
+    pub fn message_loss_percent(&self) -> f64 {
+        if self.expected_recv_count > 0 {
+            self.expected_recv_count.saturating_sub(self.recv_count) as f64 * 100.0
+                / self.expected_recv_count as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// Prints how the messages were spread over the subscribers of a shared subscription.
+    pub fn print_consumer_distribution(&self) {
+        if self.consumer_counts.is_empty() {
+            println!("Consumers: none");
+            return;
+        }
+
+        let mut counts = self.consumer_counts.clone();
+        counts.sort_unstable();
+        println!("Messages per consumer:");
+        for (id, count) in &counts {
+            println!("  client{:<6} {:>10}", id, count);
+        }
+
+        let n = counts.len() as f64;
+        let mean = counts.iter().map(|(_, count)| *count as f64).sum::<f64>() / n;
+        let stddev = (counts
+            .iter()
+            .map(|(_, count)| (*count as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n)
+            .sqrt();
+        println!(
+            "Consumer distribution: min {}, max {}, mean {:.3}, stddev {:.3} ({:.3}% of mean)",
+            counts
+                .iter()
+                .map(|(_, count)| *count)
+                .min()
+                .unwrap_or_default(),
+            counts
+                .iter()
+                .map(|(_, count)| *count)
+                .max()
+                .unwrap_or_default(),
+            mean,
+            stddev,
+            if mean > 0.0 {
+                stddev * 100.0 / mean
+            } else {
+                0.0
+            }
+        );
+    }
+
+    pub fn print_latency(&self) {
+        print_histogram("Latency", &self.latency);
+    }
+
+    pub fn print_connect_latency(&self) {
+        print_histogram("Connect latency", &self.connect_latency);
+        if self.reconnect_count > 0 {
+            println!("Reconnections: {}", self.reconnect_count);
+        }
+    }
+}
+
+/// Prints the percentiles and a histogram of values in microseconds.
+fn print_histogram(name: &str, histogram: &Histogram<u64>) {
+    if histogram.is_empty() {
+        println!("{}: no samples", name);
+        return;
+    }
+
+    let ms = |value: u64| value as f64 / 1000.0;
+    println!(
+        "{}: p50 {:.3}ms, p90 {:.3}ms, p99 {:.3}ms, max {:.3}ms",
+        name,
+        ms(histogram.value_at_quantile(0.5)),
+        ms(histogram.value_at_quantile(0.9)),
+        ms(histogram.value_at_quantile(0.99)),
+        ms(histogram.max()),
+    );
+
+    println!("{} histogram:", name);
+    let total = histogram.len();
+    for value in histogram.iter_log(1000, 2.0) {
+        let count = value.count_since_last_iteration();
+        if count == 0 {
+            continue;
+        }
+        println!(
+            "  <= {:>10.3}ms {:>10} {}",
+            ms(value.value_iterated_to()),
+            count,
+            "#".repeat((count * 50 / total) as usize),
+        );
+    }
+}
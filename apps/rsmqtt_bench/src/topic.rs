@@ -0,0 +1,56 @@
+use std::str::FromStr;
+
+use bytestring::ByteString;
+
+/// A topic name with placeholders.
+///
+/// - `{topic}` is replaced with the topic index
+/// - `{client}` is replaced with the id of the publishing client
+/// - `{seq}` is replaced with the sequence number of the message
+/// - `{rand}` is replaced with a random number
+///
+/// Subscribers only know the topic index, so the other placeholders are replaced with the `+`
+/// wildcard in their filters and must therefore span a whole topic level.
+#[derive(Debug, Clone)]
+pub struct TopicTemplate(String);
+
+impl FromStr for TopicTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty topic template".to_string());
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl TopicTemplate {
+    /// Returns `true` if the topic name changes with every message.
+    pub fn is_dynamic(&self) -> bool {
+        self.0.contains("{seq}") || self.0.contains("{rand}")
+    }
+
+    pub fn publish_topic(&self, topic: usize, client: usize, seq: u64) -> ByteString {
+        let mut name = self
+            .0
+            .replace("{topic}", &topic.to_string())
+            .replace("{client}", &client.to_string());
+        if name.contains("{seq}") {
+            name = name.replace("{seq}", &seq.to_string());
+        }
+        if name.contains("{rand}") {
+            name = name.replace("{rand}", &fastrand::u32(..).to_string());
+        }
+        name.into()
+    }
+
+    pub fn subscribe_filter(&self, topic: usize) -> ByteString {
+        self.0
+            .replace("{topic}", &topic.to_string())
+            .replace("{client}", "+")
+            .replace("{seq}", "+")
+            .replace("{rand}", "+")
+            .into()
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt::{self, Display, Formatter};
+
+use anyhow::{Context, Result};
+use hyper::body::Bytes;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Deserialize)]
+pub struct ClientInfo {
+    pub client_id: String,
+    pub uid: Option<String>,
+    pub remote_addr: String,
+    pub level: String,
+    pub keep_alive: u16,
+    pub connected_at: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "reason_code", rename_all = "snake_case")]
+pub enum DisconnectReason {
+    Client(String),
+    Server(String),
+    ConnectionLost,
+}
+
+impl Display for DisconnectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DisconnectReason::Client(reason_code) => write!(f, "client: {}", reason_code),
+            DisconnectReason::Server(reason_code) => write!(f, "server: {}", reason_code),
+            DisconnectReason::ConnectionLost => write!(f, "connection lost"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DisconnectRecord {
+    pub timestamp: u64,
+    pub reason: DisconnectReason,
+}
+
+#[derive(Deserialize)]
+pub struct RetainedMessage {
+    pub topic: String,
+    pub qos: u8,
+    pub payload: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Ban {
+    ClientId(String),
+    User(String),
+    Address(String),
+}
+
+#[derive(Deserialize)]
+pub struct Redirect {
+    pub server_reference: String,
+    pub permanent: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RedirectResult {
+    pub clients: usize,
+}
+
+#[derive(Deserialize)]
+pub struct BanInfo {
+    #[serde(flatten)]
+    pub ban: Ban,
+    pub remaining: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum TraceTarget {
+    ClientId(String),
+    Topic(String),
+}
+
+impl Display for TraceTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceTarget::ClientId(client_id) => write!(f, "client-id={}", client_id),
+            TraceTarget::Topic(topic) => write!(f, "topic={}", topic),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TraceOptions {
+    pub target: TraceTarget,
+    pub file: Option<String>,
+    pub max_packets: usize,
+}
+
+#[derive(Deserialize)]
+pub struct TraceInfo {
+    pub id: String,
+    pub options: TraceOptions,
+    pub packets: usize,
+    pub remaining: u64,
+}
+
+#[derive(Deserialize)]
+pub struct TraceStarted {
+    pub id: String,
+}
+
+/// A client of the `/api/v1` endpoints of rsmqttd.
+pub struct Api {
+    client: Client<HttpConnector>,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl Api {
+    pub fn new(url: &str, token: Option<String>) -> Result<Self> {
+        anyhow::ensure!(
+            url.starts_with("http://"),
+            "only http urls are supported: {}",
+            url
+        );
+        Ok(Self {
+            client: Client::new(),
+            base_url: format!("{}/api/v1", url.trim_end_matches('/')),
+            token,
+        })
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: Option<&[(&str, &str)]>,
+        body: Option<Value>,
+    ) -> Result<Bytes> {
+        let mut uri = format!("{}/{}", self.base_url, path);
+        if let Some(query) = query {
+            uri.push('?');
+            uri.push_str(&serde_urlencoded::to_string(query)?);
+        }
+
+        let mut req = Request::builder()
+            .method(method)
+            .uri(&uri)
+            .header("content-type", "application/json");
+        if let Some(token) = &self.token {
+            req = req.header("authorization", format!("Bearer {}", token));
+        }
+        let req = req.body(match body {
+            Some(body) => Body::from(serde_json::to_vec(&body)?),
+            None => Body::empty(),
+        })?;
+        let resp = self
+            .client
+            .request(req)
+            .await
+            .with_context(|| format!("failed to request {}", uri))?;
+        let status = resp.status();
+        let data = hyper::body::to_bytes(resp.into_body()).await?;
+
+        if !status.is_success() {
+            let message = String::from_utf8_lossy(&data);
+            match message.trim() {
+                "" => anyhow::bail!("{}", status),
+                message => anyhow::bail!("{}: {}", status, message),
+            }
+        }
+        Ok(data)
+    }
+
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: Option<&[(&str, &str)]>,
+    ) -> Result<T> {
+        let data = self.request(Method::GET, path, query, None).await?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub async fn post(&self, path: &str, body: Value) -> Result<()> {
+        self.request(Method::POST, path, None, Some(body)).await?;
+        Ok(())
+    }
+
+    pub async fn post_json<T: DeserializeOwned>(&self, path: &str, body: Value) -> Result<T> {
+        let data = self.request(Method::POST, path, None, Some(body)).await?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub async fn put_json<T: DeserializeOwned>(&self, path: &str, body: Value) -> Result<T> {
+        let data = self.request(Method::PUT, path, None, Some(body)).await?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub async fn delete(
+        &self,
+        path: &str,
+        query: Option<&[(&str, &str)]>,
+        body: Option<Value>,
+    ) -> Result<()> {
+        self.request(Method::DELETE, path, query, body).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::default_trait_access)]
+
+mod api;
+
+use anyhow::Result;
+use serde_json::json;
+use structopt::StructOpt;
+
+use crate::api::{
+    Api, Ban, BanInfo, ClientInfo, DisconnectRecord, Redirect, RedirectResult, RetainedMessage,
+    TraceInfo, TraceStarted,
+};
+
+#[derive(StructOpt)]
+struct Options {
+    /// url of the HTTP server of rsmqttd, the `api` option must be enabled.
+    #[structopt(long, short, default_value = "http://127.0.0.1:8080")]
+    url: String,
+
+    /// token of the api, required by the commands that change the state of the broker.
+    #[structopt(long, short)]
+    token: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Lists the connected clients.
+    Clients,
+
+    /// Disconnects a client.
+    Kick {
+        /// client id
+        client_id: String,
+    },
+
+    /// Lists the last disconnect reasons of a client.
+    Disconnects {
+        /// client id
+        client_id: String,
+    },
+
+    /// Publishes a message.
+    Publish {
+        /// QoS of the message (0, 1, 2)
+        #[structopt(long, short, default_value = "0")]
+        qos: u8,
+
+        /// retain the message.
+        #[structopt(long, short)]
+        retain: bool,
+
+        /// topic
+        topic: String,
+
+        /// payload
+        #[structopt(default_value = "")]
+        payload: String,
+    },
+
+    /// Lists the retained messages.
+    Retained {
+        /// topic filter
+        #[structopt(default_value = "#")]
+        filter: String,
+    },
+
+    /// Prints the metrics.
+    Metrics,
+
+    /// Manages the bans, banned clients are rejected when they connect.
+    Ban(BanCommand),
+
+    /// Manages the packet traces.
+    Trace(TraceCommand),
+
+    /// Redirects the clients to another server.
+    Redirect(RedirectCommand),
+}
+
+#[derive(StructOpt)]
+enum RedirectCommand {
+    /// Prints the redirect of the new connections.
+    Show,
+
+    /// Redirects the new connections.
+    Set {
+        /// the address of the other server, sent to the clients as the server reference.
+        server_reference: String,
+
+        /// the server has moved permanently (ServerMoved instead of UseAnotherServer).
+        #[structopt(long)]
+        permanent: bool,
+
+        /// also disconnect the connected clients with the redirect.
+        #[structopt(long)]
+        disconnect_clients: bool,
+    },
+
+    /// Accepts the new connections again.
+    Clear,
+}
+
+#[derive(StructOpt)]
+enum BanCommand {
+    /// Lists the bans.
+    List,
+
+    /// Adds a ban.
+    Add {
+        #[structopt(flatten)]
+        options: BanOptions,
+
+        /// remove the ban after this number of seconds, the ban is permanent if not specified.
+        #[structopt(long)]
+        duration: Option<u64>,
+    },
+
+    /// Removes a ban.
+    Remove(BanOptions),
+}
+
+#[derive(StructOpt)]
+struct BanOptions {
+    /// what is banned (client-id, user, address)
+    kind: String,
+
+    /// client id, uid or IP address
+    value: String,
+}
+
+#[derive(StructOpt)]
+enum TraceCommand {
+    /// Lists the running traces.
+    List,
+
+    /// Starts tracing the packets of a client or of the messages published to a topic, prints the
+    /// id of the trace.
+    ///
+    /// The packets are published to `$SYS/trace/{id}` unless a file is specified.
+    Start {
+        /// trace the packets sent and received by this client.
+        #[structopt(long, group = "target", required_unless = "topic")]
+        client_id: Option<String>,
+
+        /// trace the messages published to the topics matching this filter.
+        #[structopt(long, group = "target")]
+        topic: Option<String>,
+
+        /// append the packets to the file with this name in the `trace_dir` of the broker.
+        #[structopt(long)]
+        file: Option<String>,
+
+        /// stop the trace after this number of seconds.
+        #[structopt(long, default_value = "60")]
+        duration: u64,
+
+        /// stop the trace after this number of packets.
+        #[structopt(long, default_value = "1000")]
+        max_packets: usize,
+    },
+
+    /// Stops a trace.
+    Stop {
+        /// trace id
+        id: String,
+    },
+}
+
+impl BanOptions {
+    fn into_ban(self) -> Result<Ban> {
+        Ok(match self.kind.as_str() {
+            "client-id" => Ban::ClientId(self.value),
+            "user" => Ban::User(self.value),
+            "address" => Ban::Address(self.value),
+            _ => anyhow::bail!("unknown ban kind: {}", self.kind),
+        })
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let options: Options = Options::from_args();
+    let api = Api::new(&options.url, options.token)?;
+
+    match options.command {
+        Command::Clients => {
+            let clients: Vec<ClientInfo> = api.get("clients", None).await?;
+            for client in clients {
+                println!(
+                    "{}\tuid={}\taddr={}\tlevel={}\tkeep_alive={}\tconnected_at={}",
+                    client.client_id,
+                    client.uid.as_deref().unwrap_or("-"),
+                    client.remote_addr,
+                    client.level,
+                    client.keep_alive,
+                    client.connected_at,
+                );
+            }
+        }
+        Command::Kick { client_id } => {
+            api.delete("clients", Some(&[("client_id", &client_id)]), None)
+                .await?;
+        }
+        Command::Disconnects { client_id } => {
+            let records: Vec<DisconnectRecord> = api
+                .get("disconnects", Some(&[("client_id", &client_id)]))
+                .await?;
+            for record in records {
+                println!("{}\t{}", record.timestamp, record.reason);
+            }
+        }
+        Command::Publish {
+            qos,
+            retain,
+            topic,
+            payload,
+        } => {
+            api.post(
+                "publish",
+                json!({
+                    "topic": topic,
+                    "qos": qos,
+                    "retain": retain,
+                    "payload": payload,
+                }),
+            )
+            .await?;
+        }
+        Command::Retained { filter } => {
+            let msgs: Vec<RetainedMessage> =
+                api.get("retained", Some(&[("filter", &filter)])).await?;
+            for msg in msgs {
+                println!("{}\tqos={}\t{}", msg.topic, msg.qos, msg.payload);
+            }
+        }
+        Command::Metrics => {
+            let metrics: serde_json::Value = api.get("metrics", None).await?;
+            println!("{}", serde_json::to_string_pretty(&metrics)?);
+        }
+        Command::Ban(BanCommand::List) => {
+            let bans: Vec<BanInfo> = api.get("bans", None).await?;
+            for BanInfo { ban, remaining } in bans {
+                let remaining = match remaining {
+                    Some(remaining) => format!("{}s", remaining),
+                    None => "-".to_string(),
+                };
+                match ban {
+                    Ban::ClientId(value) => {
+                        println!("client-id\t{}\tremaining={}", value, remaining)
+                    }
+                    Ban::User(value) => println!("user\t{}\tremaining={}", value, remaining),
+                    Ban::Address(value) => {
+                        println!("address\t{}\tremaining={}", value, remaining)
+                    }
+                }
+            }
+        }
+        Command::Ban(BanCommand::Add { options, duration }) => {
+            let mut req = serde_json::to_value(options.into_ban()?)?;
+            if let Some(duration) = duration {
+                req["duration"] = duration.into();
+            }
+            api.post("bans", req).await?;
+        }
+        Command::Ban(BanCommand::Remove(options)) => {
+            api.delete(
+                "bans",
+                None,
+                Some(serde_json::to_value(options.into_ban()?)?),
+            )
+            .await?;
+        }
+        Command::Trace(TraceCommand::List) => {
+            let traces: Vec<TraceInfo> = api.get("traces", None).await?;
+            for trace in traces {
+                println!(
+                    "{}\t{}\tpackets={}/{}\tremaining={}s\t{}",
+                    trace.id,
+                    trace.options.target,
+                    trace.packets,
+                    trace.options.max_packets,
+                    trace.remaining,
+                    trace.options.file.as_deref().unwrap_or("$SYS"),
+                );
+            }
+        }
+        Command::Trace(TraceCommand::Start {
+            client_id,
+            topic,
+            file,
+            duration,
+            max_packets,
+        }) => {
+            let target = match (client_id, topic) {
+                (Some(client_id), _) => json!({ "type": "client_id", "value": client_id }),
+                (None, Some(topic)) => json!({ "type": "topic", "value": topic }),
+                (None, None) => unreachable!(),
+            };
+            let started: TraceStarted = api
+                .post_json(
+                    "traces",
+                    json!({
+                        "target": target,
+                        "file": file,
+                        "duration": duration,
+                        "max_packets": max_packets,
+                    }),
+                )
+                .await?;
+            println!("{}", started.id);
+        }
+        Command::Trace(TraceCommand::Stop { id }) => {
+            api.delete("traces", Some(&[("id", &id)]), None).await?;
+        }
+        Command::Redirect(RedirectCommand::Show) => {
+            let redirect: Option<Redirect> = api.get("redirect", None).await?;
+            match redirect {
+                Some(redirect) => println!(
+                    "{}\tpermanent={}",
+                    redirect.server_reference, redirect.permanent
+                ),
+                None => println!("-"),
+            }
+        }
+        Command::Redirect(RedirectCommand::Set {
+            server_reference,
+            permanent,
+            disconnect_clients,
+        }) => {
+            let res: RedirectResult = api
+                .put_json(
+                    "redirect",
+                    json!({
+                        "server_reference": server_reference,
+                        "permanent": permanent,
+                        "disconnect_clients": disconnect_clients,
+                    }),
+                )
+                .await?;
+            if disconnect_clients {
+                println!("{} clients disconnected", res.clients);
+            }
+        }
+        Command::Redirect(RedirectCommand::Clear) => {
+            api.delete("redirect", None, None).await?;
+        }
+    }
+
+    Ok(())
+}
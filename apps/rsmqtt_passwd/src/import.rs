@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::Hasher;
+
+#[derive(Deserialize)]
+struct Entry {
+    username: String,
+    password: String,
+}
+
+/// A JSON file is either a list of `{"username": ..., "password": ...}` objects or a map of
+/// user names to passwords.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonUsers {
+    List(Vec<Entry>),
+    Map(BTreeMap<String, String>),
+}
+
+/// Reads the user name and password pairs from a `.json` or `.csv` file.
+///
+/// A CSV file has one `username,password` pair per line, an optional header line is skipped.
+pub fn read_users(path: &Path) -> Result<Vec<(String, String)>> {
+    let data =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => {
+            let users = serde_json::from_str(&data)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            Ok(match users {
+                JsonUsers::List(entries) => entries
+                    .into_iter()
+                    .map(|entry| (entry.username, entry.password))
+                    .collect(),
+                JsonUsers::Map(users) => users.into_iter().collect(),
+            })
+        }
+        Some("csv") => {
+            let mut users = Vec::new();
+            for (idx, line) in data.lines().enumerate() {
+                let line = line.trim_end_matches('\r');
+                if line.is_empty() || (idx == 0 && line == "username,password") {
+                    continue;
+                }
+                let (username, password) = line.split_once(',').with_context(|| {
+                    format!("{}:{}: expected username,password", path.display(), idx + 1)
+                })?;
+                users.push((username.to_string(), password.to_string()));
+            }
+            Ok(users)
+        }
+        _ => anyhow::bail!(
+            "unsupported file type: {}, expected a .json or .csv file",
+            path.display()
+        ),
+    }
+}
+
+/// Hashes the passwords on all available cores.
+pub fn hash_users(users: Vec<(String, String)>, hasher: &Hasher) -> Result<Vec<(String, String)>> {
+    let num_threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let chunk_size = users.len().div_ceil(num_threads).max(1);
+
+    std::thread::scope(|scope| {
+        let handles = users
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(user, password)| Ok((user.clone(), hasher.create_phc(password)?)))
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut hashed = Vec::with_capacity(users.len());
+        for handle in handles {
+            hashed.extend(handle.join().unwrap()?);
+        }
+        Ok(hashed)
+    })
+}
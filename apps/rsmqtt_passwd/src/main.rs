@@ -1,19 +1,324 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::default_trait_access)]
 
-use passwd_util::HashType;
+mod import;
+mod password;
+mod users;
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use passwd_util::{HashParams, HashType, Pepper};
 use structopt::StructOpt;
 
+use crate::users::UsersFile;
+
+#[derive(StructOpt)]
+enum Command {
+    /// Prints the PHC string of a password.
+    Hash {
+        /// hash type (argon2d, argon2i, argon2id, pbkdf2-sha256, pbkdf2-sha512, scrypt)
+        hash: HashType,
+
+        #[structopt(flatten)]
+        params: HashOptions,
+
+        #[structopt(flatten)]
+        password: PasswordOptions,
+    },
+
+    /// Checks a password against a PHC string, exits with an error if it does not match.
+    Verify {
+        /// PHC string
+        phc: String,
+
+        /// read the password from the first line of stdin instead of prompting for it.
+        #[structopt(long)]
+        stdin: bool,
+
+        #[structopt(flatten)]
+        pepper: PepperOptions,
+    },
+
+    /// Adds a user to the users file.
+    Add {
+        #[structopt(flatten)]
+        user: UserOptions,
+    },
+
+    /// Changes the password of a user in the users file.
+    Update {
+        #[structopt(flatten)]
+        user: UserOptions,
+    },
+
+    /// Deletes a user from the users file.
+    Delete {
+        /// users file
+        #[structopt(long, short, parse(from_os_str))]
+        file: PathBuf,
+
+        /// user name
+        user: String,
+    },
+
+    /// Hashes the passwords of a `.json` or `.csv` file of user names and passwords and adds
+    /// the users to the users file.
+    Import {
+        /// users file
+        #[structopt(long, short, parse(from_os_str))]
+        file: PathBuf,
+
+        /// hash type (argon2d, argon2i, argon2id, pbkdf2-sha256, pbkdf2-sha512, scrypt)
+        #[structopt(long, default_value = "argon2id")]
+        hash: HashType,
+
+        #[structopt(flatten)]
+        params: HashOptions,
+
+        /// replace the passwords of existing users instead of failing.
+        #[structopt(long)]
+        overwrite: bool,
+
+        /// file of user names and passwords
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+
+    /// Lists the users in the users file.
+    List {
+        /// users file
+        #[structopt(long, short, parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
 #[derive(StructOpt)]
-struct Options {
+struct UserOptions {
+    /// users file
+    #[structopt(long, short, parse(from_os_str))]
+    file: PathBuf,
+
     /// hash type (argon2d, argon2i, argon2id, pbkdf2-sha256, pbkdf2-sha512, scrypt)
+    #[structopt(long, default_value = "argon2id")]
     hash: HashType,
 
-    /// password
-    password: String,
+    #[structopt(flatten)]
+    params: HashOptions,
+
+    /// user name
+    user: String,
+
+    #[structopt(flatten)]
+    password: PasswordOptions,
+}
+
+/// Overrides of the default hash parameters.
+#[derive(StructOpt)]
+struct HashOptions {
+    /// argon2 memory size in KiB.
+    #[structopt(long)]
+    argon2_memory: Option<u32>,
+
+    /// argon2 number of iterations.
+    #[structopt(long)]
+    argon2_iterations: Option<u32>,
+
+    /// argon2 degree of parallelism.
+    #[structopt(long)]
+    argon2_parallelism: Option<u32>,
+
+    /// pbkdf2 number of rounds.
+    #[structopt(long)]
+    pbkdf2_iterations: Option<u32>,
+
+    /// scrypt log2 of the work factor N.
+    #[structopt(long)]
+    scrypt_log_n: Option<u8>,
+
+    /// scrypt block size.
+    #[structopt(long)]
+    scrypt_r: Option<u32>,
+
+    /// scrypt parallelization.
+    #[structopt(long)]
+    scrypt_p: Option<u32>,
+
+    #[structopt(flatten)]
+    pepper: PepperOptions,
+}
+
+impl HashOptions {
+    fn params(&self) -> HashParams {
+        let default = HashParams::default();
+        HashParams {
+            argon2_memory: self.argon2_memory.unwrap_or(default.argon2_memory),
+            argon2_iterations: self.argon2_iterations.unwrap_or(default.argon2_iterations),
+            argon2_parallelism: self
+                .argon2_parallelism
+                .unwrap_or(default.argon2_parallelism),
+            pbkdf2_iterations: self.pbkdf2_iterations.unwrap_or(default.pbkdf2_iterations),
+            scrypt_log_n: self.scrypt_log_n.unwrap_or(default.scrypt_log_n),
+            scrypt_r: self.scrypt_r.unwrap_or(default.scrypt_r),
+            scrypt_p: self.scrypt_p.unwrap_or(default.scrypt_p),
+        }
+    }
+
+    fn hasher(&self, hash: HashType) -> Result<Hasher> {
+        Ok(Hasher {
+            hash,
+            params: self.params(),
+            pepper: self.pepper.load()?,
+        })
+    }
+}
+
+/// The pepper passwords are combined with before hashing.
+#[derive(StructOpt)]
+struct PepperOptions {
+    /// id of the pepper, stored in the `kid` parameter of the hash.
+    #[structopt(long, requires = "pepper-source")]
+    pepper_id: Option<String>,
+
+    /// name of the environment variable that contains the pepper.
+    #[structopt(long, group = "pepper-source", requires = "pepper-id")]
+    pepper_env: Option<String>,
+
+    /// file that contains the pepper.
+    #[structopt(
+        long,
+        group = "pepper-source",
+        requires = "pepper-id",
+        parse(from_os_str)
+    )]
+    pepper_file: Option<PathBuf>,
+}
+
+impl PepperOptions {
+    fn load(&self) -> Result<Option<Pepper>> {
+        let id = match &self.pepper_id {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let pepper = match (&self.pepper_env, &self.pepper_file) {
+            (Some(name), _) => Pepper::from_env(id, name)?,
+            (None, Some(path)) => Pepper::from_file(id, path)?,
+            (None, None) => anyhow::bail!("--pepper-env or --pepper-file is required"),
+        };
+        Ok(Some(pepper))
+    }
+}
+
+struct Hasher {
+    hash: HashType,
+    params: HashParams,
+    pepper: Option<Pepper>,
+}
+
+impl Hasher {
+    fn create_phc(&self, password: &str) -> Result<String> {
+        match &self.pepper {
+            Some(pepper) => self
+                .hash
+                .create_phc_with_pepper(password, &self.params, pepper),
+            None => self.hash.create_phc(password, &self.params),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+struct PasswordOptions {
+    /// password, prompted for without echo if omitted.
+    ///
+    /// Passing the password as an argument leaves it in the shell history.
+    password: Option<String>,
+
+    /// read the password from the first line of stdin instead of prompting for it.
+    #[structopt(long)]
+    stdin: bool,
+}
+
+impl PasswordOptions {
+    fn read(self) -> Result<String> {
+        password::read_password(self.password, self.stdin, true)
+    }
 }
 
-fn main() {
-    let options: Options = Options::from_args();
-    println!("{}", options.hash.create_phc(options.password));
+fn main() -> Result<()> {
+    match Command::from_args() {
+        Command::Hash {
+            hash,
+            params,
+            password,
+        } => println!("{}", params.hasher(hash)?.create_phc(&password.read()?)?),
+        Command::Verify { phc, stdin, pepper } => {
+            let peppers: Vec<_> = pepper.load()?.into_iter().collect();
+            let password = password::read_password(None, stdin, false)?;
+            anyhow::ensure!(
+                passwd_util::verify_password_with_peppers(&phc, password, &peppers),
+                "password does not match"
+            );
+            println!("password matches");
+        }
+        Command::Add { user } => {
+            let mut users = UsersFile::load(&user.file)?;
+            anyhow::ensure!(
+                !users.contains(&user.user),
+                "user '{}' already exists",
+                user.user
+            );
+            let phc = user
+                .params
+                .hasher(user.hash)?
+                .create_phc(&user.password.read()?)?;
+            users.insert(user.user, phc);
+            users.save()?;
+        }
+        Command::Update { user } => {
+            let mut users = UsersFile::load(&user.file)?;
+            anyhow::ensure!(
+                users.contains(&user.user),
+                "user '{}' does not exist",
+                user.user
+            );
+            let phc = user
+                .params
+                .hasher(user.hash)?
+                .create_phc(&user.password.read()?)?;
+            users.insert(user.user, phc);
+            users.save()?;
+        }
+        Command::Delete { file, user } => {
+            let mut users = UsersFile::load(&file)?;
+            anyhow::ensure!(users.remove(&user), "user '{}' does not exist", user);
+            users.save()?;
+        }
+        Command::Import {
+            file,
+            hash,
+            params,
+            overwrite,
+            input,
+        } => {
+            let mut users = UsersFile::load(&file)?;
+            let imported = import::read_users(&input)?;
+            if !overwrite {
+                if let Some((user, _)) = imported.iter().find(|(user, _)| users.contains(user)) {
+                    anyhow::bail!("user '{}' already exists", user);
+                }
+            }
+            let count = imported.len();
+            for (user, phc) in import::hash_users(imported, &params.hasher(hash)?)? {
+                users.insert(user, phc);
+            }
+            users.save()?;
+            println!("imported {} users", count);
+        }
+        Command::List { file } => {
+            for (user, _) in UsersFile::load(&file)?.users() {
+                println!("{}", user);
+            }
+        }
+    }
+    Ok(())
 }
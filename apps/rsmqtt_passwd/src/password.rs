@@ -0,0 +1,34 @@
+use std::io::BufRead;
+
+use anyhow::{Context, Result};
+
+/// Returns the password given on the command line, or reads it from stdin or a hidden prompt.
+///
+/// With `confirm`, the prompt asks for the password twice so that typing errors are caught.
+pub fn read_password(password: Option<String>, stdin: bool, confirm: bool) -> Result<String> {
+    if let Some(password) = password {
+        return Ok(password);
+    }
+
+    if stdin {
+        let mut line = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .context("failed to read the password from stdin")?;
+        let password = line.trim_end_matches(&['\r', '\n'][..]).to_string();
+        anyhow::ensure!(!password.is_empty(), "empty password");
+        return Ok(password);
+    }
+
+    let password = rpassword::read_password_from_tty(Some("Password: "))
+        .context("failed to read the password")?;
+    anyhow::ensure!(!password.is_empty(), "empty password");
+    if !confirm {
+        return Ok(password);
+    }
+    let confirmation = rpassword::read_password_from_tty(Some("Confirm password: "))
+        .context("failed to read the password")?;
+    anyhow::ensure!(password == confirmation, "passwords do not match");
+    Ok(password)
+}
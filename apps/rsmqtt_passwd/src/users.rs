@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// A YAML map of user names to PHC strings, as read by the `users_file` option of the
+/// basic-auth plugin.
+pub struct UsersFile {
+    path: PathBuf,
+    users: BTreeMap<String, String>,
+}
+
+impl UsersFile {
+    /// Loads the file, a missing file is treated as empty.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let users = if path.exists() {
+            let data =
+                fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+            if data.iter().all(u8::is_ascii_whitespace) {
+                BTreeMap::new()
+            } else {
+                serde_yaml::from_slice(&data)
+                    .with_context(|| format!("failed to parse {}", path.display()))?
+            }
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self { path, users })
+    }
+
+    /// Writes the file through a temporary file, so that a broker reading it never sees a
+    /// partially written file.
+    pub fn save(&self) -> Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_yaml::to_string(&self.users)?)
+            .with_context(|| format!("failed to write {}", Path::new(&tmp_path).display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        Ok(())
+    }
+
+    pub fn contains(&self, user: &str) -> bool {
+        self.users.contains_key(user)
+    }
+
+    pub fn insert(&mut self, user: String, phc: String) {
+        self.users.insert(user, phc);
+    }
+
+    pub fn remove(&mut self, user: &str) -> bool {
+        self.users.remove(user).is_some()
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.users
+            .iter()
+            .map(|(user, phc)| (user.as_str(), phc.as_str()))
+    }
+}
@@ -1,16 +1,320 @@
+use std::convert::{Infallible, TryFrom};
 use std::sync::Arc;
+use std::time::Duration;
 
-use service::ServiceState;
+use serde::{Deserialize, Serialize};
+use service::codec::Qos;
+use service::{Ban, Message, Redirect, ServiceState, TraceOptions};
+use subtle::ConstantTimeEq;
+use warp::http::{Method, StatusCode};
 use warp::reply::Response;
 use warp::{Filter, Rejection, Reply};
 
+#[derive(Deserialize)]
+struct ClientParams {
+    client_id: String,
+}
+
+#[derive(Deserialize)]
+struct PublishRequest {
+    topic: String,
+    #[serde(default)]
+    qos: u8,
+    #[serde(default)]
+    retain: bool,
+    #[serde(default)]
+    payload: String,
+}
+
+#[derive(Deserialize)]
+struct RetainedParams {
+    #[serde(default = "default_retained_filter")]
+    filter: String,
+}
+
+fn default_retained_filter() -> String {
+    "#".to_string()
+}
+
+#[derive(Deserialize)]
+struct AddBan {
+    #[serde(flatten)]
+    ban: Ban,
+    /// The ban is removed after this number of seconds, it is permanent if not specified.
+    duration: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct SetRedirect {
+    #[serde(flatten)]
+    redirect: Redirect,
+    /// Also disconnects the connected clients with the redirect.
+    #[serde(default)]
+    disconnect_clients: bool,
+}
+
+#[derive(Serialize)]
+struct RedirectResult {
+    /// The number of disconnected clients.
+    clients: usize,
+}
+
+#[derive(Deserialize)]
+struct StopTraceParams {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct TraceStarted {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct RetainedMessage {
+    topic: String,
+    qos: u8,
+    payload: String,
+}
+
+fn with_state(
+    state: Arc<ServiceState>,
+) -> impl Filter<Extract = (Arc<ServiceState>,), Error = Infallible> + Clone {
+    warp::any().map(move || state.clone())
+}
+
+/// Answers the requests that change the state of the broker with Unauthorized unless they send
+/// the token, the other requests are passed to the routes.
+fn unauthorized(
+    token: Option<String>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let token = Arc::new(token);
+    warp::method()
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(move |method: Method, authorization: Option<String>| {
+            let token = token.clone();
+            async move {
+                if method == Method::GET {
+                    return Err(warp::reject());
+                }
+                let authorized = match (&*token, authorization) {
+                    (Some(token), Some(authorization)) => authorization
+                        .strip_prefix("Bearer ")
+                        .map(|value| bool::from(value.as_bytes().ct_eq(token.as_bytes())))
+                        .unwrap_or_default(),
+                    _ => false,
+                };
+                if authorized {
+                    Err(warp::reject())
+                } else {
+                    Ok(StatusCode::UNAUTHORIZED.into_response())
+                }
+            }
+        })
+}
+
+fn bad_request(err: impl ToString) -> Response {
+    warp::reply::with_status(err.to_string(), StatusCode::BAD_REQUEST).into_response()
+}
+
 pub fn metrics(
     state: Arc<ServiceState>,
 ) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
     warp::path!("metrics")
-        .and(warp::any().map(move || state.clone()))
+        .and(warp::get())
+        .and(with_state(state))
         .map(|state: Arc<ServiceState>| {
             let metrics = state.metrics();
             warp::reply::json(&metrics).into_response()
         })
 }
+
+pub fn clients(
+    state: Arc<ServiceState>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("clients")
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(|state: Arc<ServiceState>| async move {
+            Ok::<_, Rejection>(warp::reply::json(&state.clients().await).into_response())
+        })
+}
+
+pub fn kick(
+    state: Arc<ServiceState>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("clients")
+        .and(warp::delete())
+        .and(warp::query::<ClientParams>())
+        .and(with_state(state))
+        .and_then(
+            |params: ClientParams, state: Arc<ServiceState>| async move {
+                Ok::<_, Rejection>(match state.kick(&params.client_id).await {
+                    true => StatusCode::NO_CONTENT.into_response(),
+                    false => StatusCode::NOT_FOUND.into_response(),
+                })
+            },
+        )
+}
+
+pub fn disconnects(
+    state: Arc<ServiceState>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("disconnects")
+        .and(warp::get())
+        .and(warp::query::<ClientParams>())
+        .and(with_state(state))
+        .map(|params: ClientParams, state: Arc<ServiceState>| {
+            warp::reply::json(&state.disconnect_history(&params.client_id)).into_response()
+        })
+}
+
+pub fn publish(
+    state: Arc<ServiceState>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("publish")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(state))
+        .map(|req: PublishRequest, state: Arc<ServiceState>| {
+            let qos = match Qos::try_from(req.qos) {
+                Ok(qos) => qos,
+                Err(_) => return bad_request(format!("invalid qos: {}", req.qos)),
+            };
+            let msg =
+                Message::new(req.topic, qos, req.payload.into_bytes()).with_retain(req.retain);
+            match state.publish(msg) {
+                Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                Err(err) => bad_request(err),
+            }
+        })
+}
+
+pub fn retained(
+    state: Arc<ServiceState>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("retained")
+        .and(warp::get())
+        .and(warp::query::<RetainedParams>())
+        .and(with_state(state))
+        .map(|params: RetainedParams, state: Arc<ServiceState>| {
+            match state.retained_messages(&params.filter) {
+                Ok(msgs) => warp::reply::json(
+                    &msgs
+                        .iter()
+                        .map(|msg| RetainedMessage {
+                            topic: msg.topic().to_string(),
+                            qos: msg.qos().into(),
+                            payload: String::from_utf8_lossy(msg.payload()).into_owned(),
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .into_response(),
+                Err(err) => bad_request(err),
+            }
+        })
+}
+
+pub fn bans(
+    state: Arc<ServiceState>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let list = warp::get()
+        .and(with_state(state.clone()))
+        .map(|state: Arc<ServiceState>| warp::reply::json(&state.bans()).into_response());
+    let add = warp::post()
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .map(|req: AddBan, state: Arc<ServiceState>| {
+            state.add_ban(req.ban, req.duration.map(Duration::from_secs));
+            StatusCode::NO_CONTENT.into_response()
+        });
+    let remove = warp::delete()
+        .and(warp::body::json())
+        .and(with_state(state))
+        .map(
+            |ban: Ban, state: Arc<ServiceState>| match state.remove_ban(&ban) {
+                true => StatusCode::NO_CONTENT.into_response(),
+                false => StatusCode::NOT_FOUND.into_response(),
+            },
+        );
+    warp::path!("bans").and(list.or(add).unify().or(remove).unify())
+}
+
+pub fn redirect(
+    state: Arc<ServiceState>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let get = warp::get()
+        .and(with_state(state.clone()))
+        .map(|state: Arc<ServiceState>| warp::reply::json(&state.redirect()).into_response());
+    let set = warp::put()
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(|req: SetRedirect, state: Arc<ServiceState>| async move {
+            state.set_redirect(Some(req.redirect.clone()));
+            let clients = if req.disconnect_clients {
+                state.redirect_clients(&req.redirect).await
+            } else {
+                0
+            };
+            Ok::<_, Rejection>(warp::reply::json(&RedirectResult { clients }).into_response())
+        });
+    let clear = warp::delete()
+        .and(with_state(state))
+        .map(|state: Arc<ServiceState>| {
+            state.set_redirect(None);
+            StatusCode::NO_CONTENT.into_response()
+        });
+    warp::path!("redirect").and(get.or(set).unify().or(clear).unify())
+}
+
+pub fn traces(
+    state: Arc<ServiceState>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let list = warp::get()
+        .and(with_state(state.clone()))
+        .map(|state: Arc<ServiceState>| warp::reply::json(&state.traces()).into_response());
+    let start = warp::post()
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .map(
+            |options: TraceOptions, state: Arc<ServiceState>| match state.start_trace(options) {
+                Ok(id) => warp::reply::json(&TraceStarted { id }).into_response(),
+                Err(err) => bad_request(format!("{:#}", err)),
+            },
+        );
+    let stop = warp::delete()
+        .and(warp::query::<StopTraceParams>())
+        .and(with_state(state))
+        .map(|params: StopTraceParams, state: Arc<ServiceState>| {
+            match state.stop_trace(&params.id) {
+                true => StatusCode::NO_CONTENT.into_response(),
+                false => StatusCode::NOT_FOUND.into_response(),
+            }
+        });
+    warp::path!("traces").and(list.or(start).unify().or(stop).unify())
+}
+
+/// All routes of the admin API, the requests other than GET must send the token.
+pub fn routes(
+    state: Arc<ServiceState>,
+    token: Option<String>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    unauthorized(token)
+        .or(metrics(state.clone()))
+        .unify()
+        .or(clients(state.clone()))
+        .unify()
+        .or(kick(state.clone()))
+        .unify()
+        .or(disconnects(state.clone()))
+        .unify()
+        .or(publish(state.clone()))
+        .unify()
+        .or(retained(state.clone()))
+        .unify()
+        .or(bans(state.clone()))
+        .unify()
+        .or(redirect(state.clone()))
+        .unify()
+        .or(traces(state))
+        .unify()
+}
@@ -1,6 +1,9 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
 use serde::Deserialize;
 use serde_yaml::Value;
-use service::ServiceConfig;
+use service::{ListenerSettings, ServiceConfig};
 
 #[derive(Debug, Deserialize, Default)]
 pub struct Config {
@@ -12,12 +15,35 @@ pub struct Config {
 
     #[serde(default)]
     pub plugins: Vec<Value>,
+
+    /// The storage backend and its options, selected by `type`, the sessions and retained
+    /// messages are kept in memory if not specified.
+    #[serde(default)]
+    pub storage: Option<Value>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct TlsConfig {
     pub cert: String,
+    /// A PKCS#8 or RSA private key.
     pub key: String,
+    /// The protocols offered with ALPN by the TCP listener, e.g. `mqtt`.
+    #[serde(default)]
+    pub alpn: Vec<String>,
+    /// The CA certificates the client certificates are verified with, the listener requires a
+    /// client certificate if specified. Only the TCP listeners authenticate the clients with
+    /// their certificates, the HTTP and WebSocket listeners just verify them.
+    pub client_ca: Option<String>,
+    /// Also accept the clients without a certificate when `client_ca` is specified.
+    #[serde(default)]
+    pub client_cert_optional: bool,
+}
+
+/// Byte-rate limits in bytes per second, shared fairly by all connections of a listener.
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct BandwidthConfig {
+    pub ingress: Option<usize>,
+    pub egress: Option<usize>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -25,7 +51,12 @@ pub struct TcpConfig {
     #[serde(default = "default_host")]
     pub host: String,
     pub port: Option<u16>,
+    /// Binds these addresses instead of `host` and `port`, e.g. `[::]:1883` and `0.0.0.0:1883`.
+    #[serde(default)]
+    pub bind: Vec<SocketAddr>,
     pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub bandwidth: BandwidthConfig,
 }
 
 impl TcpConfig {
@@ -43,7 +74,16 @@ pub struct HttpConfig {
     pub tls: Option<TlsConfig>,
     pub websocket: bool,
     pub api: bool,
+    /// The token the requests of the admin API that change the state of the broker must send
+    /// as `Authorization: Bearer {token}`, only the GET requests are accepted if not set.
+    pub api_token: Option<String>,
     pub graphql_api: bool,
+    /// Limits the WebSocket connections.
+    #[serde(default)]
+    pub bandwidth: BandwidthConfig,
+    /// Origins allowed to open a WebSocket connection, any origin is allowed if empty.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
 }
 
 impl HttpConfig {
@@ -53,10 +93,99 @@ impl HttpConfig {
     }
 }
 
+/// A listener that only serves the WebSocket transport, so it can use its own certificate.
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebSocketConfig {
+    #[serde(default = "default_host")]
+    pub host: String,
+    pub port: Option<u16>,
+    /// Binds these addresses instead of `host` and `port`, an IPv6 address also accepts IPv4
+    /// connections if the system allows it, so it can't share its port with an IPv4 address.
+    #[serde(default)]
+    pub bind: Vec<SocketAddr>,
+    /// The path of the WebSocket endpoint.
+    #[serde(default = "default_websocket_path")]
+    pub path: String,
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub bandwidth: BandwidthConfig,
+    /// Origins allowed to open a connection, any origin is allowed if empty.
+    ///
+    /// Browsers always send the `Origin` header, so this prevents other sites from connecting
+    /// with the credentials of the user, the clients that don't send it are still accepted.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+impl WebSocketConfig {
+    pub fn port(&self) -> u16 {
+        self.port
+            .unwrap_or_else(|| if self.tls.is_some() { 8084 } else { 8083 })
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerTransport {
+    Tcp,
+    #[serde(rename = "websocket")]
+    WebSocket,
+}
+
+/// A listener whose settings override the service config for its connections.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ListenerConfig {
+    pub transport: ListenerTransport,
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// Defaults to the port of the transport.
+    pub port: Option<u16>,
+    /// Binds these addresses instead of `host` and `port`.
+    #[serde(default)]
+    pub bind: Vec<SocketAddr>,
+    /// The path of the WebSocket endpoint.
+    #[serde(default = "default_websocket_path")]
+    pub path: String,
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub bandwidth: BandwidthConfig,
+    /// Origins allowed to open a WebSocket connection, any origin is allowed if empty.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(flatten)]
+    pub settings: ListenerSettings,
+}
+
+/// A UDP gateway for MQTT-SN clients, each client is served by its own MQTT connection.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MqttSnConfig {
+    #[serde(default = "default_host")]
+    pub host: String,
+    pub port: Option<u16>,
+    /// The topic names of the predefined topic ids.
+    #[serde(default)]
+    pub predefined_topics: HashMap<u16, String>,
+    /// Accepts the QoS -1 messages of clients without a connection, they are published without
+    /// authentication and ACL checks.
+    #[serde(default)]
+    pub qos_minus_one: bool,
+}
+
+impl MqttSnConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(1884)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct NetworkConfig {
     pub tcp: Option<TcpConfig>,
     pub http: Option<HttpConfig>,
+    pub websocket: Option<WebSocketConfig>,
+    pub mqttsn: Option<MqttSnConfig>,
+    /// Additional listeners with their own settings.
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
 }
 
 impl Default for NetworkConfig {
@@ -65,7 +194,9 @@ impl Default for NetworkConfig {
             tcp: Some(TcpConfig {
                 host: default_host(),
                 port: None,
+                bind: Vec::new(),
                 tls: None,
+                bandwidth: BandwidthConfig::default(),
             }),
             http: Some(HttpConfig {
                 host: default_host(),
@@ -73,8 +204,14 @@ impl Default for NetworkConfig {
                 tls: None,
                 websocket: true,
                 api: true,
+                api_token: None,
                 graphql_api: true,
+                bandwidth: BandwidthConfig::default(),
+                allowed_origins: Vec::new(),
             }),
+            websocket: None,
+            mqttsn: None,
+            listeners: Vec::new(),
         }
     }
 }
@@ -82,3 +219,7 @@ impl Default for NetworkConfig {
 fn default_host() -> String {
     "127.0.0.1".to_string()
 }
+
+fn default_websocket_path() -> String {
+    "/mqtt".to_string()
+}
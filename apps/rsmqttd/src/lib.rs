@@ -1,9 +1,17 @@
+mod api;
+pub mod config;
+mod mqttsn;
+pub mod server;
+mod throttle;
+mod ws_transport;
+
 use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::Result;
 use serde_yaml::Value;
 use service::plugin::{Plugin, PluginFactory};
+use service::{Storage, StorageFactory, StorageMemoryFactory};
 
 macro_rules! register_plugin {
     ($feature:literal, $registry:expr, $ty:expr) => {
@@ -15,6 +23,16 @@ macro_rules! register_plugin {
     };
 }
 
+macro_rules! register_storage {
+    ($feature:literal, $registry:expr, $ty:expr) => {
+        #[cfg(feature = $feature)]
+        {
+            let factory = $ty;
+            $registry.insert(factory.name(), Box::new(factory) as Box<dyn StorageFactory>);
+        }
+    };
+}
+
 pub async fn create_plugins(configs: Vec<Value>) -> Result<Vec<(&'static str, Arc<dyn Plugin>)>> {
     let mut registry: HashMap<&'static str, Box<dyn PluginFactory>> = HashMap::new();
     let mut plugins = Vec::new();
@@ -25,6 +43,11 @@ pub async fn create_plugins(configs: Vec<Value>) -> Result<Vec<(&'static str, Ar
         rsmqtt_plugin_basic_auth::BasicAuth
     );
     register_plugin!("plugin-oso-acl", registry, rsmqtt_plugin_oso_acl::OsoAcl);
+    register_plugin!(
+        "plugin-scram-auth",
+        registry,
+        rsmqtt_plugin_scram_auth::ScramAuth
+    );
 
     for config in configs {
         let plugin_type = match config.get("type") {
@@ -40,3 +63,28 @@ pub async fn create_plugins(configs: Vec<Value>) -> Result<Vec<(&'static str, Ar
 
     Ok(plugins)
 }
+
+/// Creates the storage backend of the `storage` config, the memory storage if not specified.
+pub async fn create_storage(config: Option<Value>) -> Result<Arc<dyn Storage>> {
+    let mut registry: HashMap<&'static str, Box<dyn StorageFactory>> = HashMap::new();
+    registry.insert(StorageMemoryFactory.name(), Box::new(StorageMemoryFactory));
+    register_storage!(
+        "storage-sled",
+        registry,
+        rsmqtt_storage_sled::StorageSledFactory
+    );
+
+    let config = match config {
+        Some(config) => config,
+        None => return StorageMemoryFactory.create(Value::Null).await,
+    };
+    let storage_type = match config.get("type") {
+        Some(Value::String(ty)) => ty.as_str(),
+        Some(_) => anyhow::bail!("invalid storage type, expect string"),
+        None => anyhow::bail!("require storage type"),
+    };
+    let factory = registry
+        .get(storage_type)
+        .ok_or_else(|| anyhow::anyhow!("storage not registered: {}", storage_type))?;
+    factory.create(config).await
+}
@@ -1,11 +1,6 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::default_trait_access)]
 
-mod api;
-mod config;
-mod server;
-mod ws_transport;
-
 use std::path::PathBuf;
 use std::time::Duration;
 
@@ -17,8 +12,8 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 
-use config::Config;
-use rsmqttd::create_plugins;
+use rsmqttd::config::Config;
+use rsmqttd::{create_plugins, create_storage, server};
 
 const DEFAULT_CONFIG_FILENAME: &str = ".rsmqttd";
 
@@ -63,7 +58,18 @@ async fn run() -> Result<()> {
     };
 
     let plugins = create_plugins(config.plugins).await?;
-    let state = ServiceState::new(config.service, plugins)?;
+    for listener in &config.network.listeners {
+        for name in listener.settings.plugins.iter().flatten() {
+            anyhow::ensure!(
+                plugins.iter().any(|(plugin_name, _)| plugin_name == name),
+                "unknown plugin of the listener '{}': {}",
+                listener.settings.name.as_deref().unwrap_or(&listener.host),
+                name
+            );
+        }
+    }
+    let storage = create_storage(config.storage).await?;
+    let state = ServiceState::with_storage(config.service, plugins, storage)?;
 
     tokio::spawn({
         let state = state.clone();
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::num::NonZeroU16;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use bytestring::ByteString;
+use service::codec::mqttsn::{self, ReturnCode, TopicId};
+use service::codec::{
+    Codec, Connect, ConnectProperties, ConnectReasonCode, Disconnect, DisconnectProperties,
+    DisconnectReasonCode, Packet, ProtocolLevel, PubAckReasonCode, PubRel, PubRelProperties,
+    PubRelReasonCode, Publish, PublishProperties, Qos,
+};
+use service::{client_loop, ListenerSettings, Message, RemoteAddr, ServiceState};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+use crate::config::MqttSnConfig;
+
+/// The number of datagrams of a client that can be queued before they are dropped.
+const QUEUE_SIZE: usize = 32;
+
+pub async fn run_mqttsn_gateway(state: Arc<ServiceState>, config: MqttSnConfig) -> Result<()> {
+    let port = config.port();
+    let socket = Arc::new(UdpSocket::bind((config.host.as_str(), port)).await?);
+    tracing::info!(
+        host = %config.host,
+        port = port,
+        "mqtt-sn gateway listening",
+    );
+
+    let predefined_topics = Arc::new(config.predefined_topics);
+    let mut clients: HashMap<SocketAddr, (u64, mpsc::Sender<mqttsn::Packet>)> = HashMap::new();
+    let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
+    let mut next_session_id = 0u64;
+    let mut buf = vec![0; 65536];
+
+    loop {
+        tokio::select! {
+            res = socket.recv_from(&mut buf) => {
+                let (size, addr) = res?;
+                let packet = match mqttsn::Packet::decode(Bytes::copy_from_slice(&buf[..size])) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        tracing::debug!(
+                            remote_addr = %addr,
+                            error = %err,
+                            "invalid mqtt-sn packet",
+                        );
+                        continue;
+                    }
+                };
+
+                match packet {
+                    mqttsn::Packet::Connect(connect) => {
+                        if !state.is_ip_allowed(&ListenerSettings::default(), Some(addr.ip())) {
+                            tracing::debug!(
+                                remote_addr = %addr,
+                                "ip address not allowed",
+                            );
+                            continue;
+                        }
+
+                        // the clients are limited before a task is spawned for them, a client
+                        // that connects again keeps its slot
+                        let limit_reached = !clients.contains_key(&addr)
+                            && matches!(
+                                state.config.overload.max_connections,
+                                Some(max_connections) if clients.len() >= max_connections
+                            );
+                        if limit_reached {
+                            let conn_ack = mqttsn::Packet::ConnAck {
+                                return_code: ReturnCode::Congestion,
+                            };
+                            send(&socket, addr, &conn_ack).await;
+                            continue;
+                        }
+
+                        // a new CONNECT replaces the connection of the address
+                        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+                        let session_id = next_session_id;
+                        next_session_id += 1;
+                        clients.insert(addr, (session_id, tx));
+
+                        let state = state.clone();
+                        let socket = socket.clone();
+                        let predefined_topics = predefined_topics.clone();
+                        let closed_tx = closed_tx.clone();
+                        tokio::spawn(async move {
+                            serve_client(state, socket, addr, connect, rx, predefined_topics)
+                                .await;
+                            closed_tx.send((addr, session_id)).ok();
+                        });
+                    }
+                    mqttsn::Packet::Publish(publish)
+                        if publish.qos == mqttsn::Qos::NoSession =>
+                    {
+                        if !config.qos_minus_one {
+                            continue;
+                        }
+                        if !state.is_ip_allowed(&ListenerSettings::default(), Some(addr.ip())) {
+                            tracing::debug!(
+                                remote_addr = %addr,
+                                "ip address not allowed",
+                            );
+                            continue;
+                        }
+                        publish_without_session(&state, &predefined_topics, addr, publish);
+                    }
+                    packet => match clients.get(&addr) {
+                        Some((_, tx)) => {
+                            // like any UDP packet, it is lost if the client can't keep up
+                            tx.try_send(packet).ok();
+                        }
+                        None => {
+                            // tell the client to connect again
+                            let disconnect = mqttsn::Packet::Disconnect { duration: None };
+                            send(&socket, addr, &disconnect).await;
+                        }
+                    },
+                }
+            }
+            Some((addr, session_id)) = closed_rx.recv() => {
+                if matches!(clients.get(&addr), Some((id, _)) if *id == session_id) {
+                    clients.remove(&addr);
+                }
+            }
+        }
+    }
+}
+
+async fn send(socket: &UdpSocket, addr: SocketAddr, packet: &mqttsn::Packet) {
+    let mut data = BytesMut::new();
+    if let Err(err) = packet.encode(&mut data) {
+        tracing::debug!(error = %err, "failed to encode mqtt-sn packet");
+        return;
+    }
+    if let Err(err) = socket.send_to(&data, addr).await {
+        tracing::debug!(
+            remote_addr = %addr,
+            error = %err,
+            "failed to send mqtt-sn packet",
+        );
+    }
+}
+
+/// Returns the topic name of a topic id, normal ids are registered by the client.
+fn resolve_topic(
+    topic_id: TopicId,
+    registered: &HashMap<u16, ByteString>,
+    predefined: &HashMap<u16, String>,
+) -> Option<ByteString> {
+    match topic_id {
+        TopicId::Normal(id) => registered.get(&id).cloned(),
+        TopicId::Predefined(id) => predefined.get(&id).map(|topic| topic.as_str().into()),
+        TopicId::Short(name) => std::str::from_utf8(&name).ok().map(Into::into),
+    }
+}
+
+/// Publishes a QoS -1 message, which only uses predefined or short topic ids.
+fn publish_without_session(
+    state: &ServiceState,
+    predefined_topics: &HashMap<u16, String>,
+    addr: SocketAddr,
+    publish: mqttsn::Publish,
+) {
+    let topic = match publish.topic_id {
+        TopicId::Normal(_) => None,
+        topic_id => resolve_topic(topic_id, &HashMap::new(), predefined_topics),
+    };
+    let res = match topic {
+        Some(topic) => state.publish(
+            Message::new(topic, Qos::AtMostOnce, publish.data).with_retain(publish.retain),
+        ),
+        None => Err(anyhow::anyhow!("unknown topic id")),
+    };
+    if let Err(err) = res {
+        tracing::debug!(
+            remote_addr = %addr,
+            error = %err,
+            "failed to publish qos -1 message",
+        );
+    }
+}
+
+fn connack_return_code(reason_code: ConnectReasonCode) -> ReturnCode {
+    match reason_code {
+        ConnectReasonCode::Success => ReturnCode::Accepted,
+        ConnectReasonCode::ServerBusy | ConnectReasonCode::ServerUnavailable => {
+            ReturnCode::Congestion
+        }
+        _ => ReturnCode::NotSupported,
+    }
+}
+
+fn puback_return_code(reason_code: PubAckReasonCode) -> ReturnCode {
+    match reason_code {
+        PubAckReasonCode::Success | PubAckReasonCode::NoMatchingSubscribers => ReturnCode::Accepted,
+        PubAckReasonCode::QuotaExceeded => ReturnCode::Congestion,
+        PubAckReasonCode::TopicNameInvalid => ReturnCode::InvalidTopicId,
+        _ => ReturnCode::NotSupported,
+    }
+}
+
+/// Serves a MQTT-SN client with a MQTT connection to the service, so that its session, the
+/// authentication and the ACL are handled like for any other client.
+async fn serve_client(
+    state: Arc<ServiceState>,
+    socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+    connect: mqttsn::Connect,
+    rx: mpsc::Receiver<mqttsn::Packet>,
+    predefined_topics: Arc<HashMap<u16, String>>,
+) {
+    if connect.will {
+        send(
+            &socket,
+            addr,
+            &mqttsn::Packet::ConnAck {
+                return_code: ReturnCode::NotSupported,
+            },
+        )
+        .await;
+        return;
+    }
+
+    tracing::debug!(
+        protocol = "mqttsn",
+        remote_addr = %addr,
+        "incoming connection",
+    );
+
+    let (gateway_side, service_side) = tokio::io::duplex(65536);
+    let (reader, writer) = tokio::io::split(service_side);
+    let service = client_loop(
+        state,
+        reader,
+        writer,
+        RemoteAddr {
+            protocol: "mqttsn".into(),
+            addr: Some(addr.to_string().into()),
+            client_cert: None,
+            listener: None,
+        },
+    );
+    let gateway = async move {
+        let (reader, writer) = tokio::io::split(gateway_side);
+        let mut codec = Codec::new(reader, writer);
+        if let Err(err) =
+            translate(&mut codec, &socket, addr, connect, rx, &predefined_topics).await
+        {
+            tracing::debug!(
+                remote_addr = %addr,
+                error = %err,
+                "mqtt-sn gateway",
+            );
+        }
+    };
+    tokio::join!(service, gateway);
+
+    tracing::debug!(
+        protocol = "mqttsn",
+        remote_addr = %addr,
+        "connection disconnected",
+    );
+}
+
+/// Translates the packets of the client and of the service until one of them disconnects.
+async fn translate<R, W>(
+    codec: &mut Codec<R, W>,
+    socket: &UdpSocket,
+    addr: SocketAddr,
+    connect: mqttsn::Connect,
+    mut rx: mpsc::Receiver<mqttsn::Packet>,
+    predefined_topics: &HashMap<u16, String>,
+) -> Result<()>
+where
+    R: tokio::io::AsyncRead + Send + Unpin,
+    W: tokio::io::AsyncWrite + Send + Unpin,
+{
+    codec
+        .encode(&Packet::Connect(Connect {
+            level: ProtocolLevel::V4,
+            keep_alive: connect.duration,
+            clean_start: connect.clean_session,
+            client_id: connect.client_id,
+            last_will: None,
+            login: None,
+            properties: ConnectProperties::default(),
+        }))
+        .await?;
+
+    let mut registered: HashMap<u16, ByteString> = HashMap::new();
+    let mut next_topic_id = 1u16;
+    // the topic ids of the inflight messages, PUBACK repeats them
+    let mut inflight: HashMap<u16, u16> = HashMap::new();
+    let mut connected = false;
+
+    loop {
+        tokio::select! {
+            packet = rx.recv() => match packet {
+                Some(mqttsn::Packet::Register(register)) => {
+                    let topic_id = match registered
+                        .iter()
+                        .find(|(_, topic)| **topic == register.topic_name)
+                    {
+                        Some((topic_id, _)) => Some(*topic_id),
+                        None if next_topic_id < u16::MAX => {
+                            let topic_id = next_topic_id;
+                            next_topic_id += 1;
+                            registered.insert(topic_id, register.topic_name);
+                            Some(topic_id)
+                        }
+                        None => None,
+                    };
+                    let reg_ack = mqttsn::RegAck {
+                        topic_id: topic_id.unwrap_or_default(),
+                        msg_id: register.msg_id,
+                        return_code: match topic_id {
+                            Some(_) => ReturnCode::Accepted,
+                            None => ReturnCode::Congestion,
+                        },
+                    };
+                    send(socket, addr, &mqttsn::Packet::RegAck(reg_ack)).await;
+                }
+                Some(mqttsn::Packet::Publish(publish)) => {
+                    let topic = resolve_topic(publish.topic_id, &registered, predefined_topics);
+                    let topic = match topic {
+                        Some(topic) => topic,
+                        None => {
+                            let pub_ack = mqttsn::PubAck {
+                                topic_id: publish.topic_id.value(),
+                                msg_id: publish.msg_id,
+                                return_code: ReturnCode::InvalidTopicId,
+                            };
+                            send(socket, addr, &mqttsn::Packet::PubAck(pub_ack)).await;
+                            continue;
+                        }
+                    };
+                    let qos = match publish.qos {
+                        mqttsn::Qos::AtMostOnce | mqttsn::Qos::NoSession => Qos::AtMostOnce,
+                        mqttsn::Qos::AtLeastOnce => Qos::AtLeastOnce,
+                        mqttsn::Qos::ExactlyOnce => Qos::ExactlyOnce,
+                    };
+                    let packet_id = match qos {
+                        Qos::AtMostOnce => None,
+                        _ => match NonZeroU16::new(publish.msg_id) {
+                            Some(packet_id) => Some(packet_id),
+                            None => continue,
+                        },
+                    };
+                    if packet_id.is_some() {
+                        inflight.insert(publish.msg_id, publish.topic_id.value());
+                    }
+                    codec
+                        .encode(&Packet::Publish(Publish {
+                            dup: publish.dup,
+                            qos,
+                            retain: publish.retain,
+                            topic,
+                            packet_id,
+                            properties: PublishProperties::default(),
+                            payload: publish.data,
+                        }))
+                        .await?;
+                }
+                Some(mqttsn::Packet::PubRel { msg_id }) => {
+                    if let Some(packet_id) = NonZeroU16::new(msg_id) {
+                        codec
+                            .encode(&Packet::PubRel(PubRel {
+                                packet_id,
+                                reason_code: PubRelReasonCode::Success,
+                                properties: PubRelProperties::default(),
+                            }))
+                            .await?;
+                    }
+                }
+                Some(mqttsn::Packet::PingReq { .. }) => {
+                    codec.encode(&Packet::PingReq).await?;
+                }
+                Some(mqttsn::Packet::Disconnect { .. }) => {
+                    codec
+                        .encode(&Packet::Disconnect(Disconnect {
+                            reason_code: DisconnectReasonCode::NormalDisconnection,
+                            properties: DisconnectProperties::default(),
+                        }))
+                        .await?;
+                    break;
+                }
+                Some(_) => {}
+                None => {
+                    // replaced by a new connection of the address
+                    connected = false;
+                    break;
+                }
+            },
+            packet = codec.decode() => {
+                let packet = match packet? {
+                    Some((packet, _)) => packet,
+                    None => break,
+                };
+                let packet = match packet {
+                    Packet::ConnAck(conn_ack) => {
+                        connected = conn_ack.reason_code == ConnectReasonCode::Success;
+                        mqttsn::Packet::ConnAck {
+                            return_code: connack_return_code(conn_ack.reason_code),
+                        }
+                    }
+                    Packet::PubAck(pub_ack) => mqttsn::Packet::PubAck(mqttsn::PubAck {
+                        topic_id: inflight.remove(&pub_ack.packet_id.get()).unwrap_or_default(),
+                        msg_id: pub_ack.packet_id.get(),
+                        return_code: puback_return_code(pub_ack.reason_code),
+                    }),
+                    Packet::PubRec(pub_rec) => mqttsn::Packet::PubRec {
+                        msg_id: pub_rec.packet_id.get(),
+                    },
+                    Packet::PubComp(pub_comp) => {
+                        inflight.remove(&pub_comp.packet_id.get());
+                        mqttsn::Packet::PubComp {
+                            msg_id: pub_comp.packet_id.get(),
+                        }
+                    }
+                    Packet::PingResp => mqttsn::Packet::PingResp,
+                    _ => continue,
+                };
+                send(socket, addr, &packet).await;
+            }
+        }
+    }
+
+    if connected {
+        send(socket, addr, &mqttsn::Packet::Disconnect { duration: None }).await;
+    }
+    Ok(())
+}
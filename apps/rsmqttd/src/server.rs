@@ -1,112 +1,297 @@
-use std::io::{BufReader, Cursor};
-use std::net::IpAddr;
+use std::io::{BufReader, Cursor, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
-use service::{client_loop, RemoteAddr, ServiceState};
-use tokio::net::TcpListener;
-use tokio_rustls::rustls::ServerConfig;
+use futures_util::future::{join_all, try_join_all};
+use service::{client_loop_with_settings, ClientCert, ListenerSettings, RemoteAddr, ServiceState};
+use socket2::{Domain, Socket, Type};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth,
+    RootCertStore, ServerConfig, ServerSession, Session,
+};
+use tokio_rustls::server::TlsStream;
 use tokio_rustls::{rustls, TlsAcceptor};
+use warp::filters::BoxedFilter;
+use warp::path::FullPath;
+use warp::reply::Response;
 use warp::{Filter, Reply};
+use x509_parser::extensions::GeneralName;
 
-use crate::config::{HttpConfig, NetworkConfig, TcpConfig};
+use crate::config::{
+    HttpConfig, ListenerTransport, NetworkConfig, TcpConfig, TlsConfig, WebSocketConfig,
+};
+use crate::throttle::Bandwidth;
 
-async fn run_tcp_server(state: Arc<ServiceState>, tcp_config: TcpConfig) -> Result<()> {
-    let port = tcp_config.port();
+fn load_tls_config(tls_config: &TlsConfig) -> Result<Arc<ServerConfig>> {
+    let cert_data = std::fs::read(&tls_config.cert)
+        .with_context(|| format!("failed to read certificates file: {}", tls_config.cert))?;
+    let key_data = std::fs::read(&tls_config.key)
+        .with_context(|| format!("failed to read key file: {}", tls_config.key))?;
 
-    tracing::info!(
-        host = %tcp_config.host,
-        port = port,
-        "tcp listening",
+    let cert = rustls::internal::pemfile::certs(&mut BufReader::new(Cursor::new(&cert_data)))
+        .map_err(|_| anyhow::anyhow!("failed to load tls certificates"))?;
+    let mut keys =
+        rustls::internal::pemfile::pkcs8_private_keys(&mut BufReader::new(Cursor::new(&key_data)))
+            .map_err(|_| anyhow::anyhow!("failed to load tls key"))?;
+    if keys.is_empty() {
+        keys = rustls::internal::pemfile::rsa_private_keys(&mut BufReader::new(Cursor::new(
+            &key_data,
+        )))
+        .map_err(|_| anyhow::anyhow!("failed to load tls key"))?;
+    }
+    let key = keys
+        .pop()
+        .ok_or_else(|| anyhow::anyhow!("no tls key found in: {}", tls_config.key))?;
+
+    let client_cert_verifier = match &tls_config.client_ca {
+        Some(client_ca) => {
+            let ca_data = std::fs::read(client_ca)
+                .with_context(|| format!("failed to read client CA file: {}", client_ca))?;
+            let mut roots = RootCertStore::empty();
+            match roots.add_pem_file(&mut BufReader::new(Cursor::new(&ca_data))) {
+                Ok((valid, _)) if valid > 0 => {}
+                _ => anyhow::bail!("no valid CA certificate found in: {}", client_ca),
+            }
+            if tls_config.client_cert_optional {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+            } else {
+                AllowAnyAuthenticatedClient::new(roots)
+            }
+        }
+        None => NoClientAuth::new(),
+    };
+
+    let mut config = ServerConfig::new(client_cert_verifier);
+    config
+        .set_single_cert(cert, key)
+        .context("failed to set tls certificate")?;
+    config.set_protocols(
+        &tls_config
+            .alpn
+            .iter()
+            .map(|protocol| protocol.as_bytes().to_vec())
+            .collect::<Vec<_>>(),
     );
+    Ok(Arc::new(config))
+}
 
-    if let Some(tls_config) = &tcp_config.tls {
-        let cert_data = std::fs::read(&tls_config.cert)
-            .with_context(|| format!("failed to read certificates file: {}", tls_config.cert))?;
-        let key_data = std::fs::read(&tls_config.key)
-            .with_context(|| format!("failed to read key file: {}", tls_config.cert))?;
-
-        let cert = rustls::internal::pemfile::certs(&mut BufReader::new(Cursor::new(cert_data)))
-            .map_err(|_| anyhow::anyhow!("failed to load tls certificates"))?;
-        let mut keys =
-            rustls::internal::pemfile::rsa_private_keys(&mut BufReader::new(Cursor::new(key_data)))
-                .map_err(|_| anyhow::anyhow!("failed to load tls key"))?;
-        let mut config = ServerConfig::new(rustls::NoClientAuth::new());
-        config
-            .set_single_cert(cert, keys.pop().unwrap())
-            .context("failed to set tls certificate")?;
-        let config = Arc::new(config);
-
-        let listener = TcpListener::bind((tcp_config.host.as_str(), port)).await?;
-
-        loop {
-            let (stream, addr) = listener.accept().await?;
-            let acceptor = TlsAcceptor::from(config.clone());
-            if let Ok(stream) = acceptor.accept(stream).await {
-                let state = state.clone();
-                tokio::spawn(async move {
-                    tracing::debug!(
-                        protocol = "tcp",
-                        remote_addr = %addr,
-                        "incoming connection",
-                    );
+/// Returns the identity of the verified certificate of the client.
+fn client_cert(session: &ServerSession) -> Option<ClientCert> {
+    let certs = session.get_peer_certificates()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(&certs.first()?.0).ok()?;
 
-                    let (reader, writer) = tokio::io::split(stream);
-                    client_loop(
-                        state,
-                        reader,
-                        writer,
-                        RemoteAddr {
-                            protocol: "tcp".into(),
-                            addr: Some(addr.to_string().into()),
-                        },
-                    )
-                    .await;
+    let common_name = cert
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|common_name| common_name.as_str().ok())
+        .map(ToString::to_string);
+    let subject_alt_names = match cert.subject_alternative_name() {
+        Ok(Some(ext)) => ext
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name)
+                | GeneralName::RFC822Name(name)
+                | GeneralName::URI(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
 
-                    tracing::debug!(
-                        protocol = "tcp",
-                        remote_addr = %addr,
-                        "connection disconnected",
-                    );
-                });
-            }
-        }
-    } else {
-        let listener = TcpListener::bind((tcp_config.host.as_str(), port)).await?;
+    Some(ClientCert {
+        common_name,
+        subject_alt_names,
+    })
+}
 
-        loop {
-            let (stream, addr) = listener.accept().await?;
-            let state = state.clone();
+async fn serve_connection<S>(
+    state: Arc<ServiceState>,
+    stream: S,
+    protocol: &'static str,
+    addr: SocketAddr,
+    client_cert: Option<ClientCert>,
+    settings: Arc<ListenerSettings>,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    tracing::debug!(
+        protocol = protocol,
+        remote_addr = %addr,
+        "incoming connection",
+    );
 
-            tokio::spawn(async move {
-                tracing::debug!(
-                    protocol = "tcp",
-                    remote_addr = %addr,
-                    "incoming connection",
-                );
+    let (reader, writer) = tokio::io::split(stream);
+    client_loop_with_settings(
+        state,
+        reader,
+        writer,
+        RemoteAddr {
+            protocol: protocol.into(),
+            addr: Some(addr.to_string().into()),
+            client_cert,
+            listener: settings.name.clone(),
+        },
+        settings,
+    )
+    .await;
 
-                let (reader, writer) = tokio::io::split(stream);
-                client_loop(
-                    state,
-                    reader,
-                    writer,
-                    RemoteAddr {
-                        protocol: "tcp".into(),
-                        addr: Some(addr.to_string().into()),
-                    },
-                )
-                .await;
-
-                tracing::debug!(
-                    protocol = "tcp",
-                    remote_addr = %addr,
-                    "connection disconnected",
-                );
-            });
+    tracing::debug!(
+        protocol = protocol,
+        remote_addr = %addr,
+        "connection disconnected",
+    );
+}
+
+/// Binds a TCP listener, an IPv6 address only accepts IPv6 connections so the same port can be
+/// bound on an IPv4 address too.
+fn bind_tcp(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("failed to bind: {}", addr))?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+async fn run_tcp_server(
+    state: Arc<ServiceState>,
+    tcp_config: TcpConfig,
+    settings: Arc<ListenerSettings>,
+) -> Result<()> {
+    let port = tcp_config.port();
+    let bandwidth = Bandwidth::new(&tcp_config.bandwidth);
+    let acceptor = match &tcp_config.tls {
+        Some(tls_config) => Some(TlsAcceptor::from(load_tls_config(tls_config)?)),
+        None => None,
+    };
+
+    let listeners = if tcp_config.bind.is_empty() {
+        tracing::info!(
+            host = %tcp_config.host,
+            port = port,
+            tls = acceptor.is_some(),
+            "tcp listening",
+        );
+        vec![TcpListener::bind((tcp_config.host.as_str(), port)).await?]
+    } else {
+        let mut listeners = Vec::new();
+        for addr in &tcp_config.bind {
+            tracing::info!(
+                addr = %addr,
+                tls = acceptor.is_some(),
+                "tcp listening",
+            );
+            listeners.push(bind_tcp(*addr)?);
+        }
+        listeners
+    };
+
+    try_join_all(listeners.into_iter().map(|listener| {
+        accept_tcp(
+            state.clone(),
+            listener,
+            bandwidth.clone(),
+            acceptor.clone(),
+            settings.clone(),
+        )
+    }))
+    .await?;
+    Ok(())
+}
+
+async fn accept_tcp(
+    state: Arc<ServiceState>,
+    listener: TcpListener,
+    bandwidth: Bandwidth,
+    acceptor: Option<TlsAcceptor>,
+    settings: Arc<ListenerSettings>,
+) -> Result<()> {
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        if !state.is_ip_allowed(&settings, Some(addr.ip())) {
+            tracing::debug!(
+                remote_addr = %addr,
+                "ip address not allowed",
+            );
+            continue;
         }
+
+        let state = state.clone();
+        let bandwidth = bandwidth.clone();
+        let acceptor = acceptor.clone();
+        let settings = settings.clone();
+
+        // the handshake runs in the task of the connection, so a slow client does not block the
+        // listener
+        tokio::spawn(async move {
+            match acceptor {
+                Some(acceptor) => match tls_handshake(&state, acceptor, stream).await {
+                    Ok(stream) => {
+                        let client_cert = client_cert(stream.get_ref().1);
+                        serve_connection(
+                            state,
+                            bandwidth.throttle(stream),
+                            "tls",
+                            addr,
+                            client_cert,
+                            settings,
+                        )
+                        .await
+                    }
+                    Err(err) => tracing::debug!(
+                        remote_addr = %addr,
+                        error = %err,
+                        "tls handshake failed",
+                    ),
+                },
+                None => {
+                    serve_connection(
+                        state,
+                        bandwidth.throttle(stream),
+                        "tcp",
+                        addr,
+                        None,
+                        settings,
+                    )
+                    .await
+                }
+            }
+        });
     }
 }
 
+/// Accepts a TLS connection, the handshake is limited by the connect timeout because that only
+/// starts once the handshake is completed.
+async fn tls_handshake(
+    state: &ServiceState,
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+) -> std::io::Result<TlsStream<TcpStream>> {
+    let connect_timeout = state.config.connect_timeout;
+    if connect_timeout == 0 {
+        return acceptor.accept(stream).await;
+    }
+    tokio::time::timeout(
+        Duration::from_secs(connect_timeout),
+        acceptor.accept(stream),
+    )
+    .await
+    .map_err(|_| std::io::Error::new(ErrorKind::TimedOut, "tls handshake timed out"))?
+}
+
 async fn run_http_server(state: Arc<ServiceState>, http_config: HttpConfig) -> Result<()> {
     let port = http_config.port();
 
@@ -121,33 +306,116 @@ async fn run_http_server(state: Arc<ServiceState>, http_config: HttpConfig) -> R
     if http_config.websocket {
         tracing::info!("websocket transport enabled");
         routes = routes
-            .or(warp::path!("ws").and(crate::ws_transport::handler(state.clone())))
+            .or(warp::path!("ws").and(crate::ws_transport::handler(
+                state.clone(),
+                Bandwidth::new(&http_config.bandwidth),
+                if http_config.tls.is_some() {
+                    "wss"
+                } else {
+                    "ws"
+                },
+                Arc::new(http_config.allowed_origins.clone()),
+                Arc::default(),
+            )))
             .unify()
             .boxed();
     }
 
     if http_config.api {
         tracing::info!("api enabled");
+        if http_config.api_token.is_none() {
+            tracing::warn!("api_token is not set, the api only accepts the GET requests");
+        }
 
         let api = warp::path!("api" / "v1" / ..)
-            .and(crate::api::metrics(state.clone()))
+            .and(crate::api::routes(
+                state.clone(),
+                http_config.api_token.clone(),
+            ))
             .boxed();
         routes = routes.or(api).unify().boxed();
     }
 
-    if let Some(tls_config) = &http_config.tls {
-        warp::serve(routes)
-            .tls()
-            .cert_path(&tls_config.cert)
-            .key_path(&tls_config.key)
-            .bind((http_config.host.parse::<IpAddr>()?, port))
-            .await;
+    let addr = SocketAddr::new(http_config.host.parse::<IpAddr>()?, port);
+    serve_http(routes, vec![addr], http_config.tls.as_ref()).await
+}
+
+async fn run_websocket_server(
+    state: Arc<ServiceState>,
+    ws_config: WebSocketConfig,
+    settings: Arc<ListenerSettings>,
+) -> Result<()> {
+    let addrs = if ws_config.bind.is_empty() {
+        vec![SocketAddr::new(
+            ws_config.host.parse::<IpAddr>()?,
+            ws_config.port(),
+        )]
     } else {
-        warp::serve(routes)
-            .run((http_config.host.parse::<IpAddr>()?, port))
-            .await;
+        ws_config.bind.clone()
+    };
+
+    for addr in &addrs {
+        tracing::info!(
+            addr = %addr,
+            path = %ws_config.path,
+            tls = ws_config.tls.is_some(),
+            "websocket listening",
+        );
     }
 
+    let path = ws_config.path.clone();
+    let routes = warp::path::full()
+        .and_then(move |full_path: FullPath| {
+            let matched = full_path.as_str() == path;
+            async move {
+                if matched {
+                    Ok(())
+                } else {
+                    Err(warp::reject::not_found())
+                }
+            }
+        })
+        .untuple_one()
+        .and(crate::ws_transport::handler(
+            state,
+            Bandwidth::new(&ws_config.bandwidth),
+            if ws_config.tls.is_some() { "wss" } else { "ws" },
+            Arc::new(ws_config.allowed_origins.clone()),
+            settings,
+        ))
+        .boxed();
+
+    serve_http(routes, addrs, ws_config.tls.as_ref()).await
+}
+
+async fn serve_http(
+    routes: BoxedFilter<(Response,)>,
+    addrs: Vec<SocketAddr>,
+    tls_config: Option<&TlsConfig>,
+) -> Result<()> {
+    join_all(addrs.into_iter().map(|addr| {
+        let server = warp::serve(routes.clone());
+        async move {
+            match tls_config {
+                Some(tls_config) => {
+                    let mut server = server
+                        .tls()
+                        .cert_path(&tls_config.cert)
+                        .key_path(&tls_config.key);
+                    if let Some(client_ca) = &tls_config.client_ca {
+                        server = if tls_config.client_cert_optional {
+                            server.client_auth_optional_path(client_ca)
+                        } else {
+                            server.client_auth_required_path(client_ca)
+                        };
+                    }
+                    server.bind(addr).await
+                }
+                None => server.run(addr).await,
+            }
+        }
+    }))
+    .await;
     Ok(())
 }
 
@@ -157,7 +425,7 @@ pub async fn run(state: Arc<ServiceState>, network_config: NetworkConfig) -> Res
     if let Some(tcp_config) = network_config.tcp {
         let state = state.clone();
         servers.push(tokio::spawn(async move {
-            if let Err(err) = run_tcp_server(state, tcp_config).await {
+            if let Err(err) = run_tcp_server(state, tcp_config, Arc::default()).await {
                 tracing::error!(
                     error = %err,
                     "tcp server",
@@ -178,6 +446,75 @@ pub async fn run(state: Arc<ServiceState>, network_config: NetworkConfig) -> Res
         }));
     }
 
+    if let Some(ws_config) = network_config.websocket {
+        let state = state.clone();
+        servers.push(tokio::spawn(async move {
+            if let Err(err) = run_websocket_server(state, ws_config, Arc::default()).await {
+                tracing::error!(
+                    error = %err,
+                    "websocket server",
+                );
+            }
+        }));
+    }
+
+    if let Some(mqttsn_config) = network_config.mqttsn {
+        let state = state.clone();
+        servers.push(tokio::spawn(async move {
+            if let Err(err) = crate::mqttsn::run_mqttsn_gateway(state, mqttsn_config).await {
+                tracing::error!(
+                    error = %err,
+                    "mqtt-sn gateway",
+                );
+            }
+        }));
+    }
+
+    for listener in network_config.listeners {
+        let state = state.clone();
+        servers.push(tokio::spawn(async move {
+            let settings = Arc::new(listener.settings);
+            let res = match listener.transport {
+                ListenerTransport::Tcp => {
+                    run_tcp_server(
+                        state,
+                        TcpConfig {
+                            host: listener.host,
+                            port: listener.port,
+                            bind: listener.bind,
+                            tls: listener.tls,
+                            bandwidth: listener.bandwidth,
+                        },
+                        settings,
+                    )
+                    .await
+                }
+                ListenerTransport::WebSocket => {
+                    run_websocket_server(
+                        state,
+                        WebSocketConfig {
+                            host: listener.host,
+                            port: listener.port,
+                            bind: listener.bind,
+                            path: listener.path,
+                            tls: listener.tls,
+                            bandwidth: listener.bandwidth,
+                            allowed_origins: listener.allowed_origins,
+                        },
+                        settings,
+                    )
+                    .await
+                }
+            };
+            if let Err(err) = res {
+                tracing::error!(
+                    error = %err,
+                    "listener",
+                );
+            }
+        }));
+    }
+
     for handle in servers {
         handle.await.ok();
     }
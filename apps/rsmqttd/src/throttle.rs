@@ -0,0 +1,171 @@
+use std::future::Future;
+use std::io::Result;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::ready;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Semaphore;
+
+use crate::config::BandwidthConfig;
+
+const REFILL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The largest number of bytes a connection takes from a limiter at once.
+///
+/// The semaphore serves the waiting connections in turn, so the small chunks share the bandwidth
+/// fairly between them.
+const CHUNK_SIZE: usize = 1024;
+
+struct RateLimiter {
+    semaphore: Semaphore,
+    bytes_per_second: usize,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_second: usize) -> Arc<Self> {
+        let bytes_per_second = bytes_per_second.max(1);
+        let limiter = Arc::new(Self {
+            semaphore: Semaphore::new(bytes_per_second),
+            bytes_per_second,
+        });
+
+        tokio::spawn({
+            let limiter = Arc::downgrade(&limiter);
+            async move {
+                let mut interval = tokio::time::interval(REFILL_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    let limiter = match limiter.upgrade() {
+                        Some(limiter) => limiter,
+                        None => break,
+                    };
+
+                    // at most one second of traffic can be saved up
+                    let available = limiter.semaphore.available_permits();
+                    let refill = (limiter.bytes_per_second / 10)
+                        .max(1)
+                        .min(limiter.bytes_per_second.saturating_sub(available));
+                    limiter.semaphore.add_permits(refill);
+                }
+            }
+        });
+
+        limiter
+    }
+
+    async fn acquire(self: Arc<Self>, n: usize) -> usize {
+        let n = n.min(CHUNK_SIZE).min(self.bytes_per_second).max(1);
+        if let Ok(permit) = self.semaphore.acquire_many(n as u32).await {
+            permit.forget();
+        }
+        n
+    }
+}
+
+type AcquireFuture = Pin<Box<dyn Future<Output = usize> + Send + Sync>>;
+
+struct Direction {
+    limiter: Arc<RateLimiter>,
+    allowance: usize,
+    acquire: Option<AcquireFuture>,
+}
+
+impl Direction {
+    fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            limiter,
+            allowance: 0,
+            acquire: None,
+        }
+    }
+
+    fn poll_allowance(&mut self, cx: &mut Context<'_>, wanted: usize) -> Poll<usize> {
+        if self.allowance == 0 {
+            if self.acquire.is_none() {
+                self.acquire = Some(Box::pin(self.limiter.clone().acquire(wanted)));
+            }
+            self.allowance = ready!(self.acquire.as_mut().unwrap().as_mut().poll(cx));
+            self.acquire = None;
+        }
+        Poll::Ready(self.allowance)
+    }
+}
+
+/// The byte-rate limits of a listener, shared by all its connections.
+#[derive(Clone, Default)]
+pub struct Bandwidth {
+    ingress: Option<Arc<RateLimiter>>,
+    egress: Option<Arc<RateLimiter>>,
+}
+
+impl Bandwidth {
+    pub fn new(config: &BandwidthConfig) -> Self {
+        Self {
+            ingress: config.ingress.map(RateLimiter::new),
+            egress: config.egress.map(RateLimiter::new),
+        }
+    }
+
+    /// Throttles the bytes read from and written to the stream.
+    pub fn throttle<S>(&self, stream: S) -> Throttled<S> {
+        Throttled {
+            inner: stream,
+            ingress: self.ingress.clone().map(Direction::new),
+            egress: self.egress.clone().map(Direction::new),
+        }
+    }
+}
+
+pub struct Throttled<S> {
+    inner: S,
+    ingress: Option<Direction>,
+    egress: Option<Direction>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let ingress = match &mut this.ingress {
+            Some(ingress) if buf.remaining() > 0 => ingress,
+            _ => return Pin::new(&mut this.inner).poll_read(cx, buf),
+        };
+
+        let allowance = ready!(ingress.poll_allowance(cx, buf.remaining()));
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(allowance.min(buf.remaining())));
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+        let n = limited.filled().len();
+        buf.advance(n);
+        ingress.allowance -= n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        let egress = match &mut this.egress {
+            Some(egress) if !buf.is_empty() => egress,
+            _ => return Pin::new(&mut this.inner).poll_write(cx, buf),
+        };
+
+        let allowance = ready!(egress.poll_allowance(cx, buf.len()));
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..allowance.min(buf.len())]))?;
+        egress.allowance -= n;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
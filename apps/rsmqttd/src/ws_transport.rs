@@ -6,12 +6,15 @@ use std::task::Poll;
 
 use bytes::Bytes;
 use futures_util::{Sink, SinkExt, StreamExt, TryStreamExt};
-use service::{client_loop, RemoteAddr, ServiceState};
+use service::{client_loop_with_settings, ListenerSettings, RemoteAddr, ServiceState};
 use tokio::io::AsyncWrite;
+use warp::http::StatusCode;
 use warp::reply::Response;
 use warp::ws::{Message as WsMessage, Ws};
 use warp::{Filter, Rejection, Reply};
 
+use crate::throttle::Bandwidth;
+
 struct SinkWriter<T>(T);
 
 impl<T> AsyncWrite for SinkWriter<T>
@@ -59,55 +62,108 @@ where
     }
 }
 
+/// Returns `true` if the client offers the `mqtt` subprotocol.
+fn offers_mqtt(protocols: Option<&str>) -> bool {
+    protocols
+        .into_iter()
+        .flat_map(|protocols| protocols.split(','))
+        .any(|protocol| protocol.trim() == "mqtt")
+}
+
+/// Returns `true` if the origin is allowed, the requests without an origin are not sent by
+/// browsers, so they are always allowed.
+fn is_origin_allowed(allowed_origins: &[String], origin: Option<&str>) -> bool {
+    match origin {
+        Some(origin) if !allowed_origins.is_empty() => allowed_origins
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin)),
+        _ => true,
+    }
+}
+
+/// `protocol` is `ws` or `wss`, it is reported in the remote address of the connections.
 pub fn handler(
     state: Arc<ServiceState>,
+    bandwidth: Bandwidth,
+    protocol: &'static str,
+    allowed_origins: Arc<Vec<String>>,
+    settings: Arc<ListenerSettings>,
 ) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
     warp::any()
         .map(move || state.clone())
         .and(warp::get())
         .and(warp::filters::addr::remote())
+        .and(warp::header::optional::<String>("sec-websocket-protocol"))
+        .and(warp::header::optional::<String>("origin"))
         .and(warp::ws())
-        .map(move |state, addr: Option<SocketAddr>, ws: Ws| {
-            let reply = ws.on_upgrade(move |websocket| async move {
-                let addr = addr
-                    .map(|addr| addr.to_string())
-                    .unwrap_or_else(|| "unknown".to_string());
-
-                tracing::debug!(
-                    protocol = "websocket",
-                    remote_addr = %addr,
-                    "incoming connection",
-                );
-
-                let (sink, stream) = websocket.split();
-
-                let reader = tokio_util::io::StreamReader::new(
-                    stream
-                        .try_filter_map(|msg| async move {
-                            Ok(msg.is_binary().then(move || Bytes::from(msg.into_bytes())))
-                        })
-                        .map_err(|err| std::io::Error::new(ErrorKind::Other, err.to_string())),
-                );
-                tokio::pin!(reader);
-
-                client_loop(
-                    state,
-                    reader,
-                    SinkWriter(sink),
-                    RemoteAddr {
-                        protocol: "ws".into(),
-                        addr: Some(addr.clone().into()),
-                    },
-                )
-                .await;
-
-                tracing::debug!(
-                    protocol = "websocket",
-                    remote_addr = %addr,
-                    "connection disconnected",
-                );
-            });
-
-            warp::reply::with_header(reply, "Sec-WebSocket-Protocol", "mqtt").into_response()
-        })
+        .map(
+            move |state,
+                  addr: Option<SocketAddr>,
+                  protocols: Option<String>,
+                  origin: Option<String>,
+                  ws: Ws| {
+                if !offers_mqtt(protocols.as_deref()) {
+                    return warp::reply::with_status(
+                        "the mqtt subprotocol is required",
+                        StatusCode::BAD_REQUEST,
+                    )
+                    .into_response();
+                }
+                if !is_origin_allowed(&allowed_origins, origin.as_deref()) {
+                    tracing::debug!(
+                        origin = origin.as_deref().unwrap_or_default(),
+                        "websocket origin not allowed",
+                    );
+                    return warp::reply::with_status("origin not allowed", StatusCode::FORBIDDEN)
+                        .into_response();
+                }
+
+                let bandwidth = bandwidth.clone();
+                let settings = settings.clone();
+                let reply = ws.on_upgrade(move |websocket| async move {
+                    let addr = addr
+                        .map(|addr| addr.to_string())
+                        .unwrap_or_else(|| "unknown".to_string());
+
+                    tracing::debug!(
+                        protocol = "websocket",
+                        remote_addr = %addr,
+                        "incoming connection",
+                    );
+
+                    let (sink, stream) = websocket.split();
+
+                    let reader = tokio_util::io::StreamReader::new(
+                        stream
+                            .try_filter_map(|msg| async move {
+                                Ok(msg.is_binary().then(move || Bytes::from(msg.into_bytes())))
+                            })
+                            .map_err(|err| std::io::Error::new(ErrorKind::Other, err.to_string())),
+                    );
+                    tokio::pin!(reader);
+
+                    client_loop_with_settings(
+                        state,
+                        bandwidth.throttle(reader),
+                        bandwidth.throttle(SinkWriter(sink)),
+                        RemoteAddr {
+                            protocol: protocol.into(),
+                            addr: Some(addr.clone().into()),
+                            client_cert: None,
+                            listener: settings.name.clone(),
+                        },
+                        settings,
+                    )
+                    .await;
+
+                    tracing::debug!(
+                        protocol = "websocket",
+                        remote_addr = %addr,
+                        "connection disconnected",
+                    );
+                });
+
+                warp::reply::with_header(reply, "Sec-WebSocket-Protocol", "mqtt").into_response()
+            },
+        )
 }
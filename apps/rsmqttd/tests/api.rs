@@ -0,0 +1,78 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use rsmqttd::config::{HttpConfig, NetworkConfig};
+use rsmqttd::server;
+use service::{ServiceConfig, ServiceState};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Sends a request to the admin API, returns the status code of the response.
+async fn request(addr: SocketAddr, method: &str, authorization: Option<&str>) -> u16 {
+    let body = r#"{"type": "client_id", "value": "a"}"#;
+    let mut req = format!(
+        "{} /api/v1/bans HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\
+         content-type: application/json\r\ncontent-length: {}\r\n",
+        method,
+        body.len()
+    );
+    if let Some(authorization) = authorization {
+        req.push_str(&format!("authorization: {}\r\n", authorization));
+    }
+    req.push_str("\r\n");
+    req.push_str(body);
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(req.as_bytes()).await.unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    resp.split(' ').nth(1).unwrap().parse().unwrap()
+}
+
+#[tokio::test]
+async fn api_token() {
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let state = ServiceState::new(ServiceConfig::default(), Vec::new()).unwrap();
+    tokio::spawn(server::run(
+        state.clone(),
+        NetworkConfig {
+            tcp: None,
+            http: Some(HttpConfig {
+                host: Ipv4Addr::LOCALHOST.to_string(),
+                port: Some(port),
+                tls: None,
+                websocket: false,
+                api: true,
+                api_token: Some("secret".to_string()),
+                graphql_api: false,
+                bandwidth: Default::default(),
+                allowed_origins: Vec::new(),
+            }),
+            websocket: None,
+            mqttsn: None,
+            listeners: Vec::new(),
+        },
+    ));
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    while TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(request(addr, "POST", None).await, 401);
+    assert_eq!(request(addr, "POST", Some("Bearer other")).await, 401);
+    assert_eq!(request(addr, "POST", Some("secret")).await, 401);
+    assert!(state.bans().is_empty());
+
+    assert_eq!(request(addr, "POST", Some("Bearer secret")).await, 204);
+    assert_eq!(state.bans().len(), 1);
+
+    // the GET requests do not need the token
+    assert_eq!(request(addr, "GET", None).await, 200);
+    assert_eq!(request(addr, "DELETE", None).await, 401);
+    assert_eq!(state.bans().len(), 1);
+}
@@ -0,0 +1,69 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use client::{Client, FilterBuilder};
+use rsmqttd::config::{BandwidthConfig, NetworkConfig, TcpConfig};
+use rsmqttd::server;
+use service::{ServiceConfig, ServiceState};
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn egress_bandwidth() {
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let state = ServiceState::new(ServiceConfig::default(), Vec::new()).unwrap();
+    tokio::spawn(server::run(
+        state,
+        NetworkConfig {
+            tcp: Some(TcpConfig {
+                host: Ipv4Addr::LOCALHOST.to_string(),
+                port: Some(port),
+                bind: Vec::new(),
+                tls: None,
+                bandwidth: BandwidthConfig {
+                    ingress: None,
+                    egress: Some(10_000),
+                },
+            }),
+            http: None,
+            websocket: None,
+            mqttsn: None,
+            listeners: Vec::new(),
+        },
+    ));
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    while tokio::net::TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let (client, messages) = Client::new(addr)
+        .client_id("bandwidth")
+        .clean_start()
+        .build()
+        .await
+        .unwrap();
+    tokio::pin!(messages);
+    client
+        .subscribe()
+        .filter(FilterBuilder::new("bandwidth"))
+        .send()
+        .await
+        .unwrap();
+
+    let start = Instant::now();
+    client
+        .publish("bandwidth")
+        .payload(vec![0; 30_000])
+        .send()
+        .await
+        .unwrap();
+    let msg = messages.next().await.unwrap();
+    assert_eq!(msg.payload().len(), 30_000);
+
+    // one second of traffic is allowed as a burst, the rest is sent at 10 KB/s
+    assert!(start.elapsed() >= Duration::from_millis(1500));
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use service::codec::{Codec, Packet};
+use service::{client_loop, RemoteAddr, ServiceState};
+use tokio::io::{DuplexStream, ReadHalf, WriteHalf};
+
+pub type ClientCodec = Codec<ReadHalf<DuplexStream>, WriteHalf<DuplexStream>>;
+
+/// Runs a `client_loop` over an in-memory stream, returns the codec of the client side.
+pub fn connect_client(state: &Arc<ServiceState>) -> ClientCodec {
+    let (client, server) = tokio::io::duplex(4096);
+    let (reader, writer) = tokio::io::split(server);
+    tokio::spawn(client_loop(
+        state.clone(),
+        reader,
+        writer,
+        RemoteAddr {
+            protocol: "memory".into(),
+            addr: None,
+            client_cert: None,
+            listener: None,
+        },
+    ));
+    let (reader, writer) = tokio::io::split(client);
+    Codec::new(reader, writer)
+}
+
+/// Receives the next packet, fails if the connection is closed or nothing arrives in 5 seconds.
+pub async fn recv(codec: &mut ClientCodec) -> Packet {
+    tokio::time::timeout(Duration::from_secs(5), codec.decode())
+        .await
+        .expect("timed out waiting for a packet")
+        .unwrap()
+        .unwrap()
+        .0
+}
@@ -0,0 +1,64 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use client::Client;
+use rsmqttd::config::{NetworkConfig, TcpConfig};
+use rsmqttd::server;
+use service::codec::DisconnectReasonCode;
+use service::{DisconnectReason, ServiceConfig, ServiceState};
+
+#[tokio::test]
+async fn session_taken_over() {
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let state = ServiceState::new(ServiceConfig::default(), Vec::new()).unwrap();
+    tokio::spawn(server::run(
+        state.clone(),
+        NetworkConfig {
+            tcp: Some(TcpConfig {
+                host: Ipv4Addr::LOCALHOST.to_string(),
+                port: Some(port),
+                bind: Vec::new(),
+                tls: None,
+                bandwidth: Default::default(),
+            }),
+            http: None,
+            websocket: None,
+            mqttsn: None,
+            listeners: Vec::new(),
+        },
+    ));
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    while tokio::net::TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let _first = Client::new(addr)
+        .client_id("device")
+        .clean_start()
+        .build()
+        .await
+        .unwrap();
+    let _second = Client::new(addr)
+        .client_id("device")
+        .clean_start()
+        .build()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // both clients reconnect when they are taken over, only the last reasons are kept
+    let history = state.disconnect_history("device");
+    assert!(!history.is_empty());
+    assert!(history.len() <= ServiceConfig::default().disconnect_history_size);
+    for record in history {
+        assert_eq!(
+            record.reason,
+            DisconnectReason::Server(DisconnectReasonCode::SessionTakenOver)
+        );
+    }
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use service::codec::{
+    Auth, AuthProperties, AuthReasonCode, ConnAck, Connect, ConnectProperties, ConnectReasonCode,
+    Packet, ProtocolLevel,
+};
+use service::plugin::{EnhancedAuth, EnhancedAuthStep, Plugin, PluginResult};
+use service::{ServiceConfig, ServiceState};
+
+use common::{connect_client, recv};
+
+/// Sends a challenge and accepts the client that echoes it back.
+struct Challenge {
+    sent: bool,
+}
+
+#[async_trait::async_trait]
+impl EnhancedAuth for Challenge {
+    async fn step(&mut self, data: Option<Bytes>) -> PluginResult<EnhancedAuthStep> {
+        match (self.sent, data.as_deref()) {
+            (false, Some(b"alice")) => {
+                self.sent = true;
+                Ok(EnhancedAuthStep::Continue(Some(Bytes::from_static(
+                    b"challenge",
+                ))))
+            }
+            (true, Some(b"challenge")) => Ok(EnhancedAuthStep::Success(
+                "alice".to_string().into(),
+                Some(Bytes::from_static(b"welcome")),
+            )),
+            _ => Ok(EnhancedAuthStep::Failed),
+        }
+    }
+}
+
+struct ChallengePlugin;
+
+#[async_trait::async_trait]
+impl Plugin for ChallengePlugin {
+    async fn enhanced_auth(&self, method: &str) -> PluginResult<Option<Box<dyn EnhancedAuth>>> {
+        Ok((method == "challenge").then(|| Box::new(Challenge { sent: false }) as _))
+    }
+}
+
+fn connect_packet(method: &str, data: &'static [u8]) -> Packet {
+    Packet::Connect(Connect {
+        level: ProtocolLevel::V5,
+        keep_alive: 60,
+        clean_start: true,
+        client_id: "a".into(),
+        last_will: None,
+        login: None,
+        properties: ConnectProperties {
+            authentication_method: Some(method.into()),
+            authentication_data: Some(Bytes::from_static(data)),
+            ..ConnectProperties::default()
+        },
+    })
+}
+
+fn auth_packet(reason_code: AuthReasonCode, data: &'static [u8]) -> Packet {
+    Packet::Auth(Auth {
+        reason_code,
+        properties: AuthProperties {
+            authentication_method: Some("challenge".into()),
+            authentication_data: Some(Bytes::from_static(data)),
+            ..AuthProperties::default()
+        },
+    })
+}
+
+fn new_state() -> Arc<ServiceState> {
+    ServiceState::new(
+        ServiceConfig::default(),
+        vec![("challenge", Arc::new(ChallengePlugin) as Arc<dyn Plugin>)],
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn auth_exchange() {
+    let state = new_state();
+    let mut codec = connect_client(&state);
+
+    codec
+        .encode(&connect_packet("challenge", b"alice"))
+        .await
+        .unwrap();
+    assert_eq!(
+        recv(&mut codec).await,
+        auth_packet(AuthReasonCode::ContinueAuthentication, b"challenge")
+    );
+
+    codec
+        .encode(&auth_packet(
+            AuthReasonCode::ContinueAuthentication,
+            b"challenge",
+        ))
+        .await
+        .unwrap();
+    match recv(&mut codec).await {
+        Packet::ConnAck(ConnAck {
+            reason_code: ConnectReasonCode::Success,
+            properties,
+            ..
+        }) => {
+            assert_eq!(
+                properties.authentication_method.as_deref(),
+                Some("challenge")
+            );
+            assert_eq!(
+                properties.authentication_data.as_deref(),
+                Some(&b"welcome"[..])
+            );
+        }
+        packet => panic!("unexpected packet: {:?}", packet),
+    }
+    assert_eq!(state.clients().await[0].uid.as_deref(), Some("alice"));
+
+    // re-authenticate
+    codec
+        .encode(&auth_packet(AuthReasonCode::ReAuthenticate, b"alice"))
+        .await
+        .unwrap();
+    assert_eq!(
+        recv(&mut codec).await,
+        auth_packet(AuthReasonCode::ContinueAuthentication, b"challenge")
+    );
+    codec
+        .encode(&auth_packet(
+            AuthReasonCode::ContinueAuthentication,
+            b"challenge",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        recv(&mut codec).await,
+        auth_packet(AuthReasonCode::Success, b"welcome")
+    );
+}
+
+#[tokio::test]
+async fn auth_failed() {
+    let state = new_state();
+    let mut codec = connect_client(&state);
+
+    codec
+        .encode(&connect_packet("challenge", b"alice"))
+        .await
+        .unwrap();
+    recv(&mut codec).await;
+    codec
+        .encode(&auth_packet(
+            AuthReasonCode::ContinueAuthentication,
+            b"wrong",
+        ))
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv(&mut codec).await,
+        Packet::ConnAck(ConnAck {
+            reason_code: ConnectReasonCode::NotAuthorized,
+            ..
+        })
+    ));
+    assert!(codec.decode().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn bad_auth_method() {
+    let state = new_state();
+    let mut codec = connect_client(&state);
+
+    codec
+        .encode(&connect_packet("unknown", b"alice"))
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv(&mut codec).await,
+        Packet::ConnAck(ConnAck {
+            reason_code: ConnectReasonCode::BadAuthenticationMethod,
+            ..
+        })
+    ));
+    assert!(codec.decode().await.unwrap().is_none());
+}
@@ -0,0 +1,61 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use client::Client;
+use rsmqttd::config::{ListenerConfig, ListenerTransport, NetworkConfig};
+use rsmqttd::server;
+use service::{ListenerSettings, ServiceConfig, ServiceState};
+
+#[tokio::test]
+async fn bind_multiple_addresses() {
+    let port = std::net::TcpListener::bind((Ipv6Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let v6_addr = SocketAddr::from((Ipv6Addr::LOCALHOST, port));
+    let v4_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let state = ServiceState::new(ServiceConfig::default(), Vec::new()).unwrap();
+    tokio::spawn(server::run(
+        state.clone(),
+        NetworkConfig {
+            tcp: None,
+            http: None,
+            websocket: None,
+            mqttsn: None,
+            listeners: vec![ListenerConfig {
+                transport: ListenerTransport::Tcp,
+                host: String::new(),
+                port: None,
+                bind: vec![v6_addr, v4_addr],
+                path: String::new(),
+                tls: None,
+                bandwidth: Default::default(),
+                allowed_origins: Vec::new(),
+                settings: ListenerSettings {
+                    name: Some("local".to_string()),
+                    ..ListenerSettings::default()
+                },
+            }],
+        },
+    ));
+
+    for addr in [v6_addr, v4_addr] {
+        while tokio::net::TcpStream::connect(addr).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    let _v6_client = Client::new(v6_addr).client_id("v6").build().await.unwrap();
+    let _v4_client = Client::new(v4_addr).client_id("v4").build().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut clients = state.clients().await;
+    clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+    assert_eq!(clients.len(), 2);
+    assert!(clients[0].remote_addr.starts_with("tcp://127.0.0.1:"));
+    assert!(clients[1].remote_addr.starts_with("tcp://[::1]:"));
+    assert!(clients
+        .iter()
+        .all(|client| client.listener.as_deref() == Some("local")));
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::num::NonZeroU16;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use service::codec::{
+    ConnAck, Connect, ConnectProperties, ConnectReasonCode, Login, Packet, ProtocolLevel, Publish,
+    PublishProperties, Qos, RetainHandling, SubAck, Subscribe, SubscribeFilter,
+    SubscribeProperties,
+};
+use service::plugin::{AuthResult, Plugin, PluginResult};
+use service::{ServiceConfig, ServiceState};
+
+use common::{connect_client, recv, ClientCodec};
+
+/// Mounts the topics of every user under `{username}/`.
+struct TenantPlugin;
+
+#[async_trait::async_trait]
+impl Plugin for TenantPlugin {
+    async fn auth(&self, username: &str, _password: &[u8]) -> PluginResult<Option<AuthResult>> {
+        Ok(Some(AuthResult {
+            mount_point: Some(format!("{}/", username)),
+            ..AuthResult::from(username.to_string())
+        }))
+    }
+}
+
+async fn login(state: &Arc<ServiceState>, client_id: &str, username: Option<&str>) -> ClientCodec {
+    let mut codec = connect_client(state);
+    codec
+        .encode(&Packet::Connect(Connect {
+            level: ProtocolLevel::V5,
+            keep_alive: 60,
+            clean_start: true,
+            client_id: client_id.to_string().into(),
+            last_will: None,
+            login: username.map(|username| Login {
+                username: username.to_string().into(),
+                password: Bytes::new(),
+            }),
+            properties: ConnectProperties::default(),
+        }))
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv(&mut codec).await,
+        Packet::ConnAck(ConnAck {
+            reason_code: ConnectReasonCode::Success,
+            ..
+        })
+    ));
+    codec
+}
+
+async fn subscribe(codec: &mut ClientCodec, path: &str) {
+    codec
+        .encode(&Packet::Subscribe(Subscribe {
+            packet_id: NonZeroU16::new(1).unwrap(),
+            properties: SubscribeProperties::default(),
+            filters: vec![SubscribeFilter {
+                path: path.to_string().into(),
+                qos: Qos::AtMostOnce,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: RetainHandling::OnEverySubscribe,
+            }],
+        }))
+        .await
+        .unwrap();
+    assert!(matches!(recv(codec).await, Packet::SubAck(SubAck { .. })));
+}
+
+fn publish_packet(topic: &str, payload: &'static [u8]) -> Packet {
+    Packet::Publish(Publish {
+        dup: false,
+        qos: Qos::AtMostOnce,
+        retain: false,
+        topic: topic.to_string().into(),
+        packet_id: None,
+        properties: PublishProperties::default(),
+        payload: Bytes::from_static(payload),
+    })
+}
+
+#[tokio::test]
+async fn mount_point() {
+    let state = ServiceState::new(
+        ServiceConfig::default(),
+        vec![("tenant", Arc::new(TenantPlugin) as Arc<dyn Plugin>)],
+    )
+    .unwrap();
+    let mut tenant = login(&state, "a", Some("tenant1")).await;
+    let mut other = login(&state, "b", None).await;
+    subscribe(&mut tenant, "t").await;
+    subscribe(&mut other, "tenant1/t").await;
+
+    // the mount point is stripped from the topics delivered to the tenant
+    tenant.encode(&publish_packet("t", b"1")).await.unwrap();
+    assert_eq!(recv(&mut tenant).await, publish_packet("t", b"1"));
+    assert_eq!(recv(&mut other).await, publish_packet("tenant1/t", b"1"));
+
+    // the tenant does not receive the messages outside of its mount point
+    other.encode(&publish_packet("t", b"2")).await.unwrap();
+    other
+        .encode(&publish_packet("tenant1/t", b"3"))
+        .await
+        .unwrap();
+    assert_eq!(recv(&mut tenant).await, publish_packet("t", b"3"));
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use client::{Client, FilterBuilder};
+use rsmqttd::config::{MqttSnConfig, NetworkConfig, TcpConfig};
+use rsmqttd::server;
+use service::codec::mqttsn::{
+    Connect, Packet, PubAck, Publish, Qos, RegAck, Register, ReturnCode, TopicId,
+};
+use service::{ServiceConfig, ServiceState};
+use tokio::net::UdpSocket;
+use tokio_stream::StreamExt;
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+async fn send(socket: &UdpSocket, packet: Packet) {
+    let mut data = BytesMut::new();
+    packet.encode(&mut data).unwrap();
+    socket.send(&data).await.unwrap();
+}
+
+async fn recv(socket: &UdpSocket) -> Packet {
+    let mut buf = vec![0; 65536];
+    let size = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    Packet::decode(Bytes::copy_from_slice(&buf[..size])).unwrap()
+}
+
+#[tokio::test]
+async fn publish_through_gateway() {
+    let tcp_port = free_port();
+    let sn_port = free_port();
+    let mut predefined_topics = HashMap::new();
+    predefined_topics.insert(1, "sensors/predefined".to_string());
+    let state = ServiceState::new(ServiceConfig::default(), Vec::new()).unwrap();
+    tokio::spawn(server::run(
+        state,
+        NetworkConfig {
+            tcp: Some(TcpConfig {
+                host: Ipv4Addr::LOCALHOST.to_string(),
+                port: Some(tcp_port),
+                bind: Vec::new(),
+                tls: None,
+                bandwidth: Default::default(),
+            }),
+            http: None,
+            websocket: None,
+            mqttsn: Some(MqttSnConfig {
+                host: Ipv4Addr::LOCALHOST.to_string(),
+                port: Some(sn_port),
+                predefined_topics,
+                qos_minus_one: true,
+            }),
+            listeners: Vec::new(),
+        },
+    ));
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, tcp_port));
+    while tokio::net::TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let (client, messages) = Client::new(addr)
+        .client_id("subscriber")
+        .build()
+        .await
+        .unwrap();
+    tokio::pin!(messages);
+    client
+        .subscribe()
+        .filter(FilterBuilder::new("sensors/#"))
+        .send()
+        .await
+        .unwrap();
+
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    socket
+        .connect((Ipv4Addr::LOCALHOST, sn_port))
+        .await
+        .unwrap();
+
+    send(
+        &socket,
+        Packet::Connect(Connect {
+            will: false,
+            clean_session: true,
+            duration: 60,
+            client_id: "sensor".into(),
+        }),
+    )
+    .await;
+    assert_eq!(
+        recv(&socket).await,
+        Packet::ConnAck {
+            return_code: ReturnCode::Accepted,
+        }
+    );
+
+    send(
+        &socket,
+        Packet::Register(Register {
+            topic_id: 0,
+            msg_id: 1,
+            topic_name: "sensors/temperature".into(),
+        }),
+    )
+    .await;
+    assert_eq!(
+        recv(&socket).await,
+        Packet::RegAck(RegAck {
+            topic_id: 1,
+            msg_id: 1,
+            return_code: ReturnCode::Accepted,
+        })
+    );
+
+    send(
+        &socket,
+        Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::AtLeastOnce,
+            retain: false,
+            topic_id: TopicId::Normal(1),
+            msg_id: 2,
+            data: Bytes::from_static(b"22.5"),
+        }),
+    )
+    .await;
+    assert_eq!(
+        recv(&socket).await,
+        Packet::PubAck(PubAck {
+            topic_id: 1,
+            msg_id: 2,
+            return_code: ReturnCode::Accepted,
+        })
+    );
+    let msg = messages.next().await.unwrap();
+    assert_eq!(msg.topic(), "sensors/temperature");
+    assert_eq!(msg.payload(), b"22.5");
+
+    // an unknown topic id
+    send(
+        &socket,
+        Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::AtLeastOnce,
+            retain: false,
+            topic_id: TopicId::Normal(7),
+            msg_id: 3,
+            data: Bytes::new(),
+        }),
+    )
+    .await;
+    assert_eq!(
+        recv(&socket).await,
+        Packet::PubAck(PubAck {
+            topic_id: 7,
+            msg_id: 3,
+            return_code: ReturnCode::InvalidTopicId,
+        })
+    );
+
+    send(&socket, Packet::PingReq { client_id: None }).await;
+    assert_eq!(recv(&socket).await, Packet::PingResp);
+
+    send(&socket, Packet::Disconnect { duration: None }).await;
+    assert_eq!(recv(&socket).await, Packet::Disconnect { duration: None });
+
+    // QoS -1 without a connection
+    send(
+        &socket,
+        Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::NoSession,
+            retain: false,
+            topic_id: TopicId::Predefined(1),
+            msg_id: 0,
+            data: Bytes::from_static(b"on"),
+        }),
+    )
+    .await;
+    let msg = messages.next().await.unwrap();
+    assert_eq!(msg.topic(), "sensors/predefined");
+    assert_eq!(msg.payload(), b"on");
+}
+
+#[tokio::test]
+async fn gateway_clients_limit() {
+    let sn_port = free_port();
+    let mut config = ServiceConfig::default();
+    config.overload.max_connections = Some(1);
+    let state = ServiceState::new(config, Vec::new()).unwrap();
+    tokio::spawn(server::run(
+        state,
+        NetworkConfig {
+            tcp: None,
+            http: None,
+            websocket: None,
+            mqttsn: Some(MqttSnConfig {
+                host: Ipv4Addr::LOCALHOST.to_string(),
+                port: Some(sn_port),
+                predefined_topics: HashMap::new(),
+                qos_minus_one: false,
+            }),
+            listeners: Vec::new(),
+        },
+    ));
+
+    let mut sockets = Vec::new();
+    for (client_id, return_code) in [
+        ("sensor1", ReturnCode::Accepted),
+        ("sensor2", ReturnCode::Congestion),
+    ]
+    .iter()
+    {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        socket
+            .connect((Ipv4Addr::LOCALHOST, sn_port))
+            .await
+            .unwrap();
+        let connect = Packet::Connect(Connect {
+            will: false,
+            clean_session: true,
+            duration: 60,
+            client_id: (*client_id).into(),
+        });
+
+        // the datagrams sent before the gateway is bound are lost
+        let conn_ack = loop {
+            send(&socket, connect.clone()).await;
+            let mut buf = vec![0; 65536];
+            if let Ok(Ok(size)) =
+                tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf)).await
+            {
+                break Packet::decode(Bytes::copy_from_slice(&buf[..size])).unwrap();
+            }
+        };
+        assert_eq!(
+            conn_ack,
+            Packet::ConnAck {
+                return_code: *return_code,
+            }
+        );
+        sockets.push(socket);
+    }
+}
+
+#[tokio::test]
+async fn qos_minus_one_ip_filter() {
+    let sn_port = free_port();
+    let config: ServiceConfig = serde_yaml::from_str("ip_filter: {allow: [127.0.0.1/32]}").unwrap();
+    let state = ServiceState::new(config, Vec::new()).unwrap();
+    let mut predefined_topics = HashMap::new();
+    for (topic_id, topic) in [(1, "a"), (2, "b"), (3, "c")].iter() {
+        predefined_topics.insert(*topic_id, topic.to_string());
+    }
+    tokio::spawn(server::run(
+        state.clone(),
+        NetworkConfig {
+            tcp: None,
+            http: None,
+            websocket: None,
+            mqttsn: Some(MqttSnConfig {
+                host: Ipv4Addr::LOCALHOST.to_string(),
+                port: Some(sn_port),
+                predefined_topics,
+                qos_minus_one: true,
+            }),
+            listeners: Vec::new(),
+        },
+    ));
+
+    let socket = |ip: Ipv4Addr| async move {
+        let socket = UdpSocket::bind((ip, 0)).await.unwrap();
+        socket
+            .connect((Ipv4Addr::LOCALHOST, sn_port))
+            .await
+            .unwrap();
+        socket
+    };
+    let allowed = socket(Ipv4Addr::LOCALHOST).await;
+    let denied = socket(Ipv4Addr::new(127, 0, 0, 2)).await;
+    let publish = |topic_id| {
+        Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::NoSession,
+            retain: true,
+            topic_id: TopicId::Predefined(topic_id),
+            msg_id: 0,
+            data: Bytes::from_static(b"1"),
+        })
+    };
+    let is_retained = |topic: &str| {
+        state
+            .retained_messages(topic)
+            .unwrap()
+            .iter()
+            .any(|msg| &**msg.topic() == topic)
+    };
+
+    // the datagrams sent before the gateway is bound are lost
+    while !is_retained("a") {
+        send(&allowed, publish(1)).await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // the messages are handled in order, so the denied one was handled when `c` is retained
+    send(&denied, publish(2)).await;
+    send(&allowed, publish(3)).await;
+    while !is_retained("c") {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(!is_retained("b"));
+}
@@ -0,0 +1,122 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use client::{Client, TlsConfig as ClientTlsConfig, Transport};
+use rsmqttd::config::{NetworkConfig, TcpConfig, TlsConfig};
+use rsmqttd::server;
+use service::plugin::{AuthResult, Plugin, PluginResult};
+use service::{ClientCert, ServiceConfig, ServiceState};
+use tokio::io::AsyncReadExt;
+
+const CERTS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs");
+
+/// Uses the common name of the certificate as the uid.
+struct CertAuth;
+
+#[async_trait::async_trait]
+impl Plugin for CertAuth {
+    async fn auth_cert(&self, cert: &ClientCert) -> PluginResult<Option<AuthResult>> {
+        assert_eq!(
+            cert.subject_alt_names,
+            vec!["device-1.local", "device-1@example.com"]
+        );
+        Ok(cert.common_name.clone().map(Into::into))
+    }
+}
+
+async fn start_server(config: ServiceConfig) -> (Arc<ServiceState>, SocketAddr) {
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let state = ServiceState::new(
+        config,
+        vec![("cert-auth", Arc::new(CertAuth) as Arc<dyn Plugin>)],
+    )
+    .unwrap();
+    tokio::spawn(server::run(
+        state.clone(),
+        NetworkConfig {
+            tcp: Some(TcpConfig {
+                host: Ipv4Addr::LOCALHOST.to_string(),
+                port: Some(port),
+                bind: Vec::new(),
+                tls: Some(TlsConfig {
+                    cert: format!("{}/server.pem", CERTS_DIR),
+                    key: format!("{}/server.key", CERTS_DIR),
+                    alpn: Vec::new(),
+                    client_ca: Some(format!("{}/client-ca.pem", CERTS_DIR)),
+                    client_cert_optional: false,
+                }),
+                bandwidth: Default::default(),
+            }),
+            http: None,
+            websocket: None,
+            mqttsn: None,
+            listeners: Vec::new(),
+        },
+    ));
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    while tokio::net::TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    (state, addr)
+}
+
+fn client_tls() -> ClientTlsConfig {
+    ClientTlsConfig::new("localhost").ca_file(format!("{}/ca.pem", CERTS_DIR))
+}
+
+#[tokio::test]
+async fn client_cert_uid() {
+    let (state, addr) = start_server(ServiceConfig::default()).await;
+
+    let _client = Client::new(addr)
+        .transport(Transport::Tls(client_tls().client_cert(
+            format!("{}/client.pem", CERTS_DIR),
+            format!("{}/client.key", CERTS_DIR),
+        )))
+        .client_id("device")
+        .build()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let clients = state.clients().await;
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].uid.as_deref(), Some("device-1"));
+}
+
+#[tokio::test]
+async fn client_cert_required() {
+    let (state, addr) = start_server(ServiceConfig::default()).await;
+
+    let _client = Client::new(addr)
+        .transport(Transport::Tls(client_tls()))
+        .client_id("device")
+        .build()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    assert!(state.clients().await.is_empty());
+}
+
+#[tokio::test]
+async fn tls_handshake_timeout() {
+    let (_state, addr) = start_server(ServiceConfig {
+        connect_timeout: 1,
+        ..ServiceConfig::default()
+    })
+    .await;
+
+    // the connection is closed if the client never starts the handshake
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let res = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut [0; 16]))
+        .await
+        .unwrap();
+    assert!(matches!(res, Ok(0) | Err(_)));
+}
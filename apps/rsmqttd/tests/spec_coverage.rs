@@ -0,0 +1,15 @@
+use std::path::Path;
+
+#[test]
+fn spec_coverage() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let coverage = testutil::spec_coverage(
+        &root.join("conformance/statements.yaml"),
+        &root.join("tests"),
+    );
+    println!("{}", coverage);
+    assert!(
+        coverage.unknown.is_empty(),
+        "suites reference statements that are not in the catalog"
+    );
+}
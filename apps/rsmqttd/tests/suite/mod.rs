@@ -0,0 +1,107 @@
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use client::{Transport, WebSocketConfig};
+use rsmqttd::config::{HttpConfig, NetworkConfig, TcpConfig, TlsConfig};
+use rsmqttd::{create_plugins, server};
+use service::{ServiceState, Storage};
+use testutil::{Endpoint, Listener};
+
+const CERTS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs");
+
+fn server_tls() -> TlsConfig {
+    TlsConfig {
+        cert: format!("{}/server.pem", CERTS_DIR),
+        key: format!("{}/server.key", CERTS_DIR),
+        alpn: vec!["mqtt".to_string()],
+        client_ca: None,
+        client_cert_optional: false,
+    }
+}
+
+fn client_tls() -> client::TlsConfig {
+    client::TlsConfig::new("localhost").ca_file(format!("{}/ca.pem", CERTS_DIR))
+}
+
+async fn listen(state: Arc<ServiceState>, listener: Listener) -> Endpoint {
+    // the port is released before the server binds it
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let tcp = |tls| NetworkConfig {
+        tcp: Some(TcpConfig {
+            host: Ipv4Addr::LOCALHOST.to_string(),
+            port: Some(port),
+            bind: Vec::new(),
+            tls,
+            bandwidth: Default::default(),
+        }),
+        http: None,
+        websocket: None,
+        mqttsn: None,
+        listeners: Vec::new(),
+    };
+    let http = |tls| NetworkConfig {
+        tcp: None,
+        http: Some(HttpConfig {
+            host: Ipv4Addr::LOCALHOST.to_string(),
+            port: Some(port),
+            tls,
+            websocket: true,
+            api: false,
+            api_token: None,
+            graphql_api: false,
+            bandwidth: Default::default(),
+            allowed_origins: Vec::new(),
+        }),
+        websocket: None,
+        mqttsn: None,
+        listeners: Vec::new(),
+    };
+    let websocket = || WebSocketConfig::new("localhost").path("/ws");
+
+    let (network_config, transport) = match listener {
+        Listener::Memory => unreachable!(),
+        Listener::Tcp => (tcp(None), Transport::Tcp),
+        Listener::Tls => (tcp(Some(server_tls())), Transport::Tls(client_tls())),
+        Listener::Ws => (http(None), Transport::WebSocket(websocket())),
+        Listener::Wss => (
+            http(Some(server_tls())),
+            Transport::WebSocket(websocket().tls(client_tls())),
+        ),
+    };
+    tokio::spawn(server::run(state, network_config));
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    while tokio::net::TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    Endpoint {
+        addrs: vec![addr],
+        transport,
+    }
+}
+
+/// Runs a YAML test file against a broker with the storage created by `create_storage`.
+pub fn run<S, SF>(path: &Path, create_storage: S)
+where
+    S: FnOnce() -> SF,
+    SF: Future<Output = Arc<dyn Storage>>,
+{
+    // the clock can only be paused on a current thread runtime
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(testutil::run_yaml_file(
+            path,
+            |values| async move { create_plugins(values).await.unwrap() },
+            create_storage,
+            listen,
+        ));
+}
@@ -1,13 +1,14 @@
+mod suite;
+
 use std::path::Path;
+use std::sync::Arc;
 
-use rsmqttd::create_plugins;
+use service::{Storage, StorageMemory};
 
 fn service_test(path: &Path) -> datatest_stable::Result<()> {
-    tokio::runtime::Runtime::new()
-        .unwrap()
-        .block_on(testutil::run_yaml_file(path, |values| async move {
-            create_plugins(values).await.unwrap()
-        }));
+    suite::run(path, || async {
+        Arc::new(StorageMemory::default()) as Arc<dyn Storage>
+    });
     Ok(())
 }
 
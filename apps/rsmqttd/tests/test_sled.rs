@@ -0,0 +1,30 @@
+mod suite;
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rsmqtt_storage_sled::StorageSledFactory;
+use service::StorageFactory;
+
+/// Runs the YAML tests against the sled storage, each file with a new database.
+fn service_test(path: &Path) -> datatest_stable::Result<()> {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let db_path = std::env::temp_dir().join(format!(
+        "rsmqttd-test-sled-{}-{}",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let mut config = serde_yaml::Mapping::new();
+    config.insert("path".into(), db_path.display().to_string().into());
+    suite::run(path, || async move {
+        StorageSledFactory
+            .create(serde_yaml::Value::Mapping(config))
+            .await
+            .unwrap()
+    });
+    std::fs::remove_dir_all(&db_path).ok();
+    Ok(())
+}
+
+datatest_stable::harness!(service_test, "tests", r"^.*/*.yaml");
@@ -0,0 +1,178 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use client::{Client, TlsConfig as ClientTlsConfig, Transport, WebSocketConfig as ClientWsConfig};
+use rsmqttd::config::{NetworkConfig, TlsConfig, WebSocketConfig};
+use rsmqttd::server;
+use service::{ServiceConfig, ServiceState};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const CERTS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs");
+
+async fn start_wss(client_ca: Option<String>) -> (Arc<ServiceState>, SocketAddr) {
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let state = ServiceState::new(ServiceConfig::default(), Vec::new()).unwrap();
+    tokio::spawn(server::run(
+        state.clone(),
+        NetworkConfig {
+            tcp: None,
+            http: None,
+            websocket: Some(WebSocketConfig {
+                host: Ipv4Addr::LOCALHOST.to_string(),
+                port: Some(port),
+                bind: Vec::new(),
+                path: "/mqtt".to_string(),
+                tls: Some(TlsConfig {
+                    cert: format!("{}/server.pem", CERTS_DIR),
+                    key: format!("{}/server.key", CERTS_DIR),
+                    alpn: Vec::new(),
+                    client_ca,
+                    client_cert_optional: false,
+                }),
+                bandwidth: Default::default(),
+                allowed_origins: Vec::new(),
+            }),
+            mqttsn: None,
+            listeners: Vec::new(),
+        },
+    ));
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    while tokio::net::TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    (state, addr)
+}
+
+#[tokio::test]
+async fn wss_listener() {
+    let (state, addr) = start_wss(None).await;
+
+    let tls = ClientTlsConfig::new("localhost").ca_file(format!("{}/ca.pem", CERTS_DIR));
+    let _client = Client::new(addr)
+        .transport(Transport::WebSocket(
+            ClientWsConfig::new("localhost").path("/mqtt").tls(tls),
+        ))
+        .client_id("browser")
+        .build()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let clients = state.clients().await;
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].client_id, "browser");
+    assert!(clients[0].remote_addr.starts_with("wss://127.0.0.1:"));
+}
+
+#[tokio::test]
+async fn wss_client_cert() {
+    let (state, addr) = start_wss(Some(format!("{}/client-ca.pem", CERTS_DIR))).await;
+    let connect = |client_id: &'static str, tls: ClientTlsConfig| {
+        Client::new(addr)
+            .transport(Transport::WebSocket(
+                ClientWsConfig::new("localhost").path("/mqtt").tls(tls),
+            ))
+            .client_id(client_id)
+            .build()
+    };
+    let tls = || ClientTlsConfig::new("localhost").ca_file(format!("{}/ca.pem", CERTS_DIR));
+
+    let _without_cert = connect("anonymous", tls()).await.unwrap();
+    let _with_cert = connect(
+        "device",
+        tls().client_cert(
+            format!("{}/client.pem", CERTS_DIR),
+            format!("{}/client.key", CERTS_DIR),
+        ),
+    )
+    .await
+    .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let clients = state.clients().await;
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].client_id, "device");
+}
+
+/// Sends an upgrade request with the given headers, returns the status code of the response.
+async fn upgrade(addr: SocketAddr, headers: &str) -> u16 {
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            format!(
+                "GET /mqtt HTTP/1.1\r\n\
+                 Host: localhost\r\n\
+                 Connection: Upgrade\r\n\
+                 Upgrade: websocket\r\n\
+                 Sec-WebSocket-Version: 13\r\n\
+                 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                 {}\r\n",
+                headers
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut buf = [0; 12];
+    stream.read_exact(&mut buf).await.unwrap();
+    std::str::from_utf8(&buf[9..12]).unwrap().parse().unwrap()
+}
+
+#[tokio::test]
+async fn subprotocol_and_origin() {
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let state = ServiceState::new(ServiceConfig::default(), Vec::new()).unwrap();
+    tokio::spawn(server::run(
+        state,
+        NetworkConfig {
+            tcp: None,
+            http: None,
+            websocket: Some(WebSocketConfig {
+                host: Ipv4Addr::LOCALHOST.to_string(),
+                port: Some(port),
+                bind: Vec::new(),
+                path: "/mqtt".to_string(),
+                tls: None,
+                bandwidth: Default::default(),
+                allowed_origins: vec!["https://example.com".to_string()],
+            }),
+            mqttsn: None,
+            listeners: Vec::new(),
+        },
+    ));
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    while tokio::net::TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(upgrade(addr, "").await, 400);
+    assert_eq!(upgrade(addr, "Sec-WebSocket-Protocol: chat\r\n").await, 400);
+    assert_eq!(
+        upgrade(
+            addr,
+            "Sec-WebSocket-Protocol: mqtt\r\nOrigin: https://evil.example.com\r\n"
+        )
+        .await,
+        403
+    );
+    assert_eq!(
+        upgrade(
+            addr,
+            "Sec-WebSocket-Protocol: mqttv3.1, mqtt\r\nOrigin: https://example.com\r\n"
+        )
+        .await,
+        101
+    );
+    assert_eq!(upgrade(addr, "Sec-WebSocket-Protocol: mqtt\r\n").await, 101);
+}
@@ -1,16 +1,43 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
 use bytestring::ByteString;
-use codec::{Connect, ConnectProperties, Login, ProtocolLevel};
+use codec::{Connect, ConnectProperties, Login, Packet, ProtocolLevel, SubscribeFilter};
 use tokio::net::ToSocketAddrs;
-use tokio::sync::mpsc;
 use tokio_stream::Stream;
 
-use crate::command::Command;
-use crate::core::Core;
-use crate::{Message, PublishBuilder, SubscribeBuilder, UnsubscribeBuilder};
+use crate::command::CommandSender;
+use crate::core::{Core, Options, PacketHooks, Subscriptions};
+use crate::{Error, Message, PublishBuilder, SubscribeBuilder, Transport, UnsubscribeBuilder};
+
+/// What happens when a channel between the client and its connection task is full.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Backpressure {
+    /// Wait until there is room in the channel.
+    Block,
+
+    /// Fail immediately instead of waiting.
+    Error,
+}
+
+/// When the client sends its subscriptions again after reconnecting.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ResubscribeMode {
+    /// Resubscribe after every reconnection.
+    Always,
+
+    /// Never resubscribe, the application is responsible for restoring its subscriptions.
+    Never,
+
+    /// Resubscribe only if the server has no session for this client.
+    NoSession,
+}
 
 pub struct ClientBuilder<A> {
     addrs: A,
     connect: Connect,
+    command_backpressure: Backpressure,
+    options: Options,
 }
 
 impl<A: ToSocketAddrs> ClientBuilder<A> {
@@ -26,9 +53,29 @@ impl<A: ToSocketAddrs> ClientBuilder<A> {
                 login: None,
                 properties: ConnectProperties::default(),
             },
+            command_backpressure: Backpressure::Block,
+            options: Options {
+                command_channel_capacity: 16,
+                message_channel_capacity: 16,
+                message_backpressure: Backpressure::Block,
+                resubscribe: ResubscribeMode::NoSession,
+                hooks: PacketHooks::default(),
+                transport: Transport::default(),
+            },
         }
     }
 
+    /// Sets the MQTT protocol version.
+    ///
+    /// The default value is [`ProtocolLevel::V5`], features that only exist in MQTT 5 such as
+    /// properties and reason codes are not available with [`ProtocolLevel::V3`] and
+    /// [`ProtocolLevel::V4`].
+    #[inline]
+    pub fn protocol_level(mut self, level: ProtocolLevel) -> Self {
+        self.connect.level = level;
+        self
+    }
+
     #[inline]
     pub fn keep_alive(mut self, seconds: u16) -> Self {
         self.connect.keep_alive = seconds;
@@ -48,7 +95,7 @@ impl<A: ToSocketAddrs> ClientBuilder<A> {
     }
 
     #[inline]
-    pub fn login(mut self, user: impl Into<ByteString>, password: impl Into<ByteString>) -> Self {
+    pub fn login(mut self, user: impl Into<ByteString>, password: impl Into<Bytes>) -> Self {
         self.connect.login = Some(Login {
             username: user.into(),
             password: password.into(),
@@ -93,26 +140,226 @@ impl<A: ToSocketAddrs> ClientBuilder<A> {
         self
     }
 
-    pub async fn build(self) -> Result<(Client, impl Stream<Item = Message> + Send + 'static)> {
+    /// Sets the number of subscribe, unsubscribe and publish commands that can be queued
+    /// before the connection task picks them up.
+    ///
+    /// The capacity must be greater than `0`, otherwise [`build`](Self::build) fails with
+    /// [`Error::InvalidConfig`].
+    ///
+    /// The default value is `16`.
+    #[inline]
+    pub fn command_channel_capacity(mut self, capacity: usize) -> Self {
+        self.options.command_channel_capacity = capacity;
+        self
+    }
+
+    /// Sets the behavior of `send` and `request` when the command channel is full.
+    ///
+    /// With [`Backpressure::Block`] the caller waits for a free slot, with [`Backpressure::Error`]
+    /// the call fails with [`Error::Busy`] or [`PublishError::Busy`](crate::PublishError::Busy).
+    ///
+    /// The default value is [`Backpressure::Block`].
+    #[inline]
+    pub fn command_backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.command_backpressure = backpressure;
+        self
+    }
+
+    /// Sets the number of received messages that can be buffered before they are read from
+    /// the message stream.
+    ///
+    /// The capacity must be greater than `0`, otherwise [`build`](Self::build) fails with
+    /// [`Error::InvalidConfig`].
+    ///
+    /// The default value is `16`.
+    #[inline]
+    pub fn message_channel_capacity(mut self, capacity: usize) -> Self {
+        self.options.message_channel_capacity = capacity;
+        self
+    }
+
+    /// Sets the behavior of the connection task when the message stream is full.
+    ///
+    /// With [`Backpressure::Block`] the connection task stops reading from the socket until
+    /// the stream is polled, so a consumer that waits for the result of a publish without
+    /// polling the stream can deadlock. With [`Backpressure::Error`] the message is rejected
+    /// instead: QoS 0 messages are dropped, QoS 1 and QoS 2 messages are answered with
+    /// `QuotaExceeded` so the server can handle the failure.
+    ///
+    /// The default value is [`Backpressure::Block`].
+    #[inline]
+    pub fn message_backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.options.message_backpressure = backpressure;
+        self
+    }
+
+    /// Sets the transport used to connect to the broker.
+    ///
+    /// The default value is [`Transport::Tcp`].
+    #[inline]
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.options.transport = transport;
+        self
+    }
+
+    /// Accepts any server certificate if the transport uses TLS.
+    ///
+    /// This makes the connection vulnerable to man-in-the-middle attacks, it should only be
+    /// used for testing.
+    #[cfg(feature = "tls")]
+    pub fn danger_accept_invalid_certs(mut self) -> Self {
+        match &mut self.options.transport {
+            Transport::Tls(tls) => tls.set_insecure(),
+            #[cfg(feature = "websocket")]
+            Transport::WebSocket(websocket) => {
+                if let Some(tls) = websocket.tls_mut() {
+                    tls.set_insecure();
+                }
+            }
+            _ => {}
+        }
+        self
+    }
+
+    /// Sets when the subscriptions are sent again after reconnecting.
+    ///
+    /// The default value is [`ResubscribeMode::NoSession`].
+    #[inline]
+    pub fn resubscribe(mut self, mode: ResubscribeMode) -> Self {
+        self.options.resubscribe = mode;
+        self
+    }
+
+    /// Sets a callback that is invoked with every packet written to the connection.
+    ///
+    /// The callback runs on the connection task, so it should return quickly.
+    #[inline]
+    pub fn on_packet_sent(mut self, f: impl Fn(&Packet) + Send + Sync + 'static) -> Self {
+        self.options.hooks.on_packet_sent = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets a callback that is invoked with every packet read from the connection.
+    ///
+    /// The callback runs on the connection task, so it should return quickly.
+    #[inline]
+    pub fn on_packet_received(mut self, f: impl Fn(&Packet) + Send + Sync + 'static) -> Self {
+        self.options.hooks.on_packet_received = Some(Arc::new(f));
+        self
+    }
+
+    pub async fn build(
+        self,
+    ) -> Result<(Client, impl Stream<Item = Message> + Send + 'static), Error> {
+        if self.options.command_channel_capacity == 0 {
+            return Err(Error::InvalidConfig(
+                "command channel capacity must be greater than 0".to_string(),
+            ));
+        }
+        if self.options.message_channel_capacity == 0 {
+            return Err(Error::InvalidConfig(
+                "message channel capacity must be greater than 0".to_string(),
+            ));
+        }
+
         let addrs = tokio::net::lookup_host(self.addrs).await?.collect();
-        let (tx_command, rx_msg) = Core::run(addrs, self.connect);
+        let (tx_command, rx_msg, subscriptions) = Core::run(addrs, self.connect, self.options);
         Ok((
-            Client { tx_command },
+            Client {
+                tx_command: CommandSender::new(tx_command, self.command_backpressure),
+                subscriptions,
+            },
             tokio_stream::wrappers::ReceiverStream::new(rx_msg),
         ))
     }
 }
 
+/// A handle to an MQTT connection.
+///
+/// `Client` is a cheap handle over the command channel of the connection task, it can be
+/// cloned and shared between tasks without additional synchronization. Commands sent from one
+/// task are processed in the order they were sent, commands from different tasks are
+/// interleaved. The connection task is stopped when the last handle is dropped.
 #[derive(Clone)]
 pub struct Client {
-    tx_command: mpsc::Sender<Command>,
+    tx_command: CommandSender,
+    subscriptions: Subscriptions,
 }
 
 impl Client {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new<A: ToSocketAddrs>(addrs: A) -> ClientBuilder<A> {
         ClientBuilder::new(addrs)
     }
 
+    /// Creates a client builder from a URL.
+    ///
+    /// The URL has the form `scheme://host[:port][/path]`, the supported schemes are:
+    ///
+    /// - `mqtt` and `tcp` with the default port `1883`
+    /// - `mqtts` and `ssl` with the default port `8883` if the `tls` feature is enabled
+    /// - `ws` with the default port `80` if the `websocket` feature is enabled
+    /// - `wss` with the default port `443` if the `websocket` and `tls` features are enabled
+    /// - `quic` with the default port `14567` if the `quic` feature is enabled
+    ///
+    /// The path is only used by the WebSocket transports.
+    pub fn from_url(url: &str) -> Result<ClientBuilder<String>, Error> {
+        let invalid_url = || Error::InvalidConfig(format!("invalid url: {}", url));
+        let (scheme, rest) = url.split_once("://").ok_or_else(invalid_url)?;
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => rest.split_at(idx),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(invalid_url());
+        }
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+                (host, Some(port.parse::<u16>().map_err(|_| invalid_url())?))
+            }
+            _ => (authority, None),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        #[cfg(not(feature = "websocket"))]
+        let _ = path;
+
+        let (transport, default_port) = match scheme {
+            "mqtt" | "tcp" => (Transport::Tcp, 1883),
+            #[cfg(feature = "tls")]
+            "mqtts" | "ssl" => (Transport::Tls(crate::TlsConfig::new(host)), 8883),
+            #[cfg(feature = "websocket")]
+            "ws" => (
+                Transport::WebSocket(crate::WebSocketConfig::new(authority).path(path)),
+                80,
+            ),
+            #[cfg(all(feature = "websocket", feature = "tls"))]
+            "wss" => (
+                Transport::WebSocket(
+                    crate::WebSocketConfig::new(authority)
+                        .path(path)
+                        .tls(crate::TlsConfig::new(host)),
+                ),
+                443,
+            ),
+            #[cfg(feature = "quic")]
+            "quic" => (Transport::Quic(crate::QuicConfig::new(host)), 14567),
+            _ => {
+                return Err(Error::InvalidConfig(format!(
+                    "unsupported url scheme: {}",
+                    scheme
+                )))
+            }
+        };
+
+        let addrs = if host.contains(':') {
+            format!("[{}]:{}", host, port.unwrap_or(default_port))
+        } else {
+            format!("{}:{}", host, port.unwrap_or(default_port))
+        };
+        Ok(ClientBuilder::new(addrs).transport(transport))
+    }
+
     pub fn subscribe(&self) -> SubscribeBuilder {
         SubscribeBuilder::new(self.tx_command.clone())
     }
@@ -121,6 +368,14 @@ impl Client {
         UnsubscribeBuilder::new(self.tx_command.clone())
     }
 
+    /// Returns the subscriptions the client believes are active.
+    ///
+    /// A subscription is added when it is sent to the server, and removed when it is
+    /// unsubscribed or rejected by the server.
+    pub fn subscriptions(&self) -> Vec<SubscribeFilter> {
+        self.subscriptions.lock().values().cloned().collect()
+    }
+
     pub fn publish(&self, topic: impl Into<ByteString>) -> PublishBuilder {
         PublishBuilder::new(self.tx_command.clone(), topic.into())
     }
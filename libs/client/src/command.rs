@@ -1,34 +1,32 @@
-use std::num::NonZeroU16;
-
 use bytestring::ByteString;
-use codec::{Publish, Qos, SubscribeFilter};
-use tokio::sync::oneshot;
+use codec::{Publish, SubscribeFilter};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+use tracing::Span;
 
 use crate::error::PublishError;
-use crate::{AckError, Message};
+use crate::{Backpressure, Message};
 
 pub struct SubscribeCommand {
     pub filters: Vec<SubscribeFilter>,
+    pub span: Span,
 }
 
 pub struct UnsubscribeCommand {
     pub filters: Vec<ByteString>,
+    pub span: Span,
 }
 
 pub struct PublishCommand {
     pub publish: Publish,
-    pub reply: oneshot::Sender<Result<()>, PublishError>,
+    pub reply: Option<oneshot::Sender<Result<(), PublishError>>>,
+    pub span: Span,
 }
 
 pub struct RequestCommand {
     pub publish: Publish,
-    pub reply: oneshot::Sender<Result<Message>>,
-}
-
-pub struct AckCommand {
-    pub packet_id: NonZeroU16,
-    pub qos: Qos,
-    pub reply: oneshot::Sender<Result<(), AckError>>,
+    pub reply: oneshot::Sender<Result<Message, PublishError>>,
+    pub span: Span,
 }
 
 pub enum Command {
@@ -36,5 +34,35 @@ pub enum Command {
     Unsubscribe(UnsubscribeCommand),
     Publish(PublishCommand),
     Request(RequestCommand),
-    Ack(AckCommand),
+}
+
+pub enum SendCommandError {
+    Full,
+    Closed,
+}
+
+#[derive(Clone)]
+pub struct CommandSender {
+    tx: mpsc::Sender<Command>,
+    backpressure: Backpressure,
+}
+
+impl CommandSender {
+    pub fn new(tx: mpsc::Sender<Command>, backpressure: Backpressure) -> Self {
+        Self { tx, backpressure }
+    }
+
+    pub async fn send(&self, command: Command) -> Result<(), SendCommandError> {
+        match self.backpressure {
+            Backpressure::Block => self
+                .tx
+                .send(command)
+                .await
+                .map_err(|_| SendCommandError::Closed),
+            Backpressure::Error => self.tx.try_send(command).map_err(|err| match err {
+                TrySendError::Full(_) => SendCommandError::Full,
+                TrySendError::Closed(_) => SendCommandError::Closed,
+            }),
+        }
+    }
 }
@@ -1,45 +1,67 @@
 use std::collections::HashMap;
+use std::convert::TryInto;
 use std::net::SocketAddr;
 use std::num::NonZeroU16;
 use std::pin::Pin;
+use std::sync::Arc;
 
 use bytestring::ByteString;
 use codec::{
-    Connect, Disconnect, Packet, PacketIdAllocator, PubAck, PubAckProperties, PubAckReasonCode,
-    PubComp, PubCompProperties, PubCompReasonCode, PubRec, PubRecProperties, PubRecReasonCode,
-    PubRel, PubRelProperties, PubRelReasonCode, Publish, Qos, SubAck, Subscribe, SubscribeFilter,
-    SubscribeProperties, UnsubAck, Unsubscribe,
+    Connect, Disconnect, DisconnectReasonCode, Packet, PacketIdAllocator, PubAck, PubAckProperties,
+    PubAckReasonCode, PubComp, PubCompProperties, PubCompReasonCode, PubRec, PubRecProperties,
+    PubRecReasonCode, PubRel, PubRelProperties, PubRelReasonCode, Publish, Qos, SubAck, Subscribe,
+    SubscribeFilter, SubscribeProperties, UnsubAck, Unsubscribe,
 };
 use fnv::FnvHashMap;
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpStream;
+use parking_lot::Mutex;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{Duration, Instant, Sleep};
+use tracing::{Instrument, Span};
 
 use crate::command::{
-    AckCommand, Command, PublishCommand, RequestCommand, SubscribeCommand, UnsubscribeCommand,
+    Command, PublishCommand, RequestCommand, SubscribeCommand, UnsubscribeCommand,
 };
-use crate::Message;
+use crate::error::{Error, PublishError};
+use crate::transport::{Reader, Writer};
+use crate::{Backpressure, Message, ResubscribeMode, Transport};
 
-type Codec = codec::Codec<Box<dyn AsyncRead + Send + Unpin>, Box<dyn AsyncWrite + Send + Unpin>>;
+type Codec = codec::Codec<Reader, Writer>;
 
-enum InternalError {
-    ClientClosed,
-    ProtocolError,
+pub type PacketHook = Arc<dyn Fn(&Packet) + Send + Sync>;
+
+/// Callbacks invoked for every packet that goes over the wire.
+#[derive(Default, Clone)]
+pub struct PacketHooks {
+    pub on_packet_sent: Option<PacketHook>,
+    pub on_packet_received: Option<PacketHook>,
 }
 
-enum Request {
-    Subscribe {
-        subscribe: Subscribe,
-    },
-    Publish {
-        publish: Publish,
-        reply: oneshot::Sender<Result<()>>,
-    },
-    Request {
-        publish: Publish,
-        reply: oneshot::Sender<Result<Message>>,
-    },
+pub type Subscriptions = Arc<Mutex<HashMap<ByteString, SubscribeFilter>>>;
+
+/// Options of the connection task.
+pub struct Options {
+    pub command_channel_capacity: usize,
+    pub message_channel_capacity: usize,
+    pub message_backpressure: Backpressure,
+    pub resubscribe: ResubscribeMode,
+    pub hooks: PacketHooks,
+    pub transport: Transport,
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+struct InflightPacket {
+    packet: Packet,
+    reply: Option<oneshot::Sender<Result<(), PublishError>>>,
+    /// The pending request that fails if this publication is rejected or never acknowledged.
+    req_id: Option<u64>,
+    span: Span,
+}
+
+struct PendingRequest {
+    reply: oneshot::Sender<Result<Message, PublishError>>,
+    span: Span,
 }
 
 struct ConnectedState {
@@ -47,9 +69,24 @@ struct ConnectedState {
     packet_id_allocator: PacketIdAllocator,
     keep_alive_delay: Pin<Box<Sleep>>,
     inflight_packets: FnvHashMap<NonZeroU16, InflightPacket>,
-    uncompleted_messages: FnvHashMap<NonZeroU16, Message>,
 }
 
+impl ConnectedState {
+    fn take_packet_id(&mut self) -> Result<NonZeroU16> {
+        self.packet_id_allocator
+            .take()
+            .ok_or(Error::PacketIdsExhausted)
+    }
+
+    /// Removes an inflight packet and releases its packet identifier.
+    fn remove_inflight(&mut self, packet_id: NonZeroU16) -> Option<InflightPacket> {
+        let inflight_packet = self.inflight_packets.remove(&packet_id)?;
+        self.packet_id_allocator.release(packet_id);
+        Some(inflight_packet)
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
 enum State {
     Connecting,
     Connected(ConnectedState),
@@ -57,34 +94,62 @@ enum State {
 
 pub struct Core {
     addrs: Vec<SocketAddr>,
+    transport: Transport,
     connect: Connect,
     keep_alive: u16,
-    tx_command: mpsc::Sender<Command>,
     rx_command: mpsc::Receiver<Command>,
-    subscriptions: HashMap<ByteString, SubscribeFilter>,
+    subscriptions: Subscriptions,
+    resubscribe: ResubscribeMode,
+    hooks: PacketHooks,
     tx_msg: mpsc::Sender<Message>,
+    message_backpressure: Backpressure,
     req_id: u64,
+    requests: FnvHashMap<u64, PendingRequest>,
+
+    /// Inbound QoS2 messages that have been acknowledged with PUBREC and are waiting for PUBREL.
+    ///
+    /// They are kept across reconnections, because the server resends PUBREL when the session
+    /// is resumed.
+    uncompleted_messages: FnvHashMap<NonZeroU16, Publish>,
 }
 
 impl Core {
     pub fn run(
         addrs: Vec<SocketAddr>,
         connect: Connect,
-    ) -> (mpsc::Sender<Command>, mpsc::Receiver<Message>) {
-        let (tx_command, rx_command) = mpsc::channel(16);
-        let (tx_msg, rx_msg) = mpsc::channel(16);
+        options: Options,
+    ) -> (
+        mpsc::Sender<Command>,
+        mpsc::Receiver<Message>,
+        Subscriptions,
+    ) {
+        let (tx_command, rx_command) = mpsc::channel(options.command_channel_capacity);
+        let (tx_msg, rx_msg) = mpsc::channel(options.message_channel_capacity);
+        let subscriptions = Subscriptions::default();
         let core = Self {
             addrs,
+            transport: options.transport,
             keep_alive: connect.keep_alive,
             connect,
-            tx_command: tx_command.clone(),
             rx_command,
-            subscriptions: HashMap::new(),
+            subscriptions: subscriptions.clone(),
+            resubscribe: options.resubscribe,
+            hooks: options.hooks,
             tx_msg,
+            message_backpressure: options.message_backpressure,
             req_id: 1,
+            requests: FnvHashMap::default(),
+            uncompleted_messages: FnvHashMap::default(),
         };
         tokio::spawn(core.client_loop());
-        (tx_command, rx_msg)
+        (tx_command, rx_msg, subscriptions)
+    }
+
+    /// The maximum number of inbound QoS2 publications this client is willing to process
+    /// concurrently, as advertised by the `receive_max` property of the CONNECT packet.
+    #[inline]
+    fn receive_max(&self) -> usize {
+        self.connect.properties.receive_max.unwrap_or(u16::MAX) as usize
     }
 
     async fn client_loop(mut self) {
@@ -92,33 +157,52 @@ impl Core {
 
         loop {
             match &mut state {
-                State::Connecting => match self.do_connect().await {
-                    Ok(connected_state) => {
-                        state = State::Connected(connected_state);
-                    }
-                    Err(err) => {
-                        tracing::error!(
-                            error = %err,
-                            "failed to connect to broker",
-                        );
-                        tokio::time::sleep(Duration::from_secs(1)).await;
+                State::Connecting => {
+                    let span = tracing::info_span!(
+                        "connect",
+                        client_id = %self.connect.client_id,
+                        session_present = tracing::field::Empty,
+                    );
+                    match self.do_connect().instrument(span).await {
+                        Ok(connected_state) => {
+                            state = State::Connected(connected_state);
+                        }
+                        Err(err) => {
+                            tracing::error!(
+                                error = %err,
+                                "failed to connect to broker",
+                            );
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                        }
                     }
-                },
+                }
                 State::Connected(connected_state) => {
                     if let Err(err) = self.do_connected(connected_state).await {
-                        tracing::error!(
-                            error = %err,
-                            "connection error",
-                        );
-
-                        for (_, InflightPacket { reply, .. }) in
-                            std::mem::take(&mut connected_state.inflight_packets)
+                        for (
+                            _,
+                            InflightPacket {
+                                reply,
+                                req_id,
+                                span,
+                                ..
+                            },
+                        ) in std::mem::take(&mut connected_state.inflight_packets)
                         {
+                            tracing::debug!(parent: &span, "connection closed");
                             if let Some(reply) = reply {
-                                //reply.send(Err(err.clone())).ok();
+                                reply.send(Err(PublishError::ConnectionClosed)).ok();
                             }
+                            self.fail_request(req_id, PublishError::ConnectionClosed);
+                        }
+
+                        if let Error::ClientClosed = err {
+                            return;
                         }
 
+                        tracing::error!(
+                            error = %err,
+                            "connection error",
+                        );
                         state = State::Connecting;
                     }
                 }
@@ -127,44 +211,64 @@ impl Core {
     }
 
     async fn do_connect(&mut self) -> Result<ConnectedState> {
-        let stream = TcpStream::connect(&*self.addrs).await?;
-        let (reader, writer) = stream.into_split();
+        let (reader, writer) = self.transport.connect(&self.addrs).await?;
         let mut connected_state = ConnectedState {
-            codec: Codec::new(Box::new(reader), Box::new(writer)),
+            codec: Codec::new(reader, writer),
             packet_id_allocator: PacketIdAllocator::default(),
             keep_alive_delay: Box::pin(tokio::time::sleep(Duration::from_secs(
                 self.keep_alive as u64,
             ))),
             inflight_packets: FnvHashMap::default(),
-            uncompleted_messages: FnvHashMap::default(),
         };
 
         // connect
-        connected_state
-            .codec
-            .encode(&Packet::Connect(self.connect.clone()))
-            .await?;
-
-        let packet = receive_packet(&mut connected_state.codec)
+        send_packet(
+            &mut connected_state.codec,
+            &self.hooks,
+            &Packet::Connect(self.connect.clone()),
+        )
+        .await?;
+
+        let packet = receive_packet(&mut connected_state.codec, &self.hooks)
             .await?
             .ok_or(Error::DisconnectByServer(None))?;
         let conn_ack = match packet {
             Packet::ConnAck(conn_ack) => conn_ack,
-            _ => anyhow::bail!("protocol error"),
+            _ => return Err(Error::ProtocolError),
         };
 
         if !conn_ack.reason_code.is_success() {
             return Err(Error::Handshake(conn_ack.reason_code));
         }
 
+        Span::current().record("session_present", &conn_ack.session_present);
+        tracing::info!("connected");
+
         if let Some(server_keep_alive) = conn_ack.properties.server_keep_alive {
             self.keep_alive = server_keep_alive;
         }
 
+        // PINGREQ is sent when idle, so the server is gone if nothing is received in time
+        if self.keep_alive > 0 {
+            connected_state
+                .codec
+                .set_read_timeout(Some(Duration::from_millis(self.keep_alive as u64 * 1500)));
+        }
+
+        if !conn_ack.session_present {
+            // the server has discarded the session, so no PUBREL will follow
+            self.uncompleted_messages.clear();
+        }
+
         // re-subscribe
-        if !conn_ack.session_present && !self.subscriptions.is_empty() {
-            let packet_id = connected_state.packet_id_allocator.take();
-            let filters = self.subscriptions.values().cloned().collect();
+        let resubscribe = match self.resubscribe {
+            ResubscribeMode::Always => true,
+            ResubscribeMode::Never => false,
+            ResubscribeMode::NoSession => !conn_ack.session_present,
+        };
+        let filters: Vec<_> = self.subscriptions.lock().values().cloned().collect();
+        if resubscribe && !filters.is_empty() {
+            let packet_id = connected_state.take_packet_id()?;
 
             let packet = Packet::Subscribe(Subscribe {
                 packet_id,
@@ -172,12 +276,15 @@ impl Core {
                 filters,
             });
 
-            send_packet(&mut connected_state.codec, &packet).await?;
+            let span = tracing::debug_span!("resubscribe", packet_id = packet_id.get());
+            send_packet(&mut connected_state.codec, &self.hooks, &packet).await?;
             connected_state.inflight_packets.insert(
                 packet_id,
                 InflightPacket {
                     packet,
                     reply: None,
+                    req_id: None,
+                    span,
                 },
             );
         }
@@ -187,32 +294,29 @@ impl Core {
 
     async fn do_connected(&mut self, connected_state: &mut ConnectedState) -> Result<()> {
         tokio::select! {
-            res = self.rx_command.recv() => {
+            // the commands wait while all the packet identifiers are inflight
+            res = self.rx_command.recv(), if !connected_state.packet_id_allocator.is_exhausted() => {
                 match res {
                     Some(command) => self.handle_command(connected_state, command).await,
-                    None => Err(InternalError::ClientClosed),
+                    None => Err(Error::ClientClosed),
                 }
             }
             _ = &mut connected_state.keep_alive_delay => {
-                send_packet(&mut connected_state.codec, &Packet::PingReq).await?;
+                send_packet(&mut connected_state.codec, &self.hooks, &Packet::PingReq).await?;
+                connected_state.keep_alive_delay
+                    .as_mut()
+                    .reset(Instant::now() + Duration::from_secs(self.keep_alive as u64));
                 Ok(())
             },
-            res = receive_packet(&mut connected_state.codec) => {
-                match res {
-                    Ok(Some(packet)) => {
+            res = receive_packet(&mut connected_state.codec, &self.hooks) => {
+                match res? {
+                    Some(packet) => {
                         connected_state.keep_alive_delay
                             .as_mut()
                             .reset(Instant::now() + Duration::from_secs(self.keep_alive as u64));
                         self.handle_packet(connected_state, packet).await
                     }
-                    Ok(None) => Err(Error::DisconnectByServer(None)),
-                    Err(err) => {
-                        tracing::error!(
-                            error = %err,
-                            "connection error",
-                        );
-                        Err(err)
-                    },
+                    None => Err(Error::DisconnectByServer(None)),
                 }
             }
         }
@@ -238,7 +342,6 @@ impl Core {
             Command::Request(request) => {
                 self.handle_request_command(connected_state, request).await
             }
-            Command::Ack(ack) => self.handle_ack_command(connected_state, ack).await,
         }
     }
 
@@ -247,21 +350,28 @@ impl Core {
         connected_state: &mut ConnectedState,
         subscribe: SubscribeCommand,
     ) -> Result<()> {
-        let packet_id = connected_state.packet_id_allocator.take();
-        for filter in subscribe.filters.iter().cloned() {
-            self.subscriptions.insert(filter.path.clone(), filter);
+        let packet_id = connected_state.take_packet_id()?;
+        {
+            let mut subscriptions = self.subscriptions.lock();
+            for filter in subscribe.filters.iter().cloned() {
+                subscriptions.insert(filter.path.clone(), filter);
+            }
         }
         let packet = Packet::Subscribe(Subscribe {
             packet_id,
             properties: SubscribeProperties::default(),
             filters: subscribe.filters,
         });
-        send_packet(&mut connected_state.codec, &packet).await?;
+        subscribe.span.record("packet_id", &packet_id.get());
+        send_packet(&mut connected_state.codec, &self.hooks, &packet).await?;
+        tracing::debug!(parent: &subscribe.span, "subscribe sent");
         connected_state.inflight_packets.insert(
             packet_id,
             InflightPacket {
                 packet,
                 reply: None,
+                req_id: None,
+                span: subscribe.span,
             },
         );
         Ok(())
@@ -272,21 +382,28 @@ impl Core {
         connected_state: &mut ConnectedState,
         unsubscribe: UnsubscribeCommand,
     ) -> Result<()> {
-        let packet_id = connected_state.packet_id_allocator.take();
-        for path in &unsubscribe.filters {
-            self.subscriptions.remove(path);
+        let packet_id = connected_state.take_packet_id()?;
+        {
+            let mut subscriptions = self.subscriptions.lock();
+            for path in &unsubscribe.filters {
+                subscriptions.remove(path);
+            }
         }
         let packet = Packet::Unsubscribe(Unsubscribe {
             packet_id,
             filters: unsubscribe.filters,
             properties: Default::default(),
         });
-        connected_state.codec.encode(&packet).await?;
+        unsubscribe.span.record("packet_id", &packet_id.get());
+        send_packet(&mut connected_state.codec, &self.hooks, &packet).await?;
+        tracing::debug!(parent: &unsubscribe.span, "unsubscribe sent");
         connected_state.inflight_packets.insert(
             packet_id,
             InflightPacket {
                 packet,
                 reply: None,
+                req_id: None,
+                span: unsubscribe.span,
             },
         );
         Ok(())
@@ -295,25 +412,33 @@ impl Core {
     async fn handle_publish_command(
         &mut self,
         connected_state: &mut ConnectedState,
-        publish: PublishCommand,
+        mut publish: PublishCommand,
     ) -> Result<()> {
         match publish.publish.qos {
             Qos::AtMostOnce => {
-                connected_state
-                    .codec
-                    .encode(&Packet::Publish(publish.publish))
-                    .await?;
+                send_packet(
+                    &mut connected_state.codec,
+                    &self.hooks,
+                    &Packet::Publish(publish.publish),
+                )
+                .await?;
+                tracing::debug!(parent: &publish.span, "publish sent");
                 Ok(())
             }
             Qos::AtLeastOnce | Qos::ExactlyOnce => {
-                let packet_id = connected_state.packet_id_allocator.take();
+                let packet_id = connected_state.take_packet_id()?;
+                publish.publish.packet_id = Some(packet_id);
+                publish.span.record("packet_id", &packet_id.get());
                 let packet = Packet::Publish(publish.publish);
-                send_packet(&mut connected_state.codec, &packet).await?;
+                send_packet(&mut connected_state.codec, &self.hooks, &packet).await?;
+                tracing::debug!(parent: &publish.span, "publish sent");
                 connected_state.inflight_packets.insert(
                     packet_id,
                     InflightPacket {
                         packet,
                         reply: publish.reply,
+                        req_id: None,
+                        span: publish.span,
                     },
                 );
                 Ok(())
@@ -326,30 +451,48 @@ impl Core {
         connected_state: &mut ConnectedState,
         mut request: RequestCommand,
     ) -> Result<()> {
-        request.publish.properties.correlation_data = {
-            let req_id = self.req_id;
-            self.req_id += 1;
-            let correlation_data = req_id.to_le_bytes();
-            Some(correlation_data.to_vec().into())
-        };
+        // the callers that stopped waiting, e.g. after a timeout, will never read the response
+        self.requests
+            .retain(|_, request| !request.reply.is_closed());
+
+        let req_id = self.req_id;
+        self.req_id += 1;
+        request.publish.properties.correlation_data = Some(req_id.to_le_bytes().to_vec().into());
+        request.span.record("req_id", &req_id);
+        let span = request.span.clone();
+        self.requests.insert(
+            req_id,
+            PendingRequest {
+                reply: request.reply,
+                span: request.span,
+            },
+        );
 
         match request.publish.qos {
             Qos::AtMostOnce => {
-                connected_state
-                    .codec
-                    .encode(&Packet::Publish(request.publish))
-                    .await?;
+                send_packet(
+                    &mut connected_state.codec,
+                    &self.hooks,
+                    &Packet::Publish(request.publish),
+                )
+                .await?;
+                tracing::debug!(parent: &span, "request sent");
                 Ok(())
             }
             Qos::AtLeastOnce | Qos::ExactlyOnce => {
-                let packet_id = connected_state.packet_id_allocator.take();
+                let packet_id = connected_state.take_packet_id()?;
+                request.publish.packet_id = Some(packet_id);
+                span.record("packet_id", &packet_id.get());
                 let packet = Packet::Publish(request.publish);
-                send_packet(&mut connected_state.codec, &packet).await?;
+                send_packet(&mut connected_state.codec, &self.hooks, &packet).await?;
+                tracing::debug!(parent: &span, "request sent");
                 connected_state.inflight_packets.insert(
                     packet_id,
                     InflightPacket {
                         packet,
                         reply: None,
+                        req_id: Some(req_id),
+                        span,
                     },
                 );
                 Ok(())
@@ -357,45 +500,11 @@ impl Core {
         }
     }
 
-    async fn handle_ack_command(
-        &mut self,
-        connected_state: &mut ConnectedState,
-        ack: AckCommand,
-    ) -> Result<()> {
-        match ack.qos {
-            Qos::AtMostOnce => unreachable!(),
-            Qos::AtLeastOnce => {
-                send_packet(
-                    &mut connected_state.codec,
-                    &Packet::PubAck(PubAck {
-                        packet_id: ack.packet_id,
-                        reason_code: PubAckReasonCode::Success,
-                        properties: PubAckProperties::default(),
-                    }),
-                )
-                .await?;
-                Ok(())
-            }
-            Qos::ExactlyOnce => {
-                send_packet(
-                    &mut connected_state.codec,
-                    &Packet::PubComp(PubComp {
-                        packet_id: ack.packet_id,
-                        reason_code: PubCompReasonCode::Success,
-                        properties: PubCompProperties::default(),
-                    }),
-                )
-                .await?;
-                Ok(())
-            }
-        }
-    }
-
     async fn handle_packet(
         &mut self,
         connected_state: &mut ConnectedState,
         packet: Packet,
-    ) -> Result<(), InternalError> {
+    ) -> Result<()> {
         match packet {
             Packet::PingResp => Ok(()),
             Packet::Publish(publish) => self.handle_publish(connected_state, publish).await,
@@ -406,7 +515,52 @@ impl Core {
             Packet::SubAck(sub_ack) => self.handle_sub_ack(connected_state, sub_ack).await,
             Packet::UnsubAck(ubsub_ack) => self.handle_unsub_ack(connected_state, ubsub_ack).await,
             Packet::Disconnect(disconnect) => self.handle_disconnect(disconnect).await,
-            _ => Err(InternalError::ProtocolError),
+            _ => Err(Error::ProtocolError),
+        }
+    }
+
+    /// Removes a pending request whose publication failed, so it doesn't wait for a response
+    /// that will never come.
+    fn fail_request(&mut self, req_id: Option<u64>, err: PublishError) {
+        if let Some(PendingRequest { reply, span }) =
+            req_id.and_then(|req_id| self.requests.remove(&req_id))
+        {
+            tracing::debug!(parent: &span, error = %err, "request failed");
+            reply.send(Err(err)).ok();
+        }
+    }
+
+    /// Hands an incoming message over to the pending request it answers, or to the message
+    /// stream otherwise.
+    ///
+    /// Returns `false` if the message was rejected because the message stream is full and the
+    /// message backpressure is [`Backpressure::Error`].
+    async fn deliver(&mut self, publish: Publish, backpressure: Backpressure) -> bool {
+        let req_id = publish
+            .properties
+            .correlation_data
+            .as_deref()
+            .and_then(|data| data.try_into().ok())
+            .map(u64::from_le_bytes);
+
+        if let Some(PendingRequest { reply, span }) =
+            req_id.and_then(|req_id| self.requests.remove(&req_id))
+        {
+            tracing::debug!(parent: &span, topic = %publish.topic, "response received");
+            reply.send(Ok(Message::new(publish))).ok();
+            return true;
+        }
+
+        // the message stream may have been dropped by a publish-only client
+        match backpressure {
+            Backpressure::Block => {
+                self.tx_msg.send(Message::new(publish)).await.ok();
+                true
+            }
+            Backpressure::Error => !matches!(
+                self.tx_msg.try_send(Message::new(publish)),
+                Err(TrySendError::Full(_))
+            ),
         }
     }
 
@@ -414,30 +568,27 @@ impl Core {
         &mut self,
         connected_state: &mut ConnectedState,
         publish: Publish,
-    ) -> Result<(), InternalError> {
+    ) -> Result<()> {
         match publish.qos {
             Qos::AtMostOnce => {
-                let msg = Message::new(None, publish);
-                self.tx_msg
-                    .send(msg)
-                    .await
-                    .map_err(|_| InternalError::ClientClosed)?;
+                if !self.deliver(publish, self.message_backpressure).await {
+                    tracing::warn!("message stream is full, message dropped");
+                }
                 Ok(())
             }
             Qos::AtLeastOnce => {
-                let packet_id = publish
-                    .packet_id
-                    .ok_or_else(|| InternalError::protocolError)?;
-                let msg = Message::new(Some(self.tx_command.clone()), publish);
-                self.tx_msg
-                    .send(msg)
-                    .await
-                    .map_err(|_| InternalError::ClientClosed)?;
+                let packet_id = publish.packet_id.ok_or(Error::ProtocolError)?;
+                let reason_code = if self.deliver(publish, self.message_backpressure).await {
+                    PubAckReasonCode::Success
+                } else {
+                    PubAckReasonCode::QuotaExceeded
+                };
                 send_packet(
                     &mut connected_state.codec,
+                    &self.hooks,
                     &Packet::PubAck(PubAck {
                         packet_id,
-                        reason_code: PubAckReasonCode::Success,
+                        reason_code,
                         properties: PubAckProperties::default(),
                     }),
                 )
@@ -445,37 +596,44 @@ impl Core {
                 Ok(())
             }
             Qos::ExactlyOnce => {
-                let packet_id = publish
-                    .packet_id
-                    .ok_or_else(|| InternalError::ProtocolError)?;
-                let msg = Message::new(Some(self.tx_command.clone()), publish);
-
-                if connected_state
-                    .uncompleted_messages
-                    .contains_key(&packet_id)
-                {
-                    send_packet(
-                        &mut connected_state.codec,
-                        &Packet::PubRec(PubRec {
-                            packet_id,
-                            reason_code: PubRecReasonCode::PacketIdentifierInUse,
-                            properties: PubRecProperties::default(),
-                        }),
-                    )
-                    .await?;
-                } else {
-                    connected_state.uncompleted_messages.insert(packet_id, msg);
-                    send_packet(
-                        &mut connected_state.codec,
-                        &Packet::PubRec(PubRec {
-                            packet_id,
-                            reason_code: PubRecReasonCode::Success,
-                            properties: PubRecProperties::default(),
-                        }),
-                    )
-                    .await?;
+                let packet_id = publish.packet_id.ok_or(Error::ProtocolError)?;
+
+                let mut reason_code = PubRecReasonCode::Success;
+
+                // A retransmission of a publication that is still waiting for PUBREL must be
+                // acknowledged again, but the message is only delivered once.
+                if !self.uncompleted_messages.contains_key(&packet_id) {
+                    if self.uncompleted_messages.len() >= self.receive_max() {
+                        send_packet(
+                            &mut connected_state.codec,
+                            &self.hooks,
+                            &Packet::Disconnect(Disconnect::new(
+                                DisconnectReasonCode::ReceiveMaximumExceeded,
+                            )),
+                        )
+                        .await?;
+                        return Err(Error::ReceiveMaximumExceeded);
+                    }
+
+                    if self.message_backpressure == Backpressure::Error
+                        && self.tx_msg.capacity() == 0
+                    {
+                        reason_code = PubRecReasonCode::QuotaExceeded;
+                    } else {
+                        self.uncompleted_messages.insert(packet_id, publish);
+                    }
                 }
 
+                send_packet(
+                    &mut connected_state.codec,
+                    &self.hooks,
+                    &Packet::PubRec(PubRec {
+                        packet_id,
+                        reason_code,
+                        properties: PubRecProperties::default(),
+                    }),
+                )
+                .await?;
                 Ok(())
             }
         }
@@ -489,19 +647,24 @@ impl Core {
         if let Some(InflightPacket {
             packet: Packet::Publish(Publish { .. }),
             reply,
-        }) = connected_state.inflight_packets.remove(&pub_ack.packet_id)
+            req_id,
+            span,
+        }) = connected_state.remove_inflight(pub_ack.packet_id)
         {
+            tracing::debug!(parent: &span, reason_code = ?pub_ack.reason_code, "puback received");
             if pub_ack.reason_code.is_success() {
-                reply.unwrap().send(Ok(())).ok();
+                if let Some(reply) = reply {
+                    reply.send(Ok(())).ok();
+                }
             } else {
-                reply
-                    .unwrap()
-                    .send(Err(Error::PubAck(pub_ack.reason_code)))
-                    .ok();
+                if let Some(reply) = reply {
+                    reply.send(Err(pub_ack.reason_code.into())).ok();
+                }
+                self.fail_request(req_id, pub_ack.reason_code.into());
             }
             Ok(())
         } else {
-            Err(InternalError::ProtocolError)
+            Err(Error::ProtocolError)
         }
     }
 
@@ -512,12 +675,15 @@ impl Core {
     ) -> Result<()> {
         if let Some(InflightPacket {
             packet: Packet::Publish(Publish { .. }),
+            span,
             ..
         }) = connected_state.inflight_packets.get(&pub_rec.packet_id)
         {
+            tracing::debug!(parent: span, reason_code = ?pub_rec.reason_code, "pubrec received");
             if pub_rec.reason_code.is_success() {
                 send_packet(
                     &mut connected_state.codec,
+                    &self.hooks,
                     &Packet::PubRel(PubRel {
                         packet_id: pub_rec.packet_id,
                         reason_code: PubRelReasonCode::Success,
@@ -526,18 +692,17 @@ impl Core {
                 )
                 .await?;
             } else {
-                let InflightPacket { reply, .. } = connected_state
-                    .inflight_packets
-                    .remove(&pub_rec.packet_id)
-                    .unwrap();
-                reply
-                    .unwrap()
-                    .send(Err(Error::PubRec(pub_rec.reason_code)))
-                    .ok();
+                let InflightPacket { reply, req_id, .. } =
+                    connected_state.remove_inflight(pub_rec.packet_id).unwrap();
+                if let Some(reply) = reply {
+                    reply.send(Err(pub_rec.reason_code.into())).ok();
+                }
+                self.fail_request(req_id, pub_rec.reason_code.into());
             }
         } else {
             send_packet(
                 &mut connected_state.codec,
+                &self.hooks,
                 &Packet::PubRel(PubRel {
                     packet_id: pub_rec.packet_id,
                     reason_code: PubRelReasonCode::PacketIdentifierNotFound,
@@ -554,20 +719,28 @@ impl Core {
         &mut self,
         connected_state: &mut ConnectedState,
         pub_comp: PubComp,
-    ) -> Result<(), InternalError> {
+    ) -> Result<()> {
         if let Some(InflightPacket {
             packet: Packet::Publish(Publish { .. }),
             reply,
-        }) = connected_state.inflight_packets.remove(&pub_comp.packet_id)
+            req_id,
+            span,
+        }) = connected_state.remove_inflight(pub_comp.packet_id)
         {
+            tracing::debug!(parent: &span, reason_code = ?pub_comp.reason_code, "pubcomp received");
             if pub_comp.reason_code.is_success() {
-                reply.unwrap().send(Ok(())).ok();
+                if let Some(reply) = reply {
+                    reply.send(Ok(())).ok();
+                }
             } else {
-                reply.unwrap().send(Err(InternalError::ProtocolError)).ok();
+                if let Some(reply) = reply {
+                    reply.send(Err(PublishError::UnspecifiedError)).ok();
+                }
+                self.fail_request(req_id, PublishError::UnspecifiedError);
             }
             Ok(())
         } else {
-            Err(InternalError::ProtocolError)
+            Err(Error::ProtocolError)
         }
     }
 
@@ -575,19 +748,30 @@ impl Core {
         &mut self,
         connected_state: &mut ConnectedState,
         pub_rel: PubRel,
-    ) -> Result<(), InternalError> {
-        if let Some(msg) = connected_state
-            .uncompleted_messages
-            .remove(&pub_rel.packet_id)
-        {
-            self.tx_msg
-                .send(msg)
-                .await
-                .map_err(|_| InternalError::Closed)?;
-            Ok(())
-        } else {
-            Err(InternalError::ProtocolError)
-        }
+    ) -> Result<()> {
+        let reason_code = match self.uncompleted_messages.remove(&pub_rel.packet_id) {
+            Some(publish) => {
+                // the message has already been acknowledged with PUBREC, so it can't be rejected
+                self.deliver(publish, Backpressure::Block).await;
+                PubCompReasonCode::Success
+            }
+            None => {
+                // The PUBCOMP for this packet may have been lost, and the server resends PUBREL.
+                PubCompReasonCode::PacketIdentifierNotFound
+            }
+        };
+
+        send_packet(
+            &mut connected_state.codec,
+            &self.hooks,
+            &Packet::PubComp(PubComp {
+                packet_id: pub_rel.packet_id,
+                reason_code,
+                properties: PubCompProperties::default(),
+            }),
+        )
+        .await?;
+        Ok(())
     }
 
     async fn handle_sub_ack(
@@ -597,22 +781,25 @@ impl Core {
     ) -> Result<()> {
         if let Some(InflightPacket {
             packet: Packet::Subscribe(subscribe),
+            span,
             ..
-        }) = connected_state.inflight_packets.remove(&sub_ack.packet_id)
+        }) = connected_state.remove_inflight(sub_ack.packet_id)
         {
             if sub_ack.reason_codes.len() != subscribe.filters.len() {
-                return Err(InternalError::ProtocolError);
+                return Err(Error::ProtocolError);
             }
             for (reason_code, filter) in sub_ack.reason_codes.into_iter().zip(subscribe.filters) {
                 if reason_code.is_success() {
                     tracing::debug!(
+                        parent: &span,
                         path = %filter.path,
                         qos = ?reason_code.qos(),
                         "subscribe success"
                     );
                 } else {
-                    self.subscriptions.remove(&*filter.path);
+                    self.subscriptions.lock().remove(&*filter.path);
                     tracing::debug!(
+                        parent: &span,
                         path = %filter.path,
                         reason_code = ?reason_code,
                         "subscribe failed"
@@ -621,7 +808,7 @@ impl Core {
             }
             Ok(())
         } else {
-            Err(InternalError::ProtocolError)
+            Err(Error::ProtocolError)
         }
     }
 
@@ -632,23 +819,24 @@ impl Core {
     ) -> Result<()> {
         if let Some(InflightPacket {
             packet: Packet::Unsubscribe(unsubscribe),
+            span,
             ..
-        }) = connected_state
-            .inflight_packets
-            .remove(&unsub_ack.packet_id)
+        }) = connected_state.remove_inflight(unsub_ack.packet_id)
         {
             if unsub_ack.reason_codes.len() != unsubscribe.filters.len() {
-                return Err(InternalError::ProtocolError);
+                return Err(Error::ProtocolError);
             }
             for (reason_code, path) in unsub_ack.reason_codes.into_iter().zip(unsubscribe.filters) {
                 if reason_code.is_success() {
                     tracing::debug!(
+                        parent: &span,
                         path = %path,
                         "unsubscribe success"
                     );
                 } else {
-                    self.subscriptions.remove(&path);
+                    self.subscriptions.lock().remove(&path);
                     tracing::debug!(
+                        parent: &span,
                         path = %path,
                         "unsubscribe failed"
                     );
@@ -656,7 +844,7 @@ impl Core {
             }
             Ok(())
         } else {
-            Err(InternalError::ProtocolError)
+            Err(Error::ProtocolError)
         }
     }
 
@@ -665,16 +853,22 @@ impl Core {
     }
 }
 
-async fn send_packet(codec: &mut Codec, packet: &Packet) -> Result<()> {
-    tracing::debug!(packet = ?packet, "send packet");
+async fn send_packet(codec: &mut Codec, hooks: &PacketHooks, packet: &Packet) -> Result<()> {
+    tracing::debug!(packet = %packet.summary(), "send packet");
     codec.encode(packet).await?;
+    if let Some(on_packet_sent) = &hooks.on_packet_sent {
+        on_packet_sent(packet);
+    }
     Ok(())
 }
 
-async fn receive_packet(codec: &mut Codec) -> Result<Option<Packet>> {
+async fn receive_packet(codec: &mut Codec, hooks: &PacketHooks) -> Result<Option<Packet>> {
     match codec.decode().await? {
         Some((packet, _)) => {
-            tracing::debug!(packet = ?packet, "received packet");
+            tracing::debug!(packet = %packet.summary(), "received packet");
+            if let Some(on_packet_received) = &hooks.on_packet_received {
+                on_packet_received(&packet);
+            }
             Ok(Some(packet))
         }
         None => Ok(None),
@@ -1,38 +1,151 @@
-use codec::PubAckReasonCode;
+use codec::{
+    ConnectReasonCode, DecodeError, DisconnectReasonCode, EncodeError, PubAckReasonCode,
+    PubRecReasonCode,
+};
 use thiserror::Error;
 
+use crate::command::SendCommandError;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("handshake: {0:?}")]
+    Handshake(ConnectReasonCode),
+
+    #[error("disconnect by server: {0:?}")]
+    DisconnectByServer(Option<DisconnectReasonCode>),
+
+    #[error("protocol error")]
+    ProtocolError,
+
+    #[error("receive maximum exceeded")]
+    ReceiveMaximumExceeded,
+
+    #[error("client closed")]
+    ClientClosed,
+
+    #[error("too many pending commands")]
+    Busy,
+
+    #[error("packet identifiers exhausted")]
+    PacketIdsExhausted,
+
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("decode packet: {0}")]
+    DecodePacket(#[from] DecodeError),
+
+    #[error("encode packet: {0}")]
+    EncodePacket(#[from] EncodeError),
+
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "websocket")]
+    #[error("websocket: {0}")]
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+
+    #[cfg(feature = "quic")]
+    #[error("quic connect: {0}")]
+    QuicConnect(#[from] quinn::ConnectError),
+
+    #[cfg(feature = "quic")]
+    #[error("quic connection: {0}")]
+    QuicConnection(#[from] quinn::ConnectionError),
+}
+
 #[derive(Debug, Error)]
 pub enum PublishError {
-    #[error("NoMatchingSubscribers")]
+    #[error("no matching subscribers")]
     NoMatchingSubscribers,
 
-    #[error("NoMatchingSubscribers")]
+    #[error("unspecified error")]
     UnspecifiedError,
 
-    #[error("NoMatchingSubscribers")]
+    #[error("implementation specific error")]
     ImplementationSpecificError,
 
-    #[error("NoMatchingSubscribers")]
+    #[error("not authorized")]
     NotAuthorized,
 
-    #[error("NoMatchingSubscribers")]
+    #[error("topic name invalid")]
     TopicNameInvalid,
 
-    #[error("NoMatchingSubscribers")]
+    #[error("packet identifier in use")]
     PacketIdentifierInUse,
 
-    #[error("NoMatchingSubscribers")]
+    #[error("quota exceeded")]
     QuotaExceeded,
 
-    #[error("NoMatchingSubscribers")]
+    #[error("payload format invalid")]
     PayloadFormatInvalid,
 
     #[error("connection closed")]
     ConnectionClosed,
+
+    #[error("too many pending commands")]
+    Busy,
 }
 
-#[derive(Debug, Error)]
-pub enum AckError {
-    #[error("connection closed")]
-    ConnectionClosed,
+impl From<SendCommandError> for Error {
+    fn from(err: SendCommandError) -> Self {
+        match err {
+            SendCommandError::Full => Error::Busy,
+            SendCommandError::Closed => Error::ClientClosed,
+        }
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(err))
+    }
+}
+
+impl From<SendCommandError> for PublishError {
+    fn from(err: SendCommandError) -> Self {
+        match err {
+            SendCommandError::Full => PublishError::Busy,
+            SendCommandError::Closed => PublishError::ConnectionClosed,
+        }
+    }
+}
+
+impl From<PubAckReasonCode> for PublishError {
+    fn from(reason_code: PubAckReasonCode) -> Self {
+        match reason_code {
+            PubAckReasonCode::NoMatchingSubscribers => PublishError::NoMatchingSubscribers,
+            PubAckReasonCode::ImplementationSpecificError => {
+                PublishError::ImplementationSpecificError
+            }
+            PubAckReasonCode::NotAuthorized => PublishError::NotAuthorized,
+            PubAckReasonCode::TopicNameInvalid => PublishError::TopicNameInvalid,
+            PubAckReasonCode::PacketIdentifierInUse => PublishError::PacketIdentifierInUse,
+            PubAckReasonCode::QuotaExceeded => PublishError::QuotaExceeded,
+            PubAckReasonCode::PayloadFormatInvalid => PublishError::PayloadFormatInvalid,
+            PubAckReasonCode::Success | PubAckReasonCode::UnspecifiedError => {
+                PublishError::UnspecifiedError
+            }
+        }
+    }
+}
+
+impl From<PubRecReasonCode> for PublishError {
+    fn from(reason_code: PubRecReasonCode) -> Self {
+        match reason_code {
+            PubRecReasonCode::NoMatchingSubscribers => PublishError::NoMatchingSubscribers,
+            PubRecReasonCode::ImplementationSpecificError => {
+                PublishError::ImplementationSpecificError
+            }
+            PubRecReasonCode::NotAuthorized => PublishError::NotAuthorized,
+            PubRecReasonCode::TopicNameInvalid => PublishError::TopicNameInvalid,
+            PubRecReasonCode::PacketIdentifierInUse => PublishError::PacketIdentifierInUse,
+            PubRecReasonCode::QuotaExceeded => PublishError::QuotaExceeded,
+            PubRecReasonCode::PayloadFormatInvalid => PublishError::PayloadFormatInvalid,
+            PubRecReasonCode::Success | PubRecReasonCode::UnspecifiedError => {
+                PublishError::UnspecifiedError
+            }
+        }
+    }
 }
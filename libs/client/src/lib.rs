@@ -5,12 +5,27 @@ mod error;
 mod message;
 mod publish;
 mod subscribe;
+#[cfg(feature = "tls")]
+mod tls;
+mod transport;
 mod unsubscribe;
+#[cfg(feature = "websocket")]
+mod websocket;
 
-pub use client::{Client, ClientBuilder};
-pub use codec::{ConnectReasonCode, DisconnectReasonCode, Qos, RetainHandling};
-pub use error::AckError;
+pub use client::{Backpressure, Client, ClientBuilder, ResubscribeMode};
+pub use codec::{
+    ConnectReasonCode, DisconnectReasonCode, Packet, ProtocolLevel, Qos, RetainHandling,
+    SubscribeFilter,
+};
+pub use error::{Error, PublishError};
 pub use message::Message;
 pub use publish::PublishBuilder;
 pub use subscribe::{FilterBuilder, SubscribeBuilder};
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+#[cfg(feature = "quic")]
+pub use transport::QuicConfig;
+pub use transport::Transport;
 pub use unsubscribe::UnsubscribeBuilder;
+#[cfg(feature = "websocket")]
+pub use websocket::WebSocketConfig;
@@ -1,16 +1,9 @@
-use std::num::NonZeroU16;
-
 use bytes::Bytes;
 use bytestring::ByteString;
 use codec::{Publish, PublishProperties, Qos};
-use tokio::sync::{mpsc, oneshot};
-
-use crate::command::{AckCommand, Command};
-use crate::AckError;
 
+#[derive(Debug, Clone)]
 pub struct Message {
-    tx_command: Option<mpsc::Sender<Command>>,
-    packet_id: Option<NonZeroU16>,
     topic: ByteString,
     qos: Qos,
     payload: Bytes,
@@ -19,10 +12,8 @@ pub struct Message {
 }
 
 impl Message {
-    pub(crate) fn new(tx_command: Option<mpsc::Sender<Command>>, publish: Publish) -> Self {
+    pub(crate) fn new(publish: Publish) -> Self {
         Self {
-            tx_command,
-            packet_id: publish.packet_id,
             topic: publish.topic,
             qos: publish.qos,
             payload: publish.payload,
@@ -60,25 +51,43 @@ impl Message {
     pub fn content_type(&self) -> Option<&str> {
         self.properties.content_type.as_deref()
     }
-}
 
-impl Message {
-    pub async fn ack(self) -> Result<(), AckError> {
-        match self.qos {
-            Qos::AtMostOnce => Ok(()),
-            Qos::AtLeastOnce | Qos::ExactlyOnce => {
-                let (tx_reply, rx_reply) = oneshot::channel();
-                self.tx_command
-                    .unwrap()
-                    .send(Command::Ack(AckCommand {
-                        packet_id: self.packet_id.unwrap(),
-                        qos: Qos::AtMostOnce,
-                        reply: tx_reply,
-                    }))
-                    .await
-                    .map_err(|_| InternalError::Closed)?;
-                Ok(())
-            }
-        }
+    /// Returns `true` if the publisher indicated that the payload is UTF-8 encoded.
+    #[inline]
+    pub fn is_utf8_payload(&self) -> bool {
+        self.properties.payload_format_indicator.unwrap_or_default()
+    }
+
+    /// Returns the remaining lifetime of the message in seconds.
+    #[inline]
+    pub fn expiry_interval(&self) -> Option<u32> {
+        self.properties.message_expiry_interval
+    }
+
+    #[inline]
+    pub fn response_topic(&self) -> Option<&str> {
+        self.properties.response_topic.as_deref()
+    }
+
+    #[inline]
+    pub fn correlation_data(&self) -> Option<&[u8]> {
+        self.properties.correlation_data.as_deref()
+    }
+
+    #[inline]
+    pub fn user_properties(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.properties
+            .user_properties
+            .iter()
+            .map(|(name, value)| (&**name, &**value))
+    }
+
+    /// Returns the identifiers of the subscriptions that matched this message.
+    #[inline]
+    pub fn subscription_identifiers(&self) -> impl Iterator<Item = usize> + '_ {
+        self.properties
+            .subscription_identifiers
+            .iter()
+            .map(|id| id.get())
     }
 }
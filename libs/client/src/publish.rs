@@ -1,18 +1,20 @@
 use bytes::Bytes;
 use bytestring::ByteString;
 use codec::{Publish, PublishProperties, Qos};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::oneshot;
+use tracing::Instrument;
 
-use crate::command::{Command, PublishCommand, RequestCommand};
+use crate::command::{Command, CommandSender, PublishCommand, RequestCommand};
+use crate::error::PublishError;
 use crate::Message;
 
 pub struct PublishBuilder {
-    tx_command: mpsc::Sender<Command>,
+    tx_command: CommandSender,
     publish: Publish,
 }
 
 impl PublishBuilder {
-    pub(crate) fn new(tx_command: mpsc::Sender<Command>, topic: ByteString) -> Self {
+    pub(crate) fn new(tx_command: CommandSender, topic: ByteString) -> Self {
         Self {
             tx_command,
             publish: Publish {
@@ -70,41 +72,70 @@ impl PublishBuilder {
         self
     }
 
-    pub async fn send(self) -> Result<()> {
+    pub async fn send(self) -> Result<(), PublishError> {
+        let span = tracing::debug_span!(
+            "publish",
+            topic = %self.publish.topic,
+            qos = ?self.publish.qos,
+            packet_id = tracing::field::Empty,
+        );
+
         match self.publish.qos {
             Qos::AtMostOnce => {
                 self.tx_command
                     .send(Command::Publish(PublishCommand {
                         publish: self.publish,
                         reply: None,
+                        span: span.clone(),
                     }))
-                    .await
-                    .map_err(|_| Error::Closed)?;
+                    .instrument(span)
+                    .await?;
                 Ok(())
             }
             Qos::AtLeastOnce | Qos::ExactlyOnce => {
                 let (tx_reply, rx_reply) = oneshot::channel();
-                self.tx_command
-                    .send(Command::Publish(PublishCommand {
-                        publish: self.publish,
-                        reply: Some(tx_reply),
-                    }))
-                    .await
-                    .map_err(|_| Error::Closed)?;
-                rx_reply.await.map_err(|_| Error::Closed)?
+                let tx_command = self.tx_command;
+                let command = Command::Publish(PublishCommand {
+                    publish: self.publish,
+                    reply: Some(tx_reply),
+                    span: span.clone(),
+                });
+                async move {
+                    tx_command.send(command).await?;
+                    rx_reply.await.map_err(|_| PublishError::ConnectionClosed)?
+                }
+                .instrument(span)
+                .await
             }
         }
     }
 
-    pub async fn request(self) -> Result<Message> {
+    /// Publishes the message and waits for the response, which is the message that carries the
+    /// correlation data of this request.
+    ///
+    /// The call fails if the publication is rejected or the connection is closed before it is
+    /// acknowledged. The responder may never answer, so the call is usually wrapped in a
+    /// timeout, a dropped request is discarded by the connection task.
+    pub async fn request(self) -> Result<Message, PublishError> {
+        let span = tracing::debug_span!(
+            "request",
+            topic = %self.publish.topic,
+            qos = ?self.publish.qos,
+            req_id = tracing::field::Empty,
+            packet_id = tracing::field::Empty,
+        );
         let (tx_reply, rx_reply) = oneshot::channel();
-        self.tx_command
-            .send(Command::Request(RequestCommand {
-                publish: self.publish,
-                reply: Some(tx_reply),
-            }))
-            .await
-            .map_err(|_| Error::Closed)?;
-        rx_reply.await.map_err(|_| Error::Closed)?
+        let tx_command = self.tx_command;
+        let command = Command::Request(RequestCommand {
+            publish: self.publish,
+            reply: tx_reply,
+            span: span.clone(),
+        });
+        async move {
+            tx_command.send(command).await?;
+            rx_reply.await.map_err(|_| PublishError::ConnectionClosed)?
+        }
+        .instrument(span)
+        .await
     }
 }
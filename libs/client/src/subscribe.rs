@@ -1,17 +1,17 @@
-use anyhow::Result;
 use bytestring::ByteString;
 use codec::{Qos, RetainHandling, SubscribeFilter};
-use tokio::sync::mpsc;
+use tracing::Instrument;
 
-use crate::command::{Command, SubscribeCommand};
+use crate::command::{Command, CommandSender, SubscribeCommand};
+use crate::Error;
 
 pub struct SubscribeBuilder {
-    tx_command: mpsc::Sender<Command>,
+    tx_command: CommandSender,
     filters: Vec<SubscribeFilter>,
 }
 
 impl SubscribeBuilder {
-    pub(crate) fn new(tx_command: mpsc::Sender<Command>) -> Self {
+    pub(crate) fn new(tx_command: CommandSender) -> Self {
         Self {
             tx_command,
             filters: Vec::new(),
@@ -30,13 +30,20 @@ impl SubscribeBuilder {
         self
     }
 
-    pub async fn send(self) -> Result<()> {
+    pub async fn send(self) -> Result<(), Error> {
+        let span = tracing::debug_span!(
+            "subscribe",
+            filters = self.filters.len(),
+            packet_id = tracing::field::Empty,
+        );
         self.tx_command
             .send(Command::Subscribe(SubscribeCommand {
                 filters: self.filters,
+                span: span.clone(),
             }))
-            .await
-            .map_err(|_| Error::Closed)
+            .instrument(span)
+            .await?;
+        Ok(())
     }
 }
 
@@ -0,0 +1,149 @@
+use std::io::{BufReader, Cursor};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{
+    Certificate, ClientConfig, PrivateKey, RootCertStore, ServerCertVerified, ServerCertVerifier,
+    TLSError,
+};
+use tokio_rustls::webpki::{DNSName, DNSNameRef};
+use tokio_rustls::TlsConnector;
+
+use crate::Error;
+
+/// Options of the TLS transport.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    server_name: String,
+    ca_file: Option<String>,
+    client_cert: Option<(String, String)>,
+    insecure: bool,
+}
+
+impl TlsConfig {
+    /// Creates the options for a server, the name is used to verify the server certificate.
+    pub fn new(server_name: impl Into<String>) -> Self {
+        Self {
+            server_name: server_name.into(),
+            ca_file: None,
+            client_cert: None,
+            insecure: false,
+        }
+    }
+
+    /// Verifies the server certificate with the certificates in a PEM file instead of the
+    /// platform's root certificates.
+    #[inline]
+    pub fn ca_file(self, path: impl Into<String>) -> Self {
+        Self {
+            ca_file: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// Authenticates with the certificate chain and the PKCS#8 or RSA private key in PEM files.
+    #[inline]
+    pub fn client_cert(self, cert_file: impl Into<String>, key_file: impl Into<String>) -> Self {
+        Self {
+            client_cert: Some((cert_file.into(), key_file.into())),
+            ..self
+        }
+    }
+
+    /// Accepts any server certificate.
+    ///
+    /// This makes the connection vulnerable to man-in-the-middle attacks, it should only be
+    /// used for testing.
+    #[inline]
+    pub fn danger_accept_invalid_certs(self) -> Self {
+        Self {
+            insecure: true,
+            ..self
+        }
+    }
+
+    pub(crate) fn set_insecure(&mut self) {
+        self.insecure = true;
+    }
+
+    pub(crate) async fn connect_stream(
+        &self,
+        addrs: &[SocketAddr],
+    ) -> Result<impl AsyncRead + AsyncWrite + Send + Unpin, Error> {
+        let mut config = ClientConfig::new();
+        config.root_store = match &self.ca_file {
+            Some(path) => {
+                let data = std::fs::read(path)?;
+                let mut root_store = RootCertStore::empty();
+                root_store
+                    .add_pem_file(&mut BufReader::new(Cursor::new(data)))
+                    .map_err(|_| {
+                        Error::InvalidConfig(format!("failed to load ca file: {}", path))
+                    })?;
+                root_store
+            }
+            None => rustls_native_certs::load_native_certs().map_err(|(_, err)| err)?,
+        };
+        if let Some((cert_file, key_file)) = &self.client_cert {
+            let (certs, key) = load_client_cert(cert_file, key_file)?;
+            config.set_single_client_cert(certs, key).map_err(|err| {
+                Error::InvalidConfig(format!("invalid client certificate: {}", err))
+            })?;
+        }
+        if self.insecure {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoCertificateVerification));
+        }
+
+        let dns_name: DNSName = DNSNameRef::try_from_ascii_str(&self.server_name)
+            .map_err(|_| {
+                Error::InvalidConfig(format!("invalid server name: {}", self.server_name))
+            })?
+            .to_owned();
+        let stream = TcpStream::connect(addrs).await?;
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(dns_name.as_ref(), stream)
+            .await?;
+        Ok(stream)
+    }
+}
+
+fn load_client_cert(
+    cert_file: &str,
+    key_file: &str,
+) -> Result<(Vec<Certificate>, PrivateKey), Error> {
+    let cert_data = std::fs::read(cert_file)?;
+    let key_data = std::fs::read(key_file)?;
+
+    let certs = pemfile::certs(&mut BufReader::new(Cursor::new(&cert_data))).map_err(|_| {
+        Error::InvalidConfig(format!("failed to load certificate file: {}", cert_file))
+    })?;
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(Cursor::new(&key_data)))
+        .unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(Cursor::new(&key_data)))
+            .unwrap_or_default();
+    }
+    let key = keys
+        .pop()
+        .ok_or_else(|| Error::InvalidConfig(format!("no private key found in: {}", key_file)))?;
+    Ok((certs, key))
+}
+
+struct NoCertificateVerification;
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
@@ -0,0 +1,178 @@
+use std::net::SocketAddr;
+#[cfg(feature = "quic")]
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use crate::Error;
+#[cfg(feature = "tls")]
+use crate::TlsConfig;
+#[cfg(feature = "websocket")]
+use crate::WebSocketConfig;
+
+pub type Reader = Box<dyn AsyncRead + Send + Unpin>;
+pub type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// The transport used to connect to the broker.
+#[derive(Debug, Clone, Default)]
+pub enum Transport {
+    /// Plain TCP.
+    #[default]
+    Tcp,
+
+    /// TCP with TLS.
+    #[cfg(feature = "tls")]
+    Tls(TlsConfig),
+
+    /// WebSocket, with or without TLS.
+    #[cfg(feature = "websocket")]
+    WebSocket(WebSocketConfig),
+
+    /// MQTT over a QUIC bidirectional stream.
+    #[cfg(feature = "quic")]
+    Quic(QuicConfig),
+}
+
+impl Transport {
+    /// Opens a connection to the broker, returns the streams that the packets are read from and
+    /// written to.
+    pub async fn connect(&self, addrs: &[SocketAddr]) -> Result<(Reader, Writer), Error> {
+        match self {
+            Transport::Tcp => {
+                let stream = TcpStream::connect(addrs).await?;
+                let (reader, writer) = stream.into_split();
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+            #[cfg(feature = "tls")]
+            Transport::Tls(config) => {
+                let (reader, writer) = tokio::io::split(config.connect_stream(addrs).await?);
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+            #[cfg(feature = "websocket")]
+            Transport::WebSocket(config) => config.connect(addrs).await,
+            #[cfg(feature = "quic")]
+            Transport::Quic(config) => config.connect(addrs).await,
+        }
+    }
+}
+
+/// Options of the QUIC transport.
+#[cfg(feature = "quic")]
+#[derive(Debug, Clone)]
+pub struct QuicConfig {
+    server_name: String,
+    /// Shared by all the connections, so a reconnect can resume the TLS session of the
+    /// previous connection and send 0-RTT data.
+    client_config: quinn::ClientConfig,
+    enable_0rtt: bool,
+    keep_alive_interval: Option<Duration>,
+    max_idle_timeout: Option<Duration>,
+}
+
+#[cfg(feature = "quic")]
+impl QuicConfig {
+    /// Creates the options for a server, the name is used to verify the server certificate.
+    pub fn new(server_name: impl Into<String>) -> Self {
+        Self {
+            server_name: server_name.into(),
+            // the TLS configuration of `with_native_roots` enables early data
+            client_config: quinn::ClientConfig::with_native_roots(),
+            enable_0rtt: false,
+            keep_alive_interval: Some(Duration::from_secs(10)),
+            max_idle_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+
+    /// Sends the CONNECT packet as 0-RTT data when resuming the TLS session of a previous
+    /// connection, such as when the client reconnects.
+    ///
+    /// 0-RTT data can be replayed by an attacker, only enable it if this is acceptable for the
+    /// CONNECT packet of this client.
+    #[inline]
+    pub fn enable_0rtt(self) -> Self {
+        Self {
+            enable_0rtt: true,
+            ..self
+        }
+    }
+
+    /// Sets the interval of QUIC keep-alive packets, `None` to disable them.
+    ///
+    /// The default value is `10` seconds.
+    #[inline]
+    pub fn keep_alive_interval(self, interval: Option<Duration>) -> Self {
+        Self {
+            keep_alive_interval: interval,
+            ..self
+        }
+    }
+
+    /// Sets the time after which an idle connection is closed, `None` to never close it.
+    ///
+    /// The default value is `30` seconds.
+    #[inline]
+    pub fn max_idle_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            max_idle_timeout: timeout,
+            ..self
+        }
+    }
+
+    async fn connect(&self, addrs: &[SocketAddr]) -> Result<(Reader, Writer), Error> {
+        use std::convert::TryInto;
+        use std::sync::Arc;
+
+        use quinn::TransportConfig;
+
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(self.keep_alive_interval);
+        transport.max_idle_timeout(
+            self.max_idle_timeout
+                .map(|timeout| timeout.try_into())
+                .transpose()
+                .map_err(|_| Error::InvalidConfig("max idle timeout out of range".to_string()))?,
+        );
+        let mut config = self.client_config.clone();
+        config.transport = Arc::new(transport);
+
+        let mut last_err = None;
+        for addr in addrs {
+            match self.connect_addr(config.clone(), *addr).await {
+                Ok(streams) => return Ok(streams),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address to connect to").into()
+        }))
+    }
+
+    async fn connect_addr(
+        &self,
+        config: quinn::ClientConfig,
+        addr: SocketAddr,
+    ) -> Result<(Reader, Writer), Error> {
+        use std::net::{Ipv4Addr, Ipv6Addr};
+
+        use quinn::Endpoint;
+
+        let bind_addr: SocketAddr = if addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let endpoint = Endpoint::client(bind_addr)?;
+        let connecting = endpoint.connect_with(config, addr, &self.server_name)?;
+        let new_conn = if self.enable_0rtt {
+            match connecting.into_0rtt() {
+                Ok((new_conn, _)) => new_conn,
+                Err(connecting) => connecting.await?,
+            }
+        } else {
+            connecting.await?
+        };
+        let (writer, reader) = new_conn.connection.open_bi().await?;
+        Ok((Box::new(reader), Box::new(writer)))
+    }
+}
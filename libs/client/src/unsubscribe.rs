@@ -1,16 +1,16 @@
-use anyhow::Result;
 use bytestring::ByteString;
-use tokio::sync::mpsc;
+use tracing::Instrument;
 
-use crate::command::{Command, UnsubscribeCommand};
+use crate::command::{Command, CommandSender, UnsubscribeCommand};
+use crate::Error;
 
 pub struct UnsubscribeBuilder {
-    tx_command: mpsc::Sender<Command>,
+    tx_command: CommandSender,
     filters: Vec<ByteString>,
 }
 
 impl UnsubscribeBuilder {
-    pub(crate) fn new(tx_command: mpsc::Sender<Command>) -> Self {
+    pub(crate) fn new(tx_command: CommandSender) -> Self {
         Self {
             tx_command,
             filters: Vec::new(),
@@ -22,12 +22,19 @@ impl UnsubscribeBuilder {
         self
     }
 
-    pub async fn send(self) -> Result<()> {
+    pub async fn send(self) -> Result<(), Error> {
+        let span = tracing::debug_span!(
+            "unsubscribe",
+            filters = self.filters.len(),
+            packet_id = tracing::field::Empty,
+        );
         self.tx_command
             .send(Command::Unsubscribe(UnsubscribeCommand {
                 filters: self.filters,
+                span: span.clone(),
             }))
-            .await
-            .map_err(|_| Error::Closed)
+            .instrument(span)
+            .await?;
+        Ok(())
     }
 }
@@ -0,0 +1,146 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures_util::{future, Sink, SinkExt, StreamExt, TryStreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+
+use crate::transport::{Reader, Writer};
+use crate::Error;
+#[cfg(feature = "tls")]
+use crate::TlsConfig;
+
+/// Options of the WebSocket transport.
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    host: String,
+    path: String,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+}
+
+impl WebSocketConfig {
+    /// Creates the options for a server, `host` is sent in the `Host` header of the upgrade
+    /// request.
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            path: "/".to_string(),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Sets the path of the upgrade request.
+    ///
+    /// The default value is `/`.
+    #[inline]
+    pub fn path(self, path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            ..self
+        }
+    }
+
+    /// Connects over TLS (`wss`).
+    #[cfg(feature = "tls")]
+    #[inline]
+    pub fn tls(self, tls: TlsConfig) -> Self {
+        Self {
+            tls: Some(tls),
+            ..self
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn tls_mut(&mut self) -> Option<&mut TlsConfig> {
+        self.tls.as_mut()
+    }
+
+    pub(crate) async fn connect(&self, addrs: &[SocketAddr]) -> Result<(Reader, Writer), Error> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let stream = tls.connect_stream(addrs).await?;
+            return self.handshake("wss", stream).await;
+        }
+
+        let stream = TcpStream::connect(addrs).await?;
+        self.handshake("ws", stream).await
+    }
+
+    async fn handshake<S>(&self, scheme: &str, stream: S) -> Result<(Reader, Writer), Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let mut request =
+            format!("{}://{}{}", scheme, self.host, self.path).into_client_request()?;
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", HeaderValue::from_static("mqtt"));
+        let (websocket, _) = tokio_tungstenite::client_async(request, stream).await?;
+        let (sink, stream) = websocket.split();
+
+        let reader = tokio_util::io::StreamReader::new(
+            stream
+                .try_filter_map(|msg| {
+                    future::ready(Ok(msg
+                        .is_binary()
+                        .then(move || Bytes::from(msg.into_data()))))
+                })
+                .map_err(|err| std::io::Error::other(err.to_string())),
+        );
+        Ok((Box::new(reader), Box::new(SinkWriter(sink))))
+    }
+}
+
+struct SinkWriter<T>(T);
+
+impl<T> AsyncWrite for SinkWriter<T>
+where
+    T: Sink<WsMessage, Error = WsError> + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        match self.0.poll_ready_unpin(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(err)) => {
+                return Poll::Ready(Err(std::io::Error::other(err.to_string())))
+            }
+            Poll::Pending => return Poll::Pending,
+        }
+
+        self.0
+            .start_send_unpin(WsMessage::binary(buf))
+            .map_err(|err| std::io::Error::other(err.to_string()))?;
+        self.0
+            .poll_flush_unpin(cx)
+            .map_err(|err| std::io::Error::other(err.to_string()))
+            .map_ok(|_| buf.len())
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        self.0
+            .poll_flush_unpin(cx)
+            .map_err(|err| std::io::Error::other(err.to_string()))
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        self.0
+            .poll_close_unpin(cx)
+            .map_err(|err| std::io::Error::other(err.to_string()))
+    }
+}
@@ -0,0 +1,182 @@
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+use std::time::Duration;
+
+use codec::{
+    ConnAck, ConnectReasonCode, Disconnect, DisconnectReasonCode, Packet, PubAck, PubAckReasonCode,
+    PubComp, PubCompReasonCode, PubRec, PubRecReasonCode, PubRel, PubRelReasonCode, Publish, Qos,
+};
+use rsmqtt_client::{Client, ClientBuilder, Message, PublishError};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpListener;
+use tokio_stream::{Stream, StreamExt};
+
+/// A broker whose side of the connection is driven by the test.
+struct Broker {
+    codec: codec::Codec<OwnedReadHalf, OwnedWriteHalf>,
+}
+
+impl Broker {
+    async fn send(&mut self, packet: Packet) {
+        self.codec.encode(&packet).await.unwrap();
+    }
+
+    async fn recv(&mut self) -> Packet {
+        tokio::time::timeout(Duration::from_secs(5), self.codec.decode())
+            .await
+            .expect("timed out waiting for a packet")
+            .unwrap()
+            .unwrap()
+            .0
+    }
+}
+
+/// Connects a client to a new broker, `f` configures the client.
+async fn connect(
+    f: impl FnOnce(ClientBuilder<(Ipv4Addr, u16)>) -> ClientBuilder<(Ipv4Addr, u16)>,
+) -> (Client, impl Stream<Item = Message>, Broker) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (client, messages) = f(Client::new((Ipv4Addr::LOCALHOST, port)))
+        .build()
+        .await
+        .unwrap();
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let (reader, writer) = stream.into_split();
+    let mut broker = Broker {
+        codec: codec::Codec::new(reader, writer),
+    };
+    assert!(matches!(broker.recv().await, Packet::Connect(_)));
+    broker
+        .send(Packet::ConnAck(ConnAck {
+            session_present: false,
+            reason_code: ConnectReasonCode::Success,
+            properties: Default::default(),
+        }))
+        .await;
+    (client, messages, broker)
+}
+
+fn publish(qos: Qos, packet_id: u16, payload: &'static str) -> Publish {
+    Publish {
+        dup: false,
+        qos,
+        retain: false,
+        topic: "a/b".into(),
+        packet_id: NonZeroU16::new(packet_id),
+        properties: Default::default(),
+        payload: payload.into(),
+    }
+}
+
+fn pub_rec(packet_id: u16) -> Packet {
+    Packet::PubRec(PubRec {
+        packet_id: NonZeroU16::new(packet_id).unwrap(),
+        reason_code: PubRecReasonCode::Success,
+        properties: Default::default(),
+    })
+}
+
+fn pub_rel(packet_id: u16) -> Packet {
+    Packet::PubRel(PubRel {
+        packet_id: NonZeroU16::new(packet_id).unwrap(),
+        reason_code: PubRelReasonCode::Success,
+        properties: Default::default(),
+    })
+}
+
+#[tokio::test]
+async fn qos2_duplicate_publish() {
+    let (_client, messages, mut broker) = connect(|builder| builder).await;
+    tokio::pin!(messages);
+
+    broker
+        .send(Packet::Publish(publish(Qos::ExactlyOnce, 1, "1")))
+        .await;
+    assert_eq!(broker.recv().await, pub_rec(1));
+
+    // the retransmission is acknowledged again
+    broker
+        .send(Packet::Publish(Publish {
+            dup: true,
+            ..publish(Qos::ExactlyOnce, 1, "1")
+        }))
+        .await;
+    assert_eq!(broker.recv().await, pub_rec(1));
+
+    broker.send(pub_rel(1)).await;
+    assert_eq!(
+        broker.recv().await,
+        Packet::PubComp(PubComp {
+            packet_id: NonZeroU16::new(1).unwrap(),
+            reason_code: PubCompReasonCode::Success,
+            properties: Default::default(),
+        })
+    );
+
+    // the message that follows proves that the duplicate was not delivered
+    broker
+        .send(Packet::Publish(publish(Qos::AtMostOnce, 0, "2")))
+        .await;
+    assert_eq!(messages.next().await.unwrap().payload(), b"1");
+    assert_eq!(messages.next().await.unwrap().payload(), b"2");
+}
+
+#[tokio::test]
+async fn qos2_unknown_pub_rel() {
+    let (_client, _messages, mut broker) = connect(|builder| builder).await;
+
+    broker.send(pub_rel(9)).await;
+    assert_eq!(
+        broker.recv().await,
+        Packet::PubComp(PubComp {
+            packet_id: NonZeroU16::new(9).unwrap(),
+            reason_code: PubCompReasonCode::PacketIdentifierNotFound,
+            properties: Default::default(),
+        })
+    );
+}
+
+#[tokio::test]
+async fn receive_max_exceeded() {
+    let (_client, _messages, mut broker) = connect(|builder| builder.receive_max(1)).await;
+
+    broker
+        .send(Packet::Publish(publish(Qos::ExactlyOnce, 1, "1")))
+        .await;
+    assert_eq!(broker.recv().await, pub_rec(1));
+
+    broker
+        .send(Packet::Publish(publish(Qos::ExactlyOnce, 2, "2")))
+        .await;
+    assert_eq!(
+        broker.recv().await,
+        Packet::Disconnect(Disconnect::new(
+            DisconnectReasonCode::ReceiveMaximumExceeded
+        ))
+    );
+}
+
+#[tokio::test]
+async fn rejected_request() {
+    let (client, _messages, mut broker) = connect(|builder| builder).await;
+
+    let request =
+        tokio::spawn(async move { client.publish("a/b").qos(Qos::AtLeastOnce).request().await });
+    let packet_id = match broker.recv().await {
+        Packet::Publish(publish) => publish.packet_id.unwrap(),
+        packet => panic!("unexpected packet: {:?}", packet),
+    };
+    broker
+        .send(Packet::PubAck(PubAck {
+            packet_id,
+            reason_code: PubAckReasonCode::NotAuthorized,
+            properties: Default::default(),
+        }))
+        .await;
+    assert!(matches!(
+        request.await.unwrap(),
+        Err(PublishError::NotAuthorized)
+    ));
+}
@@ -0,0 +1,16 @@
+use rsmqtt_client::{Client, Error};
+
+#[tokio::test]
+async fn zero_channel_capacity() {
+    let res = Client::new("127.0.0.1:1883")
+        .command_channel_capacity(0)
+        .build()
+        .await;
+    assert!(matches!(res, Err(Error::InvalidConfig(_))));
+
+    let res = Client::new("127.0.0.1:1883")
+        .message_channel_capacity(0)
+        .build()
+        .await;
+    assert!(matches!(res, Err(Error::InvalidConfig(_))));
+}
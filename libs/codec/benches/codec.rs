@@ -1,49 +1,120 @@
-use bytes::{Buf, BytesMut};
-use criterion::{criterion_group, criterion_main, Criterion};
-use rsmqtt_codec::{Packet, ProtocolLevel, Publish, PublishProperties, Qos};
+use std::num::NonZeroU16;
 
-fn encode_publish(c: &mut Criterion) {
-    let packet = Packet::Publish(Publish {
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rsmqtt_codec::{
+    Connect, ConnectProperties, LastWill, Login, Packet, ProtocolLevel, Publish, PublishProperties,
+    Qos, RetainHandling, Subscribe, SubscribeFilter, SubscribeProperties, WillProperties,
+};
+
+const PAYLOAD_SIZES: [usize; 5] = [0, 64, 1024, 16 * 1024, 256 * 1024];
+
+fn connect_packet() -> Packet {
+    Packet::Connect(Connect {
+        level: ProtocolLevel::V5,
+        keep_alive: 60,
+        clean_start: true,
+        client_id: "abcdefgabcdefg".into(),
+        last_will: Some(LastWill {
+            topic: "abcdefg/will".into(),
+            payload: "abcdefgabcdefg".into(),
+            qos: Qos::AtLeastOnce,
+            retain: false,
+            properties: WillProperties::default(),
+        }),
+        login: Some(Login {
+            username: "abcdefg".into(),
+            password: "abcdefgabcdefg".into(),
+        }),
+        properties: ConnectProperties {
+            session_expiry_interval: Some(60),
+            receive_max: Some(100),
+            user_properties: vec![("abc".into(), "defg".into())],
+            ..ConnectProperties::default()
+        },
+    })
+}
+
+fn subscribe_packet() -> Packet {
+    Packet::Subscribe(Subscribe {
+        packet_id: NonZeroU16::new(1).unwrap(),
+        properties: SubscribeProperties::default(),
+        filters: (0..10)
+            .map(|idx| SubscribeFilter {
+                path: format!("abcdefg/{}/+/#", idx).into(),
+                qos: Qos::AtLeastOnce,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: RetainHandling::OnEverySubscribe,
+            })
+            .collect(),
+    })
+}
+
+fn publish_packet(payload_size: usize) -> Packet {
+    Packet::Publish(Publish {
         dup: false,
-        qos: Qos::AtMostOnce,
+        qos: Qos::AtLeastOnce,
         retain: false,
-        topic: "abcdefg".into(),
-        packet_id: None,
+        topic: "abcdefg/abcdefg".into(),
+        packet_id: NonZeroU16::new(1),
         properties: PublishProperties::default(),
-        payload: "abcdefgabcdefgabcdefgabcdefgabcdefgabcdefg".into(),
-    });
+        payload: vec![b'a'; payload_size].into(),
+    })
+}
+
+fn encoded(packet: &Packet) -> Bytes {
     let mut buf = BytesMut::new();
+    packet
+        .encode(&mut buf, ProtocolLevel::V5, usize::MAX)
+        .unwrap();
+    buf.freeze()
+}
 
-    c.bench_function("encode publish", |b| {
-        b.iter(|| {
-            buf.clear();
-            Packet::encode(&packet, &mut buf, ProtocolLevel::V5, usize::MAX).unwrap();
-        });
-    });
+/// The packets to measure, publish with payloads of different sizes.
+fn packets() -> Vec<(BenchmarkId, Packet)> {
+    let mut packets = vec![
+        (BenchmarkId::from_parameter("connect"), connect_packet()),
+        (BenchmarkId::from_parameter("subscribe"), subscribe_packet()),
+    ];
+    for size in PAYLOAD_SIZES {
+        packets.push((BenchmarkId::new("publish", size), publish_packet(size)));
+    }
+    packets
 }
 
-fn decode_publish(c: &mut Criterion) {
-    let packet = Packet::Publish(Publish {
-        dup: false,
-        qos: Qos::AtMostOnce,
-        retain: false,
-        topic: "abcdefg".into(),
-        packet_id: None,
-        properties: PublishProperties::default(),
-        payload: "abcdefgabcdefgabcdefgabcdefgabcdefgabcdefg".into(),
-    });
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
     let mut buf = BytesMut::new();
-    Packet::encode(&packet, &mut buf, ProtocolLevel::V5, usize::MAX).unwrap();
-    let mut packet_data = buf.freeze();
-    let flag = packet_data[0];
-    packet_data.advance(2);
-
-    c.bench_function("decode publish", |b| {
-        b.iter(|| {
-            Packet::decode(packet_data.clone(), flag, ProtocolLevel::V5).unwrap();
+    for (id, packet) in packets() {
+        group.throughput(Throughput::Bytes(encoded(&packet).len() as u64));
+        group.bench_function(id, |b| {
+            b.iter(|| {
+                buf.clear();
+                packet
+                    .encode(&mut buf, ProtocolLevel::V5, usize::MAX)
+                    .unwrap();
+            });
+        });
+    }
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for (id, packet) in packets() {
+        let data = encoded(&packet);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(id, |b| {
+            b.iter(|| {
+                Packet::parse(&mut data.clone(), ProtocolLevel::V5)
+                    .unwrap()
+                    .unwrap();
+            });
         });
-    });
+    }
+    group.finish();
 }
 
-criterion_group!(benches, encode_publish, decode_publish);
+criterion_group!(benches, encode, decode);
 criterion_main!(benches);
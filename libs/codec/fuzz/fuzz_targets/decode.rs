@@ -0,0 +1,31 @@
+//! Feeds arbitrary bytes to the decoder of every protocol level.
+//!
+//! Run with `cargo +nightly fuzz run decode` in `libs/codec`.
+
+#![no_main]
+
+use bytes::{Bytes, BytesMut};
+use libfuzzer_sys::fuzz_target;
+use rsmqtt_codec::{DecodeOptions, Decoder, Packet, ProtocolLevel};
+
+const LEVELS: [ProtocolLevel; 3] = [ProtocolLevel::V3, ProtocolLevel::V4, ProtocolLevel::V5];
+
+fuzz_target!(|data: &[u8]| {
+    for level in LEVELS {
+        let mut buf = Bytes::copy_from_slice(data);
+        while let Ok(Some(_)) = Packet::parse(&mut buf, level) {}
+
+        for options in [DecodeOptions::strict(), DecodeOptions::lenient()] {
+            let mut decoder = Decoder::default();
+            decoder.set_protocol_level(level);
+            decoder.set_decode_options(options);
+
+            // split the input so that partially received packets are decoded too
+            let (head, tail) = data.split_at(data.len() / 2);
+            let mut buf = BytesMut::from(head);
+            while let Ok(Some(_)) = decoder.decode_buf(&mut buf) {}
+            buf.extend_from_slice(tail);
+            while let Ok(Some(_)) = decoder.decode_buf(&mut buf) {}
+        }
+    }
+});
@@ -0,0 +1,38 @@
+//! Decodes arbitrary bytes, encodes the decoded packets again and checks that decoding the
+//! result yields the same packets.
+//!
+//! Run with `cargo +nightly fuzz run round_trip` in `libs/codec`.
+
+#![no_main]
+
+use bytes::{Bytes, BytesMut};
+use libfuzzer_sys::fuzz_target;
+use rsmqtt_codec::{Packet, ProtocolLevel};
+
+const LEVELS: [ProtocolLevel; 3] = [ProtocolLevel::V3, ProtocolLevel::V4, ProtocolLevel::V5];
+
+fuzz_target!(|data: &[u8]| {
+    for level in LEVELS {
+        let mut buf = Bytes::copy_from_slice(data);
+        while let Ok(Some(packet)) = Packet::parse(&mut buf, level) {
+            // a CONNECT packet is encoded with its own protocol level
+            let encode_level = match &packet {
+                Packet::Connect(connect) => connect.level,
+                _ => level,
+            };
+            let mut encoded = BytesMut::new();
+            if packet
+                .encode(&mut encoded, encode_level, usize::MAX)
+                .is_err()
+            {
+                continue;
+            }
+            let mut encoded = encoded.freeze();
+            let decoded = Packet::parse(&mut encoded, level)
+                .expect("failed to decode an encoded packet")
+                .expect("incomplete encoded packet");
+            assert_eq!(decoded, packet);
+            assert!(encoded.is_empty());
+        }
+    }
+});
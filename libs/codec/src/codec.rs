@@ -1,24 +1,31 @@
-use bytes::{Buf, BytesMut};
+use std::collections::VecDeque;
+use std::io::IoSlice;
+use std::time::Duration;
+
+use bytes::{Buf, Bytes, BytesMut};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
 
-use crate::{DecodeError, EncodeError, Packet, ProtocolLevel};
+use crate::decoder::{Decoder, StreamItem};
+use crate::{DecodeError, DecodeOptions, EncodeError, Packet, ProtocolLevel};
 
-#[derive(Debug, Copy, Clone)]
-enum DecoderState {
-    Flag,
-    Length(u8),
-    Body(u8, usize),
-}
+/// Payloads smaller than this are copied to the write buffer instead of being written as a
+/// separate slice.
+const COPY_PAYLOAD_MAX_SIZE: usize = 1024;
+
+/// The maximum number of slices passed to a single vectored write.
+const MAX_IO_SLICES: usize = 64;
 
 pub struct Codec<R, W> {
     reader: R,
     writer: W,
-    level: ProtocolLevel,
-    input_max_size: usize,
+    decoder: Decoder,
     output_max_size: usize,
-    read_buf: BytesMut,
+    read_timeout: Option<Duration>,
+    last_read: Instant,
     write_buf: BytesMut,
-    decoder_state: DecoderState,
+    /// The bytes queued before `write_buf`.
+    write_chunks: VecDeque<Bytes>,
 }
 
 impl<R, W> Codec<R, W>
@@ -30,23 +37,35 @@ where
         Self {
             reader,
             writer,
-            level: ProtocolLevel::V4,
-            input_max_size: usize::MAX,
+            decoder: Decoder::default(),
             output_max_size: usize::MAX,
-            read_buf: BytesMut::new(),
+            read_timeout: None,
+            last_read: Instant::now(),
             write_buf: BytesMut::new(),
-            decoder_state: DecoderState::Flag,
+            write_chunks: VecDeque::new(),
         }
     }
 
     #[inline]
     pub fn protocol_level(&self) -> ProtocolLevel {
-        self.level
+        self.decoder.protocol_level()
+    }
+
+    /// Sets the protocol level used to encode and decode packets, it is otherwise taken from
+    /// the CONNECT packet.
+    #[inline]
+    pub fn set_protocol_level(&mut self, level: ProtocolLevel) {
+        self.decoder.set_protocol_level(level);
     }
 
     #[inline]
     pub fn set_input_max_size(&mut self, size: usize) {
-        self.input_max_size = size;
+        self.decoder.set_input_max_size(size);
+    }
+
+    #[inline]
+    pub fn set_decode_options(&mut self, options: DecodeOptions) {
+        self.decoder.set_decode_options(options);
     }
 
     #[inline]
@@ -54,86 +73,195 @@ where
         self.output_max_size = size;
     }
 
+    /// Sets how long [`Codec::decode`] waits for bytes before it fails with
+    /// [`DecodeError::ReadTimeout`], `None` waits forever.
+    ///
+    /// The time is counted from the last bytes received, so it is not restarted when a
+    /// `decode` future is dropped and created again.
+    #[inline]
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+        self.last_read = Instant::now();
+    }
+
+    async fn read(&mut self, data: &mut [u8]) -> Result<usize, DecodeError> {
+        let sz = match self.read_timeout {
+            Some(timeout) => {
+                tokio::time::timeout_at(self.last_read + timeout, self.reader.read(data))
+                    .await
+                    .map_err(|_| DecodeError::ReadTimeout)??
+            }
+            None => self.reader.read(data).await?,
+        };
+        self.last_read = Instant::now();
+        Ok(sz)
+    }
+
     pub async fn decode(&mut self) -> Result<Option<(Packet, usize)>, DecodeError> {
         let mut data = [0; 256];
 
         loop {
-            match self.decoder_state {
-                DecoderState::Flag => {
-                    if !self.read_buf.is_empty() {
-                        self.decoder_state = DecoderState::Length(self.read_buf.get_u8());
-                        continue;
-                    }
-                }
-                DecoderState::Length(flag) => {
-                    if let Some((packet_size, len_size)) = get_remaining_length(&self.read_buf)? {
-                        if packet_size > self.input_max_size {
-                            return Err(DecodeError::PacketTooLarge);
-                        }
-                        self.read_buf.advance(len_size);
-                        self.decoder_state = DecoderState::Body(flag, packet_size);
-                        continue;
-                    }
-                }
-                DecoderState::Body(flag, packet_size) => {
-                    if self.read_buf.len() >= packet_size {
-                        let data = self.read_buf.split_to(packet_size).freeze();
-                        self.decoder_state = DecoderState::Flag;
-                        let packet = Packet::decode(data, flag, self.level)?;
-                        if let Packet::Connect(connect) = &packet {
-                            self.level = connect.level;
-                        }
-                        return Ok(Some((packet, packet_size)));
-                    }
-                }
+            if let Some(res) = self.decoder.decode()? {
+                return Ok(Some(res));
+            }
+
+            let sz = self.read(&mut data).await?;
+            if sz == 0 {
+                return if self.decoder.is_partial() {
+                    Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
+                } else {
+                    Ok(None)
+                };
+            }
+            self.decoder.feed(&data[..sz]);
+        }
+    }
+
+    /// Like [`Codec::decode`], but the payload of a PUBLISH packet larger than `threshold` bytes
+    /// is returned in chunks, see [`Decoder::decode_stream`].
+    pub async fn decode_stream(
+        &mut self,
+        threshold: usize,
+    ) -> Result<Option<StreamItem>, DecodeError> {
+        let mut data = [0; 4096];
+
+        loop {
+            if let Some(item) = self.decoder.decode_stream(threshold)? {
+                return Ok(Some(item));
             }
 
-            let sz = self.reader.read(&mut data).await?;
+            let sz = self.read(&mut data).await?;
             if sz == 0 {
-                return match self.decoder_state {
-                    DecoderState::Flag => Ok(None),
-                    DecoderState::Length(_) | DecoderState::Body(_, _) => {
-                        Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
-                    }
+                return if self.decoder.is_partial() {
+                    Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
+                } else {
+                    Ok(None)
                 };
             }
-            self.read_buf.extend_from_slice(&data[..sz]);
+            self.decoder.feed(&data[..sz]);
         }
     }
 
+    /// Writes a packet and returns its size.
     pub async fn encode(&mut self, packet: &Packet) -> Result<usize, EncodeError> {
+        let size = self.feed(packet)?;
+        self.flush().await?;
+        Ok(size)
+    }
+
+    /// Queues a packet without writing it and returns its size, the queued packets are written
+    /// with a single vectored write by [`Codec::flush`].
+    ///
+    /// The payload of a large PUBLISH packet is not copied, so the same message can be sent to
+    /// many connections without copying it for each of them.
+    pub fn feed(&mut self, packet: &Packet) -> Result<usize, EncodeError> {
         if let Packet::Connect(connect) = &packet {
-            self.level = connect.level;
+            self.decoder.set_protocol_level(connect.level);
+        }
+
+        let len = self.write_buf.len();
+        let payload = match packet.encode_vectored(
+            &mut self.write_buf,
+            self.decoder.protocol_level(),
+            self.output_max_size,
+        ) {
+            Ok(payload) => payload,
+            Err(err) => {
+                self.write_buf.truncate(len);
+                return Err(err);
+            }
+        };
+        let mut size = self.write_buf.len() - len;
+
+        if let Some(payload) = payload {
+            size += payload.len();
+            if payload.len() <= COPY_PAYLOAD_MAX_SIZE {
+                self.write_buf.extend_from_slice(payload);
+            } else {
+                self.write_chunks.push_back(self.write_buf.split().freeze());
+                self.write_chunks.push_back(payload.clone());
+            }
         }
-        packet.encode(&mut self.write_buf, self.level, self.output_max_size)?;
-        self.writer.write_all(&self.write_buf).await?;
-        let size = self.write_buf.len();
-        self.write_buf.clear();
+
         Ok(size)
     }
-}
 
-#[inline]
-fn get_remaining_length(data: &[u8]) -> Result<Option<(usize, usize)>, DecodeError> {
-    let mut n = 0;
-    let mut shift = 0;
-    let mut bytes = 0;
+    /// Returns `true` if there are packets queued by [`Codec::feed`].
+    #[inline]
+    pub fn has_pending_writes(&self) -> bool {
+        !self.write_chunks.is_empty() || !self.write_buf.is_empty()
+    }
 
-    for i in 0.. {
-        if i >= data.len() {
-            return Ok(None);
+    /// Writes the packets queued by [`Codec::feed`].
+    pub async fn flush(&mut self) -> Result<(), std::io::Error> {
+        if !self.write_buf.is_empty() {
+            self.write_chunks.push_back(self.write_buf.split().freeze());
         }
 
-        let byte = data[i];
-        bytes += 1;
-        n += ((byte & 0x7f) as usize) << shift;
-        let done = (byte & 0x80) == 0;
-        if done {
-            break;
+        while !self.write_chunks.is_empty() {
+            let mut slices = [IoSlice::new(&[]); MAX_IO_SLICES];
+            let count = self.write_chunks.len().min(MAX_IO_SLICES);
+            for (slice, chunk) in slices.iter_mut().zip(&self.write_chunks) {
+                *slice = IoSlice::new(chunk);
+            }
+
+            let mut written = self.writer.write_vectored(&slices[..count]).await?;
+            if written == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            while written > 0 {
+                let chunk = self.write_chunks.front_mut().unwrap();
+                if written < chunk.len() {
+                    chunk.advance(written);
+                    break;
+                }
+                written -= chunk.len();
+                self.write_chunks.pop_front();
+            }
         }
-        shift += 7;
-        ensure!(shift <= 21, DecodeError::MalformedPacket);
+
+        Ok(())
     }
 
-    Ok(Some((n, bytes)))
+    /// Writes bytes that are not necessarily a valid packet.
+    pub async fn write_raw(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        self.writer.write_all(data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PINGREQ;
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        tokio::time::pause();
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut codec = Codec::new(server, tokio::io::sink());
+        codec.set_read_timeout(Some(Duration::from_secs(10)));
+
+        // dropping the future does not restart the timeout
+        for _ in 0..2 {
+            assert!(tokio::time::timeout(Duration::from_secs(4), codec.decode())
+                .await
+                .is_err());
+        }
+        let start = Instant::now();
+        assert!(matches!(
+            codec.decode().await,
+            Err(DecodeError::ReadTimeout)
+        ));
+        assert_eq!(start.elapsed().as_secs_f64().round(), 2.0);
+
+        // the timeout is counted from the last bytes received
+        client.write_all(&[PINGREQ << 4, 0]).await.unwrap();
+        assert_eq!(codec.decode().await.unwrap().unwrap().0, Packet::PingReq);
+        let start = Instant::now();
+        assert!(matches!(
+            codec.decode().await,
+            Err(DecodeError::ReadTimeout)
+        ));
+        assert_eq!(start.elapsed().as_secs_f64().round(), 10.0);
+    }
 }
@@ -6,9 +6,10 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
 
 use crate::packet::CONNACK;
+use crate::property::PropertySet;
 use crate::reader::PacketReader;
 use crate::writer::{bytes_remaining_length, PacketWriter};
-use crate::{property, DecodeError, EncodeError, ProtocolLevel, Qos};
+use crate::{property, DecodeError, DecodeOptions, EncodeError, ProtocolLevel, Qos};
 
 #[derive(
     Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive, Serialize, Deserialize,
@@ -228,11 +229,13 @@ impl ConnAckProperties {
         Ok(())
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = ConnAckProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            seen.insert(flag, options)?;
 
             match flag {
                 property::SESSION_EXPIRY_INTERVAL => {
@@ -287,6 +290,7 @@ impl ConnAckProperties {
                 property::AUTHENTICATION_DATA => {
                     properties.authentication_data = Some(data.read_binary()?)
                 }
+                _ if !options.reject_unknown_properties => break,
                 _ => return Err(DecodeError::InvalidConnAckProperty(flag)),
             }
         }
@@ -341,7 +345,7 @@ impl ConnAck {
         });
 
         match level {
-            ProtocolLevel::V4 => {
+            ProtocolLevel::V3 | ProtocolLevel::V4 => {
                 data.put_u8(Into::<ConnectReasonCodeV4>::into(self.reason_code).into());
             }
             ProtocolLevel::V5 => {
@@ -354,16 +358,21 @@ impl ConnAck {
         Ok(())
     }
 
-    pub(crate) fn decode(mut data: Bytes, level: ProtocolLevel) -> Result<Self, DecodeError> {
+    pub(crate) fn decode(
+        mut data: Bytes,
+        level: ProtocolLevel,
+        options: &DecodeOptions,
+    ) -> Result<Self, DecodeError> {
         let flag = data.read_u8()?;
-        if flag & 0b11111110 > 0 {
-            return Err(DecodeError::MalformedPacket);
-        }
+        ensure!(
+            !options.reject_reserved_flags || flag & 0b11111110 == 0,
+            DecodeError::MalformedPacket
+        );
         let session_present = flag & 0x1 > 0;
         let n_reason_code = data.read_u8()?;
 
         let (reason_code, properties) = match level {
-            ProtocolLevel::V4 => {
+            ProtocolLevel::V3 | ProtocolLevel::V4 => {
                 let reason_code = TryInto::<ConnectReasonCodeV4>::try_into(n_reason_code)
                     .map_err(|_| DecodeError::InvalidConnAckReasonCode(n_reason_code))?
                     .into();
@@ -381,7 +390,7 @@ impl ConnAck {
                 );
                 (
                     reason_code,
-                    ConnAckProperties::decode(data.split_to(properties_len))?,
+                    ConnAckProperties::decode(data.split_to(properties_len), options)?,
                 )
             }
         };
@@ -5,9 +5,10 @@ use bytestring::ByteString;
 use serde::{Deserialize, Serialize};
 
 use crate::packet::CONNECT;
+use crate::property::PropertySet;
 use crate::reader::PacketReader;
 use crate::writer::{bytes_remaining_length, PacketWriter};
-use crate::{property, DecodeError, EncodeError, Login, ProtocolLevel, Qos};
+use crate::{property, DecodeError, DecodeOptions, EncodeError, Login, ProtocolLevel, Qos};
 
 const CF_USERNAME: u8 = 0b10000000;
 const CF_PASSWORD: u8 = 0b01000000;
@@ -15,6 +16,7 @@ const CF_WILL_RETAIN: u8 = 0b00100000;
 const CF_WILL_QOS: u8 = 0b00011000;
 const CF_WILL: u8 = 0b00000100;
 const CF_CLEAN_START: u8 = 0b00000010;
+const CF_RESERVED: u8 = 0b00000001;
 
 const QOS_SHIFT: u8 = 3;
 
@@ -61,11 +63,13 @@ impl WillProperties {
         Ok(len)
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = WillProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            seen.insert(flag, options)?;
 
             match flag {
                 property::WILL_DELAY_INTERVAL => properties.delay_interval = Some(data.read_u32()?),
@@ -85,6 +89,7 @@ impl WillProperties {
                     let value = data.read_string()?;
                     properties.user_properties.push((key, value));
                 }
+                _ if !options.reject_unknown_properties => break,
                 _ => return Err(DecodeError::InvalidWillProperty(flag)),
             }
         }
@@ -168,11 +173,13 @@ impl ConnectProperties {
         Ok(len)
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = ConnectProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            seen.insert(flag, options)?;
 
             match flag {
                 property::SESSION_EXPIRY_INTERVAL => {
@@ -202,6 +209,7 @@ impl ConnectProperties {
                 property::AUTHENTICATION_DATA => {
                     properties.authentication_data = Some(data.read_binary()?)
                 }
+                _ if !options.reject_unknown_properties => break,
                 _ => return Err(DecodeError::InvalidConnectProperty(flag)),
             }
         }
@@ -280,12 +288,20 @@ fn default_keep_alive() -> u16 {
     60
 }
 
+#[inline]
+fn protocol_name(level: ProtocolLevel) -> &'static str {
+    match level {
+        ProtocolLevel::V3 => "MQIsdp",
+        ProtocolLevel::V4 | ProtocolLevel::V5 => "MQTT",
+    }
+}
+
 impl Connect {
     #[inline]
     fn variable_header_length(&self, level: ProtocolLevel) -> Result<usize, EncodeError> {
         let mut len =
             // protocol
-            2 + 4 +
+            2 + protocol_name(level).len() +
             // level
             1 +
             // flags
@@ -321,9 +337,7 @@ impl Connect {
         }
 
         if let Some(login) = &self.login {
-            if !login.username.is_empty() {
-                len += 2 + login.username.len();
-            }
+            len += 2 + login.username.len();
             if !login.password.is_empty() {
                 len += 2 + login.password.len();
             }
@@ -332,18 +346,36 @@ impl Connect {
         Ok(len)
     }
 
-    pub(crate) fn decode(mut data: Bytes, _level: ProtocolLevel) -> Result<Self, DecodeError> {
+    pub(crate) fn decode(
+        mut data: Bytes,
+        _level: ProtocolLevel,
+        options: &DecodeOptions,
+    ) -> Result<Self, DecodeError> {
         // parse header
         let protocol = data.read_string()?;
-        ensure!(protocol == "MQTT", DecodeError::InvalidProtocol(protocol));
+        ensure!(
+            protocol == "MQTT" || protocol == "MQIsdp",
+            DecodeError::InvalidProtocol(protocol)
+        );
 
         let n_level = data.read_u8()?;
         let level = n_level
             .try_into()
             .map_err(|_| DecodeError::UnsupportedProtocolLevel(n_level))?;
+        ensure!(
+            protocol == protocol_name(level),
+            DecodeError::InvalidProtocol(protocol)
+        );
 
         let connect_flags = data.read_u8()?;
 
+        // The Server MUST validate that the reserved flag in the CONNECT packet is set to 0
+        // [MQTT-3.1.2-3].
+        ensure!(
+            !options.reject_reserved_flags || connect_flags & CF_RESERVED == 0,
+            DecodeError::InvalidConnectFlags
+        );
+
         if connect_flags & CF_WILL == 0 {
             // If the Will Flag is set to 0, then the Will QoS MUST be set to 0 (0x00) [MQTT-3.1.2-11].
             ensure!(
@@ -375,22 +407,21 @@ impl Connect {
                 data.remaining() >= properties_len,
                 DecodeError::MalformedPacket
             );
-            properties = ConnectProperties::decode(data.split_to(properties_len))?;
+            properties = ConnectProperties::decode(data.split_to(properties_len), options)?;
         };
 
         // parse payload
         let client_id = data.read_string()?;
 
         let last_will = if connect_flags & CF_WILL > 0 {
-            let will_properties_len = data.read_remaining_length()?;
-            ensure!(
-                data.remaining() >= will_properties_len,
-                DecodeError::MalformedPacket
-            );
-
             let mut properties = WillProperties::default();
             if level == ProtocolLevel::V5 {
-                properties = WillProperties::decode(data.split_to(will_properties_len))?;
+                let will_properties_len = data.read_remaining_length()?;
+                ensure!(
+                    data.remaining() >= will_properties_len,
+                    DecodeError::MalformedPacket
+                );
+                properties = WillProperties::decode(data.split_to(will_properties_len), options)?;
             }
 
             let topic = data.read_string()?;
@@ -413,7 +444,7 @@ impl Connect {
                 None
             };
             let password = if connect_flags & CF_PASSWORD > 0 {
-                Some(data.read_string()?)
+                Some(data.read_binary()?)
             } else {
                 None
             };
@@ -448,7 +479,7 @@ impl Connect {
         data.write_remaining_length(size)?;
 
         // write variable header
-        data.write_string("MQTT")?;
+        data.write_string(protocol_name(level))?;
         data.put_u8(level.into());
 
         let mut flag = 0;
@@ -463,9 +494,7 @@ impl Connect {
             }
         }
         if let Some(login) = &self.login {
-            if !login.username.is_empty() {
-                flag |= CF_USERNAME;
-            }
+            flag |= CF_USERNAME;
             if !login.password.is_empty() {
                 flag |= CF_PASSWORD;
             }
@@ -496,11 +525,9 @@ impl Connect {
         }
 
         if let Some(login) = &self.login {
-            if !login.username.is_empty() {
-                data.write_string(&login.username)?;
-            }
+            data.write_string(&login.username)?;
             if !login.password.is_empty() {
-                data.write_string(&login.password)?;
+                data.write_binary(&login.password)?;
             }
         }
 
@@ -0,0 +1,543 @@
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::packet::PUBLISH;
+use crate::{DecodeError, DecodeOptions, Packet, ProtocolLevel, Publish};
+
+#[derive(Debug, Copy, Clone)]
+enum DecoderState {
+    Flag,
+    Length(u8),
+    Body(u8, usize),
+    /// The remaining size of a streamed PUBLISH payload.
+    Payload(usize),
+}
+
+/// An item returned by [`Decoder::decode_stream`].
+#[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum StreamItem {
+    /// A complete packet and its size without the fixed header.
+    Packet(Packet, usize),
+
+    /// A PUBLISH packet with an empty payload, followed by [`StreamItem::PayloadChunk`] items
+    /// with the given number of bytes in total.
+    PublishHeader(Publish, usize),
+
+    /// A part of the payload of the last [`StreamItem::PublishHeader`].
+    PayloadChunk(Bytes),
+}
+
+/// Decodes packets from the bytes fed to it, without reading them from a stream.
+pub struct Decoder {
+    level: ProtocolLevel,
+    input_max_size: usize,
+    options: DecodeOptions,
+    buf: BytesMut,
+    state: DecoderState,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            level: ProtocolLevel::V4,
+            input_max_size: usize::MAX,
+            options: DecodeOptions::default(),
+            buf: BytesMut::new(),
+            state: DecoderState::Flag,
+        }
+    }
+}
+
+impl Decoder {
+    #[inline]
+    pub fn protocol_level(&self) -> ProtocolLevel {
+        self.level
+    }
+
+    /// Sets the protocol level used to decode packets, it is otherwise taken from the CONNECT
+    /// packet.
+    #[inline]
+    pub fn set_protocol_level(&mut self, level: ProtocolLevel) {
+        self.level = level;
+    }
+
+    #[inline]
+    pub fn set_input_max_size(&mut self, size: usize) {
+        self.input_max_size = size;
+    }
+
+    #[inline]
+    pub fn set_decode_options(&mut self, options: DecodeOptions) {
+        self.options = options;
+    }
+
+    /// Appends the bytes to the buffer of the decoder.
+    #[inline]
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Returns `true` if a packet has been partially received.
+    #[inline]
+    pub fn is_partial(&self) -> bool {
+        !matches!(self.state, DecoderState::Flag) || !self.buf.is_empty()
+    }
+
+    /// Returns the number of bytes that are at least needed to decode the next packet, after
+    /// [`Decoder::decode`] returned `None`.
+    ///
+    /// It is the exact number once the remaining length of the packet has been received.
+    pub fn needed_bytes(&self) -> usize {
+        match self.state {
+            // the fixed header is at least two bytes
+            DecoderState::Flag => 2usize.saturating_sub(self.buf.len()),
+            DecoderState::Length(_) | DecoderState::Payload(_) => 1,
+            DecoderState::Body(_, packet_size) => packet_size.saturating_sub(self.buf.len()),
+        }
+    }
+
+    /// Decodes the next packet and returns it with its size without the fixed header, returns
+    /// `None` if more bytes are needed.
+    pub fn decode(&mut self) -> Result<Option<(Packet, usize)>, DecodeError> {
+        let mut buf = std::mem::take(&mut self.buf);
+        let res = self.decode_buf(&mut buf);
+        self.buf = buf;
+        res
+    }
+
+    /// Like [`Decoder::decode`], but decodes from a buffer owned by the caller instead of the
+    /// bytes fed to the decoder, the consumed bytes are removed from the buffer.
+    ///
+    /// The decoder keeps the state of a partially received packet, so the same buffer must be
+    /// passed until it returns a packet.
+    pub fn decode_buf(
+        &mut self,
+        buf: &mut BytesMut,
+    ) -> Result<Option<(Packet, usize)>, DecodeError> {
+        match self.decode_item(buf, usize::MAX)? {
+            Some(StreamItem::Packet(packet, packet_size)) => Ok(Some((packet, packet_size))),
+            Some(_) => panic!("a streamed payload must be decoded with `decode_stream`"),
+            None => Ok(None),
+        }
+    }
+
+    /// Like [`Decoder::decode`], but the payload of a PUBLISH packet larger than `threshold`
+    /// bytes is returned in chunks as soon as they are received, so it does not have to be
+    /// buffered entirely.
+    ///
+    /// [`Decoder::decode`] must not be called until all the chunks have been returned.
+    pub fn decode_stream(&mut self, threshold: usize) -> Result<Option<StreamItem>, DecodeError> {
+        let mut buf = std::mem::take(&mut self.buf);
+        let res = self.decode_item(&mut buf, threshold);
+        self.buf = buf;
+        res
+    }
+
+    fn decode_item(
+        &mut self,
+        buf: &mut BytesMut,
+        threshold: usize,
+    ) -> Result<Option<StreamItem>, DecodeError> {
+        loop {
+            match self.state {
+                DecoderState::Flag => {
+                    if buf.is_empty() {
+                        return Ok(None);
+                    }
+                    self.state = DecoderState::Length(buf.get_u8());
+                }
+                DecoderState::Length(flag) => match get_remaining_length(buf)? {
+                    Some((packet_size, len_size)) => {
+                        if packet_size > self.input_max_size {
+                            return Err(DecodeError::PacketTooLarge);
+                        }
+                        buf.advance(len_size);
+                        self.state = DecoderState::Body(flag, packet_size);
+                    }
+                    None => return Ok(None),
+                },
+                DecoderState::Body(flag, packet_size)
+                    if packet_size > threshold && (flag & 0xf0) >> 4 == PUBLISH =>
+                {
+                    let header_size = publish_header_size(buf, flag, self.level, packet_size)?;
+                    let header_size = match header_size {
+                        Some(header_size) => header_size,
+                        None => return Ok(None),
+                    };
+                    let data = buf.split_to(header_size).freeze();
+                    let payload_size = packet_size - header_size;
+                    self.state = if payload_size > 0 {
+                        DecoderState::Payload(payload_size)
+                    } else {
+                        DecoderState::Flag
+                    };
+                    let publish = Publish::decode(data, self.level, flag, &self.options)?;
+                    return Ok(Some(StreamItem::PublishHeader(publish, payload_size)));
+                }
+                DecoderState::Body(flag, packet_size) => {
+                    if buf.len() < packet_size {
+                        return Ok(None);
+                    }
+                    let data = buf.split_to(packet_size).freeze();
+                    self.state = DecoderState::Flag;
+                    let packet =
+                        Packet::decode_with_options(data, flag, self.level, &self.options)?;
+                    if let Packet::Connect(connect) = &packet {
+                        self.level = connect.level;
+                    }
+                    return Ok(Some(StreamItem::Packet(packet, packet_size)));
+                }
+                DecoderState::Payload(remaining) => {
+                    if buf.is_empty() {
+                        return Ok(None);
+                    }
+                    let chunk = buf.split_to(remaining.min(buf.len())).freeze();
+                    self.state = match remaining - chunk.len() {
+                        0 => DecoderState::Flag,
+                        remaining => DecoderState::Payload(remaining),
+                    };
+                    return Ok(Some(StreamItem::PayloadChunk(chunk)));
+                }
+            }
+        }
+    }
+}
+
+/// Returns the size of the variable header of a PUBLISH packet, or `None` if it has not been
+/// received entirely.
+fn publish_header_size(
+    data: &[u8],
+    flag: u8,
+    level: ProtocolLevel,
+    packet_size: usize,
+) -> Result<Option<usize>, DecodeError> {
+    if data.len() < 2 {
+        return Ok(None);
+    }
+    let mut size = 2 + u16::from_be_bytes([data[0], data[1]]) as usize;
+    if flag & 0b110 != 0 {
+        size += 2;
+    }
+    if level == ProtocolLevel::V5 {
+        let (properties_len, len_size) =
+            match get_remaining_length(data.get(size..).unwrap_or_default())? {
+                Some(res) => res,
+                None => return Ok(None),
+            };
+        size += len_size + properties_len;
+    }
+    ensure!(size <= packet_size, DecodeError::MalformedPacket);
+    Ok(if data.len() >= size { Some(size) } else { None })
+}
+
+#[inline]
+pub(crate) fn get_remaining_length(data: &[u8]) -> Result<Option<(usize, usize)>, DecodeError> {
+    let mut n = 0;
+    let mut shift = 0;
+    let mut bytes = 0;
+
+    for i in 0.. {
+        if i >= data.len() {
+            return Ok(None);
+        }
+
+        let byte = data[i];
+        bytes += 1;
+        n += ((byte & 0x7f) as usize) << shift;
+        let done = (byte & 0x80) == 0;
+        if done {
+            break;
+        }
+        shift += 7;
+        ensure!(shift <= 21, DecodeError::MalformedPacket);
+    }
+
+    Ok(Some((n, bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{
+        Auth, AuthProperties, AuthReasonCode, Connect, ConnectProperties, Login, PubAck, Publish,
+        PublishProperties, Qos,
+    };
+
+    #[test]
+    fn test_need_more() {
+        let publish = Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::AtMostOnce,
+            retain: false,
+            topic: "a/b".into(),
+            packet_id: None,
+            properties: PublishProperties::default(),
+            payload: Bytes::from(vec![1; 200]),
+        });
+        let mut data = BytesMut::new();
+        publish
+            .encode(&mut data, ProtocolLevel::V4, usize::MAX)
+            .unwrap();
+
+        let mut decoder = Decoder::default();
+        assert!(decoder.decode().unwrap().is_none());
+        assert_eq!(decoder.needed_bytes(), 2);
+
+        decoder.feed(&data[..2]);
+        assert!(decoder.decode().unwrap().is_none());
+        assert_eq!(decoder.needed_bytes(), 1);
+
+        decoder.feed(&data[2..3]);
+        let body_size = data.len() - 3;
+        assert!(decoder.decode().unwrap().is_none());
+        assert_eq!(decoder.needed_bytes(), body_size);
+
+        decoder.feed(&data[3..]);
+        let (packet, size) = decoder.decode().unwrap().unwrap();
+        assert_eq!(packet, publish);
+        assert_eq!(size, body_size);
+        assert!(!decoder.is_partial());
+        assert_eq!(decoder.needed_bytes(), 2);
+    }
+
+    #[test]
+    fn test_decode_stream() {
+        let publish = Publish {
+            dup: false,
+            qos: Qos::AtMostOnce,
+            retain: false,
+            topic: "a/b".into(),
+            packet_id: None,
+            properties: PublishProperties::default(),
+            payload: Bytes::from((0..1000).map(|n| n as u8).collect::<Vec<_>>()),
+        };
+        let mut data = BytesMut::new();
+        Packet::Publish(publish.clone())
+            .encode(&mut data, ProtocolLevel::V5, usize::MAX)
+            .unwrap();
+        Packet::PingReq
+            .encode(&mut data, ProtocolLevel::V5, usize::MAX)
+            .unwrap();
+
+        let mut decoder = Decoder::default();
+        decoder.set_protocol_level(ProtocolLevel::V5);
+        let mut items = Vec::new();
+        for chunk in data.chunks(300) {
+            decoder.feed(chunk);
+            while let Some(item) = decoder.decode_stream(100).unwrap() {
+                items.push(item);
+            }
+        }
+
+        let payload_size = match &items[0] {
+            StreamItem::PublishHeader(header, payload_size) => {
+                assert_eq!(header.topic, publish.topic);
+                assert!(header.payload.is_empty());
+                *payload_size
+            }
+            item => panic!("unexpected item: {:?}", item),
+        };
+        assert_eq!(payload_size, 1000);
+        let mut payload = Vec::new();
+        for item in &items[1..items.len() - 1] {
+            match item {
+                StreamItem::PayloadChunk(chunk) => payload.extend_from_slice(chunk),
+                item => panic!("unexpected item: {:?}", item),
+            }
+        }
+        assert_eq!(payload, publish.payload);
+        assert_eq!(items.last(), Some(&StreamItem::Packet(Packet::PingReq, 0)));
+        assert!(!decoder.is_partial());
+    }
+
+    #[test]
+    fn test_decode_options() {
+        let decode = |data: &[u8], options: DecodeOptions| {
+            let mut decoder = Decoder::default();
+            decoder.set_protocol_level(ProtocolLevel::V5);
+            decoder.set_decode_options(options);
+            decoder.feed(data);
+            decoder.decode().map(|res| res.unwrap().0)
+        };
+
+        // reserved flag bits of PINGREQ
+        let data = [0xc1, 0x00];
+        assert!(matches!(
+            decode(&data, DecodeOptions::strict()),
+            Err(DecodeError::MalformedPacket)
+        ));
+        assert_eq!(
+            decode(&data, DecodeOptions::lenient()).unwrap(),
+            Packet::PingReq
+        );
+
+        // PUBACK with a duplicate reason string
+        let data = [0x40, 12, 0, 1, 0, 8, 0x1f, 0, 1, b'a', 0x1f, 0, 1, b'b'];
+        assert!(matches!(
+            decode(&data, DecodeOptions::strict()),
+            Err(DecodeError::DuplicateProperty(0x1f))
+        ));
+        assert!(matches!(
+            decode(&data, DecodeOptions::lenient()).unwrap(),
+            Packet::PubAck(PubAck { properties, .. }) if properties.reason_string.as_deref() == Some("b")
+        ));
+
+        // PUBACK with an unknown property
+        let data = [0x40, 9, 0, 1, 0, 5, 0x1f, 0, 1, b'a', 0x7f];
+        assert!(matches!(
+            decode(&data, DecodeOptions::strict()),
+            Err(DecodeError::InvalidPubAckProperty(0x7f))
+        ));
+        assert!(matches!(
+            decode(&data, DecodeOptions::lenient()).unwrap(),
+            Packet::PubAck(PubAck { properties, .. }) if properties.reason_string.as_deref() == Some("a")
+        ));
+    }
+
+    #[test]
+    fn test_property_limits() {
+        let decode = |packet: &Packet, options: DecodeOptions| {
+            let mut data = BytesMut::new();
+            packet
+                .encode(&mut data, ProtocolLevel::V5, usize::MAX)
+                .unwrap();
+            let mut decoder = Decoder::default();
+            decoder.set_protocol_level(ProtocolLevel::V5);
+            decoder.set_decode_options(options);
+            decoder.feed(&data);
+            decoder.decode().map(|res| res.unwrap().0)
+        };
+        let publish = Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::AtMostOnce,
+            retain: false,
+            topic: "a/b".into(),
+            packet_id: None,
+            properties: PublishProperties {
+                user_properties: vec![("a".into(), "b".into()); 3],
+                subscription_identifiers: (1..=3)
+                    .map(|id| NonZeroUsize::new(id).unwrap())
+                    .collect(),
+                ..PublishProperties::default()
+            },
+            payload: Bytes::new(),
+        });
+
+        let options = DecodeOptions {
+            max_user_properties: Some(3),
+            max_subscription_identifiers: Some(3),
+            max_properties_size: Some(27),
+            ..DecodeOptions::default()
+        };
+        assert_eq!(decode(&publish, options).unwrap(), publish);
+
+        for options in [
+            DecodeOptions {
+                max_user_properties: Some(2),
+                ..options
+            },
+            DecodeOptions {
+                max_subscription_identifiers: Some(2),
+                ..options
+            },
+            DecodeOptions {
+                max_properties_size: Some(26),
+                ..options
+            },
+        ] {
+            assert!(matches!(
+                decode(&publish, options),
+                Err(DecodeError::LimitExceeded)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_mqisdp_connect() {
+        let connect = Packet::Connect(Connect {
+            level: ProtocolLevel::V3,
+            keep_alive: 60,
+            clean_start: true,
+            client_id: "legacy".into(),
+            last_will: None,
+            login: None,
+            properties: ConnectProperties::default(),
+        });
+        let mut data = BytesMut::new();
+        connect
+            .encode(&mut data, ProtocolLevel::V3, usize::MAX)
+            .unwrap();
+        assert_eq!(&data[4..10], b"MQIsdp");
+
+        let mut decoder = Decoder::default();
+        decoder.feed(&data);
+        let (packet, _) = decoder.decode().unwrap().unwrap();
+        assert_eq!(packet, connect);
+        assert_eq!(decoder.protocol_level(), ProtocolLevel::V3);
+
+        // the protocol name must match the protocol level
+        data[10] = 4;
+        let mut decoder = Decoder::default();
+        decoder.feed(&data);
+        assert!(matches!(
+            decoder.decode(),
+            Err(DecodeError::InvalidProtocol(_))
+        ));
+    }
+
+    #[test]
+    fn test_binary_password() {
+        let connect = Packet::Connect(Connect {
+            level: ProtocolLevel::V5,
+            keep_alive: 60,
+            clean_start: true,
+            client_id: "a".into(),
+            last_will: None,
+            login: Some(Login {
+                username: "token".into(),
+                password: Bytes::from_static(&[0xff, 0x00, 0xfe]),
+            }),
+            properties: ConnectProperties::default(),
+        });
+        let mut data = BytesMut::new();
+        connect
+            .encode(&mut data, ProtocolLevel::V5, usize::MAX)
+            .unwrap();
+
+        let mut decoder = Decoder::default();
+        decoder.feed(&data);
+        assert_eq!(decoder.decode().unwrap().unwrap().0, connect);
+    }
+
+    #[test]
+    fn test_auth() {
+        let auth = Packet::Auth(Auth {
+            reason_code: AuthReasonCode::ContinueAuthentication,
+            properties: AuthProperties {
+                authentication_method: Some("SCRAM-SHA-1".into()),
+                authentication_data: Some(Bytes::from_static(b"data")),
+                ..AuthProperties::default()
+            },
+        });
+        let mut data = BytesMut::new();
+        auth.encode(&mut data, ProtocolLevel::V5, usize::MAX)
+            .unwrap();
+
+        let mut decoder = Decoder::default();
+        decoder.set_protocol_level(ProtocolLevel::V5);
+        decoder.feed(&data);
+        assert_eq!(decoder.decode().unwrap().unwrap().0, auth);
+
+        // the packet type is reserved before MQTT 5
+        let mut decoder = Decoder::default();
+        decoder.feed(&data);
+        assert!(matches!(
+            decoder.decode(),
+            Err(DecodeError::UnknownPacketType(_))
+        ));
+    }
+}
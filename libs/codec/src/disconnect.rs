@@ -6,10 +6,11 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
 
 use crate::packet::DISCONNECT;
+use crate::property::PropertySet;
 use crate::reader::PacketReader;
 use crate::writer::bytes_remaining_length;
 use crate::writer::PacketWriter;
-use crate::{property, DecodeError, EncodeError, ProtocolLevel};
+use crate::{property, DecodeError, DecodeOptions, EncodeError, ProtocolLevel};
 
 #[derive(
     Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive, Serialize, Deserialize,
@@ -25,6 +26,7 @@ pub enum DisconnectReasonCode {
     NotAuthorized = 0x87,
     ServerBusy = 0x89,
     ServerShuttingDown = 0x8B,
+    BadAuthenticationMethod = 0x8C,
     KeepAliveTimeout = 0x8D,
     SessionTakenOver = 0x8E,
     TopicFilterInvalid = 0x8F,
@@ -105,11 +107,13 @@ impl DisconnectProperties {
         Ok(())
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = DisconnectProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            seen.insert(flag, options)?;
 
             match flag {
                 property::SESSION_EXPIRY_INTERVAL => {
@@ -124,6 +128,7 @@ impl DisconnectProperties {
                 property::SERVER_REFERENCE => {
                     properties.server_reference = Some(data.read_string()?)
                 }
+                _ if !options.reject_unknown_properties => break,
                 _ => return Err(DecodeError::InvalidDisconnectProperty(flag)),
             }
         }
@@ -163,7 +168,7 @@ impl Disconnect {
     #[inline]
     fn variable_header_length(&self, level: ProtocolLevel) -> Result<usize, EncodeError> {
         match level {
-            ProtocolLevel::V4 => Ok(0),
+            ProtocolLevel::V3 | ProtocolLevel::V4 => Ok(0),
             ProtocolLevel::V5 => {
                 if !self.properties.is_empty() {
                     let properties_len = self.properties.bytes_length()?;
@@ -186,9 +191,13 @@ impl Disconnect {
         Ok(0)
     }
 
-    pub(crate) fn decode(mut data: Bytes, level: ProtocolLevel) -> Result<Self, DecodeError> {
+    pub(crate) fn decode(
+        mut data: Bytes,
+        level: ProtocolLevel,
+        options: &DecodeOptions,
+    ) -> Result<Self, DecodeError> {
         match level {
-            ProtocolLevel::V4 => {
+            ProtocolLevel::V3 | ProtocolLevel::V4 => {
                 if !data.is_empty() {
                     return Err(DecodeError::MalformedPacket);
                 }
@@ -217,7 +226,7 @@ impl Disconnect {
                         data.remaining() >= properties_len,
                         DecodeError::MalformedPacket
                     );
-                    DisconnectProperties::decode(data.split_to(properties_len))?
+                    DisconnectProperties::decode(data.split_to(properties_len), options)?
                 } else {
                     DisconnectProperties::default()
                 };
@@ -66,6 +66,12 @@ pub enum DecodeError {
     #[error("invalid pub comp property: {0}")]
     InvalidPubCompProperty(u8),
 
+    #[error("invalid auth property: {0}")]
+    InvalidAuthProperty(u8),
+
+    #[error("duplicate property: {0}")]
+    DuplicateProperty(u8),
+
     #[error("invalid conn ack reason code: {0}")]
     InvalidConnAckReasonCode(u8),
 
@@ -90,12 +96,23 @@ pub enum DecodeError {
     #[error("invalid unsub ack reason code: {0}")]
     InvalidUnsubAckReasonCode(u8),
 
+    #[error("invalid auth reason code: {0}")]
+    InvalidAuthReasonCode(u8),
+
     #[error("invalid packet id: 0")]
     InvalidPacketId,
 
     #[error("invalid topic alias: 0")]
     InvalidTopicAlias,
 
+    /// A limit of the [`DecodeOptions`](crate::DecodeOptions) has been exceeded.
+    #[error("limit exceeded")]
+    LimitExceeded,
+
+    /// No bytes have been received within the read timeout of the [`Codec`](crate::Codec).
+    #[error("read timeout")]
+    ReadTimeout,
+
     #[error("io: {0}")]
     Io(#[from] std::io::Error),
 }
@@ -0,0 +1,119 @@
+use bytes::BytesMut;
+
+use crate::decoder::Decoder;
+use crate::{DecodeError, DecodeOptions, EncodeError, Packet, ProtocolLevel};
+
+/// Implements [`tokio_util::codec::Decoder`] and [`tokio_util::codec::Encoder`], so that
+/// packets can be read and written with `Framed` on any transport.
+pub struct PacketCodec {
+    decoder: Decoder,
+    output_max_size: usize,
+}
+
+impl Default for PacketCodec {
+    fn default() -> Self {
+        Self {
+            decoder: Decoder::default(),
+            output_max_size: usize::MAX,
+        }
+    }
+}
+
+impl PacketCodec {
+    #[inline]
+    pub fn protocol_level(&self) -> ProtocolLevel {
+        self.decoder.protocol_level()
+    }
+
+    /// Sets the protocol level used to encode and decode packets, it is otherwise taken from
+    /// the CONNECT packet.
+    #[inline]
+    pub fn set_protocol_level(&mut self, level: ProtocolLevel) {
+        self.decoder.set_protocol_level(level);
+    }
+
+    #[inline]
+    pub fn set_input_max_size(&mut self, size: usize) {
+        self.decoder.set_input_max_size(size);
+    }
+
+    #[inline]
+    pub fn set_decode_options(&mut self, options: DecodeOptions) {
+        self.decoder.set_decode_options(options);
+    }
+
+    #[inline]
+    pub fn set_output_max_size(&mut self, size: usize) {
+        self.output_max_size = size;
+    }
+}
+
+impl tokio_util::codec::Decoder for PacketCodec {
+    type Item = (Packet, usize);
+    type Error = DecodeError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decoder.decode_buf(src)
+    }
+}
+
+impl tokio_util::codec::Encoder<&Packet> for PacketCodec {
+    type Error = EncodeError;
+
+    fn encode(&mut self, packet: &Packet, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        if let Packet::Connect(connect) = packet {
+            self.decoder.set_protocol_level(connect.level);
+        }
+        packet.encode(dst, self.decoder.protocol_level(), self.output_max_size)
+    }
+}
+
+impl tokio_util::codec::Encoder<Packet> for PacketCodec {
+    type Error = EncodeError;
+
+    #[inline]
+    fn encode(&mut self, packet: Packet, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode(&packet, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::codec::{Decoder as _, Encoder as _};
+
+    use super::*;
+    use crate::{Connect, ConnectProperties};
+
+    #[test]
+    fn test_packet_codec() {
+        let connect = Packet::Connect(Connect {
+            level: ProtocolLevel::V5,
+            keep_alive: 60,
+            clean_start: true,
+            client_id: "a".into(),
+            last_will: None,
+            login: None,
+            properties: ConnectProperties::default(),
+        });
+        let ping = Packet::PingReq;
+
+        let mut codec = PacketCodec::default();
+        let mut buf = BytesMut::new();
+        codec.encode(&connect, &mut buf).unwrap();
+        codec.encode(&ping, &mut buf).unwrap();
+        assert_eq!(codec.protocol_level(), ProtocolLevel::V5);
+
+        // feed the bytes one by one
+        let mut codec = PacketCodec::default();
+        let mut src = BytesMut::new();
+        let mut packets = Vec::new();
+        for byte in buf {
+            src.extend_from_slice(&[byte]);
+            if let Some((packet, _)) = codec.decode(&mut src).unwrap() {
+                packets.push(packet);
+            }
+        }
+        assert_eq!(packets, vec![connect, ping]);
+        assert_eq!(codec.protocol_level(), ProtocolLevel::V5);
+    }
+}
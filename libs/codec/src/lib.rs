@@ -3,11 +3,18 @@
 
 #[macro_use]
 mod macros;
+mod auth;
+#[cfg(feature = "tokio")]
 mod codec;
 mod connack;
 mod connect;
+mod decoder;
 mod disconnect;
 mod error;
+#[cfg(feature = "tokio-util")]
+mod framed;
+pub mod mqttsn;
+mod options;
 mod packet;
 mod packet_id_allocator;
 mod property;
@@ -19,16 +26,23 @@ mod pubrel;
 mod reader;
 mod suback;
 mod subscribe;
+mod summary;
 mod types;
 mod unsuback;
 mod unsubscribe;
 mod writer;
 
+pub use auth::{Auth, AuthProperties, AuthReasonCode};
+#[cfg(feature = "tokio")]
 pub use codec::Codec;
 pub use connack::{ConnAck, ConnAckProperties, ConnectReasonCode};
 pub use connect::{Connect, ConnectProperties, LastWill, WillProperties};
+pub use decoder::{Decoder, StreamItem};
 pub use disconnect::{Disconnect, DisconnectProperties, DisconnectReasonCode};
 pub use error::{DecodeError, EncodeError};
+#[cfg(feature = "tokio-util")]
+pub use framed::PacketCodec;
+pub use options::DecodeOptions;
 pub use packet::Packet;
 pub use packet_id_allocator::PacketIdAllocator;
 pub use puback::{PubAck, PubAckProperties, PubAckReasonCode};
@@ -38,6 +52,7 @@ pub use pubrec::{PubRec, PubRecProperties, PubRecReasonCode};
 pub use pubrel::{PubRel, PubRelProperties, PubRelReasonCode};
 pub use suback::{SubAck, SubAckProperties, SubscribeReasonCode};
 pub use subscribe::{RetainHandling, Subscribe, SubscribeFilter, SubscribeProperties};
+pub use summary::PacketSummary;
 pub use types::{Login, ProtocolLevel, Qos};
 pub use unsuback::{UnsubAck, UnsubAckProperties, UnsubAckReasonCode};
 pub use unsubscribe::{Unsubscribe, UnsubscribeProperties};
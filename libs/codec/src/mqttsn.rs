@@ -0,0 +1,446 @@
+//! MQTT-SN 1.2 packets, the subset needed by a gateway for clients that publish.
+
+use std::convert::TryInto;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytestring::ByteString;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{Deserialize, Serialize};
+
+use crate::{DecodeError, EncodeError};
+
+pub const CONNECT: u8 = 0x04;
+pub const CONNACK: u8 = 0x05;
+pub const REGISTER: u8 = 0x0a;
+pub const REGACK: u8 = 0x0b;
+pub const PUBLISH: u8 = 0x0c;
+pub const PUBACK: u8 = 0x0d;
+pub const PUBCOMP: u8 = 0x0e;
+pub const PUBREC: u8 = 0x0f;
+pub const PUBREL: u8 = 0x10;
+pub const PINGREQ: u8 = 0x16;
+pub const PINGRESP: u8 = 0x17;
+pub const DISCONNECT: u8 = 0x18;
+
+const PROTOCOL_ID: u8 = 0x01;
+
+const FLAG_DUP: u8 = 0b10000000;
+const FLAG_QOS: u8 = 0b01100000;
+const FLAG_RETAIN: u8 = 0b00010000;
+const FLAG_WILL: u8 = 0b00001000;
+const FLAG_CLEAN_SESSION: u8 = 0b00000100;
+const FLAG_TOPIC_ID_TYPE: u8 = 0b00000011;
+
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, IntoPrimitive, TryFromPrimitive, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum ReturnCode {
+    Accepted = 0x00,
+    Congestion = 0x01,
+    InvalidTopicId = 0x02,
+    NotSupported = 0x03,
+}
+
+/// QoS of a PUBLISH, `NoSession` (QoS -1) is sent by clients without a connection.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Qos {
+    AtMostOnce,
+    AtLeastOnce,
+    ExactlyOnce,
+    NoSession,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TopicId {
+    /// An id assigned with REGISTER.
+    Normal(u16),
+    /// An id known in advance by the client and the gateway.
+    Predefined(u16),
+    /// A topic name of two characters.
+    Short([u8; 2]),
+}
+
+impl TopicId {
+    fn decode(flags: u8, id: u16) -> Self {
+        match flags & FLAG_TOPIC_ID_TYPE {
+            0b01 => TopicId::Predefined(id),
+            0b10 => TopicId::Short(id.to_be_bytes()),
+            _ => TopicId::Normal(id),
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        match self {
+            TopicId::Normal(_) => 0b00,
+            TopicId::Predefined(_) => 0b01,
+            TopicId::Short(_) => 0b10,
+        }
+    }
+
+    /// The value of the Topic Id field.
+    pub fn value(&self) -> u16 {
+        match self {
+            TopicId::Normal(id) | TopicId::Predefined(id) => *id,
+            TopicId::Short(name) => u16::from_be_bytes(*name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Connect {
+    pub will: bool,
+    pub clean_session: bool,
+    /// The keep alive in seconds.
+    pub duration: u16,
+    pub client_id: ByteString,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Register {
+    /// Set to 0 by the clients.
+    pub topic_id: u16,
+    pub msg_id: u16,
+    pub topic_name: ByteString,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegAck {
+    pub topic_id: u16,
+    pub msg_id: u16,
+    pub return_code: ReturnCode,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Publish {
+    pub dup: bool,
+    pub qos: Qos,
+    pub retain: bool,
+    pub topic_id: TopicId,
+    /// Set to 0 if QoS is 0 or -1.
+    pub msg_id: u16,
+    pub data: Bytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PubAck {
+    pub topic_id: u16,
+    pub msg_id: u16,
+    pub return_code: ReturnCode,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Packet {
+    Connect(Connect),
+    ConnAck {
+        return_code: ReturnCode,
+    },
+    Register(Register),
+    RegAck(RegAck),
+    Publish(Publish),
+    PubAck(PubAck),
+    PubRec {
+        msg_id: u16,
+    },
+    PubRel {
+        msg_id: u16,
+    },
+    PubComp {
+        msg_id: u16,
+    },
+    PingReq {
+        /// Only sent by sleeping clients.
+        #[serde(default)]
+        client_id: Option<ByteString>,
+    },
+    PingResp,
+    Disconnect {
+        /// The sleep duration of the client.
+        #[serde(default)]
+        duration: Option<u16>,
+    },
+}
+
+fn read_u8(data: &mut Bytes) -> Result<u8, DecodeError> {
+    ensure!(data.remaining() >= 1, DecodeError::MalformedPacket);
+    Ok(data.get_u8())
+}
+
+fn read_u16(data: &mut Bytes) -> Result<u16, DecodeError> {
+    ensure!(data.remaining() >= 2, DecodeError::MalformedPacket);
+    Ok(data.get_u16())
+}
+
+fn read_return_code(data: &mut Bytes) -> Result<ReturnCode, DecodeError> {
+    read_u8(data)?
+        .try_into()
+        .map_err(|_| DecodeError::MalformedPacket)
+}
+
+fn into_string(data: Bytes) -> Result<ByteString, DecodeError> {
+    data.try_into().map_err(|_| DecodeError::MalformedPacket)
+}
+
+impl Packet {
+    /// Decodes a datagram that contains exactly one packet.
+    pub fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+        let (len, header_len) = match read_u8(&mut data)? {
+            0x01 => (read_u16(&mut data)? as usize, 3),
+            len => (len as usize, 1),
+        };
+        ensure!(
+            len > header_len && data.remaining() == len - header_len,
+            DecodeError::MalformedPacket
+        );
+
+        let packet = match read_u8(&mut data)? {
+            CONNECT => {
+                let flags = read_u8(&mut data)?;
+                ensure!(
+                    read_u8(&mut data)? == PROTOCOL_ID,
+                    DecodeError::MalformedPacket
+                );
+                let duration = read_u16(&mut data)?;
+                Packet::Connect(Connect {
+                    will: flags & FLAG_WILL > 0,
+                    clean_session: flags & FLAG_CLEAN_SESSION > 0,
+                    duration,
+                    client_id: into_string(data)?,
+                })
+            }
+            CONNACK => Packet::ConnAck {
+                return_code: read_return_code(&mut data)?,
+            },
+            REGISTER => Packet::Register(Register {
+                topic_id: read_u16(&mut data)?,
+                msg_id: read_u16(&mut data)?,
+                topic_name: into_string(data)?,
+            }),
+            REGACK => Packet::RegAck(RegAck {
+                topic_id: read_u16(&mut data)?,
+                msg_id: read_u16(&mut data)?,
+                return_code: read_return_code(&mut data)?,
+            }),
+            PUBLISH => {
+                let flags = read_u8(&mut data)?;
+                let topic_id = TopicId::decode(flags, read_u16(&mut data)?);
+                let msg_id = read_u16(&mut data)?;
+                Packet::Publish(Publish {
+                    dup: flags & FLAG_DUP > 0,
+                    qos: match (flags & FLAG_QOS) >> 5 {
+                        0 => Qos::AtMostOnce,
+                        1 => Qos::AtLeastOnce,
+                        2 => Qos::ExactlyOnce,
+                        _ => Qos::NoSession,
+                    },
+                    retain: flags & FLAG_RETAIN > 0,
+                    topic_id,
+                    msg_id,
+                    data,
+                })
+            }
+            PUBACK => Packet::PubAck(PubAck {
+                topic_id: read_u16(&mut data)?,
+                msg_id: read_u16(&mut data)?,
+                return_code: read_return_code(&mut data)?,
+            }),
+            PUBREC => Packet::PubRec {
+                msg_id: read_u16(&mut data)?,
+            },
+            PUBREL => Packet::PubRel {
+                msg_id: read_u16(&mut data)?,
+            },
+            PUBCOMP => Packet::PubComp {
+                msg_id: read_u16(&mut data)?,
+            },
+            PINGREQ if data.is_empty() => Packet::PingReq { client_id: None },
+            PINGREQ => Packet::PingReq {
+                client_id: Some(into_string(data)?),
+            },
+            PINGRESP => Packet::PingResp,
+            DISCONNECT if data.is_empty() => Packet::Disconnect { duration: None },
+            DISCONNECT => Packet::Disconnect {
+                duration: Some(read_u16(&mut data)?),
+            },
+            n => return Err(DecodeError::UnknownPacketType(n)),
+        };
+        Ok(packet)
+    }
+
+    pub fn encode(&self, data: &mut BytesMut) -> Result<(), EncodeError> {
+        let mut body = BytesMut::new();
+        let msg_type = match self {
+            Packet::Connect(connect) => {
+                let mut flags = 0;
+                if connect.will {
+                    flags |= FLAG_WILL;
+                }
+                if connect.clean_session {
+                    flags |= FLAG_CLEAN_SESSION;
+                }
+                body.put_u8(flags);
+                body.put_u8(PROTOCOL_ID);
+                body.put_u16(connect.duration);
+                body.put_slice(connect.client_id.as_bytes());
+                CONNECT
+            }
+            Packet::ConnAck { return_code } => {
+                body.put_u8((*return_code).into());
+                CONNACK
+            }
+            Packet::Register(register) => {
+                body.put_u16(register.topic_id);
+                body.put_u16(register.msg_id);
+                body.put_slice(register.topic_name.as_bytes());
+                REGISTER
+            }
+            Packet::RegAck(reg_ack) => {
+                body.put_u16(reg_ack.topic_id);
+                body.put_u16(reg_ack.msg_id);
+                body.put_u8(reg_ack.return_code.into());
+                REGACK
+            }
+            Packet::Publish(publish) => {
+                let mut flags = publish.topic_id.flags();
+                if publish.dup {
+                    flags |= FLAG_DUP;
+                }
+                flags |= match publish.qos {
+                    Qos::AtMostOnce => 0,
+                    Qos::AtLeastOnce => 1,
+                    Qos::ExactlyOnce => 2,
+                    Qos::NoSession => 3,
+                } << 5;
+                if publish.retain {
+                    flags |= FLAG_RETAIN;
+                }
+                body.put_u8(flags);
+                body.put_u16(publish.topic_id.value());
+                body.put_u16(publish.msg_id);
+                body.put_slice(&publish.data);
+                PUBLISH
+            }
+            Packet::PubAck(pub_ack) => {
+                body.put_u16(pub_ack.topic_id);
+                body.put_u16(pub_ack.msg_id);
+                body.put_u8(pub_ack.return_code.into());
+                PUBACK
+            }
+            Packet::PubRec { msg_id } => {
+                body.put_u16(*msg_id);
+                PUBREC
+            }
+            Packet::PubRel { msg_id } => {
+                body.put_u16(*msg_id);
+                PUBREL
+            }
+            Packet::PubComp { msg_id } => {
+                body.put_u16(*msg_id);
+                PUBCOMP
+            }
+            Packet::PingReq { client_id } => {
+                if let Some(client_id) = client_id {
+                    body.put_slice(client_id.as_bytes());
+                }
+                PINGREQ
+            }
+            Packet::PingResp => PINGRESP,
+            Packet::Disconnect { duration } => {
+                if let Some(duration) = duration {
+                    body.put_u16(*duration);
+                }
+                DISCONNECT
+            }
+        };
+
+        // the length includes the length field and the message type
+        let len = body.len() + 2;
+        if len <= 0xff {
+            data.put_u8(len as u8);
+        } else {
+            ensure!(len + 2 <= u16::MAX as usize, EncodeError::PacketTooLarge);
+            data.put_u8(0x01);
+            data.put_u16((len + 2) as u16);
+        }
+        data.put_u8(msg_type);
+        data.put_slice(&body);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(packet: Packet) -> BytesMut {
+        let mut data = BytesMut::new();
+        packet.encode(&mut data).unwrap();
+        assert_eq!(Packet::decode(data.clone().freeze()).unwrap(), packet);
+        let json = serde_json::to_string(&packet).unwrap();
+        assert_eq!(serde_json::from_str::<Packet>(&json).unwrap(), packet);
+        data
+    }
+
+    #[test]
+    fn test_packets() {
+        let data = round_trip(Packet::Connect(Connect {
+            will: false,
+            clean_session: true,
+            duration: 60,
+            client_id: "sensor".into(),
+        }));
+        assert_eq!(&data[..], b"\x0c\x04\x04\x01\x00\x3csensor");
+
+        round_trip(Packet::ConnAck {
+            return_code: ReturnCode::Accepted,
+        });
+        round_trip(Packet::Register(Register {
+            topic_id: 0,
+            msg_id: 1,
+            topic_name: "a/b".into(),
+        }));
+        round_trip(Packet::RegAck(RegAck {
+            topic_id: 1,
+            msg_id: 1,
+            return_code: ReturnCode::Accepted,
+        }));
+        let data = round_trip(Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::NoSession,
+            retain: true,
+            topic_id: TopicId::Short(*b"ab"),
+            msg_id: 0,
+            data: Bytes::from_static(b"22.5"),
+        }));
+        assert_eq!(data[2], 0b01110010);
+        round_trip(Packet::PubAck(PubAck {
+            topic_id: 1,
+            msg_id: 2,
+            return_code: ReturnCode::InvalidTopicId,
+        }));
+        round_trip(Packet::PubRel { msg_id: 3 });
+        round_trip(Packet::PingReq { client_id: None });
+        round_trip(Packet::PingReq {
+            client_id: Some("sensor".into()),
+        });
+        round_trip(Packet::Disconnect { duration: Some(10) });
+
+        // the three bytes length field
+        round_trip(Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::AtLeastOnce,
+            retain: false,
+            topic_id: TopicId::Normal(1),
+            msg_id: 1,
+            data: Bytes::from(vec![0; 1000]),
+        }));
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(Packet::decode(Bytes::from_static(b"\x03\x16")).is_err());
+        assert!(Packet::decode(Bytes::from_static(b"\x02\x0d")).is_err());
+        assert!(Packet::decode(Bytes::from_static(b"\x02\xff")).is_err());
+    }
+}
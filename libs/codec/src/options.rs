@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+/// Controls how strictly the packets are checked while decoding, and the limits on their
+/// properties.
+///
+/// The default is strict and conforms to the specification, the lenient options tolerate
+/// clients that send slightly malformed packets, neither limits the properties.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecodeOptions {
+    /// Rejects the packets that contain unknown properties, otherwise the properties following
+    /// an unknown one are ignored.
+    pub reject_unknown_properties: bool,
+
+    /// Rejects the packets whose reserved flag bits are not set to the specified values.
+    pub reject_reserved_flags: bool,
+
+    /// Rejects the packets that contain a property more than once, otherwise the last value is
+    /// used.
+    pub reject_duplicate_properties: bool,
+
+    /// Rejects the topic names and topic filters that contain control characters (U+0001 to
+    /// U+001F and U+007F to U+009F), the specification only discourages them [MQTT-1.5.4-1].
+    pub reject_control_characters: bool,
+
+    /// The maximum number of User Properties in the properties of a packet.
+    pub max_user_properties: Option<usize>,
+
+    /// The maximum number of Subscription Identifiers in a PUBLISH packet.
+    pub max_subscription_identifiers: Option<usize>,
+
+    /// The maximum size in bytes of the properties of a packet, the will properties of a
+    /// CONNECT packet are checked separately.
+    pub max_properties_size: Option<usize>,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self::strict()
+    }
+}
+
+impl DecodeOptions {
+    pub fn strict() -> Self {
+        Self {
+            reject_unknown_properties: true,
+            reject_reserved_flags: true,
+            reject_duplicate_properties: true,
+            reject_control_characters: true,
+            max_user_properties: None,
+            max_subscription_identifiers: None,
+            max_properties_size: None,
+        }
+    }
+
+    pub fn lenient() -> Self {
+        Self {
+            reject_unknown_properties: false,
+            reject_reserved_flags: false,
+            reject_duplicate_properties: false,
+            reject_control_characters: false,
+            max_user_properties: None,
+            max_subscription_identifiers: None,
+            max_properties_size: None,
+        }
+    }
+}
@@ -1,9 +1,10 @@
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
 
+use crate::decoder::get_remaining_length;
 use crate::{
-    ConnAck, Connect, DecodeError, Disconnect, EncodeError, ProtocolLevel, PubAck, PubComp, PubRec,
-    PubRel, Publish, SubAck, Subscribe, UnsubAck, Unsubscribe,
+    Auth, ConnAck, Connect, DecodeError, DecodeOptions, Disconnect, EncodeError, ProtocolLevel,
+    PubAck, PubComp, PubRec, PubRel, Publish, SubAck, Subscribe, UnsubAck, Unsubscribe,
 };
 
 pub const RESERVED: u8 = 0;
@@ -21,7 +22,7 @@ pub const UNSUBACK: u8 = 11;
 pub const PINGREQ: u8 = 12;
 pub const PINGRESP: u8 = 13;
 pub const DISCONNECT: u8 = 14;
-// const AUTH: u8 = 15;
+pub const AUTH: u8 = 15;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", rename_all = "lowercase")]
@@ -40,31 +41,89 @@ pub enum Packet {
     PingReq,
     PingResp,
     Disconnect(Disconnect),
+    Auth(Auth),
 }
 
 impl Packet {
     pub fn decode(data: Bytes, flag: u8, level: ProtocolLevel) -> Result<Self, DecodeError> {
-        let packet = match (flag & 0xf0) >> 4 {
+        Self::decode_with_options(data, flag, level, &DecodeOptions::default())
+    }
+
+    pub fn decode_with_options(
+        data: Bytes,
+        flag: u8,
+        level: ProtocolLevel,
+        options: &DecodeOptions,
+    ) -> Result<Self, DecodeError> {
+        let ty = (flag & 0xf0) >> 4;
+
+        // Where a flag bit is marked as "Reserved", it is reserved for future use and MUST be
+        // set to the value listed [MQTT-2.1.3-1].
+        if options.reject_reserved_flags {
+            match ty {
+                PUBLISH => {}
+                PUBREL | SUBSCRIBE | UNSUBSCRIBE => {
+                    ensure!(flag & 0x0f == 0b0010, DecodeError::MalformedPacket)
+                }
+                _ => ensure!(flag & 0x0f == 0, DecodeError::MalformedPacket),
+            }
+        }
+
+        let packet = match ty {
             RESERVED => return Err(DecodeError::ReservedPacketType),
-            CONNECT => Self::Connect(Connect::decode(data, level)?),
-            CONNACK => Self::ConnAck(ConnAck::decode(data, level)?),
-            PUBLISH => Self::Publish(Publish::decode(data, level, flag)?),
-            PUBACK => Self::PubAck(PubAck::decode(data, level)?),
-            PUBREC => Self::PubRec(PubRec::decode(data, level)?),
-            PUBREL => Self::PubRel(PubRel::decode(data, level, flag)?),
-            PUBCOMP => Self::PubComp(PubComp::decode(data, level)?),
-            SUBSCRIBE => Self::Subscribe(Subscribe::decode(data, level, flag)?),
-            SUBACK => Self::SubAck(SubAck::decode(data, level)?),
-            UNSUBSCRIBE => Self::Unsubscribe(Unsubscribe::decode(data, level, flag)?),
-            UNSUBACK => Self::UnsubAck(UnsubAck::decode(data, level)?),
+            CONNECT => Self::Connect(Connect::decode(data, level, options)?),
+            CONNACK => Self::ConnAck(ConnAck::decode(data, level, options)?),
+            PUBLISH => Self::Publish(Publish::decode(data, level, flag, options)?),
+            PUBACK => Self::PubAck(PubAck::decode(data, level, options)?),
+            PUBREC => Self::PubRec(PubRec::decode(data, level, options)?),
+            PUBREL => Self::PubRel(PubRel::decode(data, level, options)?),
+            PUBCOMP => Self::PubComp(PubComp::decode(data, level, options)?),
+            SUBSCRIBE => Self::Subscribe(Subscribe::decode(data, level, options)?),
+            SUBACK => Self::SubAck(SubAck::decode(data, level, options)?),
+            UNSUBSCRIBE => Self::Unsubscribe(Unsubscribe::decode(data, level, options)?),
+            UNSUBACK => Self::UnsubAck(UnsubAck::decode(data, level, options)?),
             PINGREQ => Self::PingReq,
             PINGRESP => Self::PingResp,
-            DISCONNECT => Self::Disconnect(Disconnect::decode(data, level)?),
+            DISCONNECT => Self::Disconnect(Disconnect::decode(data, level, options)?),
+            AUTH => Self::Auth(Auth::decode(data, level, options)?),
             n => return Err(DecodeError::UnknownPacketType(n)),
         };
         Ok(packet)
     }
 
+    /// Parses a complete packet with its fixed header from the beginning of the buffer and
+    /// removes it, returns `None` and leaves the buffer untouched if more bytes are needed.
+    ///
+    /// Unlike [`Decoder`](crate::Decoder), the protocol level is not taken from the CONNECT
+    /// packet.
+    pub fn parse(data: &mut Bytes, level: ProtocolLevel) -> Result<Option<Self>, DecodeError> {
+        let (flag, rest) = match data.split_first() {
+            Some((flag, rest)) => (*flag, rest),
+            None => return Ok(None),
+        };
+        let (packet_size, len_size) = match get_remaining_length(rest)? {
+            Some(res) => res,
+            None => return Ok(None),
+        };
+        if rest.len() - len_size < packet_size {
+            return Ok(None);
+        }
+        data.advance(1 + len_size);
+        Self::decode(data.split_to(packet_size), flag, level).map(Some)
+    }
+
+    /// Writes the packet to the buffer and returns the number of bytes written.
+    pub fn write(
+        &self,
+        data: &mut BytesMut,
+        level: ProtocolLevel,
+        max_size: usize,
+    ) -> Result<usize, EncodeError> {
+        let len = data.len();
+        self.encode(data, level, max_size)?;
+        Ok(data.len() - len)
+    }
+
     pub fn encode(
         &self,
         data: &mut BytesMut,
@@ -92,6 +151,260 @@ impl Packet {
                 Ok(())
             }
             Packet::Disconnect(disconnect) => disconnect.encode(data, level, max_size),
+            Packet::Auth(auth) => auth.encode(data, level, max_size),
+        }
+    }
+
+    /// Like [`Packet::encode`], but the payload of a PUBLISH packet is not copied to the buffer,
+    /// it is returned so that it can be written after the buffer with a vectored write.
+    pub fn encode_vectored(
+        &self,
+        data: &mut BytesMut,
+        level: ProtocolLevel,
+        max_size: usize,
+    ) -> Result<Option<&Bytes>, EncodeError> {
+        match self {
+            Packet::Publish(publish) => {
+                publish.encode_header(data, level, max_size)?;
+                Ok(Some(&publish.payload))
+            }
+            _ => self.encode(data, level, max_size).map(|_| None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::{NonZeroU16, NonZeroUsize};
+
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_parse() {
+        let publish = Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::AtLeastOnce,
+            retain: false,
+            topic: "a/b".into(),
+            packet_id: NonZeroU16::new(1),
+            properties: PublishProperties::default(),
+            payload: Bytes::from(vec![1; 200]),
+        });
+        let mut buf = BytesMut::new();
+        let size = publish
+            .write(&mut buf, ProtocolLevel::V5, usize::MAX)
+            .unwrap();
+        assert_eq!(size, buf.len());
+        Packet::PingReq
+            .write(&mut buf, ProtocolLevel::V5, usize::MAX)
+            .unwrap();
+        let data = buf.freeze();
+
+        for len in [0, 1, 2, size - 1] {
+            let mut partial = data.slice(..len);
+            assert!(Packet::parse(&mut partial, ProtocolLevel::V5)
+                .unwrap()
+                .is_none());
+            assert_eq!(partial.len(), len);
+        }
+
+        let mut data = data;
+        assert_eq!(
+            Packet::parse(&mut data, ProtocolLevel::V5).unwrap(),
+            Some(publish)
+        );
+        assert_eq!(
+            Packet::parse(&mut data, ProtocolLevel::V5).unwrap(),
+            Some(Packet::PingReq)
+        );
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_encode_vectored() {
+        let publish = Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::AtMostOnce,
+            retain: false,
+            topic: "a/b".into(),
+            packet_id: None,
+            properties: PublishProperties::default(),
+            payload: Bytes::from(vec![1; 200]),
+        });
+        let mut expected = BytesMut::new();
+        publish
+            .encode(&mut expected, ProtocolLevel::V5, usize::MAX)
+            .unwrap();
+
+        let mut buf = BytesMut::new();
+        let payload = publish
+            .encode_vectored(&mut buf, ProtocolLevel::V5, usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert_eq!(buf.len() + payload.len(), expected.len());
+        buf.extend_from_slice(payload);
+        assert_eq!(buf, expected);
+
+        buf.clear();
+        assert!(Packet::PingReq
+            .encode_vectored(&mut buf, ProtocolLevel::V5, usize::MAX)
+            .unwrap()
+            .is_none());
+        assert_eq!(&buf[..], &[PINGREQ << 4, 0]);
+    }
+
+    #[test]
+    fn test_empty_username() {
+        let connect = Packet::Connect(Connect {
+            level: ProtocolLevel::V4,
+            keep_alive: 60,
+            clean_start: true,
+            client_id: "c".into(),
+            last_will: None,
+            login: Some(Login {
+                username: "".into(),
+                password: Bytes::from_static(b"secret"),
+            }),
+            properties: ConnectProperties::default(),
+        });
+        let mut buf = BytesMut::new();
+        connect
+            .encode(&mut buf, ProtocolLevel::V4, usize::MAX)
+            .unwrap();
+        assert_eq!(
+            Packet::parse(&mut buf.freeze(), ProtocolLevel::V4).unwrap(),
+            Some(connect)
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        let user_properties = vec![("a".into(), "b".into())];
+        let packets = vec![
+            Packet::Connect(Connect {
+                level: ProtocolLevel::V5,
+                keep_alive: 30,
+                clean_start: true,
+                client_id: "c".into(),
+                last_will: Some(LastWill {
+                    topic: "will".into(),
+                    payload: Bytes::from_static(b"bye"),
+                    qos: Qos::AtLeastOnce,
+                    retain: true,
+                    properties: WillProperties {
+                        delay_interval: Some(5),
+                        ..WillProperties::default()
+                    },
+                }),
+                login: Some(Login {
+                    username: "user".into(),
+                    password: Bytes::from_static(&[0, 1, 255]),
+                }),
+                properties: ConnectProperties {
+                    session_expiry_interval: Some(60),
+                    user_properties: user_properties.clone(),
+                    ..ConnectProperties::default()
+                },
+            }),
+            Packet::ConnAck(ConnAck {
+                session_present: true,
+                reason_code: ConnectReasonCode::Success,
+                properties: ConnAckProperties {
+                    maximum_qos: Some(Qos::AtLeastOnce),
+                    ..ConnAckProperties::default()
+                },
+            }),
+            Packet::Publish(Publish {
+                dup: true,
+                qos: Qos::ExactlyOnce,
+                retain: false,
+                topic: "a/b".into(),
+                packet_id: NonZeroU16::new(1),
+                properties: PublishProperties {
+                    subscription_identifiers: vec![NonZeroUsize::new(1).unwrap()],
+                    correlation_data: Some(Bytes::from_static(b"id")),
+                    user_properties: user_properties.clone(),
+                    ..PublishProperties::default()
+                },
+                payload: Bytes::from_static(b"payload"),
+            }),
+            Packet::PubAck(PubAck {
+                packet_id: NonZeroU16::new(1).unwrap(),
+                reason_code: PubAckReasonCode::NoMatchingSubscribers,
+                properties: PubAckProperties::default(),
+            }),
+            Packet::PubRec(PubRec {
+                packet_id: NonZeroU16::new(1).unwrap(),
+                reason_code: PubRecReasonCode::Success,
+                properties: PubRecProperties::default(),
+            }),
+            Packet::PubRel(PubRel {
+                packet_id: NonZeroU16::new(1).unwrap(),
+                reason_code: PubRelReasonCode::Success,
+                properties: PubRelProperties::default(),
+            }),
+            Packet::PubComp(PubComp {
+                packet_id: NonZeroU16::new(1).unwrap(),
+                reason_code: PubCompReasonCode::Success,
+                properties: PubCompProperties::default(),
+            }),
+            Packet::Subscribe(Subscribe {
+                packet_id: NonZeroU16::new(2).unwrap(),
+                properties: SubscribeProperties {
+                    id: NonZeroUsize::new(3),
+                    user_properties: user_properties.clone(),
+                },
+                filters: vec![SubscribeFilter {
+                    path: "a/+".into(),
+                    qos: Qos::AtLeastOnce,
+                    no_local: true,
+                    retain_as_published: false,
+                    retain_handling: RetainHandling::Never,
+                }],
+            }),
+            Packet::SubAck(SubAck {
+                packet_id: NonZeroU16::new(2).unwrap(),
+                reason_codes: vec![SubscribeReasonCode::QoS1],
+                properties: SubAckProperties::default(),
+            }),
+            Packet::Unsubscribe(Unsubscribe {
+                packet_id: NonZeroU16::new(3).unwrap(),
+                filters: vec!["a/+".into()],
+                properties: UnsubscribeProperties::default(),
+            }),
+            Packet::UnsubAck(UnsubAck {
+                packet_id: NonZeroU16::new(3).unwrap(),
+                reason_codes: vec![UnsubAckReasonCode::Success],
+                properties: UnsubAckProperties::default(),
+            }),
+            Packet::PingReq,
+            Packet::PingResp,
+            Packet::Disconnect(Disconnect {
+                reason_code: DisconnectReasonCode::NormalDisconnection,
+                properties: DisconnectProperties {
+                    reason_string: Some("bye".into()),
+                    ..DisconnectProperties::default()
+                },
+            }),
+            Packet::Auth(Auth {
+                reason_code: AuthReasonCode::ContinueAuthentication,
+                properties: AuthProperties {
+                    authentication_method: Some("SCRAM-SHA-256".into()),
+                    authentication_data: Some(Bytes::from_static(b"data")),
+                    ..AuthProperties::default()
+                },
+            }),
+        ];
+
+        for packet in packets {
+            let json = serde_json::to_string(&packet).unwrap();
+            assert_eq!(
+                serde_json::from_str::<Packet>(&json).unwrap(),
+                packet,
+                "{}",
+                json
+            );
         }
     }
 }
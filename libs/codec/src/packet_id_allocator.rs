@@ -1,29 +1,149 @@
 use std::convert::TryInto;
 use std::num::NonZeroU16;
 
-pub struct PacketIdAllocator(u16);
+const WORDS: usize = (u16::MAX as usize + 1) / 64;
+
+/// Allocates packet identifiers, an identifier is not handed out again until it is released.
+pub struct PacketIdAllocator {
+    next: u16,
+    in_use: Box<[u64; WORDS]>,
+    count: usize,
+}
 
 impl Default for PacketIdAllocator {
     #[inline]
     fn default() -> Self {
-        Self(1.try_into().unwrap())
+        Self {
+            next: 1,
+            in_use: Box::new([0; WORDS]),
+            count: 0,
+        }
     }
 }
 
 impl PacketIdAllocator {
-    #[inline]
-    pub fn take(&mut self) -> NonZeroU16 {
-        let id = self.0;
-        if self.0 == u16::MAX {
-            self.0 = 1;
-        } else {
-            self.0 += 1;
+    /// Returns the next free identifier, or `None` if all 65535 identifiers are in use.
+    pub fn take(&mut self) -> Option<NonZeroU16> {
+        if self.is_exhausted() {
+            return None;
+        }
+        loop {
+            let id = self.next;
+            self.next = if id == u16::MAX { 1 } else { id + 1 };
+            let (word, bit) = Self::position(id);
+            if self.in_use[word] == u64::MAX {
+                // skip to the first identifier of the next word
+                self.next = match ((word as u16) + 1).checked_mul(64) {
+                    Some(next) if next != 0 => next,
+                    _ => 1,
+                };
+                continue;
+            }
+            if self.in_use[word] & bit == 0 {
+                self.in_use[word] |= bit;
+                self.count += 1;
+                return Some(id.try_into().unwrap());
+            }
         }
-        id.try_into().unwrap()
+    }
+
+    /// Marks an identifier as in use, e.g. for the inflight packets of a resumed session.
+    ///
+    /// Returns `false` if it was already in use.
+    pub fn mark_in_use(&mut self, id: NonZeroU16) -> bool {
+        let (word, bit) = Self::position(id.get());
+        if self.in_use[word] & bit != 0 {
+            return false;
+        }
+        self.in_use[word] |= bit;
+        self.count += 1;
+        true
+    }
+
+    /// Releases an identifier once its flow is complete.
+    ///
+    /// Returns `false` if it was not in use.
+    pub fn release(&mut self, id: NonZeroU16) -> bool {
+        let (word, bit) = Self::position(id.get());
+        if self.in_use[word] & bit == 0 {
+            return false;
+        }
+        self.in_use[word] &= !bit;
+        self.count -= 1;
+        true
+    }
+
+    #[inline]
+    pub fn is_in_use(&self, id: NonZeroU16) -> bool {
+        let (word, bit) = Self::position(id.get());
+        self.in_use[word] & bit != 0
+    }
+
+    /// The number of identifiers in use.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    #[inline]
+    pub fn is_exhausted(&self) -> bool {
+        self.count == u16::MAX as usize
     }
 
     #[inline]
     pub fn reset(&mut self) {
         *self = PacketIdAllocator::default();
     }
+
+    #[inline]
+    fn position(id: u16) -> (usize, u64) {
+        (id as usize / 64, 1 << (id % 64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exhaustion_and_reuse() {
+        let mut allocator = PacketIdAllocator::default();
+        let first = allocator.take().unwrap();
+        assert_eq!(first.get(), 1);
+        for _ in 1..u16::MAX {
+            allocator.take().unwrap();
+        }
+        assert!(allocator.is_exhausted());
+        assert_eq!(allocator.take(), None);
+
+        // only released identifiers are handed out again
+        let id = NonZeroU16::new(1000).unwrap();
+        assert!(allocator.release(id));
+        assert!(!allocator.release(id));
+        assert_eq!(allocator.take(), Some(id));
+        assert_eq!(allocator.take(), None);
+
+        assert!(allocator.release(first));
+        assert_eq!(allocator.take(), Some(first));
+    }
+
+    #[test]
+    fn test_skip_in_use() {
+        let mut allocator = PacketIdAllocator::default();
+        for id in 1..=200 {
+            assert!(allocator.mark_in_use(NonZeroU16::new(id).unwrap()));
+        }
+        assert!(!allocator.mark_in_use(NonZeroU16::new(3).unwrap()));
+        assert_eq!(allocator.take().unwrap().get(), 201);
+        assert_eq!(allocator.len(), 201);
+
+        allocator.reset();
+        assert!(allocator.is_empty());
+        assert_eq!(allocator.take().unwrap().get(), 1);
+    }
 }
@@ -1,3 +1,5 @@
+use crate::{DecodeError, DecodeOptions};
+
 pub const PAYLOAD_FORMAT_INDICATOR: u8 = 0x01;
 pub const MESSAGE_EXPIRY_INTERVAL: u8 = 0x02;
 pub const CONTENT_TYPE: u8 = 0x03;
@@ -25,3 +27,61 @@ pub const MAXIMUM_PACKET_SIZE: u8 = 0x27;
 pub const WILDCARD_SUBSCRIPTION_AVAILABLE: u8 = 0x28;
 pub const SUBSCRIPTION_IDENTIFIER_AVAILABLE: u8 = 0x29;
 pub const SHARED_SUBSCRIPTION_AVAILABLE: u8 = 0x2a;
+
+/// The identifiers of the properties read from a packet, to detect the duplicate ones, and the
+/// number of the properties that may appear several times, to enforce the limits of the
+/// [`DecodeOptions`].
+pub(crate) struct PropertySet {
+    seen: u64,
+    user_properties: usize,
+    subscription_identifiers: usize,
+}
+
+impl PropertySet {
+    /// Checks the size in bytes of the properties.
+    pub(crate) fn new(properties_len: usize, options: &DecodeOptions) -> Result<Self, DecodeError> {
+        if let Some(max_size) = options.max_properties_size {
+            ensure!(properties_len <= max_size, DecodeError::LimitExceeded);
+        }
+        Ok(Self {
+            seen: 0,
+            user_properties: 0,
+            subscription_identifiers: 0,
+        })
+    }
+
+    /// The User Property may appear several times.
+    pub(crate) fn insert(&mut self, flag: u8, options: &DecodeOptions) -> Result<(), DecodeError> {
+        if flag == USER_PROPERTY {
+            self.user_properties += 1;
+            if let Some(max_count) = options.max_user_properties {
+                ensure!(
+                    self.user_properties <= max_count,
+                    DecodeError::LimitExceeded
+                );
+            }
+        } else if options.reject_duplicate_properties && flag < 64 {
+            ensure!(
+                self.seen & (1 << flag) == 0,
+                DecodeError::DuplicateProperty(flag)
+            );
+            self.seen |= 1 << flag;
+        }
+        Ok(())
+    }
+
+    /// A PUBLISH packet contains a Subscription Identifier for each matching subscription.
+    pub(crate) fn insert_subscription_identifier(
+        &mut self,
+        options: &DecodeOptions,
+    ) -> Result<(), DecodeError> {
+        self.subscription_identifiers += 1;
+        if let Some(max_count) = options.max_subscription_identifiers {
+            ensure!(
+                self.subscription_identifiers <= max_count,
+                DecodeError::LimitExceeded
+            );
+        }
+        Ok(())
+    }
+}
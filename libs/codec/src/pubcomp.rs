@@ -7,9 +7,10 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
 
 use crate::packet::PUBCOMP;
+use crate::property::PropertySet;
 use crate::reader::PacketReader;
 use crate::writer::{bytes_remaining_length, PacketWriter};
-use crate::{property, DecodeError, EncodeError, ProtocolLevel};
+use crate::{property, DecodeError, DecodeOptions, EncodeError, ProtocolLevel};
 
 #[derive(
     Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive, Serialize, Deserialize,
@@ -68,11 +69,13 @@ impl PubCompProperties {
         Ok(())
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = PubCompProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            seen.insert(flag, options)?;
 
             match flag {
                 property::REASON_STRING => {
@@ -83,6 +86,7 @@ impl PubCompProperties {
                     let value = data.read_string()?;
                     properties.user_properties.push((key, value));
                 }
+                _ if !options.reject_unknown_properties => break,
                 _ => return Err(DecodeError::InvalidPubCompProperty(flag)),
             }
         }
@@ -103,7 +107,7 @@ impl PubComp {
     #[inline]
     fn variable_header_length(&self, level: ProtocolLevel) -> Result<usize, EncodeError> {
         match level {
-            ProtocolLevel::V4 => Ok(2),
+            ProtocolLevel::V3 | ProtocolLevel::V4 => Ok(2),
             ProtocolLevel::V5 => {
                 if !self.properties.is_empty() {
                     let properties_len = self.properties.bytes_length()?;
@@ -155,7 +159,11 @@ impl PubComp {
         Ok(())
     }
 
-    pub(crate) fn decode(mut data: Bytes, level: ProtocolLevel) -> Result<Self, DecodeError> {
+    pub(crate) fn decode(
+        mut data: Bytes,
+        level: ProtocolLevel,
+        options: &DecodeOptions,
+    ) -> Result<Self, DecodeError> {
         let packet_id = data
             .read_u16()?
             .try_into()
@@ -177,7 +185,7 @@ impl PubComp {
                     data.remaining() >= properties_len,
                     DecodeError::MalformedPacket
                 );
-                properties = PubCompProperties::decode(data)?;
+                properties = PubCompProperties::decode(data, options)?;
             }
         }
 
@@ -6,9 +6,10 @@ use bytestring::ByteString;
 use serde::{Deserialize, Serialize};
 
 use crate::packet::PUBLISH;
+use crate::property::PropertySet;
 use crate::reader::PacketReader;
 use crate::writer::{bytes_remaining_length, PacketWriter};
-use crate::{property, DecodeError, EncodeError, ProtocolLevel, Qos};
+use crate::{property, DecodeError, DecodeOptions, EncodeError, ProtocolLevel, Qos};
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 pub struct PublishProperties {
@@ -94,11 +95,18 @@ impl PublishProperties {
         Ok(())
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = PublishProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            // a message can match several subscriptions
+            if flag == property::SUBSCRIPTION_IDENTIFIER {
+                seen.insert_subscription_identifier(options)?;
+            } else {
+                seen.insert(flag, options)?;
+            }
 
             match flag {
                 property::PAYLOAD_FORMAT_INDICATOR => {
@@ -131,6 +139,7 @@ impl PublishProperties {
                     );
                 }
                 property::CONTENT_TYPE => properties.content_type = Some(data.read_string()?),
+                _ if !options.reject_unknown_properties => break,
                 _ => return Err(DecodeError::InvalidPublishProperty(flag)),
             }
         }
@@ -161,6 +170,7 @@ impl Publish {
         mut data: Bytes,
         level: ProtocolLevel,
         flags: u8,
+        options: &DecodeOptions,
     ) -> Result<Self, DecodeError> {
         let dup = flags & 0b1000 > 0;
         let qos: Qos = {
@@ -188,7 +198,7 @@ impl Publish {
                 data.remaining() >= properties_len,
                 DecodeError::MalformedPacket
             );
-            properties = PublishProperties::decode(data.split_to(properties_len))?;
+            properties = PublishProperties::decode(data.split_to(properties_len), options)?;
         }
 
         Ok(Self {
@@ -222,6 +232,18 @@ impl Publish {
         data: &mut BytesMut,
         level: ProtocolLevel,
         max_size: usize,
+    ) -> Result<(), EncodeError> {
+        self.encode_header(data, level, max_size)?;
+        data.put_slice(&self.payload);
+        Ok(())
+    }
+
+    /// Encodes the packet without the payload, which must be written right after it.
+    pub(crate) fn encode_header(
+        &self,
+        data: &mut BytesMut,
+        level: ProtocolLevel,
+        max_size: usize,
     ) -> Result<(), EncodeError> {
         ensure!(
             self.qos == Qos::AtMostOnce || self.packet_id.is_some(),
@@ -258,7 +280,6 @@ impl Publish {
             self.properties.encode(data)?;
         }
 
-        data.put_slice(&self.payload);
         Ok(())
     }
 }
@@ -7,9 +7,10 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
 
 use crate::packet::PUBREC;
+use crate::property::PropertySet;
 use crate::reader::PacketReader;
 use crate::writer::{bytes_remaining_length, PacketWriter};
-use crate::{property, DecodeError, EncodeError, ProtocolLevel};
+use crate::{property, DecodeError, DecodeOptions, EncodeError, ProtocolLevel};
 
 #[derive(
     Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive, Serialize, Deserialize,
@@ -75,11 +76,13 @@ impl PubRecProperties {
         Ok(())
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = PubRecProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            seen.insert(flag, options)?;
 
             match flag {
                 property::REASON_STRING => {
@@ -90,6 +93,7 @@ impl PubRecProperties {
                     let value = data.read_string()?;
                     properties.user_properties.push((key, value));
                 }
+                _ if !options.reject_unknown_properties => break,
                 _ => return Err(DecodeError::InvalidPubRecProperty(flag)),
             }
         }
@@ -110,7 +114,7 @@ impl PubRec {
     #[inline]
     fn variable_header_length(&self, level: ProtocolLevel) -> Result<usize, EncodeError> {
         match level {
-            ProtocolLevel::V4 => Ok(2),
+            ProtocolLevel::V3 | ProtocolLevel::V4 => Ok(2),
             ProtocolLevel::V5 => {
                 if !self.properties.is_empty() {
                     let properties_len = self.properties.bytes_length()?;
@@ -162,7 +166,11 @@ impl PubRec {
         Ok(())
     }
 
-    pub(crate) fn decode(mut data: Bytes, level: ProtocolLevel) -> Result<Self, DecodeError> {
+    pub(crate) fn decode(
+        mut data: Bytes,
+        level: ProtocolLevel,
+        options: &DecodeOptions,
+    ) -> Result<Self, DecodeError> {
         let packet_id = data
             .read_u16()?
             .try_into()
@@ -184,7 +192,7 @@ impl PubRec {
                     data.remaining() >= properties_len,
                     DecodeError::MalformedPacket
                 );
-                properties = PubRecProperties::decode(data)?;
+                properties = PubRecProperties::decode(data, options)?;
             }
         }
 
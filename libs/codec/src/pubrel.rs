@@ -7,9 +7,10 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
 
 use crate::packet::PUBREL;
+use crate::property::PropertySet;
 use crate::reader::PacketReader;
 use crate::writer::{bytes_remaining_length, PacketWriter};
-use crate::{property, DecodeError, EncodeError, ProtocolLevel};
+use crate::{property, DecodeError, DecodeOptions, EncodeError, ProtocolLevel};
 
 #[derive(
     Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive, Serialize, Deserialize,
@@ -68,11 +69,13 @@ impl PubRelProperties {
         Ok(())
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = PubRelProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            seen.insert(flag, options)?;
 
             match flag {
                 property::REASON_STRING => {
@@ -83,6 +86,7 @@ impl PubRelProperties {
                     let value = data.read_string()?;
                     properties.user_properties.push((key, value));
                 }
+                _ if !options.reject_unknown_properties => break,
                 _ => return Err(DecodeError::InvalidPubRelProperty(flag)),
             }
         }
@@ -103,7 +107,7 @@ impl PubRel {
     #[inline]
     fn variable_header_length(&self, level: ProtocolLevel) -> Result<usize, EncodeError> {
         match level {
-            ProtocolLevel::V4 => Ok(2),
+            ProtocolLevel::V3 | ProtocolLevel::V4 => Ok(2),
             ProtocolLevel::V5 => {
                 if !self.properties.is_empty() {
                     let properties_len = self.properties.bytes_length()?;
@@ -158,12 +162,8 @@ impl PubRel {
     pub(crate) fn decode(
         mut data: Bytes,
         level: ProtocolLevel,
-        flags: u8,
+        options: &DecodeOptions,
     ) -> Result<Self, DecodeError> {
-        if flags & 0x0f != 0b0010 {
-            return Err(DecodeError::MalformedPacket);
-        }
-
         let packet_id = data
             .read_u16()?
             .try_into()
@@ -185,7 +185,7 @@ impl PubRel {
                     data.remaining() >= properties_len,
                     DecodeError::MalformedPacket
                 );
-                properties = PubRelProperties::decode(data)?;
+                properties = PubRelProperties::decode(data, options)?;
             }
         }
 
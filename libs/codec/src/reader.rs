@@ -60,10 +60,13 @@ impl PacketReader for Bytes {
         Ok(self.get_u32())
     }
 
+    /// Surrogates are rejected by the UTF-8 validation, the null character U+0000 must not be
+    /// included either [MQTT-1.5.4-2].
     #[inline]
     fn read_string(&mut self) -> Result<ByteString, DecodeError> {
         let len = self.read_u16()? as usize;
         ensure!(self.remaining() >= len, DecodeError::MalformedPacket);
+        ensure!(!self[..len].contains(&0), DecodeError::MalformedPacket);
         self.split_to(len)
             .try_into()
             .map_err(|_| DecodeError::MalformedPacket)
@@ -76,3 +79,28 @@ impl PacketReader for Bytes {
         Ok(self.split_to(len))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_string() {
+        let mut data = Bytes::from_static(b"\x00\x03a/b");
+        assert_eq!(data.read_string().unwrap(), "a/b");
+
+        // U+0000
+        let mut data = Bytes::from_static(b"\x00\x03a\x00b");
+        assert!(matches!(
+            data.read_string(),
+            Err(DecodeError::MalformedPacket)
+        ));
+
+        // an encoded surrogate U+D800
+        let mut data = Bytes::from_static(b"\x00\x03\xed\xa0\x80");
+        assert!(matches!(
+            data.read_string(),
+            Err(DecodeError::MalformedPacket)
+        ));
+    }
+}
@@ -7,9 +7,10 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
 
 use crate::packet::SUBACK;
+use crate::property::PropertySet;
 use crate::reader::PacketReader;
 use crate::writer::{bytes_remaining_length, PacketWriter};
-use crate::{property, DecodeError, EncodeError, ProtocolLevel, Qos};
+use crate::{property, DecodeError, DecodeOptions, EncodeError, ProtocolLevel, Qos};
 
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct SubAckProperties {
@@ -47,11 +48,13 @@ impl SubAckProperties {
         Ok(())
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = SubAckProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            seen.insert(flag, options)?;
 
             match flag {
                 property::REASON_STRING => properties.reason_string = Some(data.read_string()?),
@@ -60,6 +63,7 @@ impl SubAckProperties {
                     let value = data.read_string()?;
                     properties.user_properties.push((key, value));
                 }
+                _ if !options.reject_unknown_properties => break,
                 _ => return Err(DecodeError::InvalidConnAckProperty(flag)),
             }
         }
@@ -180,14 +184,20 @@ impl SubAck {
 
         for code in self.reason_codes.iter().copied() {
             match level {
-                ProtocolLevel::V4 => data.put_u8(Into::<SubscribeReasonCodeV4>::into(code).into()),
+                ProtocolLevel::V3 | ProtocolLevel::V4 => {
+                    data.put_u8(Into::<SubscribeReasonCodeV4>::into(code).into())
+                }
                 ProtocolLevel::V5 => data.put_u8(code.into()),
             }
         }
         Ok(())
     }
 
-    pub(crate) fn decode(mut data: Bytes, level: ProtocolLevel) -> Result<Self, DecodeError> {
+    pub(crate) fn decode(
+        mut data: Bytes,
+        level: ProtocolLevel,
+        options: &DecodeOptions,
+    ) -> Result<Self, DecodeError> {
         let packet_id = data
             .read_u16()?
             .try_into()
@@ -200,7 +210,7 @@ impl SubAck {
                 data.remaining() >= properties_len,
                 DecodeError::MalformedPacket
             );
-            properties = SubAckProperties::decode(data.split_to(properties_len))?;
+            properties = SubAckProperties::decode(data.split_to(properties_len), options)?;
         }
 
         let mut reason_codes = Vec::new();
@@ -208,7 +218,7 @@ impl SubAck {
             let n_reason_code = data.read_u8()?;
 
             match level {
-                ProtocolLevel::V4 => {
+                ProtocolLevel::V3 | ProtocolLevel::V4 => {
                     reason_codes.push(
                         TryInto::<SubscribeReasonCodeV4>::try_into(n_reason_code)
                             .map_err(|_| DecodeError::InvalidSubAckReasonCode(n_reason_code))?
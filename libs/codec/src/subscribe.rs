@@ -7,9 +7,10 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
 
 use crate::packet::SUBSCRIBE;
+use crate::property::PropertySet;
 use crate::reader::PacketReader;
 use crate::writer::{bytes_remaining_length, PacketWriter};
-use crate::{property, DecodeError, EncodeError, ProtocolLevel, Qos};
+use crate::{property, DecodeError, DecodeOptions, EncodeError, ProtocolLevel, Qos};
 
 #[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
 pub struct SubscribeProperties {
@@ -47,11 +48,13 @@ impl SubscribeProperties {
         Ok(())
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = SubscribeProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            seen.insert(flag, options)?;
 
             match flag {
                 property::SUBSCRIPTION_IDENTIFIER => {
@@ -66,6 +69,7 @@ impl SubscribeProperties {
                     let value = data.read_string()?;
                     properties.user_properties.push((key, value));
                 }
+                _ if !options.reject_unknown_properties => break,
                 _ => return Err(DecodeError::InvalidSubscribeProperty(flag)),
             }
         }
@@ -106,17 +110,22 @@ fn default_retain_handling() -> RetainHandling {
 }
 
 impl SubscribeFilter {
-    fn decode(data: &mut Bytes, level: ProtocolLevel) -> Result<Self, DecodeError> {
+    fn decode(
+        data: &mut Bytes,
+        level: ProtocolLevel,
+        options: &DecodeOptions,
+    ) -> Result<Self, DecodeError> {
         let path = data.read_string()?;
 
         match level {
-            ProtocolLevel::V4 => {
-                let options = data.read_u8()?;
-                if options & 0b11111100 > 0 {
-                    return Err(DecodeError::MalformedPacket);
-                }
+            ProtocolLevel::V3 | ProtocolLevel::V4 => {
+                let flags = data.read_u8()?;
+                ensure!(
+                    !options.reject_reserved_flags || flags & 0b11111100 == 0,
+                    DecodeError::MalformedPacket
+                );
                 let qos: Qos = {
-                    let n_qos = options & 0b11;
+                    let n_qos = flags & 0b11;
                     n_qos
                         .try_into()
                         .map_err(|_| DecodeError::InvalidQOS(n_qos))?
@@ -130,17 +139,21 @@ impl SubscribeFilter {
                 })
             }
             ProtocolLevel::V5 => {
-                let options = data.read_u8()?;
+                let flags = data.read_u8()?;
+                ensure!(
+                    !options.reject_reserved_flags || flags & 0b11000000 == 0,
+                    DecodeError::MalformedPacket
+                );
                 let qos: Qos = {
-                    let n_qos = options & 0b11;
+                    let n_qos = flags & 0b11;
                     n_qos
                         .try_into()
                         .map_err(|_| DecodeError::InvalidQOS(n_qos))?
                 };
-                let no_local = options & 0b100 > 0;
-                let retain_as_published = options & 0b1000 > 0;
+                let no_local = flags & 0b100 > 0;
+                let retain_as_published = flags & 0b1000 > 0;
                 let retain_handling = {
-                    let n_retain_handling = (options & 0b110000) >> 4;
+                    let n_retain_handling = (flags & 0b110000) >> 4;
                     n_retain_handling
                         .try_into()
                         .map_err(|_| DecodeError::InvalidRetainHandling(n_retain_handling))?
@@ -236,13 +249,8 @@ impl Subscribe {
     pub(crate) fn decode(
         mut data: Bytes,
         level: ProtocolLevel,
-        flags: u8,
+        options: &DecodeOptions,
     ) -> Result<Self, DecodeError> {
-        // Bits 3,2,1 and 0 of the Fixed Header of the SUBSCRIBE packet are reserved and MUST be
-        // set to 0,0,1 and 0 respectively. The Server MUST treat any other value as malformed
-        // and close the Network Connection [MQTT-3.8.1-1].
-        ensure!((flags & 0x0f) == 0b0010, DecodeError::MalformedPacket);
-
         let packet_id = data
             .read_u16()?
             .try_into()
@@ -255,13 +263,13 @@ impl Subscribe {
                 data.remaining() >= properties_len,
                 DecodeError::MalformedPacket
             );
-            properties = SubscribeProperties::decode(data.split_to(properties_len))?;
+            properties = SubscribeProperties::decode(data.split_to(properties_len), options)?;
         }
 
         // parse payload
         let mut filters = Vec::new();
         while data.has_remaining() {
-            filters.push(SubscribeFilter::decode(&mut data, level)?);
+            filters.push(SubscribeFilter::decode(&mut data, level, options)?);
         }
 
         Ok(Self {
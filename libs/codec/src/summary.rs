@@ -0,0 +1,188 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::Packet;
+
+/// Formats the type, packet id, topic, QoS and flags of a packet, the payloads, passwords and
+/// authentication data are only printed as their length.
+pub struct PacketSummary<'a>(&'a Packet);
+
+impl Packet {
+    /// Returns a compact representation of the packet that is safe to log.
+    #[inline]
+    pub fn summary(&self) -> PacketSummary<'_> {
+        PacketSummary(self)
+    }
+}
+
+impl<'a> Display for PacketSummary<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Packet::Connect(connect) => {
+                write!(
+                    f,
+                    "CONNECT level={:?} client_id={} clean_start={} keep_alive={}",
+                    connect.level, connect.client_id, connect.clean_start, connect.keep_alive
+                )?;
+                if let Some(login) = &connect.login {
+                    write!(
+                        f,
+                        " username={} password_len={}",
+                        login.username,
+                        login.password.len()
+                    )?;
+                }
+                if let Some(last_will) = &connect.last_will {
+                    write!(
+                        f,
+                        " will_topic={} will_qos={} will_retain={} will_payload_len={}",
+                        last_will.topic,
+                        last_will.qos as u8,
+                        last_will.retain,
+                        last_will.payload.len()
+                    )?;
+                }
+                if let Some(method) = &connect.properties.authentication_method {
+                    write!(f, " auth_method={}", method)?;
+                }
+                Ok(())
+            }
+            Packet::ConnAck(conn_ack) => write!(
+                f,
+                "CONNACK session_present={} reason_code={:?}",
+                conn_ack.session_present, conn_ack.reason_code
+            ),
+            Packet::Publish(publish) => {
+                write!(f, "PUBLISH")?;
+                if let Some(packet_id) = publish.packet_id {
+                    write!(f, " packet_id={}", packet_id)?;
+                }
+                write!(
+                    f,
+                    " topic={} qos={} dup={} retain={} payload_len={}",
+                    publish.topic,
+                    publish.qos as u8,
+                    publish.dup,
+                    publish.retain,
+                    publish.payload.len()
+                )
+            }
+            Packet::PubAck(pub_ack) => write!(
+                f,
+                "PUBACK packet_id={} reason_code={:?}",
+                pub_ack.packet_id, pub_ack.reason_code
+            ),
+            Packet::PubRec(pub_rec) => write!(
+                f,
+                "PUBREC packet_id={} reason_code={:?}",
+                pub_rec.packet_id, pub_rec.reason_code
+            ),
+            Packet::PubRel(pub_rel) => write!(
+                f,
+                "PUBREL packet_id={} reason_code={:?}",
+                pub_rel.packet_id, pub_rel.reason_code
+            ),
+            Packet::PubComp(pub_comp) => write!(
+                f,
+                "PUBCOMP packet_id={} reason_code={:?}",
+                pub_comp.packet_id, pub_comp.reason_code
+            ),
+            Packet::Subscribe(subscribe) => {
+                write!(f, "SUBSCRIBE packet_id={} filters=", subscribe.packet_id)?;
+                for (idx, filter) in subscribe.filters.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", filter.path, filter.qos as u8)?;
+                }
+                Ok(())
+            }
+            Packet::SubAck(sub_ack) => write!(
+                f,
+                "SUBACK packet_id={} reason_codes={:?}",
+                sub_ack.packet_id, sub_ack.reason_codes
+            ),
+            Packet::Unsubscribe(unsubscribe) => {
+                write!(
+                    f,
+                    "UNSUBSCRIBE packet_id={} filters=",
+                    unsubscribe.packet_id
+                )?;
+                for (idx, filter) in unsubscribe.filters.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", filter)?;
+                }
+                Ok(())
+            }
+            Packet::UnsubAck(unsub_ack) => write!(
+                f,
+                "UNSUBACK packet_id={} reason_codes={:?}",
+                unsub_ack.packet_id, unsub_ack.reason_codes
+            ),
+            Packet::PingReq => write!(f, "PINGREQ"),
+            Packet::PingResp => write!(f, "PINGRESP"),
+            Packet::Disconnect(disconnect) => {
+                write!(f, "DISCONNECT reason_code={:?}", disconnect.reason_code)
+            }
+            Packet::Auth(auth) => {
+                write!(f, "AUTH reason_code={:?}", auth.reason_code)?;
+                if let Some(method) = &auth.properties.authentication_method {
+                    write!(f, " auth_method={}", method)?;
+                }
+                if let Some(data) = &auth.properties.authentication_data {
+                    write!(f, " auth_data_len={}", data.len())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU16;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{
+        Connect, ConnectProperties, Login, ProtocolLevel, Publish, PublishProperties, Qos,
+    };
+
+    #[test]
+    fn test_summary() {
+        let publish = Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::AtLeastOnce,
+            retain: true,
+            topic: "a/b".into(),
+            packet_id: NonZeroU16::new(7),
+            properties: PublishProperties::default(),
+            payload: Bytes::from_static(b"secret"),
+        });
+        assert_eq!(
+            publish.summary().to_string(),
+            "PUBLISH packet_id=7 topic=a/b qos=1 dup=false retain=true payload_len=6"
+        );
+
+        let connect = Packet::Connect(Connect {
+            level: ProtocolLevel::V5,
+            keep_alive: 60,
+            clean_start: true,
+            client_id: "c".into(),
+            last_will: None,
+            login: Some(Login {
+                username: "user".into(),
+                password: Bytes::from_static(b"secret"),
+            }),
+            properties: ConnectProperties::default(),
+        });
+        let summary = connect.summary().to_string();
+        assert_eq!(
+            summary,
+            "CONNECT level=V5 client_id=c clean_start=true keep_alive=60 username=user password_len=6"
+        );
+        assert!(!summary.contains("secret"));
+    }
+}
@@ -1,3 +1,4 @@
+use bytes::Bytes;
 use bytestring::ByteString;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
@@ -7,6 +8,8 @@ use serde::{Deserialize, Serialize};
 )]
 #[repr(u8)]
 pub enum ProtocolLevel {
+    /// MQTT 3.1, the packets are encoded like MQTT 3.1.1 except the protocol name of CONNECT.
+    V3 = 3,
     V4 = 4,
     V5 = 5,
 }
@@ -14,7 +17,8 @@ pub enum ProtocolLevel {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Login {
     pub username: ByteString,
-    pub password: ByteString,
+    /// Arbitrary binary data, e.g. a token.
+    pub password: Bytes,
 }
 
 /// Level of assurance for delivery of an Application Message.
@@ -6,9 +6,10 @@ use bytestring::ByteString;
 use serde::{Deserialize, Serialize};
 
 use crate::packet::UNSUBSCRIBE;
+use crate::property::PropertySet;
 use crate::reader::PacketReader;
 use crate::writer::{bytes_remaining_length, PacketWriter};
-use crate::{property, DecodeError, EncodeError, ProtocolLevel};
+use crate::{property, DecodeError, DecodeOptions, EncodeError, ProtocolLevel};
 
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct UnsubscribeProperties {
@@ -29,18 +30,22 @@ impl UnsubscribeProperties {
         Ok(len)
     }
 
-    fn decode(mut data: Bytes) -> Result<Self, DecodeError> {
+    fn decode(mut data: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError> {
         let mut properties = UnsubscribeProperties::default();
+        let mut seen = PropertySet::new(data.len(), options)?;
 
         while data.has_remaining() {
             let flag = data.read_u8()?;
+            seen.insert(flag, options)?;
 
             if flag == property::USER_PROPERTY {
                 let key = data.read_string()?;
                 let value = data.read_string()?;
                 properties.user_properties.push((key, value));
-            } else {
+            } else if options.reject_unknown_properties {
                 return Err(DecodeError::InvalidUnsubscribeProperty(flag));
+            } else {
+                break;
             }
         }
 
@@ -88,12 +93,8 @@ impl Unsubscribe {
     pub(crate) fn decode(
         mut data: Bytes,
         level: ProtocolLevel,
-        flags: u8,
+        options: &DecodeOptions,
     ) -> Result<Self, DecodeError> {
-        if flags & 0x0f != 0b0010 {
-            return Err(DecodeError::MalformedPacket);
-        }
-
         let packet_id = data
             .read_u16()?
             .try_into()
@@ -107,7 +108,7 @@ impl Unsubscribe {
                 data.remaining() >= properties_len,
                 DecodeError::MalformedPacket
             );
-            properties = UnsubscribeProperties::decode(data.split_to(properties_len))?;
+            properties = UnsubscribeProperties::decode(data.split_to(properties_len), options)?;
         }
 
         let mut filters = Vec::new();
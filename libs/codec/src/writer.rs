@@ -70,13 +70,37 @@ impl PacketWriter for BytesMut {
 pub fn bytes_remaining_length(value: usize) -> Result<usize, EncodeError> {
     if value < 128 {
         Ok(1)
-    } else if value < 16383 {
+    } else if value < 16_384 {
         Ok(2)
-    } else if value < 2097151 {
+    } else if value < 2_097_152 {
         Ok(3)
-    } else if value < 268435455 {
+    } else if value < 268_435_456 {
         Ok(4)
     } else {
         Err(EncodeError::PayloadTooLarge)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytes_remaining_length() {
+        for value in [
+            0,
+            127,
+            128,
+            16_383,
+            16_384,
+            2_097_151,
+            2_097_152,
+            268_435_455,
+        ] {
+            let mut data = BytesMut::new();
+            data.write_remaining_length(value).unwrap();
+            assert_eq!(bytes_remaining_length(value).unwrap(), data.len());
+        }
+        assert!(bytes_remaining_length(268_435_456).is_err());
+    }
+}
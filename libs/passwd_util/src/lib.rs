@@ -2,15 +2,20 @@
 #![warn(clippy::default_trait_access)]
 
 use std::fmt::{self, Display, Formatter};
+use std::path::Path;
 use std::str::FromStr;
 
 use anyhow::Error;
 use argon2::Argon2;
-use password_hash::{Ident, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use hmac::{Hmac, Mac, NewMac};
+use password_hash::{
+    Ident, ParamsString, PasswordHash, PasswordHasher, PasswordVerifier, SaltString, Value,
+};
 use pbkdf2::Pbkdf2;
 use rand_core::OsRng;
 use scrypt::Scrypt;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum HashType {
@@ -67,87 +72,389 @@ impl Display for HashType {
     }
 }
 
+/// Cost parameters of the hash algorithms.
+///
+/// The default values are the defaults of the underlying libraries.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HashParams {
+    /// Argon2 memory size in KiB.
+    pub argon2_memory: u32,
+
+    /// Argon2 number of iterations.
+    pub argon2_iterations: u32,
+
+    /// Argon2 degree of parallelism.
+    pub argon2_parallelism: u32,
+
+    /// PBKDF2 number of rounds.
+    pub pbkdf2_iterations: u32,
+
+    /// Scrypt log2 of the work factor `N`.
+    pub scrypt_log_n: u8,
+
+    /// Scrypt block size.
+    pub scrypt_r: u32,
+
+    /// Scrypt parallelization.
+    pub scrypt_p: u32,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        let argon2 = argon2::Params::default();
+        let scrypt = scrypt::Params::recommended();
+        Self {
+            argon2_memory: argon2.m_cost,
+            argon2_iterations: argon2.t_cost,
+            argon2_parallelism: argon2.p_cost,
+            pbkdf2_iterations: pbkdf2::Params::default().rounds,
+            scrypt_log_n: scrypt.log_n(),
+            scrypt_r: scrypt.r(),
+            scrypt_p: scrypt.p(),
+        }
+    }
+}
+
+impl HashParams {
+    fn argon2(&self) -> argon2::Params {
+        argon2::Params {
+            m_cost: self.argon2_memory,
+            t_cost: self.argon2_iterations,
+            p_cost: self.argon2_parallelism,
+            ..argon2::Params::default()
+        }
+    }
+
+    fn pbkdf2(&self) -> pbkdf2::Params {
+        pbkdf2::Params {
+            rounds: self.pbkdf2_iterations,
+            ..pbkdf2::Params::default()
+        }
+    }
+
+    fn scrypt(&self) -> Result<scrypt::Params, Error> {
+        scrypt::Params::new(self.scrypt_log_n, self.scrypt_r, self.scrypt_p)
+            .map_err(|_| anyhow::anyhow!("invalid scrypt params"))
+    }
+}
+
 impl HashType {
-    pub fn create_phc(&self, password: impl AsRef<[u8]>) -> String {
+    pub fn create_phc(
+        &self,
+        password: impl AsRef<[u8]>,
+        params: &HashParams,
+    ) -> Result<String, Error> {
         let salt = SaltString::generate(&mut OsRng);
 
-        match self {
+        let phc = match self {
             HashType::Argon2d => Argon2::default()
                 .hash_password(
                     password.as_ref(),
                     Some(argon2::Algorithm::Argon2d.ident()),
-                    argon2::Params::default(),
+                    params.argon2(),
                     salt.as_salt(),
-                )
-                .unwrap()
+                )?
                 .to_string(),
             HashType::Argon2i => Argon2::default()
                 .hash_password(
                     password.as_ref(),
                     Some(argon2::Algorithm::Argon2i.ident()),
-                    argon2::Params::default(),
+                    params.argon2(),
                     salt.as_salt(),
-                )
-                .unwrap()
+                )?
                 .to_string(),
             HashType::Argon2id => Argon2::default()
                 .hash_password(
                     password.as_ref(),
                     Some(argon2::Algorithm::Argon2id.ident()),
-                    argon2::Params::default(),
+                    params.argon2(),
                     salt.as_salt(),
-                )
-                .unwrap()
+                )?
                 .to_string(),
             HashType::Pbkdf2Sha256 => Pbkdf2
                 .hash_password(
                     password.as_ref(),
                     Some(pbkdf2::Algorithm::Pbkdf2Sha256.ident()),
-                    pbkdf2::Params::default(),
+                    params.pbkdf2(),
                     salt.as_salt(),
-                )
-                .unwrap()
+                )?
                 .to_string(),
             HashType::Pbkdf2Sha512 => Pbkdf2
                 .hash_password(
                     password.as_ref(),
                     Some(pbkdf2::Algorithm::Pbkdf2Sha512.ident()),
-                    pbkdf2::Params::default(),
+                    params.pbkdf2(),
                     salt.as_salt(),
-                )
-                .unwrap()
+                )?
                 .to_string(),
             HashType::Scrypt => Scrypt
-                .hash_password_simple(password.as_ref(), salt.as_ref())
-                .unwrap()
+                .hash_password(
+                    password.as_ref(),
+                    Some(scrypt::ALG_ID),
+                    params.scrypt()?,
+                    salt.as_salt(),
+                )?
                 .to_string(),
+        };
+        Ok(phc)
+    }
+}
+
+/// The hash algorithm and parameters that stored passwords should use.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct HashPolicy {
+    pub hash: HashType,
+
+    #[serde(flatten)]
+    pub params: HashParams,
+}
+
+impl HashPolicy {
+    pub fn create_phc(&self, password: impl AsRef<[u8]>) -> Result<String, Error> {
+        self.hash.create_phc(password, &self.params)
+    }
+}
+
+/// Returns `true` if the PHC string uses another algorithm than the policy or weaker
+/// parameters, so the password should be hashed again.
+pub fn needs_rehash(phc: impl AsRef<str>, policy: &HashPolicy) -> bool {
+    let parsed_hash = match PasswordHash::new(phc.as_ref()) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(_) => return true,
+    };
+    if parsed_hash.algorithm.as_str() != policy.hash.to_string() {
+        return true;
+    }
+
+    let params = &policy.params;
+    let weaker = |name: &str, min: u32| !matches!(parsed_hash.params.get_decimal(name), Some(value) if value >= min);
+    match policy.hash {
+        HashType::Argon2d | HashType::Argon2i | HashType::Argon2id => {
+            weaker("m", params.argon2_memory)
+                || weaker("t", params.argon2_iterations)
+                || weaker("p", params.argon2_parallelism)
+        }
+        HashType::Pbkdf2Sha256 | HashType::Pbkdf2Sha512 => weaker("i", params.pbkdf2_iterations),
+        HashType::Scrypt => {
+            weaker("ln", params.scrypt_log_n as u32)
+                || weaker("r", params.scrypt_r)
+                || weaker("p", params.scrypt_p)
         }
     }
 }
 
+/// Name of the PHC parameter that identifies the pepper of a hash.
+const PEPPER_ID_PARAM: &str = "kid";
+
+/// A secret key that is mixed into passwords with HMAC-SHA256 before they are hashed.
+///
+/// Unlike the salt, the pepper is not stored with the hashes, so a leaked password file
+/// cannot be attacked without it. The id of the pepper is stored in the `kid` parameter of
+/// the PHC string, so that a new pepper can be introduced while the hashes created with the
+/// previous one are still verified.
+#[derive(Clone)]
+pub struct Pepper {
+    id: String,
+    key: Vec<u8>,
+}
+
+impl fmt::Debug for Pepper {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pepper").field("id", &self.id).finish()
+    }
+}
+
+impl Pepper {
+    /// Creates a pepper, the id may only contain the characters `[a-zA-Z0-9/+.-]`.
+    pub fn new(id: impl Into<String>, key: impl Into<Vec<u8>>) -> Result<Self, Error> {
+        let id = id.into();
+        let key = key.into();
+        anyhow::ensure!(
+            Value::new(&id).is_ok() && !id.is_empty(),
+            "invalid pepper id: {}",
+            id
+        );
+        anyhow::ensure!(!key.is_empty(), "empty pepper key");
+        Ok(Self { id, key })
+    }
+
+    /// Reads the key from an environment variable.
+    pub fn from_env(id: impl Into<String>, name: &str) -> Result<Self, Error> {
+        let key = std::env::var(name)
+            .map_err(|_| anyhow::anyhow!("environment variable {} is not set", name))?;
+        Self::new(id, key)
+    }
+
+    /// Reads the key from a file, trailing whitespace is removed.
+    pub fn from_file(id: impl Into<String>, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut key = std::fs::read(path)
+            .map_err(|err| anyhow::anyhow!("failed to read {}: {}", path.display(), err))?;
+        while let Some(b' ' | b'\t' | b'\r' | b'\n') = key.last() {
+            key.pop();
+        }
+        Self::new(id, key)
+    }
+
+    #[inline]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn apply(&self, password: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("any key length");
+        mac.update(password);
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+impl HashType {
+    /// Like [`HashType::create_phc`], but the password is combined with a pepper first.
+    pub fn create_phc_with_pepper(
+        &self,
+        password: impl AsRef<[u8]>,
+        params: &HashParams,
+        pepper: &Pepper,
+    ) -> Result<String, Error> {
+        let phc = self.create_phc(pepper.apply(password.as_ref()), params)?;
+        let mut parsed_hash = PasswordHash::new(&phc)?;
+        parsed_hash
+            .params
+            .add_str(PEPPER_ID_PARAM, pepper.id.as_str())?;
+        Ok(parsed_hash.to_string())
+    }
+}
+
+/// Returns the id of the pepper a hash was created with.
+pub fn pepper_id(phc: impl AsRef<str>) -> Option<String> {
+    PasswordHash::new(phc.as_ref())
+        .ok()?
+        .params
+        .get_str(PEPPER_ID_PARAM)
+        .map(ToString::to_string)
+}
+
 pub fn verify_password(phc: impl AsRef<str>, password: impl AsRef<[u8]>) -> bool {
-    let parsed_hash = match PasswordHash::new(phc.as_ref()) {
+    verify_password_with_peppers(phc, password, &[])
+}
+
+/// Verifies a password, hashes tagged with a pepper id are verified with the pepper of that
+/// id and fail if it is not in `peppers`.
+pub fn verify_password_with_peppers(
+    phc: impl AsRef<str>,
+    password: impl AsRef<[u8]>,
+    peppers: &[Pepper],
+) -> bool {
+    let mut parsed_hash = match PasswordHash::new(phc.as_ref()) {
         Ok(parsed_hash) => parsed_hash,
         Err(_) => return false,
     };
 
+    let pepper_id = match parsed_hash.params.get_str(PEPPER_ID_PARAM) {
+        Some(pepper_id) => pepper_id.to_string(),
+        None => return verify_parsed_hash(&parsed_hash, password.as_ref()),
+    };
+    let pepper = match peppers.iter().find(|pepper| pepper.id == pepper_id) {
+        Some(pepper) => pepper,
+        None => return false,
+    };
+
+    // the hash functions reject unknown parameters
+    let mut params = ParamsString::new();
+    for (name, value) in parsed_hash.params.iter() {
+        if name.as_str() != PEPPER_ID_PARAM && params.add_str(name, value).is_err() {
+            return false;
+        }
+    }
+    parsed_hash.params = params;
+    verify_parsed_hash(&parsed_hash, &pepper.apply(password.as_ref()))
+}
+
+fn verify_parsed_hash(parsed_hash: &PasswordHash, password: &[u8]) -> bool {
     const PBKDF2_SHA256: Ident = Ident::new("pbkdf2-sha256");
     const PBKDF2_SHA512: Ident = Ident::new("pbkdf2-sha512");
 
     match parsed_hash.algorithm {
         argon2::ARGON2I_IDENT | argon2::ARGON2D_IDENT | argon2::ARGON2ID_IDENT => Argon2::default()
-            .verify_password(password.as_ref(), &parsed_hash)
-            .is_ok(),
-        PBKDF2_SHA256 | PBKDF2_SHA512 => Pbkdf2
-            .verify_password(password.as_ref(), &parsed_hash)
-            .is_ok(),
-        scrypt::ALG_ID => Scrypt
-            .verify_password(password.as_ref(), &parsed_hash)
+            .verify_password(password, parsed_hash)
             .is_ok(),
+        PBKDF2_SHA256 | PBKDF2_SHA512 => Pbkdf2.verify_password(password, parsed_hash).is_ok(),
+        scrypt::ALG_ID => Scrypt.verify_password(password, parsed_hash).is_ok(),
         _ => false,
     }
 }
 
+/// The keys a SCRAM-SHA-256 server stores for a password (RFC 5802, RFC 7677).
+#[derive(Debug, Clone)]
+pub struct ScramSha256Verifier {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+}
+
+impl ScramSha256Verifier {
+    /// Derives the verifier of a password with the given salt and number of iterations.
+    pub fn new(password: impl AsRef<[u8]>, salt: &[u8], iterations: u32) -> Self {
+        let mut salted_password = [0; 32];
+        pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_ref(), salt, iterations, &mut salted_password);
+        Self::from_salted_password(salt.to_vec(), iterations, &salted_password)
+    }
+
+    /// Derives the verifier from a `pbkdf2-sha256` hash, whose output is the `SaltedPassword`
+    /// of SCRAM-SHA-256.
+    ///
+    /// Hashes of other algorithms, with another output length or created with a pepper cannot
+    /// be used, because the client has to derive the same key from the password.
+    pub fn from_phc(phc: impl AsRef<str>) -> Result<Self, Error> {
+        let parsed_hash = PasswordHash::new(phc.as_ref())?;
+        anyhow::ensure!(
+            parsed_hash.algorithm.as_str() == HashType::Pbkdf2Sha256.to_string(),
+            "SCRAM-SHA-256 requires a pbkdf2-sha256 hash"
+        );
+        anyhow::ensure!(
+            parsed_hash.params.get_str(PEPPER_ID_PARAM).is_none(),
+            "SCRAM-SHA-256 does not support peppered hashes"
+        );
+        let iterations = parsed_hash
+            .params
+            .get_decimal("i")
+            .ok_or_else(|| anyhow::anyhow!("missing the number of iterations"))?;
+        let mut salt = [0; 64];
+        let salt = parsed_hash
+            .salt
+            .ok_or_else(|| anyhow::anyhow!("missing salt"))?
+            .b64_decode(&mut salt)?;
+        let salted_password = parsed_hash
+            .hash
+            .ok_or_else(|| anyhow::anyhow!("missing hash"))?;
+        anyhow::ensure!(
+            salted_password.len() == 32,
+            "SCRAM-SHA-256 requires a 32 bytes hash"
+        );
+        Ok(Self::from_salted_password(
+            salt.to_vec(),
+            iterations,
+            salted_password.as_bytes(),
+        ))
+    }
+
+    fn from_salted_password(salt: Vec<u8>, iterations: u32, salted_password: &[u8]) -> Self {
+        let hmac = |data: &[u8]| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(salted_password).expect("any key length");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        };
+        Self {
+            salt,
+            iterations,
+            stored_key: Sha256::digest(&hmac(b"Client Key")).to_vec(),
+            server_key: hmac(b"Server Key"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,9 +472,142 @@ mod tests {
 
         for hash_type in types {
             let password = "123456";
-            let phc = hash_type.create_phc(password);
+            let phc = hash_type
+                .create_phc(password, &HashParams::default())
+                .unwrap();
             assert!(verify_password(&phc, password));
             assert!(!verify_password(&phc, "abcdef"));
         }
     }
+
+    #[test]
+    fn test_hash_params() {
+        let params = HashParams {
+            argon2_memory: 1024,
+            argon2_iterations: 2,
+            argon2_parallelism: 2,
+            pbkdf2_iterations: 1000,
+            scrypt_log_n: 10,
+            scrypt_r: 4,
+            scrypt_p: 2,
+        };
+
+        let phc = HashType::Argon2id.create_phc("123456", &params).unwrap();
+        assert!(phc.contains("m=1024,t=2,p=2"));
+        assert!(verify_password(&phc, "123456"));
+
+        let phc = HashType::Pbkdf2Sha256
+            .create_phc("123456", &params)
+            .unwrap();
+        assert!(phc.contains("i=1000"));
+        assert!(verify_password(&phc, "123456"));
+
+        let phc = HashType::Scrypt.create_phc("123456", &params).unwrap();
+        assert!(phc.contains("ln=10,r=4,p=2"));
+        assert!(verify_password(&phc, "123456"));
+
+        let params = HashParams {
+            scrypt_r: 0,
+            ..HashParams::default()
+        };
+        assert!(HashType::Scrypt.create_phc("123456", &params).is_err());
+    }
+
+    #[test]
+    fn test_needs_rehash() {
+        let weak = HashPolicy {
+            hash: HashType::Pbkdf2Sha256,
+            params: HashParams {
+                pbkdf2_iterations: 1000,
+                ..HashParams::default()
+            },
+        };
+        let strong = HashPolicy {
+            hash: HashType::Pbkdf2Sha256,
+            params: HashParams {
+                pbkdf2_iterations: 2000,
+                ..HashParams::default()
+            },
+        };
+        let other = HashPolicy {
+            hash: HashType::Pbkdf2Sha512,
+            params: weak.params,
+        };
+
+        let phc = weak.create_phc("123456").unwrap();
+        assert!(!needs_rehash(&phc, &weak));
+        assert!(needs_rehash(&phc, &strong));
+        assert!(needs_rehash(&phc, &other));
+        assert!(!needs_rehash(strong.create_phc("123456").unwrap(), &weak));
+        assert!(needs_rehash("invalid", &weak));
+    }
+
+    #[test]
+    fn test_pepper() {
+        let pepper1 = Pepper::new("k1", "secret1").unwrap();
+        let pepper2 = Pepper::new("k2", "secret2").unwrap();
+        let params = HashParams::default();
+
+        for hash_type in [HashType::Argon2id, HashType::Pbkdf2Sha256, HashType::Scrypt] {
+            let phc = hash_type
+                .create_phc_with_pepper("123456", &params, &pepper1)
+                .unwrap();
+            assert_eq!(pepper_id(&phc).as_deref(), Some("k1"));
+            assert!(verify_password_with_peppers(
+                &phc,
+                "123456",
+                &[pepper2.clone(), pepper1.clone()]
+            ));
+            assert!(!verify_password_with_peppers(
+                &phc,
+                "abcdef",
+                &[pepper1.clone()]
+            ));
+            assert!(!verify_password_with_peppers(
+                &phc,
+                "123456",
+                &[pepper2.clone()]
+            ));
+            assert!(!verify_password(&phc, "123456"));
+        }
+
+        assert!(Pepper::new("invalid id", "secret").is_err());
+    }
+
+    #[test]
+    fn test_scram_verifier() {
+        let phc = HashType::Pbkdf2Sha256
+            .create_phc(
+                "pencil",
+                &HashParams {
+                    pbkdf2_iterations: 4096,
+                    ..HashParams::default()
+                },
+            )
+            .unwrap();
+        let from_phc = ScramSha256Verifier::from_phc(&phc).unwrap();
+        let mut salt = [0; 64];
+        let salt = PasswordHash::new(&phc)
+            .unwrap()
+            .salt
+            .unwrap()
+            .b64_decode(&mut salt)
+            .unwrap()
+            .to_vec();
+        assert_eq!(from_phc.iterations, 4096);
+        assert_eq!(
+            from_phc.stored_key,
+            ScramSha256Verifier::new("pencil", &salt, 4096).stored_key
+        );
+
+        let pepper = Pepper::new("k1", "secret").unwrap();
+        let phc = HashType::Pbkdf2Sha256
+            .create_phc_with_pepper("pencil", &HashParams::default(), &pepper)
+            .unwrap();
+        assert!(ScramSha256Verifier::from_phc(&phc).is_err());
+        let phc = HashType::Argon2id
+            .create_phc("pencil", &HashParams::default())
+            .unwrap();
+        assert!(ScramSha256Verifier::from_phc(&phc).is_err());
+    }
 }
@@ -1,17 +1,64 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::default_trait_access)]
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
 
+use passwd_util::{HashPolicy, Pepper};
 use serde::Deserialize;
 use serde_yaml::Value;
 
-use service::plugin::{Plugin, PluginFactory, PluginResult};
+use service::plugin::{AuthResult, Plugin, PluginFactory, PluginResult};
+use service::{ClientCert, Quota};
 
 #[derive(Debug, Deserialize)]
 struct Config {
+    #[serde(default)]
     users: HashMap<String, String>,
+
+    /// A YAML map of user names to PHC strings, as written by `rsmqtt_passwd`.
+    users_file: Option<String>,
+
+    /// Hash the password again after a successful login if the stored hash does not meet
+    /// this policy, the new hash is written back to the users file.
+    rehash: Option<HashPolicy>,
+
+    /// Peppers the passwords are combined with, new hashes use the first one.
+    #[serde(default)]
+    peppers: Vec<PepperConfig>,
+
+    /// Per-user quotas that override the `user_quota` of the service.
+    #[serde(default)]
+    quotas: HashMap<String, Quota>,
+
+    /// Authenticate the clients that connect without a login by their verified client
+    /// certificate, the common name of the certificate is the uid.
+    #[serde(default)]
+    cert_auth: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct PepperConfig {
+    id: String,
+
+    /// Name of the environment variable that contains the key.
+    env: Option<String>,
+
+    /// Path of the file that contains the key.
+    file: Option<String>,
+}
+
+impl PepperConfig {
+    fn load(&self) -> PluginResult<Pepper> {
+        match (&self.env, &self.file) {
+            (Some(name), None) => Pepper::from_env(&self.id, name),
+            (None, Some(path)) => Pepper::from_file(&self.id, path),
+            _ => anyhow::bail!(
+                "pepper '{}': exactly one of `env` and `file` must be specified",
+                self.id
+            ),
+        }
+    }
 }
 
 pub struct BasicAuth;
@@ -24,24 +71,341 @@ impl PluginFactory for BasicAuth {
 
     async fn create(&self, config: Value) -> PluginResult<Arc<dyn Plugin>> {
         let config: Config = serde_yaml::from_value(config)?;
+        let file_users = match &config.users_file {
+            Some(path) => serde_yaml::from_slice(&std::fs::read(path)?)?,
+            None => BTreeMap::new(),
+        };
         Ok(Arc::new(BasicAuthImpl {
-            users: config.users,
+            users: Arc::new(Users {
+                users: RwLock::new(config.users),
+                users_file: config.users_file,
+                file_users: RwLock::new(file_users),
+                write_lock: Mutex::new(()),
+            }),
+            rehash: config.rehash,
+            peppers: config
+                .peppers
+                .iter()
+                .map(PepperConfig::load)
+                .collect::<PluginResult<_>>()?,
+            quotas: config.quotas,
+            cert_auth: config.cert_auth,
         }))
     }
 }
 
+/// The password hashes, shared with the blocking tasks that rehash them.
+struct Users {
+    users: RwLock<HashMap<String, String>>,
+    users_file: Option<String>,
+    file_users: RwLock<BTreeMap<String, String>>,
+    /// Serializes the writes of the users file.
+    write_lock: Mutex<()>,
+}
+
+impl Users {
+    fn get_phc(&self, user: &str) -> Option<String> {
+        if let Some(phc) = self.file_users.read().unwrap().get(user) {
+            return Some(phc.clone());
+        }
+        self.users.read().unwrap().get(user).cloned()
+    }
+
+    /// Replaces the hash `old_phc` of a user, the users file is rewritten if the user comes
+    /// from it.
+    ///
+    /// The file may have been edited since it was loaded, e.g. by `rsmqtt_passwd`, so it is read
+    /// again and only the entry of the user is replaced, unless its hash has changed. The file is
+    /// written without holding the lock of the users, so the logins are not blocked by the I/O.
+    fn update_phc(&self, user: &str, old_phc: &str, phc: String) -> PluginResult<()> {
+        if let Some(path) = &self.users_file {
+            if self.file_users.read().unwrap().contains_key(user) {
+                let _write_guard = self.write_lock.lock().unwrap();
+                let mut file_users: BTreeMap<String, String> =
+                    serde_yaml::from_slice(&std::fs::read(path)?)?;
+                match file_users.get_mut(user) {
+                    Some(stored) if stored == old_phc => *stored = phc.clone(),
+                    _ => return Ok(()),
+                }
+                let tmp_path = format!("{}.tmp", path);
+                std::fs::write(&tmp_path, serde_yaml::to_string(&file_users)?)?;
+                std::fs::rename(&tmp_path, path)?;
+
+                if let Some(stored) = self.file_users.write().unwrap().get_mut(user) {
+                    if stored == old_phc {
+                        *stored = phc;
+                    }
+                }
+                return Ok(());
+            }
+        }
+
+        if let Some(stored) = self.users.write().unwrap().get_mut(user) {
+            if stored == old_phc {
+                *stored = phc;
+            }
+        }
+        Ok(())
+    }
+}
+
 struct BasicAuthImpl {
-    users: HashMap<String, String>,
+    users: Arc<Users>,
+    rehash: Option<HashPolicy>,
+    peppers: Vec<Pepper>,
+    quotas: HashMap<String, Quota>,
+    cert_auth: bool,
 }
 
 #[async_trait::async_trait]
 impl Plugin for BasicAuthImpl {
-    async fn auth(&self, user: &str, password: &str) -> PluginResult<Option<String>> {
-        match self.users.get(user) {
-            Some(phc) if passwd_util::verify_password(&phc, &password) => {
-                Ok(Some(user.to_string()))
+    async fn auth(&self, user: &str, password: &[u8]) -> PluginResult<Option<AuthResult>> {
+        let phc = match self.users.get_phc(user) {
+            Some(phc)
+                if passwd_util::verify_password_with_peppers(&phc, password, &self.peppers) =>
+            {
+                phc
+            }
+            _ => return Ok(None),
+        };
+
+        if let Some(policy) = self.rehash {
+            let pepper = self.peppers.first().cloned();
+            if passwd_util::needs_rehash(&phc, &policy)
+                || passwd_util::pepper_id(&phc).as_deref() != pepper.as_ref().map(Pepper::id)
+            {
+                // hashing and writing the users file would block the runtime, the login does not
+                // wait for it
+                let users = self.users.clone();
+                let uid = user.to_string();
+                let password = password.to_vec();
+                tokio::task::spawn_blocking(move || {
+                    let new_phc = match &pepper {
+                        Some(pepper) => {
+                            policy
+                                .hash
+                                .create_phc_with_pepper(&password, &policy.params, pepper)
+                        }
+                        None => policy.create_phc(&password),
+                    };
+                    if let Err(err) =
+                        new_phc.and_then(|new_phc| users.update_phc(&uid, &phc, new_phc))
+                    {
+                        tracing::warn!(user = %uid, error = %err, "failed to rehash the password");
+                    }
+                });
+            }
+        }
+
+        Ok(Some(AuthResult {
+            uid: user.to_string(),
+            quota: self.quotas.get(user).copied(),
+            max_queued_bytes: None,
+            publish_rate_limit: None,
+            mount_point: None,
+        }))
+    }
+
+    async fn auth_cert(&self, cert: &ClientCert) -> PluginResult<Option<AuthResult>> {
+        if !self.cert_auth {
+            return Ok(None);
+        }
+        Ok(cert.common_name.as_ref().map(|common_name| AuthResult {
+            uid: common_name.clone(),
+            quota: self.quotas.get(common_name).copied(),
+            max_queued_bytes: None,
+            publish_rate_limit: None,
+            mount_point: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use passwd_util::{HashParams, HashType};
+
+    use super::*;
+
+    const REHASH: &str = "{hash: pbkdf2-sha256, pbkdf2_iterations: 2000}";
+
+    fn weak_params() -> HashParams {
+        HashParams {
+            pbkdf2_iterations: 1000,
+            ..HashParams::default()
+        }
+    }
+
+    fn policy() -> HashPolicy {
+        serde_yaml::from_str(REHASH).unwrap()
+    }
+
+    /// A temporary directory that is removed when the test ends.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "rsmqtt-basic-auth-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write_users(&self, users: &BTreeMap<String, String>) -> PathBuf {
+            let path = self.0.join("users.yaml");
+            std::fs::write(&path, serde_yaml::to_string(users).unwrap()).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn read_users(path: &Path) -> BTreeMap<String, String> {
+        serde_yaml::from_slice(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    async fn create(config: &str) -> Arc<dyn Plugin> {
+        BasicAuth
+            .create(serde_yaml::from_str(config).unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Waits for the rehash running in the background to replace the hash of the user.
+    async fn wait_rehashed(path: &Path, user: &str, old_phc: &str) -> String {
+        for _ in 0..500 {
+            let phc = read_users(path).remove(user).unwrap();
+            if phc != old_phc {
+                return phc;
             }
-            _ => Ok(None),
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("the password of {} was not rehashed", user);
+    }
+
+    #[tokio::test]
+    async fn test_rehash_weak_hash() {
+        let dir = TestDir::new("weak-hash");
+        let weak_phc = HashType::Pbkdf2Sha256
+            .create_phc("123456", &weak_params())
+            .unwrap();
+        let mut users = BTreeMap::new();
+        users.insert("sunli".to_string(), weak_phc.clone());
+        users.insert("a".to_string(), weak_phc.clone());
+        let path = dir.write_users(&users);
+        let plugin = create(&format!(
+            "{{users_file: {}, rehash: {}}}",
+            path.display(),
+            REHASH
+        ))
+        .await;
+
+        // the entries added after the file was loaded are kept
+        users.insert("b".to_string(), weak_phc.clone());
+        dir.write_users(&users);
+
+        assert!(plugin.auth("sunli", b"123456").await.unwrap().is_some());
+        let phc = wait_rehashed(&path, "sunli", &weak_phc).await;
+        assert!(!passwd_util::needs_rehash(&phc, &policy()));
+        assert!(passwd_util::verify_password(&phc, "123456"));
+
+        let users = read_users(&path);
+        assert_eq!(users.len(), 3);
+        assert_eq!(users["a"], weak_phc);
+        assert_eq!(users["b"], weak_phc);
+        // the file is replaced by renaming the temporary one
+        assert!(!Path::new(&format!("{}.tmp", path.display())).exists());
+
+        assert!(plugin.auth("sunli", b"123456").await.unwrap().is_some());
+        assert!(plugin.auth("sunli", b"654321").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rehash_old_pepper() {
+        let dir = TestDir::new("old-pepper");
+        std::fs::write(dir.0.join("new.key"), "new key").unwrap();
+        std::fs::write(dir.0.join("old.key"), "old key").unwrap();
+        let old_pepper = Pepper::new("old", "old key").unwrap();
+        let old_phc = HashType::Pbkdf2Sha256
+            .create_phc_with_pepper("123456", &policy().params, &old_pepper)
+            .unwrap();
+        let mut users = BTreeMap::new();
+        users.insert("sunli".to_string(), old_phc.clone());
+        let path = dir.write_users(&users);
+        let plugin = create(&format!(
+            concat!(
+                "{{users_file: {}, rehash: {}, ",
+                "peppers: [{{id: new, file: {}}}, {{id: old, file: {}}}]}}"
+            ),
+            path.display(),
+            REHASH,
+            dir.0.join("new.key").display(),
+            dir.0.join("old.key").display(),
+        ))
+        .await;
+
+        assert!(plugin.auth("sunli", b"123456").await.unwrap().is_some());
+        let phc = wait_rehashed(&path, "sunli", &old_phc).await;
+        assert_eq!(passwd_util::pepper_id(&phc).as_deref(), Some("new"));
+        let new_pepper = Pepper::new("new", "new key").unwrap();
+        assert!(passwd_util::verify_password_with_peppers(
+            &phc,
+            "123456",
+            &[new_pepper]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rehash_failed() {
+        let dir = TestDir::new("failed");
+        let weak_phc = HashType::Pbkdf2Sha256
+            .create_phc("123456", &weak_params())
+            .unwrap();
+        let mut users = BTreeMap::new();
+        users.insert("sunli".to_string(), weak_phc);
+        let path = dir.write_users(&users);
+        let plugin = create(&format!(
+            "{{users_file: {}, rehash: {}}}",
+            path.display(),
+            REHASH
+        ))
+        .await;
+
+        // the users file can not be read again
+        std::fs::remove_file(&path).unwrap();
+        for _ in 0..2 {
+            assert!(plugin.auth("sunli", b"123456").await.unwrap().is_some());
         }
+        assert!(plugin.auth("sunli", b"654321").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_update_changed_phc() {
+        let dir = TestDir::new("changed");
+        let mut users = BTreeMap::new();
+        users.insert("sunli".to_string(), "changed".to_string());
+        let path = dir.write_users(&users);
+        let users = Users {
+            users: RwLock::default(),
+            users_file: Some(path.display().to_string()),
+            file_users: RwLock::new(
+                std::iter::once(("sunli".to_string(), "old".to_string())).collect(),
+            ),
+            write_lock: Mutex::new(()),
+        };
+
+        // the password was changed in the file since it was loaded
+        users.update_phc("sunli", "old", "new".to_string()).unwrap();
+        assert_eq!(read_users(&path)["sunli"], "changed");
     }
 }
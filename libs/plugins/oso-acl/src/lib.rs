@@ -38,6 +38,9 @@ impl PluginFactory for OsoAcl {
                         .map(|addr| addr.to_string())
                         .unwrap_or_default()
                 })
+                .add_attribute_getter("listener", |conn| {
+                    conn.addr.listener.clone().unwrap_or_default()
+                })
                 .add_attribute_getter("uid", |conn| {
                     conn.uid
                         .as_ref()
@@ -0,0 +1,364 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::default_trait_access)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use hmac::{Hmac, Mac, NewMac};
+use passwd_util::ScramSha256Verifier;
+use rand_core::{OsRng, RngCore};
+use serde::Deserialize;
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use service::plugin::{
+    AuthResult, EnhancedAuth, EnhancedAuthStep, Plugin, PluginFactory, PluginResult,
+};
+use service::Quota;
+
+/// The authentication method of the CONNECT and AUTH packets.
+const METHOD: &str = "SCRAM-SHA-256";
+
+/// The number of iterations sent for the unknown users if no user is configured.
+const DEFAULT_ITERATIONS: u32 = 4096;
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    /// A map of user names to `pbkdf2-sha256` PHC strings.
+    #[serde(default)]
+    users: HashMap<String, String>,
+
+    /// A YAML map of user names to PHC strings, as written by `rsmqtt_passwd`.
+    users_file: Option<String>,
+
+    /// Per-user quotas that override the `user_quota` of the service.
+    #[serde(default)]
+    quotas: HashMap<String, Quota>,
+}
+
+pub struct ScramAuth;
+
+#[async_trait::async_trait]
+impl PluginFactory for ScramAuth {
+    fn name(&self) -> &'static str {
+        "scram-auth"
+    }
+
+    async fn create(&self, config: Value) -> PluginResult<Arc<dyn Plugin>> {
+        let config: Config = serde_yaml::from_value(config)?;
+        let file_users: BTreeMap<String, String> = match &config.users_file {
+            Some(path) => serde_yaml::from_slice(&std::fs::read(path)?)?,
+            None => BTreeMap::new(),
+        };
+        let mut verifiers = HashMap::new();
+        for (user, phc) in config.users.into_iter().chain(file_users) {
+            let verifier = ScramSha256Verifier::from_phc(&phc)
+                .map_err(|err| anyhow::anyhow!("user '{}': {}", user, err))?;
+            verifiers.insert(user, verifier);
+        }
+        let mut mock_key = vec![0; 32];
+        OsRng.fill_bytes(&mut mock_key);
+        Ok(Arc::new(ScramAuthImpl {
+            users: Arc::new(Users::new(verifiers, config.quotas, mock_key)),
+        }))
+    }
+}
+
+struct Users {
+    verifiers: HashMap<String, ScramSha256Verifier>,
+    quotas: HashMap<String, Quota>,
+    /// Derives the salts of the unknown users.
+    mock_key: Vec<u8>,
+    /// The largest number of iterations of the users, sent for the unknown users.
+    mock_iterations: u32,
+}
+
+impl Users {
+    fn new(
+        verifiers: HashMap<String, ScramSha256Verifier>,
+        quotas: HashMap<String, Quota>,
+        mock_key: Vec<u8>,
+    ) -> Self {
+        let mock_iterations = verifiers
+            .values()
+            .map(|verifier| verifier.iterations)
+            .max()
+            .unwrap_or(DEFAULT_ITERATIONS);
+        Self {
+            verifiers,
+            quotas,
+            mock_key,
+            mock_iterations,
+        }
+    }
+
+    /// Returns the salt and number of iterations of a user.
+    ///
+    /// An unknown user receives a salt derived from its name, so that it is the same for every
+    /// exchange, and fails with the proof like a wrong password. This way the exchange does not
+    /// tell which users exist (RFC 5802 §5.1).
+    fn salt_and_iterations(&self, user: &str) -> (Vec<u8>, u32) {
+        match self.verifiers.get(user) {
+            Some(verifier) => (verifier.salt.clone(), verifier.iterations),
+            None => (
+                hmac(&self.mock_key, user)[..16].to_vec(),
+                self.mock_iterations,
+            ),
+        }
+    }
+}
+
+struct ScramAuthImpl {
+    users: Arc<Users>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for ScramAuthImpl {
+    async fn enhanced_auth(&self, method: &str) -> PluginResult<Option<Box<dyn EnhancedAuth>>> {
+        if method != METHOD {
+            return Ok(None);
+        }
+        let mut nonce = [0; 18];
+        OsRng.fill_bytes(&mut nonce);
+        Ok(Some(Box::new(ScramExchange::new(
+            self.users.clone(),
+            base64::encode(nonce),
+        ))))
+    }
+}
+
+enum State {
+    ClientFirst,
+    ClientFinal {
+        user: String,
+        gs2_header: String,
+        nonce: String,
+        auth_message: String,
+    },
+    Done,
+}
+
+/// The server side of a SCRAM-SHA-256 exchange (RFC 5802), channel binding is not supported
+/// and the user names are not normalized with SASLprep.
+struct ScramExchange {
+    users: Arc<Users>,
+    server_nonce: String,
+    state: State,
+}
+
+impl ScramExchange {
+    fn new(users: Arc<Users>, server_nonce: String) -> Self {
+        Self {
+            users,
+            server_nonce,
+            state: State::ClientFirst,
+        }
+    }
+
+    /// Handles `client-first-message`, returns `server-first-message`.
+    fn client_first(&mut self, message: &str) -> Option<EnhancedAuthStep> {
+        let mut parts = message.splitn(3, ',');
+        let (flag, authzid, bare) = (parts.next()?, parts.next()?, parts.next()?);
+        if !matches!(flag, "n" | "y") || !authzid.is_empty() {
+            return None;
+        }
+
+        let mut attrs = bare.split(',');
+        let user = decode_name(attrs.next()?.strip_prefix("n=")?)?;
+        let client_nonce = attrs.next()?.strip_prefix("r=")?;
+        if client_nonce.is_empty() {
+            return None;
+        }
+        let (salt, iterations) = self.users.salt_and_iterations(&user);
+
+        let nonce = format!("{}{}", client_nonce, self.server_nonce);
+        let server_first = format!("r={},s={},i={}", nonce, base64::encode(&salt), iterations);
+        self.state = State::ClientFinal {
+            user,
+            gs2_header: message[..message.len() - bare.len()].to_string(),
+            nonce,
+            auth_message: format!("{},{}", bare, server_first),
+        };
+        Some(EnhancedAuthStep::Continue(Some(server_first.into())))
+    }
+
+    /// Handles `client-final-message`, returns `server-final-message` if the proof is valid.
+    fn client_final(
+        &self,
+        message: &str,
+        user: String,
+        gs2_header: &str,
+        nonce: &str,
+        auth_message: &str,
+    ) -> Option<EnhancedAuthStep> {
+        let (without_proof, proof) = message.rsplit_once(",p=")?;
+        let mut attrs = without_proof.split(',');
+        let channel_binding = base64::decode(attrs.next()?.strip_prefix("c=")?).ok()?;
+        if channel_binding != gs2_header.as_bytes() || attrs.next()?.strip_prefix("r=")? != nonce {
+            return None;
+        }
+
+        let verifier = self.users.verifiers.get(&user)?;
+        let auth_message = format!("{},{}", auth_message, without_proof);
+        let client_signature = hmac(&verifier.stored_key, &auth_message);
+        let proof = base64::decode(proof).ok()?;
+        if proof.len() != client_signature.len() {
+            return None;
+        }
+        let client_key = proof
+            .iter()
+            .zip(&client_signature)
+            .map(|(a, b)| a ^ b)
+            .collect::<Vec<_>>();
+        if !bool::from(
+            Sha256::digest(&client_key)
+                .as_slice()
+                .ct_eq(&verifier.stored_key),
+        ) {
+            return None;
+        }
+
+        let server_signature = hmac(&verifier.server_key, &auth_message);
+        let quota = self.users.quotas.get(&user).copied();
+        Some(EnhancedAuthStep::Success(
+            AuthResult {
+                uid: user,
+                quota,
+                max_queued_bytes: None,
+                publish_rate_limit: None,
+                mount_point: None,
+            },
+            Some(format!("v={}", base64::encode(server_signature)).into()),
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl EnhancedAuth for ScramExchange {
+    async fn step(&mut self, data: Option<Bytes>) -> PluginResult<EnhancedAuthStep> {
+        let message = match data.as_deref().map(std::str::from_utf8) {
+            Some(Ok(message)) => message,
+            _ => return Ok(EnhancedAuthStep::Failed),
+        };
+        let step = match std::mem::replace(&mut self.state, State::Done) {
+            State::ClientFirst => self.client_first(message),
+            State::ClientFinal {
+                user,
+                gs2_header,
+                nonce,
+                auth_message,
+            } => self.client_final(message, user, &gs2_header, &nonce, &auth_message),
+            State::Done => None,
+        };
+        Ok(step.unwrap_or(EnhancedAuthStep::Failed))
+    }
+}
+
+/// Decodes the `=2C` and `=3D` escapes of a SCRAM user name.
+fn decode_name(name: &str) -> Option<String> {
+    let mut decoded = String::with_capacity(name.len());
+    let mut parts = name.split('=');
+    decoded.push_str(parts.next()?);
+    for part in parts {
+        match part.get(..2)? {
+            "2C" => decoded.push(','),
+            "3D" => decoded.push('='),
+            _ => return None,
+        }
+        decoded.push_str(&part[2..]);
+    }
+    Some(decoded)
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_exchange() -> ScramExchange {
+        let salt = base64::decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap();
+        let mut verifiers = HashMap::new();
+        verifiers.insert(
+            "user".to_string(),
+            ScramSha256Verifier::new("pencil", &salt, 4096),
+        );
+        ScramExchange::new(
+            Arc::new(Users::new(verifiers, HashMap::new(), vec![1; 32])),
+            "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0".to_string(),
+        )
+    }
+
+    async fn step(exchange: &mut ScramExchange, data: &'static str) -> EnhancedAuthStep {
+        exchange
+            .step(Some(Bytes::from_static(data.as_bytes())))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_exchange() {
+        // RFC 7677
+        let mut exchange = new_exchange();
+        assert!(matches!(
+            step(&mut exchange, "n,,n=user,r=rOprNGfwEbeRWgbNEkqO").await,
+            EnhancedAuthStep::Continue(Some(data)) if data == "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+        ));
+        assert!(matches!(
+            step(&mut exchange, "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=").await,
+            EnhancedAuthStep::Success(res, Some(data)) if res.uid == "user" && data == "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_proof() {
+        let mut exchange = new_exchange();
+        step(&mut exchange, "n,,n=user,r=rOprNGfwEbeRWgbNEkqO").await;
+        assert!(matches!(
+            step(&mut exchange, "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=AAAAZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=").await,
+            EnhancedAuthStep::Failed
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_user() {
+        let server_first = |exchange: &mut ScramExchange, user: &str| {
+            let client_first = format!("n,,n={},r=rOprNGfwEbeRWgbNEkqO", user);
+            match exchange.client_first(&client_first) {
+                Some(EnhancedAuthStep::Continue(Some(data))) => {
+                    String::from_utf8(data.to_vec()).unwrap()
+                }
+                _ => panic!("unexpected step"),
+            }
+        };
+
+        // the unknown users receive a stable salt and the iterations of the users
+        let mut exchange = new_exchange();
+        let unknown = server_first(&mut exchange, "unknown");
+        assert!(unknown.ends_with(",i=4096"));
+        assert_eq!(unknown, server_first(&mut new_exchange(), "unknown"));
+        assert_ne!(
+            unknown.split(',').nth(1),
+            server_first(&mut new_exchange(), "other").split(',').nth(1)
+        );
+
+        // and fail like a wrong password
+        assert!(matches!(
+            step(&mut exchange, "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=").await,
+            EnhancedAuthStep::Failed
+        ));
+    }
+
+    #[test]
+    fn test_decode_name() {
+        assert_eq!(decode_name("a=2Cb=3D").as_deref(), Some("a,b="));
+        assert_eq!(decode_name("a=2"), None);
+        assert_eq!(decode_name("a=41"), None);
+    }
+}
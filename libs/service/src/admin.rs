@@ -0,0 +1,219 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Result;
+use codec::ProtocolLevel;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::message::Message;
+use crate::state::Control;
+use crate::{filter_util, RemoteAddr, ServiceState};
+
+/// A connected client.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientInfo {
+    pub client_id: String,
+    pub uid: Option<String>,
+    pub remote_addr: String,
+    /// The name of the listener the client connected to.
+    pub listener: Option<String>,
+    pub level: ProtocolLevel,
+    pub keep_alive: u16,
+    /// Unix timestamp in seconds.
+    pub connected_at: u64,
+}
+
+/// Rejects the matching clients when they connect.
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Ban {
+    ClientId(String),
+    /// The uid returned by the auth plugin.
+    User(String),
+    /// The IP address of the client.
+    Address(String),
+}
+
+/// A ban returned by [`ServiceState::bans`].
+#[derive(Debug, Clone, Serialize)]
+pub struct BanInfo {
+    #[serde(flatten)]
+    pub ban: Ban,
+    /// The remaining seconds until the ban expires, `None` if it is permanent.
+    pub remaining: Option<u64>,
+}
+
+impl Ban {
+    fn matches(&self, client_id: &str, uid: Option<&str>, remote_addr: &RemoteAddr) -> bool {
+        match self {
+            Ban::ClientId(id) => id == client_id,
+            Ban::User(user) => Some(user.as_str()) == uid,
+            Ban::Address(ip) => match remote_addr.addr.as_deref() {
+                Some(addr) => match addr.parse::<SocketAddr>() {
+                    Ok(addr) => addr.ip().to_string() == *ip,
+                    Err(_) => addr == ip,
+                },
+                None => false,
+            },
+        }
+    }
+}
+
+impl ServiceState {
+    pub async fn clients(&self) -> Vec<ClientInfo> {
+        let mut clients = self
+            .connections
+            .read()
+            .await
+            .values()
+            .map(|handle| handle.info.clone())
+            .collect::<Vec<_>>();
+        clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        clients
+    }
+
+    /// Disconnects the client with the reason code `AdministrativeAction`, returns `false` if it
+    /// is not connected.
+    pub async fn kick(&self, client_id: &str) -> bool {
+        match self.connections.read().await.get(client_id) {
+            Some(handle) => handle.control_sender.send(Control::Kick).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Publishes a message as if it was sent by a client.
+    pub fn publish(&self, msg: Message) -> Result<()> {
+        anyhow::ensure!(
+            filter_util::valid_topic(msg.topic()),
+            "invalid topic: {}",
+            msg.topic()
+        );
+        let priority = self.is_priority_topic(msg.topic());
+        let msg = msg.with_priority(priority);
+        if msg.is_retain() {
+            self.storage.update_retained_message(msg.clone());
+        }
+        self.storage.deliver(vec![msg]);
+        Ok(())
+    }
+
+    /// Returns the retained messages matching the filter.
+    pub fn retained_messages(&self, filter: &str) -> Result<Vec<Message>> {
+        let filter = filter_util::parse_filter(filter)
+            .filter(|filter| filter.share_name.is_none())
+            .ok_or_else(|| anyhow::anyhow!("invalid topic filter: {}", filter))?;
+        Ok(self.storage.retained_messages(filter))
+    }
+
+    pub fn bans(&self) -> Vec<BanInfo> {
+        let now = Instant::now();
+        self.bans
+            .read()
+            .iter()
+            .filter(|(_, expires_at)| is_active(**expires_at, now))
+            .map(|(ban, expires_at)| BanInfo {
+                ban: ban.clone(),
+                remaining: expires_at
+                    .map(|expires_at| expires_at.saturating_duration_since(now).as_secs()),
+            })
+            .collect()
+    }
+
+    /// Bans the clients, the ban is removed after `duration` if specified.
+    ///
+    /// It does not disconnect the connected clients, adding an existing ban replaces its
+    /// duration.
+    pub fn add_ban(&self, ban: Ban, duration: Option<Duration>) {
+        let now = Instant::now();
+        let mut bans = self.bans.write();
+        bans.retain(|_, expires_at| is_active(*expires_at, now));
+        bans.insert(ban, duration.map(|duration| now + duration));
+    }
+
+    /// Returns `false` if the ban does not exist.
+    pub fn remove_ban(&self, ban: &Ban) -> bool {
+        match self.bans.write().remove(ban) {
+            Some(expires_at) => is_active(expires_at, Instant::now()),
+            None => false,
+        }
+    }
+
+    pub(crate) fn is_banned(
+        &self,
+        client_id: &str,
+        uid: Option<&str>,
+        remote_addr: &RemoteAddr,
+    ) -> bool {
+        let now = Instant::now();
+        self.bans.read().iter().any(|(ban, expires_at)| {
+            is_active(*expires_at, now) && ban.matches(client_id, uid, remote_addr)
+        })
+    }
+}
+
+fn is_active(expires_at: Option<Instant>, now: Instant) -> bool {
+    match expires_at {
+        Some(expires_at) => now < expires_at,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceConfig;
+
+    #[test]
+    fn test_ban_matches() {
+        let remote_addr = RemoteAddr {
+            protocol: "tcp".into(),
+            addr: Some("127.0.0.1:1234".into()),
+            client_cert: None,
+            listener: None,
+        };
+
+        assert!(Ban::ClientId("a".to_string()).matches("a", None, &remote_addr));
+        assert!(!Ban::ClientId("a".to_string()).matches("b", None, &remote_addr));
+        assert!(Ban::User("sunli".to_string()).matches("a", Some("sunli"), &remote_addr));
+        assert!(!Ban::User("sunli".to_string()).matches("a", None, &remote_addr));
+        assert!(Ban::Address("127.0.0.1".to_string()).matches("a", None, &remote_addr));
+        assert!(!Ban::Address("127.0.0.2".to_string()).matches("a", None, &remote_addr));
+
+        let ban: Ban = serde_yaml::from_str("{type: client_id, value: a}").unwrap();
+        assert_eq!(ban, Ban::ClientId("a".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_ban_expiry() {
+        let state = ServiceState::new(ServiceConfig::default(), Vec::new()).unwrap();
+        let remote_addr = RemoteAddr {
+            protocol: "tcp".into(),
+            addr: Some("127.0.0.1:1234".into()),
+            client_cert: None,
+            listener: None,
+        };
+
+        state.add_ban(Ban::ClientId("a".to_string()), None);
+        state.add_ban(
+            Ban::ClientId("b".to_string()),
+            Some(Duration::from_secs(60)),
+        );
+        state.add_ban(Ban::ClientId("c".to_string()), Some(Duration::ZERO));
+        assert!(state.is_banned("a", None, &remote_addr));
+        assert!(state.is_banned("b", None, &remote_addr));
+        assert!(!state.is_banned("c", None, &remote_addr));
+
+        let mut bans = state.bans();
+        bans.sort_by_key(|info| info.remaining);
+        assert_eq!(bans.len(), 2);
+        assert_eq!(bans[0].ban, Ban::ClientId("a".to_string()));
+        assert_eq!(bans[0].remaining, None);
+        assert_eq!(bans[1].ban, Ban::ClientId("b".to_string()));
+        assert!(bans[1].remaining.unwrap() <= 60);
+
+        assert!(!state.remove_ban(&Ban::ClientId("c".to_string())));
+        assert!(state.remove_ban(&Ban::ClientId("b".to_string())));
+        assert!(!state.is_banned("b", None, &remote_addr));
+    }
+}
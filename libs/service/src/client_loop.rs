@@ -1,41 +1,99 @@
 use std::borrow::Cow;
 use std::fmt::{self, Display, Formatter};
-use std::num::NonZeroU16;
+use std::num::{NonZeroU16, NonZeroU32};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::Result;
+use bytes::Bytes;
 use bytestring::ByteString;
 use codec::{
-    Codec, ConnAck, ConnAckProperties, Connect, ConnectReasonCode, DecodeError, Disconnect,
-    DisconnectProperties, DisconnectReasonCode, EncodeError, LastWill, Packet, PacketIdAllocator,
-    ProtocolLevel, PubAck, PubAckProperties, PubAckReasonCode, PubComp, PubCompProperties,
-    PubCompReasonCode, PubRec, PubRecProperties, PubRecReasonCode, PubRel, PubRelProperties,
-    PubRelReasonCode, Publish, Qos, SubAck, SubAckProperties, Subscribe, SubscribeReasonCode,
-    UnsubAck, UnsubAckProperties, UnsubAckReasonCode, Unsubscribe,
+    Auth, AuthProperties, AuthReasonCode, Codec, ConnAck, ConnAckProperties, Connect,
+    ConnectReasonCode, DecodeError, Disconnect, DisconnectProperties, DisconnectReasonCode,
+    EncodeError, LastWill, Packet, PacketIdAllocator, ProtocolLevel, PubAck, PubAckProperties,
+    PubAckReasonCode, PubComp, PubCompProperties, PubCompReasonCode, PubRec, PubRecProperties,
+    PubRecReasonCode, PubRel, PubRelProperties, PubRelReasonCode, Publish, Qos, SubAck,
+    SubAckProperties, Subscribe, SubscribeReasonCode, UnsubAck, UnsubAckProperties,
+    UnsubAckReasonCode, Unsubscribe,
 };
 use fnv::FnvHashMap;
 use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::{mpsc, Notify};
 
+use crate::admin::ClientInfo;
+use crate::clock;
+use crate::config::{ListenerSettings, PublishDenied, RewriteTarget};
+use crate::disconnects::DisconnectReason;
 use crate::error::Error;
-use crate::filter_util;
+use crate::filter_util::{self, Filter};
 use crate::message::Message;
-use crate::plugin::Action;
-use crate::state::Control;
+use crate::plugin::{Action, AuthResult, EnhancedAuth, EnhancedAuthStep, Plugin};
+use crate::quota::QuotaUsage;
+use crate::rate_limit::TokenBucket;
+use crate::state::{ConnectionHandle, Control};
+use crate::storage::QueueLimit;
+use crate::trace::Direction;
 use crate::ServiceState;
 
+/// Why the server rejected an operation, see [`Connection::problem_info`].
+struct Problem {
+    reason: String,
+    properties: Vec<(ByteString, ByteString)>,
+}
+
+impl Problem {
+    fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            properties: Vec::new(),
+        }
+    }
+
+    fn with_property(mut self, key: &'static str, value: impl Into<ByteString>) -> Self {
+        self.properties.push((key.into(), value.into()));
+        self
+    }
+
+    fn acl_denied(action: Action, topic: &str, plugin: &str) -> Self {
+        let action = match action {
+            Action::Publish => "publish",
+            Action::Subscribe => "subscribe",
+        };
+        Problem::new(format!(
+            "{} to {} denied by plugin {}",
+            action, topic, plugin
+        ))
+        .with_property("plugin", plugin)
+        .with_property("topic", topic)
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum Qos2State {
     Published,
     Recorded,
 }
 
+/// The identity of a client certificate verified by the TLS listener.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientCert {
+    /// The common name of the subject.
+    pub common_name: Option<String>,
+    /// The DNS names, email addresses and URIs of the subject alternative names.
+    #[serde(default)]
+    pub subject_alt_names: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RemoteAddr {
     pub protocol: Cow<'static, str>,
     pub addr: Option<ByteString>,
+    #[serde(default)]
+    pub client_cert: Option<ClientCert>,
+    /// The name of the listener the connection arrived on.
+    #[serde(default)]
+    pub listener: Option<String>,
 }
 
 impl Display for RemoteAddr {
@@ -52,9 +110,17 @@ impl Display for RemoteAddr {
 pub struct Connection<R, W> {
     state: Arc<ServiceState>,
     remote_addr: RemoteAddr,
+    settings: Arc<ListenerSettings>,
+    /// The plugins of the listener.
+    plugins: Vec<(&'static str, Arc<dyn Plugin>)>,
     client_id: Option<ByteString>,
     control_sender: mpsc::UnboundedSender<Control>,
     uid: Option<ByteString>,
+    /// Prefixes the topics of the client, see [`AuthResult::mount_point`].
+    ///
+    /// [`AuthResult::mount_point`]: crate::plugin::AuthResult::mount_point
+    mount_point: Option<String>,
+    quota_usage: Option<Arc<QuotaUsage>>,
     notify: Arc<Notify>,
     codec: Codec<R, W>,
     session_expiry_interval: u32,
@@ -64,12 +130,37 @@ pub struct Connection<R, W> {
     receive_out_quota: usize,
     max_topic_alias: usize,
     topic_alias: FnvHashMap<NonZeroU16, ByteString>,
+    /// The Topic Alias Maximum of the CONNECT packet, the number of aliases the client accepts.
+    max_topic_alias_out: usize,
+    /// The aliases assigned to the topics of the messages sent to the client.
+    topic_alias_out: FnvHashMap<ByteString, NonZeroU16>,
     keep_alive: u16,
-    last_active: Instant,
+    /// Limits the rate of the PUBLISH packets, see [`ServiceConfig::publish_rate_limit`].
+    ///
+    /// [`ServiceConfig::publish_rate_limit`]: crate::ServiceConfig::publish_rate_limit
+    publish_rate: Option<TokenBucket>,
+    /// Limits the bytes of the received and sent packets, see
+    /// [`ServiceConfig::bandwidth_limit`].
+    ///
+    /// [`ServiceConfig::bandwidth_limit`]: crate::ServiceConfig::bandwidth_limit
+    inbound_bandwidth: Option<TokenBucket>,
+    outbound_bandwidth: Option<TokenBucket>,
+    /// The Request Problem Information of the CONNECT packet.
+    request_problem_info: bool,
     last_will: Option<LastWill>,
     packet_id_allocator: PacketIdAllocator,
     inflight_qos2_messages: FnvHashMap<NonZeroU16, Qos2State>,
-    uncompleted_messages: FnvHashMap<NonZeroU16, Message>,
+    /// The number of times the inflight packets were sent again, see
+    /// [`Connection::retry_inflight`].
+    retries: FnvHashMap<NonZeroU16, usize>,
+    /// `None` for a message rejected by [`PublishDenied::Reject`].
+    uncompleted_messages: FnvHashMap<NonZeroU16, Option<Message>>,
+    disconnect_reason: DisconnectReason,
+    /// The enhanced authentication method given in the CONNECT packet.
+    auth_method: Option<ByteString>,
+    auth_exchange: Option<Box<dyn EnhancedAuth>>,
+    /// The CONNECT packet waiting for the enhanced authentication to complete.
+    pending_connect: Option<Connect>,
 }
 
 impl<R, W> Connection<R, W>
@@ -78,15 +169,58 @@ where
     W: AsyncWrite + Send + Unpin,
 {
     async fn send_packet(&mut self, packet: &Packet) -> Result<(), Error> {
+        self.feed_packet(packet)?;
+        self.flush().await
+    }
+
+    /// Writes the queued packets, then waits while the connection is over its outbound
+    /// bandwidth.
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.codec.flush().await?;
+        if let Some(bandwidth) = &mut self.outbound_bandwidth {
+            let wait_time = bandwidth.wait_time(tokio::time::Instant::now());
+            if !wait_time.is_zero() {
+                tokio::time::sleep(wait_time).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits while the connection is over its inbound bandwidth after receiving a packet.
+    async fn throttle_inbound(&mut self, packet_size: usize) {
+        if let Some(bandwidth) = &mut self.inbound_bandwidth {
+            let now = tokio::time::Instant::now();
+            bandwidth.take(packet_size, now);
+            let wait_time = bandwidth.wait_time(now);
+            if !wait_time.is_zero() {
+                tokio::time::sleep(wait_time).await;
+            }
+        }
+    }
+
+    /// Queues a packet without writing it, see [`Codec::feed`].
+    fn feed_packet(&mut self, packet: &Packet) -> Result<(), Error> {
         tracing::debug!(
             remote_addr = %self.remote_addr,
-            packet = ?packet,
+            packet = %packet.summary(),
             "send packet",
         );
-        match self.codec.encode(packet).await {
+        self.state
+            .trace_packet(self.client_id.as_deref(), Direction::Sent, packet);
+        if let Packet::Disconnect(disconnect) = packet {
+            self.disconnect_reason = DisconnectReason::Server(disconnect.reason_code);
+            if self.codec.protocol_level() != ProtocolLevel::V5 {
+                // MQTT 3.1.1 has no DISCONNECT from the server, the connection is just closed
+                return Ok(());
+            }
+        }
+        match self.codec.feed(packet) {
             Ok(packet_size) => {
                 self.state.service_metrics.inc_msgs_sent(1);
                 self.state.service_metrics.inc_bytes_sent(packet_size);
+                if let Some(bandwidth) = &mut self.outbound_bandwidth {
+                    bandwidth.take(packet_size, tokio::time::Instant::now());
+                }
                 if let Packet::Publish(publish) = packet {
                     self.state
                         .service_metrics
@@ -101,6 +235,13 @@ where
         }
     }
 
+    #[inline]
+    fn maximum_qos(&self) -> Qos {
+        self.settings
+            .maximum_qos
+            .unwrap_or(self.state.config.maximum_qos)
+    }
+
     async fn send_disconnect(
         &mut self,
         reason_code: DisconnectReasonCode,
@@ -113,18 +254,66 @@ where
         .await
     }
 
-    async fn check_acl(&self, action: Action, topic: &str) -> Result<(), Error> {
-        let mut allow = true;
+    /// Returns `true` if a message that matched `matches` sessions is acknowledged with the
+    /// reason code No matching subscribers.
+    #[inline]
+    fn no_matching_subscribers(&self, matches: usize) -> bool {
+        matches == 0 && self.state.config.no_matching_subscribers
+    }
+
+    /// Returns the reason string and user properties of a packet rejecting an operation, if
+    /// enabled in the config.
+    ///
+    /// A client that sets Request Problem Information to 0 only receives them with DISCONNECT
+    /// [MQTT-3.1.2-29].
+    fn problem_info(
+        &self,
+        is_disconnect: bool,
+        problem: impl FnOnce() -> Problem,
+    ) -> (Option<ByteString>, Vec<(ByteString, ByteString)>) {
+        let config = &self.state.config;
+        if !config.reason_strings || (!is_disconnect && !self.request_problem_info) {
+            return (None, Vec::new());
+        }
+        let problem = problem();
+        let properties = if config.diagnostic_user_properties {
+            problem.properties
+        } else {
+            Vec::new()
+        };
+        (Some(problem.reason.into()), properties)
+    }
 
-        for (name, plugin) in &self.state.plugins {
+    /// Like [`Error::server_disconnect`], with the problem information telling the client why.
+    fn disconnect_with_problem(
+        &self,
+        reason_code: DisconnectReasonCode,
+        problem: impl FnOnce() -> Problem,
+    ) -> Error {
+        let (reason_string, user_properties) = self.problem_info(true, problem);
+        Error::ServerDisconnect(Some(Disconnect {
+            reason_code,
+            properties: DisconnectProperties {
+                reason_string,
+                user_properties,
+                ..DisconnectProperties::default()
+            },
+        }))
+    }
+
+    /// Returns the name of the plugin that denies the action to the user.
+    async fn acl_denied_by(
+        &self,
+        uid: Option<&str>,
+        action: Action,
+        topic: &str,
+    ) -> Result<Option<&'static str>, Error> {
+        for (name, plugin) in &self.plugins {
             match plugin
-                .check_acl(&self.remote_addr, self.uid.as_deref(), action, topic)
+                .check_acl(&self.remote_addr, uid, action, topic)
                 .await
             {
-                Ok(false) => {
-                    allow = false;
-                    break;
-                }
+                Ok(false) => return Ok(Some(*name)),
                 Ok(true) => {}
                 Err(err) => {
                     tracing::error!(
@@ -132,23 +321,73 @@ where
                         error = %err,
                         "failed to call plugin::check_acl",
                     );
-                    return Err(Error::server_disconnect(
+                    return Err(self.disconnect_with_problem(
                         DisconnectReasonCode::UnspecifiedError,
+                        || {
+                            Problem::new(format!("ACL check failed in plugin {}", name))
+                                .with_property("plugin", *name)
+                        },
                     ));
                 }
             }
         }
+        Ok(None)
+    }
 
-        if !allow {
-            return Err(Error::server_disconnect(
-                DisconnectReasonCode::NotAuthorized,
-            ));
+    /// Drops a message denied by the plugin `name` and acknowledges it, see
+    /// [`PublishDenied::Reject`].
+    async fn reject_publish(&mut self, publish: &Publish, name: &str) -> Result<(), Error> {
+        self.state.service_metrics.inc_msg_dropped(1);
+        let problem = || Problem::acl_denied(Action::Publish, &publish.topic, name);
+        match (publish.qos, publish.packet_id) {
+            (Qos::AtLeastOnce, Some(packet_id)) => {
+                let (reason_string, user_properties) = self.problem_info(false, problem);
+                self.send_packet(&Packet::PubAck(PubAck {
+                    packet_id,
+                    reason_code: PubAckReasonCode::NotAuthorized,
+                    properties: PubAckProperties {
+                        reason_string,
+                        user_properties,
+                    },
+                }))
+                .await
+            }
+            (Qos::ExactlyOnce, Some(packet_id))
+                if self.codec.protocol_level() == ProtocolLevel::V5 =>
+            {
+                let (reason_string, user_properties) = self.problem_info(false, problem);
+                self.send_packet(&Packet::PubRec(PubRec {
+                    packet_id,
+                    reason_code: PubRecReasonCode::NotAuthorized,
+                    properties: PubRecProperties {
+                        reason_string,
+                        user_properties,
+                    },
+                }))
+                .await
+            }
+            (Qos::ExactlyOnce, Some(packet_id)) => {
+                // a MQTT 3.1.1 client can't be told, so the flow is completed without a message
+                self.uncompleted_messages.insert(packet_id, None);
+                self.send_packet(&Packet::PubRec(PubRec {
+                    packet_id,
+                    reason_code: PubRecReasonCode::Success,
+                    properties: PubRecProperties::default(),
+                }))
+                .await
+            }
+            _ => Ok(()),
         }
-
-        Ok(())
     }
 
     async fn handle_packet(&mut self, packet: Packet) -> Result<(), Error> {
+        let client_id = match &packet {
+            Packet::Connect(connect) => Some(&*connect.client_id),
+            _ => self.client_id.as_deref(),
+        };
+        self.state
+            .trace_packet(client_id, Direction::Received, &packet);
+
         match packet {
             Packet::Connect(connect) => self.handle_connect(connect).await,
             Packet::Publish(publish) => self.handle_publish(publish).await,
@@ -160,21 +399,242 @@ where
             Packet::Unsubscribe(unsubscribe) => self.handle_unsubscribe(unsubscribe).await,
             Packet::PingReq => self.handle_ping_req().await,
             Packet::Disconnect(disconnect) => self.handle_disconnect(disconnect).await,
+            Packet::Auth(auth) => self.handle_auth(auth).await,
             Packet::SubAck(_) | Packet::ConnAck(_) | Packet::UnsubAck(_) | Packet::PingResp => Err(
                 Error::server_disconnect(DisconnectReasonCode::ProtocolError),
             ),
         }
     }
 
-    async fn handle_connect(&mut self, mut connect: Connect) -> Result<(), Error> {
-        let mut conn_ack_properties = ConnAckProperties::default();
+    async fn handle_connect(&mut self, connect: Connect) -> Result<(), Error> {
+        if self.client_id.is_some() || self.pending_connect.is_some() {
+            return Err(Error::server_disconnect(
+                DisconnectReasonCode::ProtocolError,
+            ));
+        }
+
+        let method = match connect.properties.authentication_method.clone() {
+            Some(method) => method,
+            None => return self.accept_connect(connect, None).await,
+        };
+        match self.start_enhanced_auth(&method).await? {
+            Some(exchange) => {
+                let data = connect.properties.authentication_data.clone();
+                self.auth_method = Some(method);
+                self.auth_exchange = Some(exchange);
+                self.pending_connect = Some(connect);
+                self.continue_enhanced_auth(data).await
+            }
+            None => {
+                self.send_packet(&Packet::ConnAck(ConnAck {
+                    session_present: false,
+                    reason_code: ConnectReasonCode::BadAuthenticationMethod,
+                    properties: ConnAckProperties::default(),
+                }))
+                .await?;
+                Err(Error::ServerDisconnect(None))
+            }
+        }
+    }
+
+    async fn start_enhanced_auth(
+        &self,
+        method: &str,
+    ) -> Result<Option<Box<dyn EnhancedAuth>>, Error> {
+        for (name, plugin) in &self.plugins {
+            match plugin.enhanced_auth(method).await {
+                Ok(Some(exchange)) => return Ok(Some(exchange)),
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::error!(
+                        plugin = %name,
+                        error = %err,
+                        "failed to call plugin::enhanced_auth",
+                    );
+                    return Err(Error::internal_error(err));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Runs a step of the enhanced authentication exchange with the data sent by the client,
+    /// the pending CONNECT packet is accepted when it succeeds.
+    async fn continue_enhanced_auth(&mut self, data: Option<Bytes>) -> Result<(), Error> {
+        let exchange = match &mut self.auth_exchange {
+            Some(exchange) => exchange,
+            None => {
+                return Err(Error::server_disconnect(
+                    DisconnectReasonCode::ProtocolError,
+                ))
+            }
+        };
+        let step = exchange.step(data).await.map_err(|err| {
+            tracing::error!(
+                error = %err,
+                "failed to call EnhancedAuth::step",
+            );
+            Error::internal_error(err)
+        })?;
+
+        match step {
+            EnhancedAuthStep::Continue(data) => {
+                self.send_packet(&Packet::Auth(Auth {
+                    reason_code: AuthReasonCode::ContinueAuthentication,
+                    properties: AuthProperties {
+                        authentication_method: self.auth_method.clone(),
+                        authentication_data: data,
+                        ..AuthProperties::default()
+                    },
+                }))
+                .await
+            }
+            EnhancedAuthStep::Success(res, data) => {
+                self.auth_exchange = None;
+                if let Some(connect) = self.pending_connect.take() {
+                    return self.accept_connect(connect, Some((res, data))).await;
+                }
+
+                // a re-authentication can't change the user
+                if self.uid.as_deref() != Some(res.uid.as_str()) {
+                    return Err(Error::server_disconnect(
+                        DisconnectReasonCode::NotAuthorized,
+                    ));
+                }
+                self.send_packet(&Packet::Auth(Auth {
+                    reason_code: AuthReasonCode::Success,
+                    properties: AuthProperties {
+                        authentication_method: self.auth_method.clone(),
+                        authentication_data: data,
+                        ..AuthProperties::default()
+                    },
+                }))
+                .await
+            }
+            EnhancedAuthStep::Failed => {
+                self.auth_exchange = None;
+                if self.pending_connect.take().is_some() {
+                    self.send_packet(&Packet::ConnAck(ConnAck {
+                        session_present: false,
+                        reason_code: ConnectReasonCode::NotAuthorized,
+                        properties: ConnAckProperties::default(),
+                    }))
+                    .await?;
+                    return Err(Error::ServerDisconnect(None));
+                }
+                Err(Error::server_disconnect(
+                    DisconnectReasonCode::NotAuthorized,
+                ))
+            }
+        }
+    }
 
-        if self.client_id.is_some() {
+    async fn handle_auth(&mut self, auth: Auth) -> Result<(), Error> {
+        // only the clients that connected with an authentication method can send AUTH packets,
+        // and the method can't change
+        if self.auth_method.is_none() || auth.properties.authentication_method != self.auth_method {
             return Err(Error::server_disconnect(
                 DisconnectReasonCode::ProtocolError,
             ));
         }
 
+        match auth.reason_code {
+            AuthReasonCode::ContinueAuthentication if self.auth_exchange.is_some() => {}
+            AuthReasonCode::ReAuthenticate
+                if self.client_id.is_some() && self.auth_exchange.is_none() =>
+            {
+                let method = self.auth_method.clone().unwrap_or_default();
+                match self.start_enhanced_auth(&method).await? {
+                    Some(exchange) => self.auth_exchange = Some(exchange),
+                    None => {
+                        return Err(Error::server_disconnect(
+                            DisconnectReasonCode::BadAuthenticationMethod,
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::server_disconnect(
+                    DisconnectReasonCode::ProtocolError,
+                ))
+            }
+        }
+
+        self.continue_enhanced_auth(auth.properties.authentication_data)
+            .await
+    }
+
+    async fn accept_connect(
+        &mut self,
+        mut connect: Connect,
+        enhanced_auth: Option<(AuthResult, Option<Bytes>)>,
+    ) -> Result<(), Error> {
+        let mut conn_ack_properties = ConnAckProperties::default();
+
+        if connect.level == ProtocolLevel::V3 {
+            if !self.state.config.accept_mqtt_v3 {
+                self.send_packet(&Packet::ConnAck(ConnAck {
+                    session_present: false,
+                    reason_code: ConnectReasonCode::UnsupportedProtocolVersion,
+                    properties: ConnAckProperties::default(),
+                }))
+                .await?;
+                return Err(Error::ServerDisconnect(None));
+            }
+
+            // MQTT 3.1 requires a client id of 1 to 23 characters
+            if connect.client_id.is_empty() || connect.client_id.chars().count() > 23 {
+                self.send_packet(&Packet::ConnAck(ConnAck {
+                    session_present: false,
+                    reason_code: ConnectReasonCode::ClientIdentifierNotValid,
+                    properties: ConnAckProperties::default(),
+                }))
+                .await?;
+                return Err(Error::ServerDisconnect(None));
+            }
+        }
+
+        // a client that takes over its connection does not increase the number of connections
+        let connection_limit_reached = self.state.is_connection_limit_reached()
+            && (connect.client_id.is_empty()
+                || !self
+                    .state
+                    .connections
+                    .read()
+                    .await
+                    .contains_key(&*connect.client_id));
+        if self.state.is_overloaded() || connection_limit_reached {
+            self.send_packet(&Packet::ConnAck(ConnAck {
+                session_present: false,
+                reason_code: ConnectReasonCode::ServerBusy,
+                properties: ConnAckProperties {
+                    server_reference: self
+                        .state
+                        .config
+                        .overload
+                        .server_reference
+                        .as_deref()
+                        .map(Into::into),
+                    ..ConnAckProperties::default()
+                },
+            }))
+            .await?;
+            return Err(Error::ServerDisconnect(None));
+        }
+
+        if let Some(redirect) = self.state.redirect() {
+            self.send_packet(&Packet::ConnAck(ConnAck {
+                session_present: false,
+                reason_code: redirect.connect_reason_code(),
+                properties: ConnAckProperties {
+                    server_reference: Some(redirect.server_reference.into()),
+                    ..ConnAckProperties::default()
+                },
+            }))
+            .await?;
+            return Err(Error::ServerDisconnect(None));
+        }
+
         let mut session_expiry_interval = {
             match connect.properties.session_expiry_interval {
                 Some(session_expiry_interval)
@@ -193,7 +653,10 @@ where
         };
 
         let keep_alive = {
-            if connect.keep_alive > self.state.config.max_keep_alive {
+            // MQTT 3.1.1 can't tell the client to use another keep alive, so it is honored
+            if connect.level == ProtocolLevel::V5
+                && connect.keep_alive > self.state.config.max_keep_alive
+            {
                 conn_ack_properties.server_keep_alive = Some(self.state.config.max_keep_alive);
                 self.state.config.max_keep_alive
             } else {
@@ -208,12 +671,16 @@ where
             .map(|x| x as usize)
             .unwrap_or(usize::MAX);
 
-        if self.state.config.maximum_qos != Qos::ExactlyOnce {
-            conn_ack_properties.maximum_qos = Some(self.state.config.maximum_qos);
+        let maximum_qos = self.maximum_qos();
+        if maximum_qos != Qos::ExactlyOnce {
+            conn_ack_properties.maximum_qos = Some(maximum_qos);
         }
 
         let max_packet_size_out = connect.properties.max_packet_size.unwrap_or(u32::MAX);
-        let max_packet_size_in = self.state.config.max_packet_size;
+        let max_packet_size_in = self
+            .settings
+            .max_packet_size
+            .unwrap_or(self.state.config.max_packet_size);
         if max_packet_size_in != u32::MAX {
             conn_ack_properties.max_packet_size = Some(max_packet_size_in);
         }
@@ -241,7 +708,7 @@ where
         };
 
         if let Some(last_will) = &connect.last_will {
-            if last_will.qos > self.state.config.maximum_qos {
+            if last_will.qos > maximum_qos {
                 self.send_packet(&Packet::ConnAck(ConnAck {
                     session_present: false,
                     reason_code: ConnectReasonCode::QoSNotSupported,
@@ -297,11 +764,44 @@ where
 
         // auth
         let mut uid = None;
-        if let Some(login) = &connect.login {
-            for (name, plugin) in &self.state.plugins {
+        let mut quota = self.state.config.user_quota;
+        let mut max_queued_bytes = self.state.config.max_queued_bytes;
+        let mut publish_rate_limit = self.state.config.publish_rate_limit;
+        let mut mount_point = None;
+        if let Some((res, data)) = enhanced_auth {
+            uid = Some(ByteString::from(res.uid));
+            if let Some(res_quota) = res.quota {
+                quota = res_quota;
+            }
+            if res.max_queued_bytes.is_some() {
+                max_queued_bytes = res.max_queued_bytes;
+            }
+            if res.publish_rate_limit.is_some() {
+                publish_rate_limit = res.publish_rate_limit;
+            }
+            if res.mount_point.is_some() {
+                mount_point = res.mount_point;
+            }
+            conn_ack_properties.authentication_method =
+                connect.properties.authentication_method.clone();
+            conn_ack_properties.authentication_data = data;
+        } else if let Some(login) = &connect.login {
+            for (name, plugin) in &self.plugins {
                 match plugin.auth(&login.username, &login.password).await {
-                    Ok(Some(res_uid)) => {
-                        uid = Some(res_uid.into());
+                    Ok(Some(res)) => {
+                        uid = Some(ByteString::from(res.uid));
+                        if let Some(res_quota) = res.quota {
+                            quota = res_quota;
+                        }
+                        if res.max_queued_bytes.is_some() {
+                            max_queued_bytes = res.max_queued_bytes;
+                        }
+                        if res.publish_rate_limit.is_some() {
+                            publish_rate_limit = res.publish_rate_limit;
+                        }
+                        if res.mount_point.is_some() {
+                            mount_point = res.mount_point;
+                        }
                         break;
                     }
                     Ok(None) => {}
@@ -317,42 +817,215 @@ where
             }
 
             if uid.is_none() {
+                if connect.level != ProtocolLevel::V5 || self.settings.bad_login_conn_ack {
+                    self.send_packet(&Packet::ConnAck(ConnAck {
+                        session_present: false,
+                        reason_code: ConnectReasonCode::BadUserNamePassword,
+                        properties: ConnAckProperties::default(),
+                    }))
+                    .await?;
+                    return Err(Error::ServerDisconnect(None));
+                }
                 return Err(Error::server_disconnect(
                     DisconnectReasonCode::NotAuthorized,
                 ));
             }
+        } else if let Some(client_cert) = &self.remote_addr.client_cert {
+            // a client without a login is anonymous unless a plugin accepts its certificate
+            for (name, plugin) in &self.plugins {
+                match plugin.auth_cert(client_cert).await {
+                    Ok(Some(res)) => {
+                        uid = Some(ByteString::from(res.uid));
+                        if let Some(res_quota) = res.quota {
+                            quota = res_quota;
+                        }
+                        if res.max_queued_bytes.is_some() {
+                            max_queued_bytes = res.max_queued_bytes;
+                        }
+                        if res.publish_rate_limit.is_some() {
+                            publish_rate_limit = res.publish_rate_limit;
+                        }
+                        if res.mount_point.is_some() {
+                            mount_point = res.mount_point;
+                        }
+                        break;
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        tracing::error!(
+                            plugin = %name,
+                            error = %err,
+                            "failed to call plugin::auth_cert",
+                        );
+                        return Err(Error::internal_error(err));
+                    }
+                }
+            }
         }
 
-        if connect.level == ProtocolLevel::V4 && !connect.clean_start {
+        if uid.is_none() && (self.settings.auth_required || !self.state.config.allow_anonymous) {
+            self.send_packet(&Packet::ConnAck(ConnAck {
+                session_present: false,
+                reason_code: ConnectReasonCode::NotAuthorized,
+                properties: ConnAckProperties::default(),
+            }))
+            .await?;
+            return Err(Error::ServerDisconnect(None));
+        }
+
+        let mut banned =
+            self.state
+                .is_banned(&connect.client_id, uid.as_deref(), &self.remote_addr);
+        if !banned {
+            for (name, plugin) in &self.plugins {
+                match plugin
+                    .is_banned(&self.remote_addr, &connect.client_id, uid.as_deref())
+                    .await
+                {
+                    Ok(true) => {
+                        banned = true;
+                        break;
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        tracing::error!(
+                            plugin = %name,
+                            error = %err,
+                            "failed to call plugin::is_banned",
+                        );
+                        return Err(Error::internal_error(err));
+                    }
+                }
+            }
+        }
+        if banned {
+            self.send_packet(&Packet::ConnAck(ConnAck {
+                session_present: false,
+                reason_code: ConnectReasonCode::Banned,
+                properties: ConnAckProperties::default(),
+            }))
+            .await?;
+            return Err(Error::ServerDisconnect(None));
+        }
+
+        // the will message is published without the client, so the plugins check it now
+        if let Some(last_will) = &connect.last_will {
+            if let Some(name) = self
+                .acl_denied_by(uid.as_deref(), Action::Publish, &last_will.topic)
+                .await?
+            {
+                tracing::debug!(
+                    plugin = %name,
+                    topic = %last_will.topic,
+                    "will message denied",
+                );
+                self.send_packet(&Packet::ConnAck(ConnAck {
+                    session_present: false,
+                    reason_code: ConnectReasonCode::NotAuthorized,
+                    properties: ConnAckProperties::default(),
+                }))
+                .await?;
+                return Err(Error::ServerDisconnect(None));
+            }
+        }
+
+        if let (Some(mount_point), Some(last_will)) = (&mount_point, &mut connect.last_will) {
+            last_will.topic = format!("{}{}", mount_point, last_will.topic).into();
+        }
+
+        if connect.level != ProtocolLevel::V5 && !connect.clean_start {
             connect.properties.session_expiry_interval =
                 Some(self.state.config.max_session_expiry_interval);
             session_expiry_interval = self.state.config.max_session_expiry_interval;
         }
 
-        {
-            let mut connections = self.state.connections.write().await;
-            if let Some(control_sender) = connections.remove(&*connect.client_id) {
-                control_sender.send(Control::SessionTakenOver).ok();
+        let mut connections = self.state.connections.write().await;
+
+        // check the session quota of the user, taking over a session of the same user does not
+        // count as a new one
+        let quota_usage = match &uid {
+            Some(uid) => {
+                let usage = self.state.update_quota(uid, quota);
+                let takeover = matches!(
+                    connections.get(&*connect.client_id),
+                    Some(handle) if handle.info.uid.as_deref() == Some(&**uid)
+                );
+                if takeover {
+                    usage.add_session();
+                } else if !usage.try_add_session() {
+                    drop(connections);
+                    self.send_packet(&Packet::ConnAck(ConnAck {
+                        session_present: false,
+                        reason_code: ConnectReasonCode::QuotaExceeded,
+                        properties: ConnAckProperties::default(),
+                    }))
+                    .await?;
+                    return Err(Error::ServerDisconnect(None));
+                }
+                Some(usage)
+            }
+            None => None,
+        };
+
+        if let Some(handle) = connections.remove(&*connect.client_id) {
+            handle.control_sender.send(Control::SessionTakenOver).ok();
+            // the subscriptions are removed with the session, the old connection does not release
+            // its exclusive subscriptions after it is taken over
+            if connect.clean_start {
+                self.state
+                    .release_exclusive_subscriptions(&connect.client_id);
             }
-            connections.insert(connect.client_id.to_string(), self.control_sender.clone());
         }
+        connections.insert(
+            connect.client_id.to_string(),
+            ConnectionHandle {
+                control_sender: self.control_sender.clone(),
+                info: ClientInfo {
+                    client_id: connect.client_id.to_string(),
+                    uid: uid.as_ref().map(ToString::to_string),
+                    remote_addr: self.remote_addr.to_string(),
+                    listener: self.remote_addr.listener.clone(),
+                    level: connect.level,
+                    keep_alive,
+                    connected_at: clock::system_now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                },
+            },
+        );
+        drop(connections);
 
         // create session
         let (session_present, notify) = self.state.storage.create_session(
             &connect.client_id,
             connect.clean_start,
+            session_expiry_interval,
             connect.last_will.clone(),
+            quota_usage.clone(),
+            QueueLimit {
+                max_messages: self.state.config.max_queued_messages,
+                max_bytes: max_queued_bytes,
+                policy: self.state.config.queue_full_policy,
+            },
         );
 
         self.uid = uid;
+        self.mount_point = mount_point;
+        self.quota_usage = quota_usage;
         self.notify = notify;
         self.client_id = Some(connect.client_id.clone());
         self.keep_alive = keep_alive;
+        self.publish_rate =
+            publish_rate_limit.map(|limit| limit.token_bucket(tokio::time::Instant::now()));
+        self.request_problem_info = connect.properties.request_problem_info.unwrap_or(true);
+        self.codec.set_read_timeout(keep_alive_timeout(keep_alive));
         self.receive_in_max = receive_in_max;
         self.receive_out_max = receive_out_max;
         self.receive_in_quota = receive_in_max;
         self.receive_out_quota = receive_out_max;
         self.max_topic_alias = max_topic_alias as usize;
+        self.max_topic_alias_out = connect.properties.topic_alias_max.unwrap_or_default() as usize;
         self.session_expiry_interval = session_expiry_interval;
         self.last_will = connect.last_will.clone();
 
@@ -367,7 +1040,7 @@ where
         .await?;
         self.state.service_metrics.inc_connection_count(1);
 
-        for (_, plugin) in &self.state.plugins {
+        for (_, plugin) in &self.plugins {
             plugin
                 .on_client_connected(
                     &self.remote_addr,
@@ -386,6 +1059,9 @@ where
                 .storage
                 .get_all_inflight_pub_packets(&connect.client_id);
             for mut publish in packets {
+                if let Some(packet_id) = publish.packet_id {
+                    self.packet_id_allocator.mark_in_use(packet_id);
+                }
                 publish.dup = true;
                 self.receive_out_quota -= 1;
                 self.send_packet(&Packet::Publish(publish)).await?;
@@ -410,6 +1086,7 @@ where
                     s.retain_as_published,
                     s.retain_handling,
                     None,
+                    None,
                 );
             }
         }
@@ -436,8 +1113,14 @@ where
         {
             // A Topic Alias value of 0 or greater than the Maximum Topic Alias is a Protocol Error, the
             // receiver uses DISCONNECT with Reason Code of 0x94 (Topic Alias invalid) as described in section 4.13.
-            return Err(Error::server_disconnect(
+            return Err(self.disconnect_with_problem(
                 DisconnectReasonCode::TopicAliasInvalid,
+                || {
+                    Problem::new(format!(
+                        "topic alias is greater than the maximum of {}",
+                        self.state.config.max_topic_alias
+                    ))
+                },
             ));
         }
 
@@ -461,15 +1144,29 @@ where
         }
 
         if publish.topic.starts_with('$') {
-            return Err(Error::server_disconnect(
+            return Err(self.disconnect_with_problem(
                 DisconnectReasonCode::TopicNameInvalid,
+                || {
+                    Problem::new("topic names starting with $ are reserved")
+                        .with_property("topic", publish.topic.clone())
+                },
             ));
         }
 
         if !publish.topic.is_empty() && !filter_util::valid_topic(&publish.topic) {
-            return Err(Error::server_disconnect(
-                DisconnectReasonCode::TopicNameInvalid,
-            ));
+            return Err(self
+                .disconnect_with_problem(DisconnectReasonCode::TopicNameInvalid, || {
+                    Problem::new("invalid topic name").with_property("topic", publish.topic.clone())
+                }));
+        }
+
+        if self.settings.decode.reject_control_characters
+            && filter_util::has_control_characters(&publish.topic)
+        {
+            return Err(self
+                .disconnect_with_problem(DisconnectReasonCode::TopicNameInvalid, || {
+                    Problem::new("topic name contains control characters")
+                }));
         }
 
         if publish.retain && !self.state.config.retain_available {
@@ -477,9 +1174,10 @@ where
             // with its value set to 0 and it receives a PUBLISH packet with the RETAIN flag is
             // set to 1, then it uses the DISCONNECT Reason Code of 0x9A (Retain not supported) as
             // described in section 4.13.
-            return Err(Error::server_disconnect(
-                DisconnectReasonCode::RetainNotSupported,
-            ));
+            return Err(self
+                .disconnect_with_problem(DisconnectReasonCode::RetainNotSupported, || {
+                    Problem::new("retained messages are not supported")
+                }));
         }
 
         if publish
@@ -488,9 +1186,10 @@ where
             .unwrap_or_default()
             && std::str::from_utf8(&publish.payload).is_err()
         {
-            return Err(Error::server_disconnect(
-                DisconnectReasonCode::PayloadFormatInvalid,
-            ));
+            return Err(self
+                .disconnect_with_problem(DisconnectReasonCode::PayloadFormatInvalid, || {
+                    Problem::new("payload is not valid UTF-8")
+                }));
         }
 
         publish.topic = match publish.properties.topic_alias {
@@ -518,14 +1217,91 @@ where
         let retain = publish.retain;
         let packet_id = publish.packet_id;
 
+        // check publish rate
+        if let Some(publish_rate) = &mut self.publish_rate {
+            if !publish_rate.try_acquire(tokio::time::Instant::now()) {
+                self.state.service_metrics.inc_msg_dropped(1);
+                let problem = || {
+                    Problem::new("message rate too high")
+                        .with_property("topic", publish.topic.clone())
+                };
+                if self.reject_quota_exceeded(&publish, problem).await? {
+                    return Ok(());
+                }
+                return Err(
+                    self.disconnect_with_problem(DisconnectReasonCode::MessageRateTooHigh, problem)
+                );
+            }
+        }
+
+        // check payload limit
+        if let Some(max_size) = self
+            .state
+            .payload_limit(&publish.topic)
+            .filter(|max_size| publish.payload.len() > *max_size)
+        {
+            self.state.service_metrics.inc_msg_dropped(1);
+            let problem = || {
+                Problem::new(format!(
+                    "payload of {} bytes exceeds the limit of {} bytes",
+                    publish.payload.len(),
+                    max_size
+                ))
+                .with_property("topic", publish.topic.clone())
+            };
+            if self.reject_quota_exceeded(&publish, problem).await? {
+                return Ok(());
+            }
+            return Err(self.disconnect_with_problem(DisconnectReasonCode::PacketTooLarge, problem));
+        }
+
         // check acl
-        self.check_acl(Action::Publish, &publish.topic).await?;
+        if let Some(name) = self
+            .acl_denied_by(self.uid.as_deref(), Action::Publish, &publish.topic)
+            .await?
+        {
+            if self.state.config.publish_denied == PublishDenied::Reject {
+                return self.reject_publish(&publish, name).await;
+            }
+            return Err(
+                self.disconnect_with_problem(DisconnectReasonCode::NotAuthorized, || {
+                    Problem::acl_denied(Action::Publish, &publish.topic, name)
+                }),
+            );
+        }
+
+        // check receive maximum, a QoS 1 message never holds the quota because its PUBACK is
+        // sent at once, so only the unacknowledged QoS 2 messages count
+        if publish.qos > Qos::AtMostOnce && self.receive_in_quota == 0 {
+            self.state.service_metrics.inc_msg_dropped(1);
+            return Err(self.disconnect_with_problem(
+                DisconnectReasonCode::ReceiveMaximumExceeded,
+                || {
+                    Problem::new(format!(
+                        "more than {} QoS 1 and QoS 2 messages are being received",
+                        self.receive_in_max
+                    ))
+                },
+            ));
+        }
 
         // rewrite
-        self.state.rewrite(&mut publish.topic);
+        if let Some(topic) = self.state.rewrite(
+            RewriteTarget::Publish,
+            &publish.topic,
+            &client_id,
+            self.uid.as_deref(),
+        ) {
+            publish.topic = topic.into();
+        }
+        if let Some(mount_point) = &self.mount_point {
+            publish.topic = format!("{}{}", mount_point, publish.topic).into();
+        }
 
         // create message
-        let mut msg = Message::from_publish(&publish).with_from_client_id(client_id.clone());
+        let mut msg = Message::from_publish(&publish)
+            .with_from_client_id(client_id.clone())
+            .with_priority(self.state.is_priority_topic(&publish.topic));
         if let Some(uid) = &self.uid {
             msg = msg.with_from_uid(uid.clone());
         }
@@ -535,7 +1311,7 @@ where
             self.state.storage.update_retained_message(msg.clone());
         }
 
-        for (_, plugin) in &self.state.plugins {
+        for (_, plugin) in &self.plugins {
             plugin
                 .on_message_publish(
                     self.client_id.as_ref().unwrap(),
@@ -551,51 +1327,70 @@ where
         // do publish
         match msg.qos() {
             Qos::AtMostOnce => {
-                self.state.storage.deliver(std::iter::once(msg));
+                if self.state.config.overload.drop_qos0 && self.state.is_overloaded() {
+                    self.state.service_metrics.inc_msg_dropped(1);
+                } else {
+                    self.state.storage.deliver(vec![msg]);
+                }
             }
             Qos::AtLeastOnce => {
-                self.state.storage.deliver(std::iter::once(msg));
+                let matches = self.state.storage.deliver(vec![msg]);
                 self.send_packet(&Packet::PubAck(PubAck {
                     packet_id: packet_id.unwrap(),
-                    reason_code: PubAckReasonCode::Success,
+                    reason_code: if self.no_matching_subscribers(matches) {
+                        PubAckReasonCode::NoMatchingSubscribers
+                    } else {
+                        PubAckReasonCode::Success
+                    },
                     properties: PubAckProperties::default(),
                 }))
                 .await?;
             }
             Qos::ExactlyOnce => {
-                if self.receive_in_quota == 0 {
-                    self.state.service_metrics.inc_msg_dropped(1);
-                    return Err(Error::server_disconnect(
-                        DisconnectReasonCode::ReceiveMaximumExceeded,
-                    ));
-                }
-
                 let packet_id = packet_id.unwrap();
+                let matches = if self.state.config.no_matching_subscribers {
+                    self.state.storage.count_matches(&msg)
+                } else {
+                    0
+                };
 
                 if self
                     .uncompleted_messages
-                    .insert(packet_id, msg.clone())
+                    .insert(packet_id, Some(msg.clone()))
                     .is_some()
                 {
-                    return if self.codec.protocol_level() == ProtocolLevel::V5 {
-                        self.send_packet(&Packet::PubRec(PubRec {
-                            packet_id,
-                            reason_code: PubRecReasonCode::PacketIdentifierInUse,
-                            properties: PubRecProperties::default(),
-                        }))
-                        .await?;
-                        Ok(())
-                    } else {
-                        Err(Error::server_disconnect(
+                    return match self.codec.protocol_level() {
+                        ProtocolLevel::V5 => {
+                            self.send_packet(&Packet::PubRec(PubRec {
+                                packet_id,
+                                reason_code: PubRecReasonCode::PacketIdentifierInUse,
+                                properties: PubRecProperties::default(),
+                            }))
+                            .await
+                        }
+                        // a MQTT 3.1.1 client resends the PUBLISH until it receives the PUBREC
+                        ProtocolLevel::V3 | ProtocolLevel::V4 if publish.dup => {
+                            self.send_packet(&Packet::PubRec(PubRec {
+                                packet_id,
+                                reason_code: PubRecReasonCode::Success,
+                                properties: PubRecProperties::default(),
+                            }))
+                            .await
+                        }
+                        ProtocolLevel::V3 | ProtocolLevel::V4 => Err(Error::server_disconnect(
                             DisconnectReasonCode::ProtocolError,
-                        ))
+                        )),
                     };
                 }
 
                 self.receive_in_quota -= 1;
                 self.send_packet(&Packet::PubRec(PubRec {
                     packet_id,
-                    reason_code: PubRecReasonCode::Success,
+                    reason_code: if self.no_matching_subscribers(matches) {
+                        PubRecReasonCode::NoMatchingSubscribers
+                    } else {
+                        PubRecReasonCode::Success
+                    },
                     properties: PubRecProperties::default(),
                 }))
                 .await?;
@@ -605,6 +1400,46 @@ where
         Ok(())
     }
 
+    /// Acknowledges a QoS 1 or QoS 2 message of a MQTT 5 client with the reason code Quota
+    /// exceeded, returns `false` if the message can't be rejected this way.
+    async fn reject_quota_exceeded(
+        &mut self,
+        publish: &Publish,
+        problem: impl FnOnce() -> Problem,
+    ) -> Result<bool, Error> {
+        if self.codec.protocol_level() != ProtocolLevel::V5 {
+            return Ok(false);
+        }
+        match (publish.qos, publish.packet_id) {
+            (Qos::AtLeastOnce, Some(packet_id)) => {
+                let (reason_string, user_properties) = self.problem_info(false, problem);
+                self.send_packet(&Packet::PubAck(PubAck {
+                    packet_id,
+                    reason_code: PubAckReasonCode::QuotaExceeded,
+                    properties: PubAckProperties {
+                        reason_string,
+                        user_properties,
+                    },
+                }))
+                .await?;
+            }
+            (Qos::ExactlyOnce, Some(packet_id)) => {
+                let (reason_string, user_properties) = self.problem_info(false, problem);
+                self.send_packet(&Packet::PubRec(PubRec {
+                    packet_id,
+                    reason_code: PubRecReasonCode::QuotaExceeded,
+                    properties: PubRecProperties {
+                        reason_string,
+                        user_properties,
+                    },
+                }))
+                .await?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
     async fn handle_pub_ack(&mut self, pub_ack: PubAck) -> Result<(), Error> {
         let client_id = match &self.client_id {
             Some(client_id) => client_id,
@@ -628,6 +1463,7 @@ where
             .get_inflight_pub_packets(client_id, pub_ack.packet_id, true)
         {
             Some(_) => {
+                self.packet_id_allocator.release(pub_ack.packet_id);
                 self.receive_out_quota += 1;
                 Ok(())
             }
@@ -672,6 +1508,8 @@ where
                     DisconnectReasonCode::ProtocolError,
                 ));
             }
+            self.inflight_qos2_messages.remove(&pub_rec.packet_id);
+            self.packet_id_allocator.release(pub_rec.packet_id);
             return Ok(());
         }
 
@@ -719,14 +1557,20 @@ where
                     return Ok(());
                 }
 
-                self.state.storage.deliver(std::iter::once(msg));
+                // a rejected message doesn't use the receive quota
+                let rejected = msg.is_none();
+                if let Some(msg) = msg {
+                    self.state.storage.deliver(vec![msg]);
+                }
                 self.send_packet(&Packet::PubComp(PubComp {
                     packet_id: pub_rel.packet_id,
                     reason_code: PubCompReasonCode::Success,
                     properties: PubCompProperties::default(),
                 }))
                 .await?;
-                self.receive_in_quota += 1;
+                if !rejected {
+                    self.receive_in_quota += 1;
+                }
             }
             None => {
                 if self.codec.protocol_level() == ProtocolLevel::V5 {
@@ -778,6 +1622,7 @@ where
                     packet_id = pub_comp.packet_id,
                     "remove inflight packet",
                 );
+                self.packet_id_allocator.release(pub_comp.packet_id);
                 self.receive_out_quota += 1;
                 self.handle_notified().await?;
             }
@@ -805,12 +1650,19 @@ where
         };
 
         let mut reason_codes = Vec::with_capacity(subscribe.filters.len());
+        let mut problems = Vec::new();
 
         for s in &subscribe.filters {
             let filter = match filter_util::parse_filter(&s.path) {
-                Some(filter) => filter,
-                None => {
+                Some(filter)
+                    if !self.settings.decode.reject_control_characters
+                        || !filter_util::has_control_characters(&s.path) =>
+                {
+                    filter
+                }
+                _ => {
                     reason_codes.push(SubscribeReasonCode::TopicFilterInvalid);
+                    problems.push(format!("invalid topic filter {}", s.path));
                     continue;
                 }
             };
@@ -826,15 +1678,70 @@ where
                 && filter_util::has_wildcards(filter.path)
             {
                 reason_codes.push(SubscribeReasonCode::WildcardSubscriptionsNotSupported);
+                problems.push(format!("wildcards are not supported in {}", s.path));
                 continue;
             }
 
             // check acl
-            self.check_acl(Action::Subscribe, &filter.path).await?;
+            if let Some(name) = self
+                .acl_denied_by(self.uid.as_deref(), Action::Subscribe, filter.path)
+                .await?
+            {
+                reason_codes.push(SubscribeReasonCode::NotAuthorized);
+                problems.push(Problem::acl_denied(Action::Subscribe, &s.path, name).reason);
+                continue;
+            }
 
-            let qos = s.qos.min(self.state.config.maximum_qos);
+            // rewrite and mount
+            let rewritten = self.rewrite_filter(&s.path, &client_id);
+            let filter = match &rewritten {
+                Some(path) => match filter_util::parse_filter(path) {
+                    Some(filter) => filter,
+                    None => {
+                        reason_codes.push(SubscribeReasonCode::TopicFilterInvalid);
+                        problems.push(format!("invalid rewritten topic filter {}", path));
+                        continue;
+                    }
+                },
+                None => filter,
+            };
+
+            if filter.exclusive
+                && !self
+                    .state
+                    .acquire_exclusive_subscription(filter.path, &client_id)
+            {
+                reason_codes.push(SubscribeReasonCode::QuotaExceeded);
+                problems.push(format!(
+                    "exclusive subscription {} is held by another client",
+                    s.path
+                ));
+                continue;
+            }
+
+            let qos = s.qos.min(self.maximum_qos());
 
-            for (_, plugin) in &self.state.plugins {
+            if !self.state.storage.subscribe(
+                &client_id,
+                filter,
+                s.qos,
+                s.no_local,
+                s.retain_as_published,
+                s.retain_handling,
+                subscribe.properties.id,
+                self.state.config.max_subscriptions_per_client,
+            ) {
+                if filter.exclusive {
+                    self.state
+                        .release_exclusive_subscription(filter.path, &client_id);
+                }
+                self.state.service_metrics.inc_subscriptions_rejected(1);
+                reason_codes.push(SubscribeReasonCode::QuotaExceeded);
+                problems.push(format!("subscription quota exceeded by {}", s.path));
+                continue;
+            }
+
+            for (_, plugin) in &self.plugins {
                 plugin
                     .on_session_subscribed(
                         self.client_id.as_ref().unwrap(),
@@ -850,22 +1757,19 @@ where
                 Qos::AtLeastOnce => SubscribeReasonCode::QoS1,
                 Qos::ExactlyOnce => SubscribeReasonCode::QoS2,
             });
-
-            self.state.storage.subscribe(
-                &client_id,
-                filter,
-                s.qos,
-                s.no_local,
-                s.retain_as_published,
-                s.retain_handling,
-                subscribe.properties.id,
-            );
         }
 
+        let (reason_string, user_properties) = match problems.is_empty() {
+            true => (None, Vec::new()),
+            false => self.problem_info(false, || Problem::new(problems.join(", "))),
+        };
         self.send_packet(&Packet::SubAck(SubAck {
             packet_id: subscribe.packet_id,
             reason_codes,
-            properties: SubAckProperties::default(),
+            properties: SubAckProperties {
+                reason_string,
+                user_properties,
+            },
         }))
         .await?;
 
@@ -882,17 +1786,38 @@ where
             }
         };
         let mut reason_codes = Vec::new();
+        let mut problems = Vec::new();
 
         for path in unsubscribe.filters {
             let filter = match filter_util::parse_filter(&*path) {
-                Some(filter) => filter,
-                None => {
+                Some(filter)
+                    if !self.settings.decode.reject_control_characters
+                        || !filter_util::has_control_characters(&path) =>
+                {
+                    filter
+                }
+                _ => {
                     reason_codes.push(UnsubAckReasonCode::TopicFilterInvalid);
+                    problems.push(format!("invalid topic filter {}", path));
                     continue;
                 }
             };
 
-            for (_, plugin) in &self.state.plugins {
+            // rewrite and mount
+            let rewritten = self.rewrite_filter(&path, client_id);
+            let filter = match &rewritten {
+                Some(path) => match filter_util::parse_filter(path) {
+                    Some(filter) => filter,
+                    None => {
+                        reason_codes.push(UnsubAckReasonCode::TopicFilterInvalid);
+                        problems.push(format!("invalid rewritten topic filter {}", path));
+                        continue;
+                    }
+                },
+                None => filter,
+            };
+
+            for (_, plugin) in &self.plugins {
                 plugin
                     .on_session_unsubscribed(
                         self.client_id.as_ref().unwrap(),
@@ -902,16 +1827,28 @@ where
                     .await;
             }
 
+            if filter.exclusive {
+                self.state
+                    .release_exclusive_subscription(filter.path, client_id);
+            }
+
             match self.state.storage.unsubscribe(client_id, filter) {
                 true => reason_codes.push(UnsubAckReasonCode::Success),
                 false => reason_codes.push(UnsubAckReasonCode::NoSubscriptionExisted),
             }
         }
 
+        let (reason_string, user_properties) = match problems.is_empty() {
+            true => (None, Vec::new()),
+            false => self.problem_info(false, || Problem::new(problems.join(", "))),
+        };
         self.send_packet(&Packet::UnsubAck(UnsubAck {
             packet_id: unsubscribe.packet_id,
             reason_codes,
-            properties: UnsubAckProperties::default(),
+            properties: UnsubAckProperties {
+                reason_string,
+                user_properties,
+            },
         }))
         .await?;
         Ok(())
@@ -933,21 +1870,43 @@ where
         if let Some(session_expiry_interval) = disconnect.properties.session_expiry_interval {
             self.session_expiry_interval = session_expiry_interval;
         }
+        self.disconnect_reason = DisconnectReason::Client(disconnect.reason_code);
         Err(Error::ClientDisconnect(disconnect))
     }
 
     async fn handle_control(&mut self, control: Control) -> Result<(), Error> {
         match control {
             Control::SessionTakenOver => {
-                self.client_id = None;
+                if let Some(client_id) = self.client_id.take() {
+                    self.state.record_disconnect(
+                        &client_id,
+                        DisconnectReason::Server(DisconnectReasonCode::SessionTakenOver),
+                    );
+                }
                 self.state.service_metrics.dec_connection_count(1);
+                if let Some(quota_usage) = self.quota_usage.take() {
+                    quota_usage.remove_session();
+                }
                 Err(Error::SessionTakenOver)
             }
+            Control::Kick => Err(Error::server_disconnect(
+                DisconnectReasonCode::AdministrativeAction,
+            )),
+            Control::Redirect(redirect) => {
+                Err(Error::ServerDisconnect(Some(redirect.disconnect())))
+            }
         }
     }
 
     async fn handle_notified(&mut self) -> Result<(), Error> {
         if let Some(client_id) = self.client_id.clone() {
+            if self.state.storage.take_queue_overflow(&client_id) {
+                return Err(self
+                    .disconnect_with_problem(DisconnectReasonCode::QuotaExceeded, || {
+                        Problem::new("the message queue of the session is full")
+                    }));
+            }
+
             if self.receive_out_quota == 0 {
                 return Ok(());
             }
@@ -958,12 +1917,15 @@ where
                 .next_messages(&client_id, Some(self.receive_out_quota));
             assert!(msgs.len() <= self.receive_out_quota);
 
+            // the messages are written together
             for msg in msgs {
                 if msg.is_expired() {
+                    self.state.service_metrics.inc_msg_dropped(1);
                     continue;
                 }
                 self.delive(msg).await?;
             }
+            self.flush().await?;
         }
 
         Ok(())
@@ -979,8 +1941,16 @@ where
             Some(publish) => publish,
             None => return Ok(()),
         };
+        if let Some(topic) = self
+            .mount_point
+            .as_deref()
+            .and_then(|mount_point| publish.topic.strip_prefix(mount_point))
+            .filter(|topic| !topic.is_empty())
+        {
+            publish.topic = topic.to_string().into();
+        }
 
-        for (_, plugin) in &self.state.plugins {
+        for (_, plugin) in &self.plugins {
             plugin
                 .on_message_delivered(
                     self.client_id.as_ref().unwrap(),
@@ -997,9 +1967,14 @@ where
 
         self.state.service_metrics.inc_pub_msgs_sent(1);
         match publish.qos {
-            Qos::AtMostOnce => self.send_packet(&Packet::Publish(publish)).await,
+            Qos::AtMostOnce => {
+                self.set_topic_alias_out(&mut publish);
+                self.feed_packet(&Packet::Publish(publish))
+            }
             Qos::AtLeastOnce | Qos::ExactlyOnce => {
-                let packet_id = self.packet_id_allocator.take();
+                let packet_id = self.packet_id_allocator.take().ok_or_else(|| {
+                    Error::InternalError("packet identifiers exhausted".to_string())
+                })?;
                 publish.packet_id = Some(packet_id);
 
                 if publish.qos > Qos::AtMostOnce {
@@ -1017,30 +1992,165 @@ where
                     .add_inflight_pub_packet(&client_id, publish.clone());
                 self.inflight_qos2_messages
                     .insert(packet_id, Qos2State::Published);
-                self.send_packet(&Packet::Publish(publish)).await?;
-                Ok(())
+                // the stored packet keeps the topic, the aliases don't outlive the connection
+                self.set_topic_alias_out(&mut publish);
+                self.feed_packet(&Packet::Publish(publish))
+            }
+        }
+    }
+
+    /// Sends the inflight packets again that were not acknowledged since the last call, called
+    /// every `retry_interval` seconds.
+    async fn retry_inflight(&mut self) -> Result<(), Error> {
+        let client_id = match self.client_id.clone() {
+            Some(client_id) => client_id,
+            None => return Ok(()),
+        };
+        // MQTT 5 only allows to send them again when the client reconnects [MQTT-4.4.0-1]
+        if self.codec.protocol_level() == ProtocolLevel::V5 {
+            return Ok(());
+        }
+
+        let mut retries = FnvHashMap::default();
+        for mut publish in self.state.storage.get_all_inflight_pub_packets(&client_id) {
+            let packet_id = match publish.packet_id {
+                Some(packet_id) => packet_id,
+                None => continue,
+            };
+            let count = match self.retries.get(&packet_id) {
+                Some(count) => count + 1,
+                None => {
+                    retries.insert(packet_id, 0);
+                    continue;
+                }
+            };
+            if matches!(self.state.config.max_retries, Some(max_retries) if count > max_retries) {
+                tracing::debug!(
+                    remote_addr = %self.remote_addr,
+                    client_id = %client_id,
+                    packet_id = packet_id,
+                    "inflight packet not acknowledged",
+                );
+                return Err(Error::ServerDisconnect(None));
+            }
+            retries.insert(packet_id, count);
+
+            match self.inflight_qos2_messages.get(&packet_id) {
+                Some(Qos2State::Recorded) => self.feed_packet(&Packet::PubRel(PubRel {
+                    packet_id,
+                    reason_code: PubRelReasonCode::Success,
+                    properties: PubRelProperties::default(),
+                }))?,
+                _ => {
+                    publish.dup = true;
+                    self.feed_packet(&Packet::Publish(publish))?;
+                }
+            }
+        }
+        self.retries = retries;
+        self.flush().await?;
+        Ok(())
+    }
+
+    /// Returns the topic filter rewritten by the rules and prefixed with the mount point, `None`
+    /// if it is unchanged.
+    fn rewrite_filter(&self, filter: &str, client_id: &str) -> Option<String> {
+        let rewritten = self
+            .state
+            .rewrite_filter(filter, client_id, self.uid.as_deref());
+        match &self.mount_point {
+            Some(mount_point) => Some(filter_util::mount_filter(
+                mount_point,
+                rewritten.as_deref().unwrap_or(filter),
+            )),
+            None => rewritten,
+        }
+    }
+
+    /// Replaces the topic of a PUBLISH sent to the client with its alias, a new alias is sent
+    /// along with the topic until the client's Topic Alias Maximum is reached.
+    fn set_topic_alias_out(&mut self, publish: &mut Publish) {
+        match self.topic_alias_out.get(&publish.topic) {
+            Some(alias) => {
+                publish.properties.topic_alias = Some(*alias);
+                publish.topic = ByteString::new();
+            }
+            None if self.topic_alias_out.len() < self.max_topic_alias_out => {
+                let alias = NonZeroU16::new(self.topic_alias_out.len() as u16 + 1).unwrap();
+                self.topic_alias_out.insert(publish.topic.clone(), alias);
+                publish.properties.topic_alias = Some(alias);
             }
+            None => {}
         }
     }
 }
 
+/// The connection is closed if nothing is received within one and a half times the keep alive.
+fn keep_alive_timeout(keep_alive: u16) -> Option<Duration> {
+    (keep_alive > 0).then(|| Duration::from_millis(keep_alive as u64 * 1500))
+}
+
 pub async fn client_loop(
     state: Arc<ServiceState>,
     reader: impl AsyncRead + Send + Unpin,
     writer: impl AsyncWrite + Send + Unpin,
     remote_addr: RemoteAddr,
 ) {
+    client_loop_with_settings(state, reader, writer, remote_addr, Arc::default()).await
+}
+
+/// Serves a connection of a listener with its own settings.
+pub async fn client_loop_with_settings(
+    state: Arc<ServiceState>,
+    reader: impl AsyncRead + Send + Unpin,
+    writer: impl AsyncWrite + Send + Unpin,
+    remote_addr: RemoteAddr,
+    settings: Arc<ListenerSettings>,
+) {
+    if !state.is_remote_addr_allowed(&settings, &remote_addr) {
+        tracing::debug!(
+            remote_addr = %remote_addr,
+            "ip address not allowed",
+        );
+        return;
+    }
+
     state.service_metrics.inc_socket_connections(1);
 
+    let plugins = state
+        .plugins
+        .iter()
+        .filter(|(name, _)| match &settings.plugins {
+            Some(names) => names.iter().any(|item| item == name),
+            None => true,
+        })
+        .cloned()
+        .collect();
+    let mut codec = Codec::new(reader, writer);
+    codec.set_decode_options(settings.decode);
     let (control_sender, mut control_receiver) = mpsc::unbounded_channel();
+    // one second of the bandwidth can be used at once
+    let bandwidth = |bytes_per_sec: Option<NonZeroU32>| {
+        bytes_per_sec.map(|bytes_per_sec| {
+            TokenBucket::new(
+                bytes_per_sec.get(),
+                bytes_per_sec.get(),
+                tokio::time::Instant::now(),
+            )
+        })
+    };
     let mut connection = Connection {
         state: state.clone(),
         remote_addr,
+        settings,
+        plugins,
         client_id: None,
         control_sender,
         uid: None,
+        mount_point: None,
+        quota_usage: None,
         notify: Arc::new(Notify::new()),
-        codec: Codec::new(reader, writer),
+        codec,
         session_expiry_interval: 0,
         receive_in_max: 0,
         receive_out_max: 0,
@@ -1048,37 +2158,51 @@ pub async fn client_loop(
         receive_out_quota: 0,
         max_topic_alias: 0,
         topic_alias: FnvHashMap::default(),
+        max_topic_alias_out: 0,
+        topic_alias_out: FnvHashMap::default(),
         keep_alive: 60,
-        last_active: Instant::now(),
+        publish_rate: None,
+        inbound_bandwidth: bandwidth(state.config.bandwidth_limit.inbound_bytes_per_sec),
+        outbound_bandwidth: bandwidth(state.config.bandwidth_limit.outbound_bytes_per_sec),
+        request_problem_info: true,
         last_will: None,
         packet_id_allocator: PacketIdAllocator::default(),
         inflight_qos2_messages: FnvHashMap::default(),
+        retries: FnvHashMap::default(),
         uncompleted_messages: FnvHashMap::default(),
+        disconnect_reason: DisconnectReason::ConnectionLost,
+        auth_method: None,
+        auth_exchange: None,
+        pending_connect: None,
     };
-    let mut keep_alive_interval = tokio::time::interval(Duration::from_secs(1));
+    // the keep alive until the CONNECT packet is received
+    connection
+        .codec
+        .set_read_timeout(keep_alive_timeout(connection.keep_alive));
+    let connect_timeout = connection.state.config.connect_timeout;
+    let connect_deadline = tokio::time::sleep(Duration::from_secs(connect_timeout));
+    tokio::pin!(connect_deadline);
+    let retry_interval = connection.state.config.retry_interval;
+    let mut retry_timer = tokio::time::interval(Duration::from_secs(retry_interval.max(1)));
 
     loop {
         tokio::select! {
-            _ = keep_alive_interval.tick() => {
-                if connection.keep_alive > 0 &&
-                    connection.last_active.elapsed().as_secs() > connection.keep_alive as u64 * 3 / 2 {
-                    tracing::debug!(
-                        remote_addr = %connection.remote_addr,
-                        "keep alive timeout",
-                    );
-                    connection.send_disconnect(DisconnectReasonCode::KeepAliveTimeout, None).await.ok();
-                    break;
-                }
+            _ = &mut connect_deadline, if connect_timeout > 0 && connection.client_id.is_none() => {
+                tracing::debug!(
+                    remote_addr = %connection.remote_addr,
+                    "connect timeout",
+                );
+                break;
             }
             res = connection.codec.decode() => {
                 match res {
                     Ok(Some((packet, packet_size))) => {
                         connection.state.service_metrics.inc_bytes_received(packet_size);
                         connection.state.service_metrics.inc_msgs_received(1);
-                        connection.last_active = Instant::now();
+                        connection.throttle_inbound(packet_size).await;
                         tracing::debug!(
                             remote_addr = %connection.remote_addr,
-                            packet = ?packet,
+                            packet = %packet.summary(),
                             "receive packet",
                         );
                         match connection.handle_packet(packet).await {
@@ -1115,6 +2239,21 @@ pub async fn client_loop(
                         }
                     }
                     Ok(None) => break,
+                    Err(DecodeError::ReadTimeout) => {
+                        tracing::debug!(
+                            remote_addr = %connection.remote_addr,
+                            "keep alive timeout",
+                        );
+                        connection.send_disconnect(DisconnectReasonCode::KeepAliveTimeout, None).await.ok();
+                        break;
+                    }
+                    Err(DecodeError::LimitExceeded) => {
+                        connection.send_disconnect(
+                            DisconnectReasonCode::ImplementationSpecificError,
+                            None,
+                        ).await.ok();
+                        break;
+                    }
                     Err(DecodeError::PacketTooLarge) => {
                         connection.send_disconnect(
                             DisconnectReasonCode::PacketTooLarge,
@@ -1128,6 +2267,8 @@ pub async fn client_loop(
                             error = %err,
                             "decode packet",
                         );
+                        connection.disconnect_reason =
+                            DisconnectReason::Server(DisconnectReasonCode::MalformedPacket);
                         break;
                     }
                 }
@@ -1143,6 +2284,10 @@ pub async fn client_loop(
                             ).await.ok();
                             break;
                         },
+                        Err(Error::ServerDisconnect(Some(disconnect))) => {
+                            connection.send_packet(&Packet::Disconnect(disconnect)).await.ok();
+                            break;
+                        }
                         Err(err) => {
                             tracing::debug!(
                                 remote_addr = %connection.remote_addr,
@@ -1154,8 +2299,8 @@ pub async fn client_loop(
                     }
                 }
             }
-            _ = connection.notify.notified() => {
-                if let Err(err) = connection.handle_notified().await {
+            _ = retry_timer.tick(), if retry_interval > 0 && connection.client_id.is_some() => {
+                if let Err(err) = connection.retry_inflight().await {
                     tracing::debug!(
                         remote_addr = %connection.remote_addr,
                         error = %err,
@@ -1164,6 +2309,23 @@ pub async fn client_loop(
                     break;
                 }
             }
+            _ = connection.notify.notified() => {
+                match connection.handle_notified().await {
+                    Ok(()) => {}
+                    Err(Error::ServerDisconnect(Some(disconnect))) => {
+                        connection.send_packet(&Packet::Disconnect(disconnect)).await.ok();
+                        break;
+                    }
+                    Err(err) => {
+                        tracing::debug!(
+                            remote_addr = %connection.remote_addr,
+                            error = %err,
+                            "error",
+                        );
+                        break;
+                    }
+                }
+            }
         }
     }
 
@@ -1177,10 +2339,26 @@ pub async fn client_loop(
         connection.state.service_metrics.dec_connection_count(1);
         connection
             .state
-            .storage
-            .disconnect_session(&client_id, connection.session_expiry_interval);
+            .record_disconnect(client_id, connection.disconnect_reason);
+        if let Some(quota_usage) = &connection.quota_usage {
+            quota_usage.remove_session();
+        }
+        // the exclusive subscriptions are only held while the client is connected
+        for path in connection.state.release_exclusive_subscriptions(client_id) {
+            let filter = Filter {
+                share_name: None,
+                exclusive: true,
+                path: &path,
+            };
+            connection.state.storage.unsubscribe(client_id, filter);
+        }
+        connection.state.storage.disconnect_session(
+            client_id,
+            connection.session_expiry_interval,
+            connection.last_will.take(),
+        );
 
-        for (_, plugin) in &connection.state.plugins {
+        for (_, plugin) in &connection.plugins {
             plugin
                 .on_client_disconnected(client_id, connection.uid.as_deref())
                 .await;
@@ -0,0 +1,17 @@
+//! The time source of the service.
+//!
+//! All timestamps follow tokio's clock, so tests can pause the time and advance it instead
+//! of sleeping.
+
+use std::time::SystemTime;
+
+use once_cell::sync::Lazy;
+use tokio::time::Instant;
+
+static BASE: Lazy<(SystemTime, Instant)> = Lazy::new(|| (SystemTime::now(), Instant::now()));
+
+/// Returns the current wall-clock time.
+pub fn system_now() -> SystemTime {
+    let (system_time, instant) = *BASE;
+    system_time + instant.elapsed()
+}
@@ -1,18 +1,129 @@
-use codec::{Qos, SubscribeFilter};
+use codec::{DecodeOptions, Qos, SubscribeFilter};
 use serde::Deserialize;
 
+use crate::ip_filter::IpFilterConfig;
+use crate::quota::Quota;
+use crate::rate_limit::{BandwidthLimit, RateLimit};
+use crate::redirect::Redirect;
+use crate::storage::{QueueFullPolicy, SharedSubscriptionStrategy};
+
+/// The topics a rewrite rule applies to.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RewriteTarget {
+    /// The topics of the published messages.
+    #[default]
+    Publish,
+    /// The topic filters of the SUBSCRIBE and UNSUBSCRIBE packets, the filter of a shared
+    /// subscription is rewritten without the `$share/{name}/` prefix.
+    Subscribe,
+    /// Both the published topics and the topic filters.
+    All,
+}
+
+/// Rewrites the topics matching the regex `pattern` with `write`, which can refer to the
+/// capture groups with `$1`, to the client id with `%c` and to the uid with `%u`.
+///
+/// A rule with `%u` is skipped for the anonymous clients.
 #[derive(Debug, Deserialize)]
 pub struct RewriteConfig {
     pub pattern: String,
     pub write: String,
+    #[serde(default)]
+    pub target: RewriteTarget,
+}
+
+/// Limits the payload size of the messages published to the topics matching `filter`.
+#[derive(Debug, Deserialize)]
+pub struct PayloadLimitConfig {
+    pub filter: String,
+    pub max_size: usize,
+}
+
+/// Thresholds above which the server is overloaded, the new connections are rejected with
+/// `ServerBusy` until the usage drops below all of them, or below `low_watermark`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct OverloadConfig {
+    /// The maximum number of sessions, including the sessions of the disconnected clients.
+    pub max_sessions: Option<usize>,
+    /// The maximum number of bytes of the stored messages.
+    pub max_messages_bytes: Option<usize>,
+    /// The maximum number of messages waiting to be delivered.
+    pub max_queued_messages: Option<usize>,
+    /// The maximum number of connected clients, unlike the other thresholds it is checked on
+    /// each CONNECT instead of periodically.
+    pub max_connections: Option<usize>,
+    /// Sent to the rejected clients as the `Server Reference` of the CONNACK, usually the address
+    /// of another server.
+    pub server_reference: Option<String>,
+    /// The ratio of the thresholds the usage must drop below to end the overload, e.g. `0.8`
+    /// keeps the server overloaded until the usage is below 80% of all thresholds. Defaults to
+    /// `1.0`.
+    pub low_watermark: Option<f64>,
+    /// Drops the QoS 0 messages published while the server is overloaded instead of delivering
+    /// them.
+    pub drop_qos0: bool,
+}
+
+/// What the server does when a plugin denies a client to publish to a topic.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PublishDenied {
+    /// Disconnects the client with the reason code Not authorized.
+    #[default]
+    Disconnect,
+    /// Drops the message and keeps the connection, the QoS 1 and QoS 2 messages are acknowledged
+    /// with the reason code Not authorized, the MQTT 3.1.1 clients can't tell it from a success.
+    Reject,
+}
+
+/// Settings of a listener that override the service config for its connections.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ListenerSettings {
+    /// Reported in the remote address of the connections, so the plugins can tell the listeners
+    /// apart.
+    pub name: Option<String>,
+    pub max_packet_size: Option<u32>,
+    pub maximum_qos: Option<Qos>,
+    /// Rejects the clients that are not authenticated by a plugin.
+    pub auth_required: bool,
+    /// Rejects the MQTT 5 clients whose login is not accepted by any plugin with a CONNACK with
+    /// the reason code Bad User Name or Password, instead of a DISCONNECT with Not authorized.
+    pub bad_login_conn_ack: bool,
+    /// The names of the plugins used by the connections, all plugins are used if not specified.
+    pub plugins: Option<Vec<String>>,
+    /// How strictly the packets sent by the clients are checked, e.g. `DecodeOptions::lenient()`
+    /// for clients that send slightly malformed packets.
+    pub decode: DecodeOptions,
+    /// Replaces the IP filter of the service for the connections.
+    pub ip_filter: Option<IpFilterConfig>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ServiceConfig {
     #[serde(default = "default_metrics_update_interval")]
     pub metrics_update_interval: u64,
+    /// The number of seconds between the removals of the expired messages from the queues of the
+    /// sessions, `0` only removes them when they are about to be sent.
+    #[serde(default = "default_expired_messages_interval")]
+    pub expired_messages_interval: u64,
     #[serde(default = "default_max_keep_alive")]
     pub max_keep_alive: u16,
+    /// The number of seconds a connection has to complete the CONNECT before it is closed, `0`
+    /// disables the timeout.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// The number of seconds after which the QoS 1 and QoS 2 messages a MQTT 3.1.1 client did not
+    /// acknowledge are sent again, `0` only sends them again when the client reconnects as
+    /// MQTT 5 requires.
+    #[serde(default)]
+    pub retry_interval: u64,
+    /// The number of times a message is sent again before the connection is closed, unlimited
+    /// if not set.
+    #[serde(default)]
+    pub max_retries: Option<usize>,
     #[serde(default = "default_max_session_expiry_interval")]
     pub max_session_expiry_interval: u32,
     #[serde(default = "default_receive_max")]
@@ -31,16 +142,98 @@ pub struct ServiceConfig {
     pub subscriptions: Vec<SubscribeFilter>,
     #[serde(default)]
     pub rewrites: Vec<RewriteConfig>,
+    /// The first limit whose filter matches the topic is applied.
+    #[serde(default)]
+    pub payload_limits: Vec<PayloadLimitConfig>,
+    /// Messages published to the topics matching these filters are delivered before the other
+    /// queued messages.
+    #[serde(default)]
+    pub priority_topics: Vec<String>,
+    /// The maximum number of messages queued for each session, unlimited if not set.
+    #[serde(default)]
+    pub max_queued_messages: Option<usize>,
+    /// The maximum size of the payloads queued for each session, unlimited if not set. An auth
+    /// plugin can override it for a user.
+    #[serde(default)]
+    pub max_queued_bytes: Option<usize>,
+    /// What happens to a message queued for a session that holds `max_queued_messages` or
+    /// `max_queued_bytes`.
+    #[serde(default)]
+    pub queue_full_policy: QueueFullPolicy,
+    /// How the messages of a shared subscription are routed to the members of its group.
+    #[serde(default)]
+    pub shared_subscription_strategy: SharedSubscriptionStrategy,
+    /// The maximum number of subscriptions of each session, a SUBSCRIBE over the limit is
+    /// acknowledged with the reason code Quota exceeded.
+    #[serde(default)]
+    pub max_subscriptions_per_client: Option<usize>,
+    /// The resource limits of each authenticated user, an auth plugin can override them.
+    #[serde(default)]
+    pub user_quota: Quota,
+    /// Limits the rate of the messages published by each connection, an auth plugin can
+    /// override it for a user.
+    ///
+    /// The MQTT 5 clients receive the reason code Quota exceeded for the QoS 1 and QoS 2
+    /// messages over the limit, the other ones are disconnected.
+    #[serde(default)]
+    pub publish_rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub bandwidth_limit: BandwidthLimit,
+    /// The number of disconnect reasons kept for each client id, `0` disables the history.
+    #[serde(default = "default_disconnect_history_size")]
+    pub disconnect_history_size: usize,
+    #[serde(default)]
+    pub overload: OverloadConfig,
+    /// Redirects the new connections to another server, it can be changed with
+    /// [`ServiceState::set_redirect`](crate::ServiceState::set_redirect).
+    #[serde(default)]
+    pub redirect: Option<Redirect>,
+    /// The IP addresses of the clients that are accepted, the listeners can override it.
+    #[serde(default)]
+    pub ip_filter: IpFilterConfig,
+    /// Accepts the MQTT 3.1 clients (protocol level 3).
+    #[serde(default)]
+    pub accept_mqtt_v3: bool,
+    /// Accepts the clients that are not authenticated by a plugin, the listeners can still
+    /// require the authentication with [`ListenerSettings::auth_required`].
+    #[serde(default = "default_allow_anonymous")]
+    pub allow_anonymous: bool,
+    #[serde(default)]
+    pub publish_denied: PublishDenied,
+    /// Acknowledges the QoS 1 and QoS 2 messages that match no subscription with the reason
+    /// code No matching subscribers instead of Success, only the MQTT 5 clients receive it.
+    #[serde(default)]
+    pub no_matching_subscribers: bool,
+    /// Adds a reason string telling why to the acknowledgements and DISCONNECT packets that
+    /// reject an operation, unless the client sets Request Problem Information to 0.
+    #[serde(default)]
+    pub reason_strings: bool,
+    /// Adds details such as the plugin that denied an operation as user properties, along with
+    /// the reason strings.
+    #[serde(default)]
+    pub diagnostic_user_properties: bool,
+    /// The directory of the files the traces can be written to, the traces are only published
+    /// to `$SYS/trace/{id}` if it is not set.
+    #[serde(default)]
+    pub trace_dir: Option<String>,
 }
 
 fn default_metrics_update_interval() -> u64 {
     5
 }
 
+fn default_expired_messages_interval() -> u64 {
+    60
+}
+
 fn default_max_keep_alive() -> u16 {
     30
 }
 
+fn default_connect_timeout() -> u64 {
+    10
+}
+
 fn default_max_session_expiry_interval() -> u32 {
     60
 }
@@ -69,11 +262,23 @@ fn default_wildcard_subscription_available() -> bool {
     true
 }
 
+fn default_disconnect_history_size() -> usize {
+    10
+}
+
+fn default_allow_anonymous() -> bool {
+    true
+}
+
 impl Default for ServiceConfig {
     fn default() -> Self {
         Self {
             metrics_update_interval: 5,
+            expired_messages_interval: default_expired_messages_interval(),
             max_keep_alive: default_max_keep_alive(),
+            connect_timeout: default_connect_timeout(),
+            retry_interval: 0,
+            max_retries: None,
             max_session_expiry_interval: default_max_session_expiry_interval(),
             receive_max: default_receive_max(),
             max_packet_size: default_max_packet_size(),
@@ -83,6 +288,27 @@ impl Default for ServiceConfig {
             wildcard_subscription_available: default_wildcard_subscription_available(),
             subscriptions: Vec::new(),
             rewrites: Vec::new(),
+            payload_limits: Vec::new(),
+            priority_topics: Vec::new(),
+            max_queued_messages: None,
+            max_queued_bytes: None,
+            queue_full_policy: QueueFullPolicy::default(),
+            shared_subscription_strategy: SharedSubscriptionStrategy::default(),
+            max_subscriptions_per_client: None,
+            user_quota: Quota::default(),
+            publish_rate_limit: None,
+            bandwidth_limit: BandwidthLimit::default(),
+            disconnect_history_size: default_disconnect_history_size(),
+            overload: OverloadConfig::default(),
+            redirect: None,
+            ip_filter: IpFilterConfig::default(),
+            accept_mqtt_v3: false,
+            allow_anonymous: default_allow_anonymous(),
+            publish_denied: PublishDenied::default(),
+            no_matching_subscribers: false,
+            reason_strings: false,
+            diagnostic_user_properties: false,
+            trace_dir: None,
         }
     }
 }
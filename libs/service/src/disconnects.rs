@@ -0,0 +1,84 @@
+use std::fmt::{self, Display, Formatter};
+use std::time::UNIX_EPOCH;
+
+use codec::{DisconnectReasonCode, Qos};
+use serde::Serialize;
+
+use crate::message::Message;
+use crate::{clock, filter_util, ServiceState};
+
+/// Why a connection was closed.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "reason_code", rename_all = "snake_case")]
+pub enum DisconnectReason {
+    /// The client sent a DISCONNECT packet.
+    Client(DisconnectReasonCode),
+    /// The server closed the connection.
+    Server(DisconnectReasonCode),
+    /// The network connection was closed without a DISCONNECT packet.
+    ConnectionLost,
+}
+
+impl Display for DisconnectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DisconnectReason::Client(reason_code) => write!(f, "client: {:?}", reason_code),
+            DisconnectReason::Server(reason_code) => write!(f, "server: {:?}", reason_code),
+            DisconnectReason::ConnectionLost => write!(f, "connection lost"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct DisconnectRecord {
+    /// Unix timestamp in seconds.
+    pub timestamp: u64,
+    pub reason: DisconnectReason,
+}
+
+impl ServiceState {
+    /// Returns the last disconnect reasons of the client, the oldest first.
+    pub fn disconnect_history(&self, client_id: &str) -> Vec<DisconnectRecord> {
+        self.disconnect_history
+            .lock()
+            .get(client_id)
+            .map(|records| records.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Records the reason and publishes the history to `$SYS/clients/{id}/disconnects` as a
+    /// retained message.
+    pub(crate) fn record_disconnect(&self, client_id: &str, reason: DisconnectReason) {
+        let history_size = self.config.disconnect_history_size;
+        if history_size == 0 {
+            return;
+        }
+
+        let payload = {
+            let mut history = self.disconnect_history.lock();
+            let records = history.entry(client_id.to_string()).or_default();
+            if records.len() >= history_size {
+                records.pop_front();
+            }
+            records.push_back(DisconnectRecord {
+                timestamp: clock::system_now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                reason,
+            });
+            records
+                .iter()
+                .map(|record| format!("{} {}\n", record.timestamp, record.reason))
+                .collect::<String>()
+        };
+
+        let topic = format!("$SYS/clients/{}/disconnects", client_id);
+        if !filter_util::valid_topic(&topic) {
+            return;
+        }
+        let msg = Message::new(topic, Qos::AtMostOnce, payload.into_bytes()).with_retain(true);
+        self.storage.update_retained_message(msg.clone());
+        self.storage.deliver(vec![msg]);
+    }
+}
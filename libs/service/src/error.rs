@@ -27,7 +27,7 @@ pub enum Error {
 impl Error {
     #[inline]
     pub fn internal_error(err: impl Display) -> Self {
-        Self::internal_error(err.to_string())
+        Self::InternalError(err.to_string())
     }
 
     #[inline]
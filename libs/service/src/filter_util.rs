@@ -3,7 +3,14 @@ pub fn valid_topic(topic: &str) -> bool {
     if topic.is_empty() {
         return false;
     }
-    !topic.contains(&['+', '#'][..])
+    !topic.contains(&['+', '#', '\0'][..])
+}
+
+/// Returns `true` if the string contains a control character other than U+0000, which is never
+/// valid.
+#[inline]
+pub fn has_control_characters(s: &str) -> bool {
+    s.chars().any(|c| c != '\0' && c.is_control())
 }
 
 #[inline]
@@ -14,12 +21,14 @@ pub fn has_wildcards(filter: &str) -> bool {
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Filter<'a> {
     pub share_name: Option<&'a str>,
+    /// `true` for the `$exclusive/` filters, only one client may hold such a subscription.
+    pub exclusive: bool,
     pub path: &'a str,
 }
 
 #[inline]
 fn valid_filter(filter: &str) -> bool {
-    if filter.is_empty() {
+    if filter.is_empty() || filter.contains('\0') {
         return false;
     }
 
@@ -51,6 +60,16 @@ pub fn parse_filter(filter: &str) -> Option<Filter> {
         }
         Some(Filter {
             share_name: Some(share_name),
+            exclusive: false,
+            path,
+        })
+    } else if let Some(path) = filter.strip_prefix("$exclusive/") {
+        if !valid_filter(path) {
+            return None;
+        }
+        Some(Filter {
+            share_name: None,
+            exclusive: true,
             path,
         })
     } else {
@@ -59,21 +78,89 @@ pub fn parse_filter(filter: &str) -> Option<Filter> {
         }
         Some(Filter {
             share_name: None,
+            exclusive: false,
             path: filter,
         })
     }
 }
 
+/// Prefixes the path of the topic filter with the mount point, keeping the prefix of a shared or
+/// exclusive subscription.
+pub fn mount_filter(mount_point: &str, filter: &str) -> String {
+    match parse_filter(filter) {
+        Some(Filter {
+            share_name: Some(share_name),
+            path,
+            ..
+        }) => format!("$share/{}/{}{}", share_name, mount_point, path),
+        Some(Filter {
+            exclusive: true,
+            path,
+            ..
+        }) => format!("$exclusive/{}{}", mount_point, path),
+        _ => format!("{}{}", mount_point, filter),
+    }
+}
+
+/// Returns `true` if the topic name matches the filter path.
+pub fn matches_topic(filter: &str, topic: &str) -> bool {
+    let mut topic_segments = topic.split('/');
+
+    for (idx, segment) in filter.split('/').enumerate() {
+        if idx == 0 && topic.starts_with('$') && (segment == "+" || segment == "#") {
+            // wildcards do not match topic names beginning with a `$` character
+            return false;
+        }
+        match (segment, topic_segments.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (segment, Some(topic_segment)) if segment == topic_segment => {}
+            _ => return false,
+        }
+    }
+
+    topic_segments.next().is_none()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_matches_topic() {
+        assert!(matches_topic("a/b", "a/b"));
+        assert!(!matches_topic("a/b", "a/b/c"));
+        assert!(!matches_topic("a/b/c", "a/b"));
+        assert!(matches_topic("a/+/c", "a/b/c"));
+        assert!(matches_topic("a/+", "a/"));
+        assert!(!matches_topic("a/+", "a"));
+        assert!(matches_topic("a/#", "a"));
+        assert!(matches_topic("a/#", "a/b/c"));
+        assert!(matches_topic("#", "a/b"));
+        assert!(!matches_topic("#", "$SYS/a"));
+        assert!(!matches_topic("+/a", "$SYS/a"));
+        assert!(matches_topic("$SYS/#", "$SYS/a"));
+    }
+
+    #[test]
+    fn test_invalid_characters() {
+        assert!(valid_topic("a/b"));
+        assert!(!valid_topic("a/\0"));
+        assert_eq!(parse_filter("a/\0/#"), None);
+
+        assert!(has_control_characters("a/\x01"));
+        assert!(has_control_characters("a/\u{9f}"));
+        assert!(!has_control_characters("a/b\u{a0}"));
+        assert!(!has_control_characters("a/\0"));
+    }
+
     #[test]
     fn test_parse() {
         assert_eq!(
             parse_filter("abc/a/b"),
             Some(Filter {
                 share_name: None,
+                exclusive: false,
                 path: "abc/a/b"
             })
         );
@@ -82,6 +169,7 @@ mod tests {
             parse_filter("$share/abc/a/b"),
             Some(Filter {
                 share_name: Some("abc"),
+                exclusive: false,
                 path: "a/b"
             })
         );
@@ -95,6 +183,7 @@ mod tests {
             parse_filter("$share/abc/a/+/c"),
             Some(Filter {
                 share_name: Some("abc"),
+                exclusive: false,
                 path: "a/+/c"
             })
         );
@@ -102,8 +191,27 @@ mod tests {
             parse_filter("$share/abc/a/#"),
             Some(Filter {
                 share_name: Some("abc"),
+                exclusive: false,
                 path: "a/#"
             })
         );
+
+        assert_eq!(
+            parse_filter("$exclusive/a/+"),
+            Some(Filter {
+                share_name: None,
+                exclusive: true,
+                path: "a/+"
+            })
+        );
+        assert_eq!(parse_filter("$exclusive/"), None);
+        assert_eq!(parse_filter("$exclusive/a/b#"), None);
+    }
+
+    #[test]
+    fn test_mount_filter() {
+        assert_eq!(mount_filter("t1/", "a/#"), "t1/a/#");
+        assert_eq!(mount_filter("t1/", "$share/g/a/+"), "$share/g/t1/a/+");
+        assert_eq!(mount_filter("t1/", "$exclusive/a"), "$exclusive/t1/a");
     }
 }
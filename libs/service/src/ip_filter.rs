@@ -0,0 +1,186 @@
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{ListenerSettings, RemoteAddr, ServiceState};
+
+/// A range of IP addresses in the CIDR notation, e.g. `10.0.0.0/8`, a single address without a
+/// prefix length is also accepted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// Returns `true` if `ip` is in the range, an IPv4-mapped IPv6 address matches the IPv4
+    /// ranges.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(ip) => ip
+                .to_ipv4_mapped()
+                .map(IpAddr::V4)
+                .unwrap_or(IpAddr::V6(ip)),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(net: &[u8], ip: &[u8], prefix_len: u8) -> bool {
+    let bytes = prefix_len as usize / 8;
+    let bits = prefix_len % 8;
+    if net[..bytes] != ip[..bytes] {
+        return false;
+    }
+    bits == 0 || (net[bytes] ^ ip[bytes]) >> (8 - bits) == 0
+}
+
+impl FromStr for IpNet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .with_context(|| format!("invalid ip address: {}", s))?;
+        let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .with_context(|| format!("invalid prefix length: {}", s))?,
+            None => max_prefix_len,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl TryFrom<String> for IpNet {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for IpNet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Restricts the IP addresses of the clients, a client is rejected if its address is in `deny`,
+/// or `allow` is not empty and its address is not in it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IpFilterConfig {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl IpFilterConfig {
+    fn is_allowed(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => {
+                !self.deny.iter().any(|net| net.contains(ip))
+                    && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip)))
+            }
+            None => self.allow.is_empty(),
+        }
+    }
+}
+
+impl ServiceState {
+    /// Returns `true` if a client with the IP address is accepted by the IP filter of the
+    /// listener, or of the service if the listener doesn't have one.
+    ///
+    /// The connections are checked before the CONNECT packet, a listener can call it when the
+    /// client connects to close the connection before the TLS handshake.
+    pub fn is_ip_allowed(&self, settings: &ListenerSettings, ip: Option<IpAddr>) -> bool {
+        settings
+            .ip_filter
+            .as_ref()
+            .unwrap_or(&self.config.ip_filter)
+            .is_allowed(ip)
+    }
+
+    pub(crate) fn is_remote_addr_allowed(
+        &self,
+        settings: &ListenerSettings,
+        remote_addr: &RemoteAddr,
+    ) -> bool {
+        let ip = remote_addr
+            .addr
+            .as_deref()
+            .and_then(|addr| match addr.parse::<SocketAddr>() {
+                Ok(addr) => Some(addr.ip()),
+                Err(_) => addr.parse::<IpAddr>().ok(),
+            });
+        self.is_ip_allowed(settings, ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip_net() {
+        let net = "10.1.0.0/16".parse::<IpNet>().unwrap();
+        assert!(net.contains("10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.2.3".parse().unwrap()));
+
+        let net = "192.168.1.128/25".parse::<IpNet>().unwrap();
+        assert!(net.contains("192.168.1.200".parse().unwrap()));
+        assert!(!net.contains("192.168.1.127".parse().unwrap()));
+
+        let net = "fd00::/8".parse::<IpNet>().unwrap();
+        assert!(net.contains("fd12::1".parse().unwrap()));
+        assert!(!net.contains("fe80::1".parse().unwrap()));
+        assert!(!net.contains("10.1.2.3".parse().unwrap()));
+
+        let net = "127.0.0.1".parse::<IpNet>().unwrap();
+        assert_eq!(net.to_string(), "127.0.0.1/32");
+        assert!(net.contains("127.0.0.1".parse().unwrap()));
+        assert!(!net.contains("127.0.0.2".parse().unwrap()));
+
+        assert!("0.0.0.0/0"
+            .parse::<IpNet>()
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("abc/8".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn test_ip_filter() {
+        let filter = IpFilterConfig {
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.0.0.1".parse().unwrap()],
+        };
+        assert!(filter.is_allowed(Some("10.0.0.2".parse().unwrap())));
+        assert!(!filter.is_allowed(Some("10.0.0.1".parse().unwrap())));
+        assert!(!filter.is_allowed(Some("192.168.0.1".parse().unwrap())));
+        assert!(!filter.is_allowed(None));
+        assert!(IpFilterConfig::default().is_allowed(None));
+    }
+}
@@ -1,24 +1,46 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::default_trait_access)]
 
+mod admin;
 mod client_loop;
+mod clock;
 mod config;
+mod disconnects;
 mod error;
 mod filter_util;
+mod ip_filter;
 mod message;
 mod metrics;
+mod overload;
+mod quota;
+mod rate_limit;
+mod redirect;
 mod rewrite;
 mod state;
 mod storage;
 mod sys_topics;
+mod trace;
 mod trie;
 
 pub mod plugin;
 
-pub use client_loop::{client_loop, RemoteAddr};
+pub use admin::{Ban, BanInfo, ClientInfo};
+pub use client_loop::{client_loop, client_loop_with_settings, ClientCert, RemoteAddr};
 pub use codec;
-pub use config::ServiceConfig;
+pub use config::{ListenerSettings, OverloadConfig, PublishDenied, ServiceConfig};
+pub use disconnects::{DisconnectReason, DisconnectRecord};
 pub use error::Error;
+pub use filter_util::{matches_topic, parse_filter, Filter};
+pub use ip_filter::{IpFilterConfig, IpNet};
 pub use message::Message;
 pub use metrics::Metrics;
+pub use quota::{Quota, QuotaUsage};
+pub use rate_limit::{BandwidthLimit, RateLimit};
+pub use redirect::Redirect;
 pub use state::ServiceState;
+pub use storage::{
+    FilterItem, QueueFullPolicy, QueueLimit, SessionChange, SessionSnapshot,
+    SharedSubscriptionStrategy, Storage, StorageFactory, StorageMemory, StorageMemoryFactory,
+    StorageMetrics,
+};
+pub use trace::{TraceInfo, TraceOptions, TraceTarget, TRACE_TOPIC_PREFIX};
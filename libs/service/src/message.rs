@@ -15,6 +15,8 @@ pub struct Message {
     payload: Bytes,
     retain: bool,
     properties: PublishProperties,
+    #[serde(default)]
+    priority: bool,
 }
 
 impl Message {
@@ -23,12 +25,13 @@ impl Message {
         Self {
             from_client_id: None,
             from_uid: None,
-            created_at: SystemTime::now(),
+            created_at: crate::clock::system_now(),
             topic: topic.into(),
             qos,
             payload: payload.into(),
             retain: false,
             properties: PublishProperties::default(),
+            priority: false,
         }
     }
 
@@ -44,6 +47,13 @@ impl Message {
         self
     }
 
+    /// Delivers the message before the normal messages in the queue of the sessions.
+    #[inline]
+    pub fn with_priority(mut self, priority: bool) -> Self {
+        self.priority = priority;
+        self
+    }
+
     #[inline]
     pub fn with_from_client_id(mut self, client_id: impl Into<ByteString>) -> Self {
         self.from_client_id = Some(client_id.into());
@@ -91,6 +101,11 @@ impl Message {
         self.retain
     }
 
+    #[inline]
+    pub fn is_priority(&self) -> bool {
+        self.priority
+    }
+
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.payload.is_empty()
@@ -100,7 +115,7 @@ impl Message {
     pub fn is_expired(&self) -> bool {
         if let Some(message_expiry_interval) = self.properties.message_expiry_interval {
             let expired_at = self.created_at + Duration::from_secs(message_expiry_interval as u64);
-            return expired_at <= SystemTime::now();
+            return expired_at <= crate::clock::system_now();
         }
         false
     }
@@ -160,7 +175,7 @@ impl Message {
         let mut publish = self.to_publish();
 
         if let Some(message_expiry_interval) = publish.properties.message_expiry_interval {
-            let now = SystemTime::now();
+            let now = crate::clock::system_now();
             let expired_at = self.created_at + Duration::from_secs(message_expiry_interval as u64);
             match expired_at.duration_since(now) {
                 Ok(duration) => {
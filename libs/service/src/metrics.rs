@@ -35,6 +35,11 @@ pub struct Metrics {
     pub store_messages_count: usize,
     pub store_messages_bytes: usize,
     pub subscriptions_count: usize,
+    /// The number of subscriptions rejected because a session or user exceeded its quota.
+    pub subscriptions_rejected: usize,
+    /// The highest ratio of the usage to the overload thresholds, the new connections are
+    /// rejected when it reaches `1.0`.
+    pub overload: f64,
     pub load_messages_received: MetricsLoad,
     pub load_messages_sent: MetricsLoad,
     pub load_publish_dropped: MetricsLoad,
@@ -183,7 +188,11 @@ impl MetricsCalc {
         let msgs_sent = service_metrics.msgs_sent.load(Ordering::SeqCst);
         let pub_msgs_received = service_metrics.pub_msgs_received.load(Ordering::SeqCst);
         let pub_msgs_sent = service_metrics.pub_msgs_sent.load(Ordering::SeqCst);
-        let msgs_dropped = service_metrics.msgs_dropped.load(Ordering::SeqCst);
+        let msgs_dropped =
+            service_metrics.msgs_dropped.load(Ordering::SeqCst) + storage_metrics.messages_dropped;
+        let subscriptions_rejected = service_metrics
+            .subscriptions_rejected
+            .load(Ordering::SeqCst);
         let socket_connections = service_metrics.socket_connections.load(Ordering::SeqCst);
         let connection_count = service_metrics.connection_count.load(Ordering::SeqCst);
         let StorageMetrics {
@@ -194,6 +203,7 @@ impl MetricsCalc {
             messages_bytes,
             subscriptions_count,
             clients_expired,
+            ..
         } = *storage_metrics;
 
         self.max_clients = self.max_clients.max(connection_count);
@@ -294,6 +304,8 @@ impl MetricsCalc {
             store_messages_count: messages_count,
             store_messages_bytes: messages_bytes,
             subscriptions_count,
+            subscriptions_rejected,
+            overload: 0.0,
             load_messages_received: MetricsLoad {
                 min1: self.msgs_received_load1.value,
                 min5: self.msgs_received_load5.value,
@@ -0,0 +1,116 @@
+use std::sync::atomic::Ordering;
+
+use crate::config::OverloadConfig;
+use crate::storage::StorageMetrics;
+use crate::ServiceState;
+
+impl OverloadConfig {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_sessions.is_some()
+            || self.max_messages_bytes.is_some()
+            || self.max_queued_messages.is_some()
+    }
+
+    /// Returns `true` if the server is overloaded at `level`, an overloaded server recovers when
+    /// the level drops below `low_watermark`.
+    fn is_overloaded(&self, overloaded: bool, level: f64) -> bool {
+        match self.low_watermark {
+            Some(low_watermark) if overloaded => level >= low_watermark,
+            _ => level >= 1.0,
+        }
+    }
+
+    /// Returns the highest ratio of the usage to its threshold, `1.0` or more means overloaded.
+    fn level(&self, metrics: &StorageMetrics) -> f64 {
+        let ratio = |value: usize, max: Option<usize>| match max {
+            Some(max) => value as f64 / max.max(1) as f64,
+            None => 0.0,
+        };
+        ratio(metrics.session_count, self.max_sessions)
+            .max(ratio(metrics.messages_bytes, self.max_messages_bytes))
+            .max(ratio(
+                metrics
+                    .messages_count
+                    .saturating_sub(metrics.retained_messages_count),
+                self.max_queued_messages,
+            ))
+    }
+}
+
+impl ServiceState {
+    /// Returns the overload level computed at the last check, the server becomes overloaded when
+    /// it reaches `1.0`.
+    pub fn overload(&self) -> f64 {
+        f64::from_bits(self.overload.load(Ordering::SeqCst))
+    }
+
+    /// Returns `true` if the new connections are rejected, see [`OverloadConfig::low_watermark`].
+    #[inline]
+    pub(crate) fn is_overloaded(&self) -> bool {
+        self.overloaded.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the number of connected clients reached `overload.max_connections`.
+    pub(crate) fn is_connection_limit_reached(&self) -> bool {
+        match self.config.overload.max_connections {
+            Some(max_connections) => {
+                self.service_metrics.connection_count.load(Ordering::SeqCst) >= max_connections
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn update_overload(&self, metrics: &StorageMetrics) {
+        let level = self.config.overload.level(metrics);
+        self.overload.store(level.to_bits(), Ordering::SeqCst);
+        let prev_overloaded = self.is_overloaded();
+        let overloaded = self.config.overload.is_overloaded(prev_overloaded, level);
+        self.overloaded.store(overloaded, Ordering::SeqCst);
+        match (prev_overloaded, overloaded) {
+            (false, true) => {
+                tracing::warn!(level = %level, "server overloaded, rejecting new connections")
+            }
+            (true, false) => tracing::info!(level = %level, "server no longer overloaded"),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overload_level() {
+        let config = OverloadConfig {
+            max_sessions: Some(10),
+            max_queued_messages: Some(100),
+            ..OverloadConfig::default()
+        };
+        let mut metrics = StorageMetrics {
+            session_count: 5,
+            retained_messages_count: 50,
+            messages_count: 60,
+            ..StorageMetrics::default()
+        };
+        assert!(config.is_enabled());
+        assert!((config.level(&metrics) - 0.5).abs() < f64::EPSILON);
+
+        metrics.messages_count = 150;
+        assert!((config.level(&metrics) - 1.0).abs() < f64::EPSILON);
+
+        assert!(config.is_overloaded(false, 1.0));
+        assert!(!config.is_overloaded(true, 0.9));
+
+        let config = OverloadConfig {
+            low_watermark: Some(0.5),
+            ..config
+        };
+        assert!(!config.is_overloaded(false, 0.9));
+        assert!(config.is_overloaded(true, 0.9));
+        assert!(!config.is_overloaded(true, 0.4));
+
+        assert!(!OverloadConfig::default().is_enabled());
+        assert!(OverloadConfig::default().level(&metrics) < f64::EPSILON);
+    }
+}
@@ -3,7 +3,7 @@ use std::sync::Arc;
 use codec::{ProtocolLevel, Qos};
 use serde_yaml::Value;
 
-use crate::RemoteAddr;
+use crate::{ClientCert, Quota, RateLimit, RemoteAddr};
 use bytes::Bytes;
 
 pub type PluginResult<T> = anyhow::Result<T>;
@@ -21,14 +21,87 @@ pub enum Action {
     Subscribe,
 }
 
+/// A user authenticated by a plugin.
+#[derive(Debug, Clone)]
+pub struct AuthResult {
+    pub uid: String,
+    /// Overrides the `user_quota` of the service config for the user.
+    pub quota: Option<Quota>,
+    /// Overrides the `max_queued_bytes` of the service config for the sessions of the user.
+    pub max_queued_bytes: Option<usize>,
+    /// Overrides the `publish_rate_limit` of the service config for the user.
+    pub publish_rate_limit: Option<RateLimit>,
+    /// Prefixes the topics published and subscribed by the client, it is stripped from the
+    /// topics of the messages delivered to the client.
+    pub mount_point: Option<String>,
+}
+
+impl From<String> for AuthResult {
+    fn from(uid: String) -> Self {
+        Self {
+            uid,
+            quota: None,
+            max_queued_bytes: None,
+            publish_rate_limit: None,
+            mount_point: None,
+        }
+    }
+}
+
+/// The result of a step of an enhanced authentication exchange.
+#[derive(Debug, Clone)]
+pub enum EnhancedAuthStep {
+    /// Sends the data to the client in an AUTH packet and waits for its response.
+    Continue(Option<Bytes>),
+    /// The client is authenticated, the data is sent in the CONNACK or the AUTH packet that
+    /// completes the exchange.
+    Success(AuthResult, Option<Bytes>),
+    /// The client is rejected.
+    Failed,
+}
+
+/// An enhanced authentication exchange (MQTT 5 AUTH packets) of a connection.
+#[async_trait::async_trait]
+pub trait EnhancedAuth: Send + Sync {
+    /// Handles the authentication data of the CONNECT packet or of an AUTH packet sent by the
+    /// client.
+    async fn step(&mut self, data: Option<Bytes>) -> PluginResult<EnhancedAuthStep>;
+}
+
 /// Represents a rsmqtt plugin
 #[allow(unused_variables, clippy::too_many_arguments)]
 #[async_trait::async_trait]
 pub trait Plugin: Send + Sync + 'static {
-    async fn auth(&self, user: &str, password: &str) -> PluginResult<Option<String>> {
+    async fn auth(&self, user: &str, password: &[u8]) -> PluginResult<Option<AuthResult>> {
         Ok(None)
     }
 
+    /// Authenticates a client that connects without a login with the certificate verified by
+    /// the TLS listener.
+    async fn auth_cert(&self, cert: &ClientCert) -> PluginResult<Option<AuthResult>> {
+        Ok(None)
+    }
+
+    /// Starts an enhanced authentication exchange, returns `None` if the plugin does not
+    /// support the authentication method.
+    ///
+    /// It is called when a client connects with an authentication method and when it
+    /// re-authenticates.
+    async fn enhanced_auth(&self, method: &str) -> PluginResult<Option<Box<dyn EnhancedAuth>>> {
+        Ok(None)
+    }
+
+    /// Rejects the client with the reason code `Banned` if it returns `true`, it is called
+    /// after the client is authenticated.
+    async fn is_banned(
+        &self,
+        remote_addr: &RemoteAddr,
+        client_id: &str,
+        uid: Option<&str>,
+    ) -> PluginResult<bool> {
+        Ok(false)
+    }
+
     async fn check_acl(
         &self,
         remote_addr: &RemoteAddr,
@@ -0,0 +1,139 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::Mutex;
+use serde::Deserialize;
+
+/// Limits of the resources used by all clients of a user, `None` means unlimited.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Quota {
+    /// Maximum number of concurrent connections.
+    pub max_sessions: Option<usize>,
+    /// Maximum number of subscriptions across all sessions.
+    pub max_subscriptions: Option<usize>,
+    /// Maximum size of the payloads queued across all sessions.
+    pub max_queued_bytes: Option<usize>,
+}
+
+/// The resources used by all clients of a user.
+#[derive(Debug, Default)]
+pub struct QuotaUsage {
+    quota: Mutex<Quota>,
+    sessions: AtomicUsize,
+    subscriptions: AtomicUsize,
+    queued_bytes: AtomicUsize,
+}
+
+#[inline]
+fn try_add(counter: &AtomicUsize, n: usize, max: Option<usize>) -> bool {
+    counter
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |value| match max {
+            Some(max) if value + n > max => None,
+            _ => Some(value + n),
+        })
+        .is_ok()
+}
+
+impl QuotaUsage {
+    pub fn new(quota: Quota) -> Self {
+        Self {
+            quota: Mutex::new(quota),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn quota(&self) -> Quota {
+        *self.quota.lock()
+    }
+
+    #[inline]
+    pub(crate) fn set_quota(&self, quota: Quota) {
+        *self.quota.lock() = quota;
+    }
+
+    #[inline]
+    pub fn sessions(&self) -> usize {
+        self.sessions.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub fn subscriptions(&self) -> usize {
+        self.subscriptions.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Returns `false` if the user already has `max_sessions` connections.
+    pub(crate) fn try_add_session(&self) -> bool {
+        try_add(&self.sessions, 1, self.quota().max_sessions)
+    }
+
+    /// Counts the session even if the quota is exceeded.
+    pub(crate) fn add_session(&self) {
+        self.sessions.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn remove_session(&self) {
+        self.sessions.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Returns `false` if the user already has `max_subscriptions` subscriptions.
+    pub fn try_add_subscription(&self) -> bool {
+        try_add(&self.subscriptions, 1, self.quota().max_subscriptions)
+    }
+
+    pub fn add_subscriptions(&self, n: usize) {
+        self.subscriptions.fetch_add(n, Ordering::SeqCst);
+    }
+
+    pub fn remove_subscriptions(&self, n: usize) {
+        self.subscriptions.fetch_sub(n, Ordering::SeqCst);
+    }
+
+    /// Returns `false` if queuing `n` bytes exceeds `max_queued_bytes`.
+    pub fn try_add_queued_bytes(&self, n: usize) -> bool {
+        try_add(&self.queued_bytes, n, self.quota().max_queued_bytes)
+    }
+
+    pub fn add_queued_bytes(&self, n: usize) {
+        self.queued_bytes.fetch_add(n, Ordering::SeqCst);
+    }
+
+    pub fn remove_queued_bytes(&self, n: usize) {
+        self.queued_bytes.fetch_sub(n, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_usage() {
+        let usage = QuotaUsage::new(Quota {
+            max_sessions: Some(1),
+            max_subscriptions: None,
+            max_queued_bytes: Some(10),
+        });
+
+        assert!(usage.try_add_session());
+        assert!(!usage.try_add_session());
+        usage.remove_session();
+        assert!(usage.try_add_session());
+
+        for _ in 0..100 {
+            assert!(usage.try_add_subscription());
+        }
+        assert_eq!(usage.subscriptions(), 100);
+
+        assert!(usage.try_add_queued_bytes(6));
+        assert!(!usage.try_add_queued_bytes(5));
+        assert!(usage.try_add_queued_bytes(4));
+        usage.remove_queued_bytes(10);
+        assert_eq!(usage.queued_bytes(), 0);
+    }
+}
@@ -0,0 +1,138 @@
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::time::Instant;
+
+/// Limits the rate of the messages published by a connection.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+pub struct RateLimit {
+    /// The number of messages per second.
+    pub messages_per_sec: u32,
+    /// The number of messages that can be published at once after an idle period, defaults to
+    /// `messages_per_sec`.
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+impl RateLimit {
+    pub(crate) fn token_bucket(&self, now: Instant) -> TokenBucket {
+        TokenBucket::new(
+            self.messages_per_sec,
+            self.burst.unwrap_or(self.messages_per_sec),
+            now,
+        )
+    }
+}
+
+/// Limits the bytes per second read from and written to each connection, a connection over the
+/// limit is paused until its rate drops below it.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BandwidthLimit {
+    /// The bytes per second of the packets received from the client, unlimited if not set.
+    pub inbound_bytes_per_sec: Option<NonZeroU32>,
+    /// The bytes per second of the packets sent to the client, unlimited if not set.
+    pub outbound_bytes_per_sec: Option<NonZeroU32>,
+}
+
+/// A token bucket holding at most `capacity` tokens, it is refilled at `rate` tokens per second.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: u32, capacity: u32, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated_at = now;
+    }
+
+    /// Takes a token, returns `false` if the bucket is empty.
+    pub(crate) fn try_acquire(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes `n` tokens even if the bucket doesn't hold them, the missing tokens are a debt
+    /// paid by the refill, see [`TokenBucket::wait_time`].
+    pub(crate) fn take(&mut self, n: usize, now: Instant) {
+        self.refill(now);
+        self.tokens -= n as f64;
+    }
+
+    /// Returns how long until the debt of the bucket is paid, the rate must not be zero.
+    pub(crate) fn wait_time(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+        let mut bucket = RateLimit {
+            messages_per_sec: 2,
+            burst: Some(3),
+        }
+        .token_bucket(now);
+        for _ in 0..3 {
+            assert!(bucket.try_acquire(now));
+        }
+        assert!(!bucket.try_acquire(now));
+
+        let now = now + Duration::from_millis(500);
+        assert!(bucket.try_acquire(now));
+        assert!(!bucket.try_acquire(now));
+
+        // the bucket holds at most `burst` tokens
+        let now = now + Duration::from_secs(10);
+        for _ in 0..3 {
+            assert!(bucket.try_acquire(now));
+        }
+        assert!(!bucket.try_acquire(now));
+    }
+
+    #[test]
+    fn test_token_bucket_debt() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(128, 128, now);
+        bucket.take(64, now);
+        assert_eq!(bucket.wait_time(now), Duration::ZERO);
+
+        bucket.take(128, now);
+        assert_eq!(bucket.wait_time(now), Duration::from_millis(500));
+
+        let now = now + Duration::from_millis(250);
+        assert_eq!(bucket.wait_time(now), Duration::from_millis(250));
+
+        let now = now + Duration::from_millis(250);
+        assert_eq!(bucket.wait_time(now), Duration::ZERO);
+    }
+}
@@ -0,0 +1,71 @@
+use codec::{ConnectReasonCode, Disconnect, DisconnectProperties, DisconnectReasonCode};
+use serde::{Deserialize, Serialize};
+
+use crate::state::Control;
+use crate::ServiceState;
+
+/// Sends the clients to another server, e.g. to migrate them to a new broker.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Redirect {
+    /// Sent to the clients as the `Server Reference`, usually the address of the other server.
+    pub server_reference: String,
+    /// The server has moved permanently (`ServerMoved`), otherwise the clients use the other
+    /// server temporarily (`UseAnotherServer`).
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+impl Redirect {
+    pub(crate) fn connect_reason_code(&self) -> ConnectReasonCode {
+        if self.permanent {
+            ConnectReasonCode::ServerMoved
+        } else {
+            ConnectReasonCode::UseAnotherServer
+        }
+    }
+
+    pub(crate) fn disconnect(&self) -> Disconnect {
+        Disconnect {
+            reason_code: if self.permanent {
+                DisconnectReasonCode::ServerMoved
+            } else {
+                DisconnectReasonCode::UseAnotherServer
+            },
+            properties: DisconnectProperties {
+                server_reference: Some(self.server_reference.clone().into()),
+                ..DisconnectProperties::default()
+            },
+        }
+    }
+}
+
+impl ServiceState {
+    /// Returns the redirect of the new connections.
+    pub fn redirect(&self) -> Option<Redirect> {
+        self.redirect.read().clone()
+    }
+
+    /// Rejects the new connections with the redirect, `None` accepts them again.
+    ///
+    /// The connected clients are not affected, see [`ServiceState::redirect_clients`].
+    pub fn set_redirect(&self, redirect: Option<Redirect>) {
+        *self.redirect.write() = redirect;
+    }
+
+    /// Disconnects all connected clients with the redirect, returns the number of clients.
+    ///
+    /// The MQTT 3.1.1 clients are disconnected without a reason.
+    pub async fn redirect_clients(&self, redirect: &Redirect) -> usize {
+        self.connections
+            .read()
+            .await
+            .values()
+            .filter(|handle| {
+                handle
+                    .control_sender
+                    .send(Control::Redirect(redirect.clone()))
+                    .is_ok()
+            })
+            .count()
+    }
+}
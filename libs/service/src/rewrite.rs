@@ -3,11 +3,17 @@ use std::borrow::Cow;
 use anyhow::Result;
 use regex::Regex;
 
-use crate::config::RewriteConfig;
+use crate::config::{RewriteConfig, RewriteTarget};
 
 pub struct Rewrite {
     re: Regex,
     rep: String,
+    target: RewriteTarget,
+}
+
+/// Escapes the `$` of a placeholder value, which the regex replacement would expand.
+fn escape(value: &str) -> String {
+    value.replace('$', "$$")
 }
 
 impl Rewrite {
@@ -15,11 +21,24 @@ impl Rewrite {
         Ok(Self {
             re: Regex::new(&rewrite.pattern)?,
             rep: rewrite.write.clone(),
+            target: rewrite.target,
         })
     }
 
-    pub fn rewrite(&self, topic: &str) -> Option<String> {
-        match self.re.replace(&*topic, &self.rep) {
+    #[inline]
+    pub fn applies_to(&self, target: RewriteTarget) -> bool {
+        self.target == RewriteTarget::All || self.target == target
+    }
+
+    pub fn rewrite(&self, topic: &str, client_id: &str, uid: Option<&str>) -> Option<String> {
+        let mut rep = Cow::Borrowed(self.rep.as_str());
+        if rep.contains("%c") {
+            rep = Cow::Owned(rep.replace("%c", &escape(client_id)));
+        }
+        if rep.contains("%u") {
+            rep = Cow::Owned(rep.replace("%u", &escape(uid?)));
+        }
+        match self.re.replace(topic, &*rep) {
             Cow::Borrowed(_) => None,
             Cow::Owned(new_topic) => Some(new_topic),
         }
@@ -35,21 +54,41 @@ mod tests {
         let rewrite = Rewrite::try_new(&RewriteConfig {
             pattern: "a/(.*)/c".to_string(),
             write: "k/$1/c".to_string(),
+            target: RewriteTarget::Publish,
         })
         .unwrap();
 
-        assert_eq!(rewrite.rewrite("a/1/c").unwrap(), "k/1/c");
+        assert_eq!(rewrite.rewrite("a/1/c", "c1", None).unwrap(), "k/1/c");
 
         let rewrite = Rewrite::try_new(&RewriteConfig {
             pattern: "a/(.*)".to_string(),
             write: "k/$1".to_string(),
+            target: RewriteTarget::Publish,
         })
         .unwrap();
 
-        assert_eq!(rewrite.rewrite("a/1/c").unwrap(), "k/1/c");
-        assert_eq!(rewrite.rewrite("a/c").unwrap(), "k/c");
-        assert_eq!(rewrite.rewrite("a/c/1/2/3").unwrap(), "k/c/1/2/3");
+        assert_eq!(rewrite.rewrite("a/1/c", "c1", None).unwrap(), "k/1/c");
+        assert_eq!(rewrite.rewrite("a/c", "c1", None).unwrap(), "k/c");
+        assert_eq!(
+            rewrite.rewrite("a/c/1/2/3", "c1", None).unwrap(),
+            "k/c/1/2/3"
+        );
+
+        assert_eq!(rewrite.rewrite("d/c/1/2/3", "c1", None), None);
+    }
+
+    #[test]
+    fn test_rewrite_placeholders() {
+        let rewrite = Rewrite::try_new(&RewriteConfig {
+            pattern: "^a/(.*)$".to_string(),
+            write: "%u/%c/$1".to_string(),
+            target: RewriteTarget::All,
+        })
+        .unwrap();
 
-        assert_eq!(rewrite.rewrite("d/c/1/2/3"), None);
+        assert!(rewrite.applies_to(RewriteTarget::Subscribe));
+        assert_eq!(rewrite.rewrite("a/b", "c1", Some("u1")).unwrap(), "u1/c1/b");
+        assert_eq!(rewrite.rewrite("a/b", "$1", Some("u1")).unwrap(), "u1/$1/b");
+        assert_eq!(rewrite.rewrite("a/b", "c1", None), None);
     }
 }
@@ -1,18 +1,24 @@
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
-use bytestring::ByteString;
 use tokio::sync::{mpsc, watch, Mutex, RwLock};
+use tokio::time::Instant;
 use tokio_stream::Stream;
 
-use crate::config::ServiceConfig;
+use crate::admin::{Ban, ClientInfo};
+use crate::config::{RewriteTarget, ServiceConfig};
+use crate::disconnects::DisconnectRecord;
+use crate::filter_util::{self, Filter};
 use crate::metrics::{Metrics, MetricsCalc};
 use crate::plugin::Plugin;
+use crate::quota::{Quota, QuotaUsage};
+use crate::redirect::Redirect;
 use crate::rewrite::Rewrite;
-use crate::storage::Storage;
+use crate::storage::{Storage, StorageMemory};
+use crate::trace::Tracer;
 
 #[derive(Debug, Default)]
 pub struct ServiceMetrics {
@@ -25,6 +31,7 @@ pub struct ServiceMetrics {
     pub pub_msgs_received: AtomicUsize,
     pub pub_msgs_sent: AtomicUsize,
     pub msgs_dropped: AtomicUsize,
+    pub subscriptions_rejected: AtomicUsize,
     pub socket_connections: AtomicUsize,
     pub connection_count: AtomicUsize,
 }
@@ -75,6 +82,12 @@ impl ServiceMetrics {
         self.msgs_dropped.fetch_add(value, Ordering::SeqCst);
     }
 
+    #[inline]
+    pub fn inc_subscriptions_rejected(&self, value: usize) {
+        self.subscriptions_rejected
+            .fetch_add(value, Ordering::SeqCst);
+    }
+
     #[inline]
     pub fn inc_socket_connections(&self, value: usize) {
         self.socket_connections.fetch_add(value, Ordering::SeqCst);
@@ -99,24 +112,58 @@ impl ServiceMetrics {
 #[derive(Debug)]
 pub enum Control {
     SessionTakenOver,
+    Kick,
+    Redirect(Redirect),
+}
+
+pub(crate) struct ConnectionHandle {
+    pub(crate) control_sender: mpsc::UnboundedSender<Control>,
+    pub(crate) info: ClientInfo,
 }
 
 pub struct ServiceState {
     pub config: ServiceConfig,
-    pub(crate) connections: RwLock<HashMap<String, mpsc::UnboundedSender<Control>>>,
-    pub(crate) storage: Storage,
+    pub(crate) connections: RwLock<HashMap<String, ConnectionHandle>>,
+    pub(crate) storage: Arc<dyn Storage>,
     pub(crate) service_metrics: Arc<ServiceMetrics>,
     pub(crate) plugins: Vec<(&'static str, Arc<dyn Plugin>)>,
+    quota_usages: parking_lot::Mutex<HashMap<String, Arc<QuotaUsage>>>,
+    /// The bans and when they expire.
+    pub(crate) bans: parking_lot::RwLock<HashMap<Ban, Option<Instant>>>,
+    pub(crate) tracers: parking_lot::RwLock<Vec<Arc<Tracer>>>,
+    pub(crate) disconnect_history: parking_lot::Mutex<HashMap<String, VecDeque<DisconnectRecord>>>,
+    /// The bits of the `f64` overload level.
+    pub(crate) overload: AtomicU64,
+    pub(crate) overloaded: AtomicBool,
+    pub(crate) redirect: parking_lot::RwLock<Option<Redirect>>,
+    /// The holders of the exclusive subscriptions by topic filter.
+    exclusive_subscriptions: parking_lot::Mutex<HashMap<String, String>>,
     rewrites: Vec<Rewrite>,
     metrics_calc: Mutex<MetricsCalc>,
     metrics_sender: watch::Sender<Metrics>,
     metrics_receiver: watch::Receiver<Metrics>,
 }
 
+fn check_topic_filter(filter: &str) -> Result<()> {
+    match filter_util::parse_filter(filter) {
+        Some(filter) if filter.share_name.is_none() => Ok(()),
+        Some(_) => anyhow::bail!("shared subscriptions are not allowed"),
+        None => anyhow::bail!("malformed topic filter"),
+    }
+}
+
 impl ServiceState {
     pub fn new(
         config: ServiceConfig,
         plugins: Vec<(&'static str, Arc<dyn Plugin>)>,
+    ) -> Result<Arc<Self>> {
+        Self::with_storage(config, plugins, Arc::new(StorageMemory::default()))
+    }
+
+    pub fn with_storage(
+        config: ServiceConfig,
+        plugins: Vec<(&'static str, Arc<dyn Plugin>)>,
+        storage: Arc<dyn Storage>,
     ) -> Result<Arc<Self>> {
         let (stat_sender, stat_receiver) = watch::channel(Metrics::default());
         let mut rewrites = Vec::new();
@@ -128,13 +175,34 @@ impl ServiceState {
                 })?);
         }
 
+        for limit_cfg in &config.payload_limits {
+            check_topic_filter(&limit_cfg.filter)
+                .with_context(|| format!("invalid payload limit filter: {}", limit_cfg.filter))?;
+        }
+
+        for filter in &config.priority_topics {
+            check_topic_filter(filter)
+                .with_context(|| format!("invalid priority topic filter: {}", filter))?;
+        }
+
+        storage.set_shared_subscription_strategy(config.shared_subscription_strategy);
+
+        let redirect = config.redirect.clone();
         let state = Arc::new(Self {
             config,
             connections: RwLock::new(HashMap::new()),
-            storage: Storage::default(),
+            storage,
             service_metrics: Arc::new(ServiceMetrics::default()),
             metrics_sender: stat_sender,
             plugins,
+            quota_usages: parking_lot::Mutex::new(HashMap::new()),
+            bans: parking_lot::RwLock::new(HashMap::new()),
+            tracers: parking_lot::RwLock::new(Vec::new()),
+            disconnect_history: parking_lot::Mutex::new(HashMap::new()),
+            overload: AtomicU64::new(0),
+            overloaded: AtomicBool::new(false),
+            redirect: parking_lot::RwLock::new(redirect),
+            exclusive_subscriptions: parking_lot::Mutex::new(HashMap::new()),
             rewrites,
             metrics_receiver: stat_receiver,
             metrics_calc: Mutex::new(MetricsCalc::new()),
@@ -150,24 +218,188 @@ impl ServiceState {
             }
         });
 
+        if state.config.expired_messages_interval > 0 {
+            tokio::spawn({
+                let state = state.clone();
+                let interval = Duration::from_secs(state.config.expired_messages_interval);
+                async move {
+                    loop {
+                        tokio::time::sleep(interval).await;
+                        let count = state.storage.remove_expired_messages();
+                        if count > 0 {
+                            tracing::debug!(count = count, "expired messages removed");
+                            state.service_metrics.inc_msg_dropped(count);
+                        }
+                    }
+                }
+            });
+        }
+
+        if state.config.overload.is_enabled() {
+            tokio::spawn({
+                let state = state.clone();
+                async move {
+                    loop {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        state.update_overload(&state.storage.metrics());
+                    }
+                }
+            });
+        }
+
         Ok(state)
     }
 
-    pub(crate) fn rewrite(&self, topic: &mut ByteString) {
-        for rewrite in &self.rewrites {
-            if let Some(new_topic) = rewrite.rewrite(topic) {
-                *topic = new_topic.into();
-                break;
+    /// Returns the topic rewritten by the first matching rule of `target`.
+    pub(crate) fn rewrite(
+        &self,
+        target: RewriteTarget,
+        topic: &str,
+        client_id: &str,
+        uid: Option<&str>,
+    ) -> Option<String> {
+        self.rewrites
+            .iter()
+            .filter(|rewrite| rewrite.applies_to(target))
+            .find_map(|rewrite| rewrite.rewrite(topic, client_id, uid))
+    }
+
+    /// Returns the topic filter rewritten by the first matching subscribe rule, keeping the
+    /// prefix of a shared or exclusive subscription.
+    pub(crate) fn rewrite_filter(
+        &self,
+        filter: &str,
+        client_id: &str,
+        uid: Option<&str>,
+    ) -> Option<String> {
+        match filter_util::parse_filter(filter) {
+            Some(Filter {
+                share_name: Some(share_name),
+                path,
+                ..
+            }) => self
+                .rewrite(RewriteTarget::Subscribe, path, client_id, uid)
+                .map(|path| format!("$share/{}/{}", share_name, path)),
+            Some(Filter {
+                exclusive: true,
+                path,
+                ..
+            }) => self
+                .rewrite(RewriteTarget::Subscribe, path, client_id, uid)
+                .map(|path| format!("$exclusive/{}", path)),
+            _ => self.rewrite(RewriteTarget::Subscribe, filter, client_id, uid),
+        }
+    }
+
+    pub(crate) fn payload_limit(&self, topic: &str) -> Option<usize> {
+        self.config
+            .payload_limits
+            .iter()
+            .find(|limit| filter_util::matches_topic(&limit.filter, topic))
+            .map(|limit| limit.max_size)
+    }
+
+    pub(crate) fn is_priority_topic(&self, topic: &str) -> bool {
+        self.config
+            .priority_topics
+            .iter()
+            .any(|filter| filter_util::matches_topic(filter, topic))
+    }
+
+    /// Makes the client the holder of the exclusive subscription, returns `false` if another
+    /// client holds it.
+    pub(crate) fn acquire_exclusive_subscription(&self, path: &str, client_id: &str) -> bool {
+        let mut exclusive_subscriptions = self.exclusive_subscriptions.lock();
+        match exclusive_subscriptions.get(path) {
+            Some(holder) => holder == client_id,
+            None => {
+                exclusive_subscriptions.insert(path.to_string(), client_id.to_string());
+                true
+            }
+        }
+    }
+
+    /// Releases the exclusive subscription if the client holds it.
+    pub(crate) fn release_exclusive_subscription(&self, path: &str, client_id: &str) {
+        let mut exclusive_subscriptions = self.exclusive_subscriptions.lock();
+        if exclusive_subscriptions.get(path).map(String::as_str) == Some(client_id) {
+            exclusive_subscriptions.remove(path);
+        }
+    }
+
+    /// Releases all the exclusive subscriptions held by the client, returns their topic filters.
+    pub(crate) fn release_exclusive_subscriptions(&self, client_id: &str) -> Vec<String> {
+        let mut paths = Vec::new();
+        self.exclusive_subscriptions.lock().retain(|path, holder| {
+            if holder == client_id {
+                paths.push(path.clone());
+                false
+            } else {
+                true
+            }
+        });
+        paths
+    }
+
+    /// Returns the resources used by the clients of the user, `None` if the user never connected.
+    pub fn quota_usage(&self, uid: &str) -> Option<Arc<QuotaUsage>> {
+        self.quota_usages.lock().get(uid).cloned()
+    }
+
+    /// Returns the usage of the user with the limits replaced by `quota`.
+    pub(crate) fn update_quota(&self, uid: &str, quota: Quota) -> Arc<QuotaUsage> {
+        let mut quota_usages = self.quota_usages.lock();
+        match quota_usages.get(uid) {
+            Some(usage) => {
+                usage.set_quota(quota);
+                usage.clone()
+            }
+            None => {
+                let usage = Arc::new(QuotaUsage::new(quota));
+                quota_usages.insert(uid.to_string(), usage.clone());
+                usage
             }
         }
     }
 
+    /// Exports the session of a disconnected client as a portable blob, returns `None` if the
+    /// session does not exist.
+    pub async fn export_session(&self, client_id: &str) -> Result<Option<Vec<u8>>> {
+        anyhow::ensure!(
+            !self.connections.read().await.contains_key(client_id),
+            "client is connected: {}",
+            client_id
+        );
+        match self.storage.export_session(client_id) {
+            Some(snapshot) => Ok(Some(serde_yaml::to_vec(&snapshot)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Imports a session exported by [`ServiceState::export_session`], the existing session of
+    /// the client is replaced.
+    pub async fn import_session(&self, client_id: &str, data: &[u8]) -> Result<()> {
+        anyhow::ensure!(
+            !self.connections.read().await.contains_key(client_id),
+            "client is connected: {}",
+            client_id
+        );
+        let snapshot = serde_yaml::from_slice(data).context("invalid session data")?;
+        self.storage.import_session(client_id, snapshot);
+        Ok(())
+    }
+
     pub async fn update_metrics(&self) {
-        let metrics = self
+        let storage_metrics = self.storage.metrics();
+        if self.config.overload.is_enabled() {
+            self.update_overload(&storage_metrics);
+        }
+        let mut metrics = self
             .metrics_calc
             .lock()
             .await
-            .update(&self.service_metrics, &self.storage.metrics());
+            .update(&self.service_metrics, &storage_metrics);
+        metrics.overload = self.overload();
         self.metrics_sender.send(metrics).ok();
     }
 
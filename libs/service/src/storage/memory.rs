@@ -0,0 +1,1427 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::num::{NonZeroU16, NonZeroUsize};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use codec::{LastWill, Publish, Qos, RetainHandling};
+use parking_lot::{Mutex, RwLock};
+use serde_yaml::Value;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::filter_util::{self, Filter};
+use crate::message::Message;
+use crate::quota::QuotaUsage;
+use crate::storage::{
+    FilterItem, QueueFullPolicy, QueueLimit, SessionSnapshot, SharedSubscriptionStrategy, Storage,
+    StorageFactory, StorageMetrics,
+};
+use crate::trie::Trie;
+
+/// A change of the sessions recorded by [`StorageMemory::with_journal`], so a storage that
+/// persists the sessions only writes what has changed.
+///
+/// The queued messages and inflight packets of a session are identified by a sequence number,
+/// it grows with every message and packet added to the session.
+#[derive(Debug, Clone)]
+pub enum SessionChange {
+    /// The session was created or the settings exported by
+    /// [`StorageMemory::export_session_settings`] have changed.
+    Updated(String),
+    /// The session was removed with its queued messages and inflight packets.
+    Removed(String),
+    /// A message was queued for the session.
+    Queued {
+        client_id: String,
+        seq: u64,
+        msg: Message,
+    },
+    /// A queued message was taken, dropped or has expired.
+    Dequeued {
+        client_id: String,
+        seq: u64,
+    },
+    InflightAdded {
+        client_id: String,
+        seq: u64,
+        publish: Publish,
+    },
+    InflightRemoved {
+        client_id: String,
+        seq: u64,
+    },
+}
+
+/// Records the changes of the sessions if it is enabled.
+#[derive(Default)]
+struct Journal(Option<Mutex<Vec<SessionChange>>>);
+
+impl Journal {
+    #[inline]
+    fn record(&self, f: impl FnOnce() -> SessionChange) {
+        if let Some(changes) = &self.0 {
+            changes.lock().push(f());
+        }
+    }
+}
+
+struct Session {
+    queue: VecDeque<(u64, Message)>,
+    /// Messages of the priority topics, taken before the messages in `queue`.
+    priority_queue: VecDeque<(u64, Message)>,
+    /// The size of the payloads in the queues.
+    queued_bytes: usize,
+    notify: Arc<Notify>,
+    last_will: Option<LastWill>,
+    inflight_pub_packets: VecDeque<(u64, Publish)>,
+    /// The sequence number of the next queued message or inflight packet.
+    next_seq: u64,
+    last_will_timeout_key: Option<TimeoutKey>,
+    remove_timeout_key: Option<TimeoutKey>,
+    quota: Option<Arc<QuotaUsage>>,
+    queue_limit: QueueLimit,
+    /// A message was dropped with [`QueueFullPolicy::Disconnect`].
+    queue_overflow: bool,
+    /// The number of subscriptions of the session.
+    subscriptions: usize,
+    /// The expiry interval of the connection, exported while the session is connected.
+    session_expiry_interval: u32,
+}
+
+impl Session {
+    /// Returns `false` if there is no filter item, so the message does not match the session.
+    ///
+    /// The messages dropped because the queue is full are counted in `dropped`.
+    #[inline]
+    fn add_message<'a>(
+        &mut self,
+        client_id: &str,
+        msg: &Message,
+        filter_items: impl IntoIterator<Item = &'a FilterItem>,
+        dropped: &AtomicUsize,
+        journal: &Journal,
+    ) -> bool {
+        let mut filter_items = filter_items.into_iter();
+        let first_item = match filter_items.next() {
+            Some(first_item) => first_item,
+            None => return false,
+        };
+        let mut qos = first_item.qos;
+        let mut retain_as_published = first_item.retain_as_published;
+        let mut ids = first_item.id.into_iter().collect::<Vec<_>>();
+
+        for item in filter_items {
+            // When Clients make subscriptions with Topic Filters that include wildcards, it is possible
+            // for a Client’s subscriptions to overlap so that a published message might match multiple filters.
+            // In this case the Server MUST deliver the message to the Client respecting the maximum QoS of all
+            // the matching subscriptions [MQTT-3.3.4-2].
+            qos = qos.max(item.qos);
+
+            retain_as_published &= item.retain_as_published;
+
+            // If the Client specified a Subscription Identifier for any of the overlapping
+            // subscriptions the Server MUST send those Subscription Identifiers in the message
+            // which is published as the result of the subscriptions [MQTT-3.3.4-3].
+            //
+            // If the Server sends a single copy of the message it MUST include in the PUBLISH packet
+            // the Subscription Identifiers for all matching subscriptions which have a Subscription Identifiers,
+            // their order is not significant [MQTT-3.3.4-4].
+            ids.extend(item.id.into_iter());
+        }
+
+        let mut new_msg = Message::new(
+            msg.topic().clone(),
+            msg.qos().min(qos),
+            msg.payload().clone(),
+        )
+        .with_properties({
+            let mut properties = msg.properties().clone();
+            properties.subscription_identifiers = ids;
+            properties
+        });
+
+        if retain_as_published {
+            new_msg = new_msg.with_retain(msg.is_retain());
+        }
+
+        let len = new_msg.payload().len();
+        if self.is_queue_full(len) {
+            tracing::debug!(
+                topic = %new_msg.topic(),
+                policy = ?self.queue_limit.policy,
+                "message dropped, queue is full",
+            );
+            match self.queue_limit.policy {
+                QueueFullPolicy::DropOldest => {
+                    // the message alone exceeds the limit, the queued ones are kept
+                    if matches!(self.queue_limit.max_bytes, Some(max_bytes) if len > max_bytes) {
+                        dropped.fetch_add(1, AtomicOrdering::Relaxed);
+                        return true;
+                    }
+                    while self.is_queue_full(len) {
+                        dropped.fetch_add(1, AtomicOrdering::Relaxed);
+                        let oldest = self
+                            .queue
+                            .pop_front()
+                            .or_else(|| self.priority_queue.pop_front());
+                        match oldest {
+                            Some((seq, oldest)) => {
+                                self.queued_bytes -= oldest.payload().len();
+                                if let Some(quota) = &self.quota {
+                                    quota.remove_queued_bytes(oldest.payload().len());
+                                }
+                                journal.record(|| SessionChange::Dequeued {
+                                    client_id: client_id.to_string(),
+                                    seq,
+                                });
+                            }
+                            // the limit admits no message
+                            None => return true,
+                        }
+                    }
+                }
+                QueueFullPolicy::DropNewest => {
+                    dropped.fetch_add(1, AtomicOrdering::Relaxed);
+                    return true;
+                }
+                QueueFullPolicy::Disconnect => {
+                    dropped.fetch_add(1, AtomicOrdering::Relaxed);
+                    self.queue_overflow = true;
+                    self.notify.notify_one();
+                    return true;
+                }
+            }
+        }
+
+        if let Some(quota) = &self.quota {
+            if !quota.try_add_queued_bytes(new_msg.payload().len()) {
+                tracing::debug!(
+                    topic = %new_msg.topic(),
+                    "message dropped, queued bytes quota exceeded",
+                );
+                dropped.fetch_add(1, AtomicOrdering::Relaxed);
+                return true;
+            }
+        }
+
+        if msg.is_priority() {
+            new_msg = new_msg.with_priority(true);
+        }
+        let seq = self.take_seq();
+        journal.record(|| SessionChange::Queued {
+            client_id: client_id.to_string(),
+            seq,
+            msg: new_msg.clone(),
+        });
+        self.push_message(seq, new_msg);
+        self.notify.notify_one();
+        true
+    }
+
+    #[inline]
+    fn push_message(&mut self, seq: u64, msg: Message) {
+        self.queued_bytes += msg.payload().len();
+        if msg.is_priority() {
+            self.priority_queue.push_back((seq, msg));
+        } else {
+            self.queue.push_back((seq, msg));
+        }
+    }
+
+    /// Takes the next message to send, the messages of the priority topics first.
+    #[inline]
+    fn pop_message(&mut self) -> Option<(u64, Message)> {
+        let (seq, msg) = self
+            .priority_queue
+            .pop_front()
+            .or_else(|| self.queue.pop_front())?;
+        self.queued_bytes -= msg.payload().len();
+        Some((seq, msg))
+    }
+
+    #[inline]
+    fn take_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    /// Removes the expired messages from the queues and returns their number.
+    fn remove_expired_messages(&mut self, client_id: &str, journal: &Journal) -> usize {
+        let mut count = 0;
+        let mut bytes = 0;
+        let mut remove = |queue: &mut VecDeque<(u64, Message)>| {
+            queue.retain(|(seq, msg)| {
+                if msg.is_expired() {
+                    count += 1;
+                    bytes += msg.payload().len();
+                    journal.record(|| SessionChange::Dequeued {
+                        client_id: client_id.to_string(),
+                        seq: *seq,
+                    });
+                    false
+                } else {
+                    true
+                }
+            })
+        };
+        remove(&mut self.priority_queue);
+        remove(&mut self.queue);
+
+        self.queued_bytes -= bytes;
+        if let Some(quota) = &self.quota {
+            quota.remove_queued_bytes(bytes);
+        }
+        count
+    }
+
+    #[inline]
+    fn queued_messages(&self) -> impl Iterator<Item = &Message> {
+        self.priority_queue
+            .iter()
+            .chain(&self.queue)
+            .map(|(_, msg)| msg)
+    }
+
+    /// Returns `true` if a message with a payload of `len` bytes exceeds the queue limit.
+    fn is_queue_full(&self, len: usize) -> bool {
+        let max_messages_reached = match self.queue_limit.max_messages {
+            Some(max_messages) => self.queue.len() + self.priority_queue.len() >= max_messages,
+            None => false,
+        };
+        let max_bytes_exceeded = match self.queue_limit.max_bytes {
+            Some(max_bytes) => self.queued_bytes + len > max_bytes,
+            None => false,
+        };
+        max_messages_reached || max_bytes_exceeded
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct TimeoutKey {
+    client_id: String,
+    timeout: Instant,
+}
+
+impl PartialOrd for TimeoutKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match self.timeout.cmp(&other.timeout) {
+            Ordering::Less => Some(Ordering::Less),
+            Ordering::Greater => Some(Ordering::Greater),
+            Ordering::Equal => self.client_id.partial_cmp(&other.client_id),
+        }
+    }
+}
+
+impl Ord for TimeoutKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.partial_cmp(other).unwrap()
+    }
+}
+
+#[derive(Default)]
+struct StorageInner {
+    sessions: HashMap<String, RwLock<Session>>,
+    filter_tree: Trie,
+    send_last_will_timeout: BTreeSet<TimeoutKey>,
+    remove_timeout: BTreeSet<TimeoutKey>,
+    clients_expired: usize,
+    messages_dropped: AtomicUsize,
+    shared_subscription_strategy: SharedSubscriptionStrategy,
+    /// The member chosen by the share group for the publisher or topic of the sticky strategies.
+    sticky_members: Mutex<HashMap<(String, String), String>>,
+    journal: Journal,
+}
+
+/// If no local is true, Application Messages MUST NOT be forwarded to a connection with a
+/// ClientID equal to the ClientID of the publishing connection [MQTT-3.8.3-3]
+fn is_forwarded(filter_item: &FilterItem, msg: &Message, client_id: &str) -> bool {
+    !filter_item.no_local || msg.from_client_id().map(|s| &**s) != Some(client_id)
+}
+
+impl StorageInner {
+    /// Returns the number of sessions the messages were queued for.
+    pub fn deliver(&self, msgs: impl IntoIterator<Item = Message>) -> usize {
+        let mut count = 0;
+        for msg in msgs {
+            if msg.is_expired() {
+                continue;
+            }
+
+            for (client_id, filter_items) in self.filter_tree.matches(msg.topic()) {
+                let filter_items = filter_items
+                    .into_iter()
+                    .filter(|filter_item| is_forwarded(filter_item, &msg, client_id));
+
+                if let Some(session) = self.sessions.get(client_id) {
+                    let mut session = session.write();
+                    count += session.add_message(
+                        client_id,
+                        &msg,
+                        filter_items,
+                        &self.messages_dropped,
+                        &self.journal,
+                    ) as usize;
+                }
+            }
+
+            let choose = |share_name: &str, members: &[&str]| {
+                self.choose_shared_member(&msg, share_name, members)
+            };
+            for (client_id, filter_items) in self.filter_tree.matches_shared(msg.topic(), choose) {
+                if let Some(session) = self.sessions.get(client_id) {
+                    let mut session = session.write();
+                    count += session.add_message(
+                        client_id,
+                        &msg,
+                        filter_items,
+                        &self.messages_dropped,
+                        &self.journal,
+                    ) as usize;
+                }
+            }
+        }
+        count
+    }
+
+    /// Returns the number of sessions the message would be queued for.
+    fn count_matches(&self, msg: &Message) -> usize {
+        let count = self
+            .filter_tree
+            .matches(msg.topic())
+            .filter(|(client_id, filter_items)| {
+                filter_items
+                    .iter()
+                    .any(|filter_item| is_forwarded(filter_item, msg, client_id))
+            })
+            .count();
+        let choose = |_: &str, _: &[&str]| 0;
+        count + self.filter_tree.matches_shared(msg.topic(), choose).count()
+    }
+
+    /// Returns the index of the member of the share group that receives the message.
+    fn choose_shared_member(&self, msg: &Message, share_name: &str, members: &[&str]) -> usize {
+        let key = match self.shared_subscription_strategy {
+            SharedSubscriptionStrategy::Random => return fastrand::usize(..members.len()),
+            SharedSubscriptionStrategy::StickyPublisher => {
+                msg.from_client_id().map(|s| &**s).unwrap_or_default()
+            }
+            SharedSubscriptionStrategy::StickyTopic => &**msg.topic(),
+        };
+
+        let mut sticky_members = self.sticky_members.lock();
+        let key = (share_name.to_string(), key.to_string());
+        if let Some(idx) = sticky_members
+            .get(&key)
+            .and_then(|member| members.iter().position(|m| m == member))
+        {
+            return idx;
+        }
+        let idx = fastrand::usize(..members.len());
+        sticky_members.insert(key, members[idx].to_string());
+        idx
+    }
+
+    fn disconnect_session(
+        &mut self,
+        client_id: &str,
+        session_expiry_interval: u32,
+        last_will: Option<LastWill>,
+    ) {
+        let mut send_last_will_timeout = None;
+        let mut remove_timeout = None;
+
+        self.sticky_members
+            .get_mut()
+            .retain(|_, member| member != client_id);
+
+        if let Some(session) = self.sessions.get(client_id) {
+            let mut session = session.write();
+            let now = Instant::now();
+
+            // the last will is removed by a DISCONNECT packet with reason code 0x00
+            session.last_will = last_will;
+
+            if let Some(interval) = session.last_will.as_ref().map(|last_will| {
+                last_will
+                    .properties
+                    .delay_interval
+                    .unwrap_or_default()
+                    .min(session_expiry_interval)
+            }) {
+                let key = TimeoutKey {
+                    client_id: client_id.to_string(),
+                    timeout: now + Duration::from_secs(interval as u64),
+                };
+                send_last_will_timeout = Some(key.clone());
+                session.last_will_timeout_key = Some(key);
+            }
+
+            let key = TimeoutKey {
+                client_id: client_id.to_string(),
+                timeout: now + Duration::from_secs(session_expiry_interval as u64),
+            };
+            remove_timeout = Some(key.clone());
+            session.remove_timeout_key = Some(key);
+            self.journal
+                .record(|| SessionChange::Updated(client_id.to_string()));
+        }
+
+        if let Some(send_last_will_timeout) = send_last_will_timeout {
+            self.send_last_will_timeout.insert(send_last_will_timeout);
+        }
+
+        if let Some(remove_timeout) = remove_timeout {
+            self.remove_timeout.insert(remove_timeout);
+        }
+    }
+
+    /// Removes the session, a last will waiting for its delay is sent because the session ends
+    /// first.
+    fn remove_session(&mut self, client_id: &str) {
+        let subscriptions = self.filter_tree.unsubscribe_all(client_id);
+        if let Some(session) = self.sessions.remove(client_id) {
+            self.journal
+                .record(|| SessionChange::Removed(client_id.to_string()));
+            let mut session = session.into_inner();
+            if let Some(key) = &session.remove_timeout_key {
+                self.remove_timeout.remove(key);
+            }
+            if let Some(quota) = &session.quota {
+                quota.remove_subscriptions(subscriptions);
+                quota.remove_queued_bytes(session.queued_bytes);
+            }
+            if let Some(key) = session.last_will_timeout_key.take() {
+                self.send_last_will_timeout.remove(&key);
+                if let Some(last_will) = session.last_will.take() {
+                    self.send_last_will(client_id, last_will);
+                }
+            }
+        }
+    }
+
+    fn send_last_will(&self, client_id: &str, last_will: LastWill) {
+        tracing::debug!(
+            publisher = %client_id,
+            topic = %last_will.topic,
+            "send last will message",
+        );
+        self.deliver(std::iter::once(Message::from_last_will(last_will)));
+    }
+
+    /// Moves the subscriptions and queued messages of the session to another quota, when it is
+    /// resumed by a different user.
+    fn set_session_quota(&mut self, client_id: &str, quota: Option<Arc<QuotaUsage>>) {
+        let mut session = match self.sessions.get(client_id) {
+            Some(session) => session.write(),
+            None => return,
+        };
+        let unchanged = match (&session.quota, &quota) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        if unchanged {
+            return;
+        }
+
+        let subscriptions = self.filter_tree.subscriptions(client_id).len();
+        let queued_bytes = session.queued_bytes;
+        if let Some(old) = &session.quota {
+            old.remove_subscriptions(subscriptions);
+            old.remove_queued_bytes(queued_bytes);
+        }
+        if let Some(new) = &quota {
+            new.add_subscriptions(subscriptions);
+            new.add_queued_bytes(queued_bytes);
+        }
+        session.quota = quota;
+    }
+}
+
+/// Creates a [`StorageMemory`], it has no options.
+pub struct StorageMemoryFactory;
+
+#[async_trait::async_trait]
+impl StorageFactory for StorageMemoryFactory {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn create(&self, _config: Value) -> anyhow::Result<Arc<dyn Storage>> {
+        Ok(Arc::new(StorageMemory::default()))
+    }
+}
+
+/// Keeps all sessions and retained messages in memory.
+#[derive(Default)]
+pub struct StorageMemory {
+    inner: RwLock<StorageInner>,
+}
+
+impl StorageMemory {
+    /// Creates a storage that records the changes of the sessions, they are taken with
+    /// [`StorageMemory::take_changes`].
+    pub fn with_journal() -> Self {
+        let storage = Self::default();
+        storage.inner.write().journal = Journal(Some(Mutex::new(Vec::new())));
+        storage
+    }
+
+    /// Takes the changes of the sessions recorded since the last call, in the order they were
+    /// made.
+    pub fn take_changes(&self) -> Vec<SessionChange> {
+        match &self.inner.read().journal.0 {
+            Some(changes) => std::mem::take(&mut *changes.lock()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Like [`Storage::export_session`], but without the queued messages and inflight packets.
+    pub fn export_session_settings(&self, client_id: &str) -> Option<SessionSnapshot> {
+        self.export(client_id, false)
+    }
+
+    fn export(&self, client_id: &str, with_messages: bool) -> Option<SessionSnapshot> {
+        let inner = self.inner.read();
+        let session = inner.sessions.get(client_id)?.read();
+        let now = Instant::now();
+
+        let (queue, inflight_pub_packets) = if with_messages {
+            (
+                session.queued_messages().cloned().collect(),
+                session
+                    .inflight_pub_packets
+                    .iter()
+                    .map(|(_, publish)| publish.clone())
+                    .collect(),
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        Some(SessionSnapshot {
+            subscriptions: inner.filter_tree.subscriptions(client_id),
+            queue,
+            inflight_pub_packets,
+            last_will: session.last_will.clone(),
+            session_expiry_interval: session
+                .remove_timeout_key
+                .as_ref()
+                .map(|key| key.timeout.saturating_duration_since(now).as_secs() as u32)
+                .unwrap_or(session.session_expiry_interval),
+        })
+    }
+}
+
+impl Storage for StorageMemory {
+    fn update_retained_message(&self, msg: Message) {
+        let mut inner = self.inner.write();
+        let topic = msg.topic().clone();
+        if !msg.is_empty() {
+            inner.filter_tree.set_retained_message(topic, Some(msg));
+        } else {
+            inner.filter_tree.set_retained_message(topic, None);
+        }
+    }
+
+    fn create_session(
+        &self,
+        client_id: &str,
+        clean_start: bool,
+        session_expiry_interval: u32,
+        last_will: Option<LastWill>,
+        quota: Option<Arc<QuotaUsage>>,
+        queue_limit: QueueLimit,
+    ) -> (bool, Arc<Notify>) {
+        let mut inner = self.inner.write();
+        let mut session_present = false;
+
+        if !clean_start {
+            let (last_will_timeout_key, remove_timeout_key) =
+                if let Some(session) = inner.sessions.get_mut(client_id) {
+                    let mut session = session.write();
+                    session.last_will = last_will.clone();
+                    session.session_expiry_interval = session_expiry_interval;
+                    session.queue_limit = queue_limit;
+                    session.queue_overflow = false;
+                    session_present = true;
+
+                    (
+                        session.last_will_timeout_key.take(),
+                        session.remove_timeout_key.take(),
+                    )
+                } else {
+                    (None, None)
+                };
+
+            if let Some(key) = last_will_timeout_key {
+                inner.send_last_will_timeout.remove(&key);
+            }
+            if let Some(key) = remove_timeout_key {
+                inner.remove_timeout.remove(&key);
+            }
+            if session_present {
+                inner.set_session_quota(client_id, quota.clone());
+            }
+        } else {
+            inner.remove_session(client_id);
+        }
+
+        if !session_present {
+            let session = RwLock::new(Session {
+                queue: VecDeque::new(),
+                priority_queue: VecDeque::new(),
+                queued_bytes: 0,
+                notify: Arc::new(Notify::new()),
+                last_will,
+                inflight_pub_packets: VecDeque::default(),
+                next_seq: 0,
+                last_will_timeout_key: None,
+                remove_timeout_key: None,
+                quota,
+                queue_limit,
+                queue_overflow: false,
+                subscriptions: 0,
+                session_expiry_interval,
+            });
+            inner.sessions.insert(client_id.to_string(), session);
+        }
+
+        inner
+            .journal
+            .record(|| SessionChange::Updated(client_id.to_string()));
+        let notify = inner.sessions.get(client_id).unwrap().read().notify.clone();
+        (session_present, notify)
+    }
+
+    fn disconnect_session(
+        &self,
+        client_id: &str,
+        session_expiry_interval: u32,
+        last_will: Option<LastWill>,
+    ) {
+        self.inner
+            .write()
+            .disconnect_session(client_id, session_expiry_interval, last_will);
+    }
+
+    fn update_sessions(&self) {
+        let mut inner = self.inner.write();
+        let now = Instant::now();
+        let mut last_wills = Vec::new();
+
+        loop {
+            match inner.send_last_will_timeout.iter().next().cloned() {
+                Some(key) if key.timeout < now => {
+                    inner.send_last_will_timeout.remove(&key);
+                    if let Some(session) = inner.sessions.get(&key.client_id) {
+                        let mut session = session.write();
+                        if let Some(last_will) = session.last_will.take() {
+                            let client_id = key.client_id.clone();
+                            inner.journal.record(|| SessionChange::Updated(client_id));
+                            last_wills.push((key.client_id, last_will));
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        loop {
+            match inner.remove_timeout.iter().next().cloned() {
+                Some(key) if key.timeout < now => {
+                    tracing::debug!(
+                        client_id = %key.client_id,
+                        "session timeout",
+                    );
+
+                    inner.remove_session(&key.client_id);
+                    inner.remove_timeout.remove(&key);
+                    inner.clients_expired += 1;
+                }
+                _ => break,
+            }
+        }
+
+        for (client_id, last_will) in last_wills {
+            inner.send_last_will(&client_id, last_will);
+        }
+    }
+
+    fn set_shared_subscription_strategy(&self, strategy: SharedSubscriptionStrategy) {
+        self.inner.write().shared_subscription_strategy = strategy;
+    }
+
+    fn subscribe(
+        &self,
+        client_id: &str,
+        filter: Filter<'_>,
+        qos: Qos,
+        no_local: bool,
+        retain_as_published: bool,
+        retain_handling: RetainHandling,
+        id: Option<NonZeroUsize>,
+        max_subscriptions: Option<usize>,
+    ) -> bool {
+        let mut inner = self.inner.write();
+        let filter_item = FilterItem {
+            qos,
+            no_local,
+            retain_as_published,
+            retain_handling,
+            id,
+        };
+
+        let is_new_subscribe = inner
+            .filter_tree
+            .subscribe(filter, client_id.to_string(), filter_item)
+            .is_none();
+
+        if is_new_subscribe {
+            let (subscriptions, quota) = match inner.sessions.get(client_id) {
+                Some(session) => {
+                    let session = session.read();
+                    (session.subscriptions, session.quota.clone())
+                }
+                None => (0, None),
+            };
+            let accepted = match max_subscriptions {
+                Some(max_subscriptions) if subscriptions >= max_subscriptions => false,
+                _ => match quota {
+                    Some(quota) => quota.try_add_subscription(),
+                    None => true,
+                },
+            };
+            if !accepted {
+                inner.filter_tree.unsubscribe(filter, client_id);
+                return false;
+            }
+            if let Some(session) = inner.sessions.get(client_id) {
+                session.write().subscriptions += 1;
+            }
+        }
+        inner
+            .journal
+            .record(|| SessionChange::Updated(client_id.to_string()));
+
+        if filter.share_name.is_none() {
+            // send retained messages
+            let publish_retain = matches!(
+                (retain_handling, is_new_subscribe),
+                (RetainHandling::OnEverySubscribe, _) | (RetainHandling::OnNewSubscribe, true)
+            );
+
+            if publish_retain {
+                for msg in inner.filter_tree.matches_retained_messages(filter.path) {
+                    if msg.is_expired() {
+                        continue;
+                    }
+
+                    if filter_item.no_local && msg.from_client_id().map(|s| &**s) == Some(client_id)
+                    {
+                        // If no local is true, Application Messages MUST NOT be forwarded to a connection with
+                        // a ClientID equal to the ClientID of the publishing connection [MQTT-3.8.3-3]
+                        continue;
+                    }
+
+                    if let Some(session) = inner.sessions.get(client_id) {
+                        let mut session = session.write();
+                        session.add_message(
+                            client_id,
+                            msg,
+                            std::iter::once(&filter_item),
+                            &inner.messages_dropped,
+                            &inner.journal,
+                        );
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    fn unsubscribe(&self, client_id: &str, filter: Filter<'_>) -> bool {
+        let mut inner = self.inner.write();
+        if inner.filter_tree.unsubscribe(filter, client_id).is_none() {
+            return false;
+        }
+        if let Some(session) = inner.sessions.get(client_id) {
+            let mut session = session.write();
+            session.subscriptions -= 1;
+            if let Some(quota) = &session.quota {
+                quota.remove_subscriptions(1);
+            }
+        }
+        inner
+            .journal
+            .record(|| SessionChange::Updated(client_id.to_string()));
+        true
+    }
+
+    fn take_queue_overflow(&self, client_id: &str) -> bool {
+        let inner = self.inner.read();
+        let mut session = inner.sessions.get(client_id).unwrap().write();
+        std::mem::take(&mut session.queue_overflow)
+    }
+
+    fn remove_expired_messages(&self) -> usize {
+        let inner = self.inner.read();
+        inner
+            .sessions
+            .iter()
+            .map(|(client_id, session)| {
+                session
+                    .write()
+                    .remove_expired_messages(client_id, &inner.journal)
+            })
+            .sum()
+    }
+
+    fn next_messages(&self, client_id: &str, limit: Option<usize>) -> Vec<Message> {
+        let inner = self.inner.read();
+        let mut session = inner.sessions.get(client_id).unwrap().write();
+        let mut limit = limit.unwrap_or(usize::MAX);
+        let mut res = Vec::new();
+
+        while limit > 0 {
+            let (seq, msg) = match session.pop_message() {
+                Some(item) => item,
+                None => break,
+            };
+            inner.journal.record(|| SessionChange::Dequeued {
+                client_id: client_id.to_string(),
+                seq,
+            });
+            res.push(msg);
+            limit -= 1;
+        }
+
+        if let Some(quota) = &session.quota {
+            quota.remove_queued_bytes(res.iter().map(|msg| msg.payload().len()).sum());
+        }
+        res
+    }
+
+    fn deliver(&self, msgs: Vec<Message>) -> usize {
+        self.inner.read().deliver(msgs)
+    }
+
+    fn count_matches(&self, msg: &Message) -> usize {
+        self.inner.read().count_matches(msg)
+    }
+
+    fn add_inflight_pub_packet(&self, client_id: &str, publish: Publish) {
+        let inner = self.inner.read();
+        let mut session = inner.sessions.get(client_id).unwrap().write();
+        let seq = session.take_seq();
+        inner.journal.record(|| SessionChange::InflightAdded {
+            client_id: client_id.to_string(),
+            seq,
+            publish: publish.clone(),
+        });
+        session.inflight_pub_packets.push_back((seq, publish));
+    }
+
+    fn get_inflight_pub_packets(
+        &self,
+        client_id: &str,
+        packet_id: NonZeroU16,
+        remove: bool,
+    ) -> Option<Publish> {
+        let inner = self.inner.read();
+        if remove {
+            let mut session = inner.sessions.get(client_id).unwrap().write();
+            if session
+                .inflight_pub_packets
+                .front()
+                .map(|(_, publish)| publish.packet_id == Some(packet_id))
+                .unwrap_or_default()
+            {
+                let (seq, publish) = session.inflight_pub_packets.pop_front()?;
+                inner.journal.record(|| SessionChange::InflightRemoved {
+                    client_id: client_id.to_string(),
+                    seq,
+                });
+                Some(publish)
+            } else {
+                None
+            }
+        } else {
+            let session = inner.sessions.get(client_id).unwrap().read();
+            session
+                .inflight_pub_packets
+                .front()
+                .filter(|(_, publish)| publish.packet_id == Some(packet_id))
+                .map(|(_, publish)| publish.clone())
+        }
+    }
+
+    fn get_all_inflight_pub_packets(&self, client_id: &str) -> Vec<Publish> {
+        let inner = self.inner.read();
+        let session = inner.sessions.get(client_id).unwrap().read();
+        session
+            .inflight_pub_packets
+            .iter()
+            .map(|(_, publish)| publish.clone())
+            .collect()
+    }
+
+    fn retained_messages(&self, filter: Filter<'_>) -> Vec<Message> {
+        let inner = self.inner.read();
+        inner
+            .filter_tree
+            .matches_retained_messages(filter.path)
+            .filter(|msg| !msg.is_expired())
+            .cloned()
+            .collect()
+    }
+
+    fn export_session(&self, client_id: &str) -> Option<SessionSnapshot> {
+        self.export(client_id, true)
+    }
+
+    fn import_session(&self, client_id: &str, snapshot: SessionSnapshot) {
+        let mut inner = self.inner.write();
+        inner.remove_session(client_id);
+
+        let mut subscriptions = 0;
+        for (filter, item) in &snapshot.subscriptions {
+            if let Some(filter) = filter_util::parse_filter(filter) {
+                inner
+                    .filter_tree
+                    .subscribe(filter, client_id.to_string(), *item);
+                subscriptions += 1;
+            }
+        }
+
+        // the queued messages and inflight packets are numbered in their order
+        let queue_len = snapshot.queue.len();
+        let (priority_queue, queue): (VecDeque<_>, VecDeque<_>) = snapshot
+            .queue
+            .into_iter()
+            .enumerate()
+            .map(|(idx, msg)| (idx as u64, msg))
+            .partition(|(_, msg)| msg.is_priority());
+        let inflight_pub_packets: VecDeque<_> = snapshot
+            .inflight_pub_packets
+            .into_iter()
+            .enumerate()
+            .map(|(idx, publish)| ((queue_len + idx) as u64, publish))
+            .collect();
+        let next_seq = (queue_len + inflight_pub_packets.len()) as u64;
+        let queued_bytes = priority_queue
+            .iter()
+            .chain(&queue)
+            .map(|(_, msg)| msg.payload().len())
+            .sum();
+        for (seq, msg) in priority_queue.iter().chain(&queue) {
+            inner.journal.record(|| SessionChange::Queued {
+                client_id: client_id.to_string(),
+                seq: *seq,
+                msg: msg.clone(),
+            });
+        }
+        for (seq, publish) in &inflight_pub_packets {
+            inner.journal.record(|| SessionChange::InflightAdded {
+                client_id: client_id.to_string(),
+                seq: *seq,
+                publish: publish.clone(),
+            });
+        }
+
+        let session = RwLock::new(Session {
+            queue,
+            priority_queue,
+            queued_bytes,
+            notify: Arc::new(Notify::new()),
+            last_will: None,
+            inflight_pub_packets,
+            next_seq,
+            last_will_timeout_key: None,
+            remove_timeout_key: None,
+            quota: None,
+            queue_limit: QueueLimit::default(),
+            queue_overflow: false,
+            subscriptions,
+            session_expiry_interval: snapshot.session_expiry_interval,
+        });
+        inner.sessions.insert(client_id.to_string(), session);
+        inner.disconnect_session(
+            client_id,
+            snapshot.session_expiry_interval,
+            snapshot.last_will,
+        );
+    }
+
+    fn metrics(&self) -> StorageMetrics {
+        let inner = self.inner.read();
+        StorageMetrics {
+            session_count: inner.sessions.len(),
+            inflight_messages_count: inner
+                .sessions
+                .values()
+                .map(|session| session.read().inflight_pub_packets.len())
+                .sum::<usize>(),
+            retained_messages_count: inner.filter_tree.retained_messages_count(),
+            messages_count: inner.filter_tree.retained_messages_count()
+                + inner
+                    .sessions
+                    .values()
+                    .map(|session| session.read().queued_messages().count())
+                    .sum::<usize>(),
+            messages_bytes: inner.filter_tree.retained_messages_bytes()
+                + inner
+                    .sessions
+                    .values()
+                    .map(|session| session.read().queued_bytes)
+                    .sum::<usize>(),
+            subscriptions_count: inner.filter_tree.subscriber_count(),
+            clients_expired: inner.clients_expired,
+            messages_dropped: inner.messages_dropped.load(AtomicOrdering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU16;
+
+    use super::*;
+    use crate::filter_util::parse_filter;
+
+    #[test]
+    fn test_export_import_session() {
+        let storage = StorageMemory::default();
+        storage.create_session("a", true, 30, None, None, QueueLimit::default());
+        storage.subscribe(
+            "a",
+            parse_filter("a/+").unwrap(),
+            Qos::AtLeastOnce,
+            false,
+            false,
+            RetainHandling::Never,
+            None,
+            None,
+        );
+        storage.subscribe(
+            "a",
+            parse_filter("$share/g/b").unwrap(),
+            Qos::AtMostOnce,
+            false,
+            false,
+            RetainHandling::Never,
+            None,
+            None,
+        );
+        storage.deliver(vec![Message::new("a/1", Qos::AtLeastOnce, &b"1"[..])]);
+        let mut publish = Message::new("a/2", Qos::AtLeastOnce, &b"2"[..]).to_publish();
+        publish.packet_id = NonZeroU16::new(1);
+        storage.add_inflight_pub_packet("a", publish);
+        // the connected session has the expiry interval of the connection
+        assert_eq!(
+            storage.export_session("a").unwrap().session_expiry_interval,
+            30
+        );
+        storage.disconnect_session("a", 60, None);
+
+        let snapshot = storage.export_session("a").unwrap();
+        let data = serde_yaml::to_vec(&snapshot).unwrap();
+        let snapshot: SessionSnapshot = serde_yaml::from_slice(&data).unwrap();
+        assert!(snapshot.session_expiry_interval > 0 && snapshot.session_expiry_interval <= 60);
+
+        let storage = StorageMemory::default();
+        storage.import_session("a", snapshot);
+        assert!(
+            storage
+                .create_session("a", false, 0, None, None, QueueLimit::default())
+                .0
+        );
+
+        let topics = |msgs: Vec<Message>| {
+            msgs.iter()
+                .map(|msg| msg.topic().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(topics(storage.next_messages("a", None)), vec!["a/1"]);
+        assert_eq!(storage.get_all_inflight_pub_packets("a").len(), 1);
+
+        storage.deliver(vec![
+            Message::new("a/3", Qos::AtLeastOnce, &b"3"[..]),
+            Message::new("b", Qos::AtLeastOnce, &b"4"[..]),
+        ]);
+        assert_eq!(topics(storage.next_messages("a", None)), vec!["a/3", "b"]);
+
+        assert!(storage.export_session("b").is_none());
+    }
+
+    #[test]
+    fn test_quota() {
+        let storage = StorageMemory::default();
+        let quota = Arc::new(QuotaUsage::new(crate::Quota {
+            max_sessions: None,
+            max_subscriptions: Some(1),
+            max_queued_bytes: Some(3),
+        }));
+        storage.create_session(
+            "a",
+            true,
+            0,
+            None,
+            Some(quota.clone()),
+            QueueLimit::default(),
+        );
+
+        let subscribe = |filter| {
+            storage.subscribe(
+                "a",
+                parse_filter(filter).unwrap(),
+                Qos::AtLeastOnce,
+                false,
+                false,
+                RetainHandling::Never,
+                None,
+                None,
+            )
+        };
+        assert!(subscribe("a/+"));
+        assert!(subscribe("a/+"));
+        assert!(!subscribe("b"));
+        assert_eq!(quota.subscriptions(), 1);
+
+        storage.deliver(vec![
+            Message::new("a/1", Qos::AtLeastOnce, &b"12"[..]),
+            Message::new("a/2", Qos::AtLeastOnce, &b"34"[..]),
+            Message::new("a/3", Qos::AtLeastOnce, &b"5"[..]),
+        ]);
+        assert_eq!(quota.queued_bytes(), 3);
+        assert_eq!(storage.next_messages("a", None).len(), 2);
+        assert_eq!(quota.queued_bytes(), 0);
+
+        assert!(storage.unsubscribe("a", parse_filter("a/+").unwrap()));
+        assert!(subscribe("b"));
+        storage.deliver(vec![Message::new("b", Qos::AtLeastOnce, &b"1"[..])]);
+        storage.create_session("a", true, 0, None, None, QueueLimit::default());
+        assert_eq!(quota.subscriptions(), 0);
+        assert_eq!(quota.queued_bytes(), 0);
+    }
+
+    #[test]
+    fn test_max_subscriptions() {
+        let storage = StorageMemory::default();
+        storage.create_session("a", true, 0, None, None, QueueLimit::default());
+
+        let subscribe = |filter| {
+            storage.subscribe(
+                "a",
+                parse_filter(filter).unwrap(),
+                Qos::AtLeastOnce,
+                false,
+                false,
+                RetainHandling::Never,
+                None,
+                Some(2),
+            )
+        };
+        assert!(subscribe("a"));
+        assert!(subscribe("b"));
+        assert!(!subscribe("c"));
+        // replacing a subscription is not limited
+        assert!(subscribe("a"));
+
+        assert!(storage.unsubscribe("a", parse_filter("a").unwrap()));
+        assert!(subscribe("c"));
+        assert_eq!(storage.export_session("a").unwrap().subscriptions.len(), 2);
+    }
+
+    #[test]
+    fn test_queue_limit() {
+        let topics = |msgs: Vec<Message>| {
+            msgs.iter()
+                .map(|msg| msg.topic().to_string())
+                .collect::<Vec<_>>()
+        };
+        let deliver = |storage: &StorageMemory, queue_limit| {
+            storage.create_session("a", true, 0, None, None, queue_limit);
+            storage.subscribe(
+                "a",
+                parse_filter("#").unwrap(),
+                Qos::AtLeastOnce,
+                false,
+                false,
+                RetainHandling::Never,
+                None,
+                None,
+            );
+            storage.deliver(vec![
+                Message::new("1", Qos::AtLeastOnce, &b"11"[..]),
+                Message::new("2", Qos::AtLeastOnce, &b"22"[..]),
+                Message::new("3", Qos::AtLeastOnce, &b"333"[..]),
+            ]);
+        };
+        let max_messages = |policy| QueueLimit {
+            max_messages: Some(2),
+            max_bytes: None,
+            policy,
+        };
+        let max_bytes = |policy| QueueLimit {
+            max_messages: None,
+            max_bytes: Some(5),
+            policy,
+        };
+
+        let storage = StorageMemory::default();
+        deliver(&storage, max_messages(QueueFullPolicy::DropNewest));
+        assert!(!storage.take_queue_overflow("a"));
+        assert_eq!(topics(storage.next_messages("a", None)), vec!["1", "2"]);
+
+        deliver(&storage, max_messages(QueueFullPolicy::DropOldest));
+        assert!(!storage.take_queue_overflow("a"));
+        assert_eq!(topics(storage.next_messages("a", None)), vec!["2", "3"]);
+
+        deliver(&storage, max_messages(QueueFullPolicy::Disconnect));
+        assert!(storage.take_queue_overflow("a"));
+        assert!(!storage.take_queue_overflow("a"));
+        assert_eq!(topics(storage.next_messages("a", None)), vec!["1", "2"]);
+
+        assert_eq!(storage.metrics().messages_dropped, 3);
+
+        deliver(&storage, max_bytes(QueueFullPolicy::DropNewest));
+        assert_eq!(topics(storage.next_messages("a", None)), vec!["1", "2"]);
+
+        deliver(&storage, max_bytes(QueueFullPolicy::DropOldest));
+        assert_eq!(topics(storage.next_messages("a", None)), vec!["2", "3"]);
+
+        assert_eq!(storage.metrics().messages_dropped, 5);
+
+        // a message larger than the limit is dropped without dropping the queued ones
+        storage.create_session(
+            "a",
+            true,
+            0,
+            None,
+            None,
+            QueueLimit {
+                max_messages: None,
+                max_bytes: Some(2),
+                policy: QueueFullPolicy::DropOldest,
+            },
+        );
+        storage.subscribe(
+            "a",
+            parse_filter("#").unwrap(),
+            Qos::AtLeastOnce,
+            false,
+            false,
+            RetainHandling::Never,
+            None,
+            None,
+        );
+        storage.deliver(vec![
+            Message::new("1", Qos::AtLeastOnce, &b"11"[..]),
+            Message::new("2", Qos::AtLeastOnce, &b"333"[..]),
+        ]);
+        assert_eq!(storage.metrics().messages_bytes, 2);
+        assert_eq!(topics(storage.next_messages("a", None)), vec!["1"]);
+        assert_eq!(storage.metrics().messages_bytes, 0);
+        assert_eq!(storage.metrics().messages_dropped, 6);
+    }
+
+    #[test]
+    fn test_sticky_shared_subscription() {
+        let storage = StorageMemory::default();
+        storage.set_shared_subscription_strategy(SharedSubscriptionStrategy::StickyPublisher);
+        for client_id in ["a", "b", "c"] {
+            storage.create_session(client_id, true, 0, None, None, QueueLimit::default());
+            storage.subscribe(
+                client_id,
+                parse_filter("$share/g/t").unwrap(),
+                Qos::AtMostOnce,
+                false,
+                false,
+                RetainHandling::Never,
+                None,
+                None,
+            );
+        }
+
+        // returns the members that received the messages and their number
+        let publish = |count| {
+            let msgs = (0..count)
+                .map(|_| Message::new("t", Qos::AtMostOnce, &b"1"[..]).with_from_client_id("p"))
+                .collect();
+            storage.deliver(msgs);
+            ["a", "b", "c"]
+                .iter()
+                .map(|client_id| (*client_id, storage.next_messages(client_id, None).len()))
+                .filter(|(_, len)| *len > 0)
+                .collect::<Vec<_>>()
+        };
+        let received = publish(10);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].1, 10);
+
+        // the messages are routed to another member after the chosen one disconnects
+        let member = received[0].0;
+        storage.disconnect_session(member, 60, None);
+        storage.unsubscribe(member, parse_filter("$share/g/t").unwrap());
+        let received = publish(10);
+        assert_eq!(received.len(), 1);
+        assert_ne!(received[0].0, member);
+        assert_eq!(received[0].1, 10);
+    }
+
+    #[test]
+    fn test_journal() {
+        let storage = StorageMemory::with_journal();
+        storage.create_session(
+            "a",
+            true,
+            0,
+            None,
+            None,
+            QueueLimit {
+                max_messages: Some(2),
+                max_bytes: None,
+                policy: QueueFullPolicy::DropOldest,
+            },
+        );
+        storage.subscribe(
+            "a",
+            parse_filter("#").unwrap(),
+            Qos::AtLeastOnce,
+            false,
+            false,
+            RetainHandling::Never,
+            None,
+            None,
+        );
+        storage.take_changes();
+
+        storage.deliver(vec![
+            Message::new("1", Qos::AtLeastOnce, &b"1"[..]),
+            Message::new("2", Qos::AtLeastOnce, &b"2"[..]),
+            Message::new("3", Qos::AtLeastOnce, &b"3"[..]),
+        ]);
+        storage.next_messages("a", Some(1));
+        let changes = storage
+            .take_changes()
+            .into_iter()
+            .map(|change| match change {
+                SessionChange::Queued { seq, msg, .. } => format!("+{} {}", seq, msg.topic()),
+                SessionChange::Dequeued { seq, .. } => format!("-{}", seq),
+                change => panic!("unexpected change: {:?}", change),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(changes, vec!["+0 1", "+1 2", "-0", "+2 3", "-1"]);
+
+        storage.create_session("a", true, 0, None, None, QueueLimit::default());
+        assert!(matches!(
+            &storage.take_changes()[..],
+            [SessionChange::Removed(_), SessionChange::Updated(_)]
+        ));
+        assert!(StorageMemory::default().take_changes().is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+mod memory;
+
+pub use memory::{SessionChange, StorageMemory, StorageMemoryFactory};
+
+use std::num::{NonZeroU16, NonZeroUsize};
+use std::sync::Arc;
+
+use codec::{LastWill, Publish, Qos, RetainHandling};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use tokio::sync::Notify;
+
+use crate::filter_util::Filter;
+use crate::message::Message;
+use crate::quota::QuotaUsage;
+
+/// What happens to a message queued for a session that holds the maximum number or size of
+/// messages.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueFullPolicy {
+    /// The new message is dropped.
+    #[default]
+    DropNewest,
+    /// The oldest queued messages are dropped to make room for the new one.
+    DropOldest,
+    /// The new message is dropped and the client is disconnected, see
+    /// [`Storage::take_queue_overflow`].
+    Disconnect,
+}
+
+/// How the messages of a shared subscription are routed to the members of its group.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SharedSubscriptionStrategy {
+    /// Each message is routed to a random member.
+    #[default]
+    Random,
+    /// The messages of the same publisher are routed to the same member until it disconnects.
+    StickyPublisher,
+    /// The messages of the same topic are routed to the same member until it disconnects.
+    StickyTopic,
+}
+
+/// Limits the messages queued for a session.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct QueueLimit {
+    /// The maximum number of queued messages, unlimited if not set.
+    pub max_messages: Option<usize>,
+    /// The maximum size of the queued payloads, unlimited if not set.
+    pub max_bytes: Option<usize>,
+    pub policy: QueueFullPolicy,
+}
+
+#[derive(Debug, Default)]
+pub struct StorageMetrics {
+    pub session_count: usize,
+    pub inflight_messages_count: usize,
+    pub retained_messages_count: usize,
+    pub messages_count: usize,
+    pub messages_bytes: usize,
+    pub subscriptions_count: usize,
+    pub clients_expired: usize,
+    /// The number of messages dropped because the queue of a session was full.
+    pub messages_dropped: usize,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FilterItem {
+    pub qos: Qos,
+    pub no_local: bool,
+    pub retain_as_published: bool,
+    pub retain_handling: RetainHandling,
+    pub id: Option<NonZeroUsize>,
+}
+
+/// A portable copy of a session, it can be imported into the storage of another broker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    /// The filters with the `$share/{name}/` prefix for shared subscriptions.
+    pub subscriptions: Vec<(String, FilterItem)>,
+    pub queue: Vec<Message>,
+    pub inflight_pub_packets: Vec<Publish>,
+    pub last_will: Option<LastWill>,
+    /// The remaining seconds until the session expires.
+    pub session_expiry_interval: u32,
+}
+
+/// Creates a storage backend from the `storage` section of the config, its `type` is the name
+/// of the factory.
+#[async_trait::async_trait]
+pub trait StorageFactory: 'static {
+    fn name(&self) -> &'static str;
+
+    async fn create(&self, config: Value) -> anyhow::Result<Arc<dyn Storage>>;
+}
+
+/// Stores the sessions, subscriptions and retained messages of the broker.
+#[allow(clippy::too_many_arguments)]
+pub trait Storage: Send + Sync + 'static {
+    /// Replaces the retained message of the topic, a message with an empty payload removes it.
+    fn update_retained_message(&self, msg: Message);
+
+    /// Creates a session or resumes the existing one if `clean_start` is `false`, returns
+    /// whether a session was present and the notify that is signaled when messages are queued.
+    ///
+    /// The subscriptions and queued messages of the session are counted in `quota`, messages
+    /// that exceed it or `queue_limit` are dropped. The snapshot of the connected session has
+    /// the `session_expiry_interval` of the connection.
+    ///
+    /// A delayed last will of the existing session is not sent if it is resumed, and sent at
+    /// once if it is replaced because `clean_start` is `true` [MQTT-3.1.3-9].
+    fn create_session(
+        &self,
+        client_id: &str,
+        clean_start: bool,
+        session_expiry_interval: u32,
+        last_will: Option<LastWill>,
+        quota: Option<Arc<QuotaUsage>>,
+        queue_limit: QueueLimit,
+    ) -> (bool, Arc<Notify>);
+
+    /// Marks the session as disconnected, it expires after `session_expiry_interval` seconds.
+    fn disconnect_session(
+        &self,
+        client_id: &str,
+        session_expiry_interval: u32,
+        last_will: Option<LastWill>,
+    );
+
+    /// Sends the delayed last wills and removes the expired sessions, called periodically.
+    fn update_sessions(&self);
+
+    /// Returns `true` once after a message was dropped because the queue of the connected
+    /// session was full with [`QueueFullPolicy::Disconnect`].
+    fn take_queue_overflow(&self, client_id: &str) -> bool;
+
+    /// Removes the expired messages from the queues of the sessions and returns their number,
+    /// called periodically so they don't pile up in the sessions of the offline clients.
+    fn remove_expired_messages(&self) -> usize;
+
+    /// Sets how the messages of the shared subscriptions are routed, called when the service
+    /// starts.
+    fn set_shared_subscription_strategy(&self, strategy: SharedSubscriptionStrategy);
+
+    /// Returns `false` if the subscription is new and exceeds the quota of the session, or the
+    /// session already has `max_subscriptions` subscriptions.
+    fn subscribe(
+        &self,
+        client_id: &str,
+        filter: Filter<'_>,
+        qos: Qos,
+        no_local: bool,
+        retain_as_published: bool,
+        retain_handling: RetainHandling,
+        id: Option<NonZeroUsize>,
+        max_subscriptions: Option<usize>,
+    ) -> bool;
+
+    /// Returns `false` if the subscription does not exist.
+    fn unsubscribe(&self, client_id: &str, filter: Filter<'_>) -> bool;
+
+    /// Takes at most `limit` messages from the queue of the session.
+    fn next_messages(&self, client_id: &str, limit: Option<usize>) -> Vec<Message>;
+
+    /// Queues the messages for all matching sessions, returns the number of sessions the
+    /// messages were queued for.
+    fn deliver(&self, msgs: Vec<Message>) -> usize;
+
+    /// Returns the number of sessions the message would be queued for by
+    /// [`Storage::deliver`].
+    fn count_matches(&self, msg: &Message) -> usize;
+
+    fn add_inflight_pub_packet(&self, client_id: &str, publish: Publish);
+
+    /// Returns the oldest inflight packet if it has the `packet_id`, and removes it if `remove`
+    /// is `true`.
+    fn get_inflight_pub_packets(
+        &self,
+        client_id: &str,
+        packet_id: NonZeroU16,
+        remove: bool,
+    ) -> Option<Publish>;
+
+    fn get_all_inflight_pub_packets(&self, client_id: &str) -> Vec<Publish>;
+
+    /// Returns the retained messages matching the filter, expired messages are skipped.
+    fn retained_messages(&self, filter: Filter<'_>) -> Vec<Message>;
+
+    /// Returns a copy of the session, `None` if it does not exist.
+    fn export_session(&self, client_id: &str) -> Option<SessionSnapshot>;
+
+    /// Replaces the session with the snapshot, the session is disconnected until the client
+    /// connects with `clean_start` set to `false`.
+    fn import_session(&self, client_id: &str, snapshot: SessionSnapshot);
+
+    fn metrics(&self) -> StorageMetrics;
+}
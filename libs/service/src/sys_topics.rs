@@ -9,14 +9,12 @@ impl ServiceState {
 
         macro_rules! update {
             ($state:expr, $topic:literal, $payload:expr) => {
-                $state.storage.deliver(std::iter::once(
-                    Message::new(
-                        $topic,
-                        Qos::AtMostOnce,
-                        bytes::Bytes::from($payload.to_string().into_bytes()),
-                    )
-                    .with_retain(true),
-                ));
+                $state.storage.deliver(vec![Message::new(
+                    $topic,
+                    Qos::AtMostOnce,
+                    bytes::Bytes::from($payload.to_string().into_bytes()),
+                )
+                .with_retain(true)]);
             };
         }
 
@@ -105,6 +103,12 @@ impl ServiceState {
             "$SYS/broker/subscriptions/count",
             metrics.subscriptions_count
         );
+        update!(
+            self,
+            "$SYS/broker/subscriptions/rejected",
+            metrics.subscriptions_rejected
+        );
+        update!(self, "$SYS/broker/overload", metrics.overload);
 
         // 1min
         update!(
@@ -0,0 +1,329 @@
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use codec::{Packet, Qos};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::message::Message;
+use crate::{clock, filter_util, ServiceState};
+
+/// The prefix of the topics the traces are published to.
+pub const TRACE_TOPIC_PREFIX: &str = "$SYS/trace/";
+
+/// Selects the packets of a trace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum TraceTarget {
+    /// All packets sent or received by the client.
+    ClientId(String),
+    /// The PUBLISH packets with a topic matching the filter.
+    Topic(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceOptions {
+    pub target: TraceTarget,
+    /// Appends the packets to the file with this name in
+    /// [`ServiceConfig::trace_dir`](crate::ServiceConfig::trace_dir) instead of publishing them to
+    /// `$SYS/trace/{id}`.
+    #[serde(default)]
+    pub file: Option<String>,
+    /// The trace stops after this number of seconds.
+    #[serde(default = "default_duration")]
+    pub duration: u64,
+    /// The trace stops after this number of packets.
+    #[serde(default = "default_max_packets")]
+    pub max_packets: usize,
+}
+
+fn default_duration() -> u64 {
+    60
+}
+
+fn default_max_packets() -> usize {
+    1000
+}
+
+/// A running trace.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceInfo {
+    pub id: String,
+    pub options: TraceOptions,
+    pub packets: usize,
+    /// The remaining seconds until the trace stops.
+    pub remaining: u64,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Direction {
+    Received,
+    Sent,
+}
+
+pub(crate) struct Tracer {
+    id: String,
+    options: TraceOptions,
+    expires_at: Instant,
+    packets: AtomicUsize,
+    file: Option<Mutex<File>>,
+}
+
+impl Tracer {
+    fn is_active(&self) -> bool {
+        Instant::now() < self.expires_at
+            && self.packets.load(Ordering::SeqCst) < self.options.max_packets
+    }
+
+    fn matches(&self, client_id: Option<&str>, packet: &Packet) -> bool {
+        if let Packet::Publish(publish) = packet {
+            // the packets delivering the traces are not traced, or a client subscribed to its own
+            // trace would never stop receiving them
+            if publish.topic.starts_with(TRACE_TOPIC_PREFIX) {
+                return false;
+            }
+        }
+
+        match (&self.options.target, packet) {
+            (TraceTarget::ClientId(id), _) => client_id == Some(id.as_str()),
+            (TraceTarget::Topic(filter), Packet::Publish(publish)) => {
+                filter_util::matches_topic(filter, &publish.topic)
+            }
+            _ => false,
+        }
+    }
+
+    /// Reserves a slot for the packet, returns `false` if the trace has reached `max_packets`.
+    fn take_slot(&self) -> bool {
+        let max_packets = self.options.max_packets;
+        self.packets
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max_packets).then(|| n + 1)
+            })
+            .is_ok()
+    }
+}
+
+impl ServiceState {
+    /// Starts tracing the packets selected by the options, returns the id of the trace.
+    pub fn start_trace(&self, options: TraceOptions) -> Result<String> {
+        if let TraceTarget::Topic(filter) = &options.target {
+            anyhow::ensure!(
+                filter_util::parse_filter(filter)
+                    .map(|filter| filter.share_name.is_none())
+                    .unwrap_or_default(),
+                "invalid topic filter: {}",
+                filter
+            );
+        }
+
+        let file = match &options.file {
+            None => None,
+            Some(name) => {
+                let dir = self
+                    .config
+                    .trace_dir
+                    .as_deref()
+                    .context("trace files are disabled, `trace_dir` is not set")?;
+                // only a file name is accepted, so that the traces cannot be written outside of
+                // the directory
+                anyhow::ensure!(
+                    Path::new(name).file_name() == Some(OsStr::new(name)),
+                    "invalid trace file name: {}",
+                    name
+                );
+                let path = Path::new(dir).join(name);
+                Some(Mutex::new(
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .with_context(|| {
+                            format!("failed to open trace file: {}", path.display())
+                        })?,
+                ))
+            }
+        };
+
+        let id = uuid::Uuid::new_v4().to_simple().to_string();
+        let tracer = Arc::new(Tracer {
+            id: id.clone(),
+            expires_at: Instant::now() + Duration::from_secs(options.duration),
+            options,
+            packets: AtomicUsize::new(0),
+            file,
+        });
+        let mut tracers = self.tracers.write();
+        tracers.retain(|tracer| tracer.is_active());
+        tracers.push(tracer);
+        Ok(id)
+    }
+
+    /// Returns `false` if the trace does not exist.
+    pub fn stop_trace(&self, id: &str) -> bool {
+        let mut tracers = self.tracers.write();
+        let len = tracers.len();
+        tracers.retain(|tracer| tracer.id != id);
+        tracers.len() != len
+    }
+
+    pub fn traces(&self) -> Vec<TraceInfo> {
+        let now = Instant::now();
+        self.tracers
+            .read()
+            .iter()
+            .filter(|tracer| tracer.is_active())
+            .map(|tracer| TraceInfo {
+                id: tracer.id.clone(),
+                options: tracer.options.clone(),
+                packets: tracer.packets.load(Ordering::SeqCst),
+                remaining: tracer.expires_at.saturating_duration_since(now).as_secs(),
+            })
+            .collect()
+    }
+
+    pub(crate) fn trace_packet(
+        &self,
+        client_id: Option<&str>,
+        direction: Direction,
+        packet: &Packet,
+    ) {
+        let tracers = self.tracers.read();
+        if tracers.is_empty() {
+            return;
+        }
+
+        for tracer in tracers.iter() {
+            if !tracer.is_active() || !tracer.matches(client_id, packet) || !tracer.take_slot() {
+                continue;
+            }
+
+            // the summary leaves out the payloads, passwords and authentication data, which must
+            // not be readable by the subscribers of the trace topic
+            let line = format!(
+                "{} {} {} {}",
+                clock::system_now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis(),
+                client_id.unwrap_or("-"),
+                match direction {
+                    Direction::Received => "<-",
+                    Direction::Sent => "->",
+                },
+                packet.summary()
+            );
+
+            match &tracer.file {
+                Some(file) => {
+                    if let Err(err) = writeln!(file.lock(), "{}", line) {
+                        tracing::warn!(trace = %tracer.id, error = %err, "failed to write trace");
+                    }
+                }
+                None => {
+                    self.storage.deliver(vec![Message::new(
+                        format!("{}{}", TRACE_TOPIC_PREFIX, tracer.id),
+                        Qos::AtMostOnce,
+                        line.into_bytes(),
+                    )]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use codec::{Publish, PublishProperties};
+
+    use super::*;
+    use crate::ServiceConfig;
+
+    fn tracer(target: TraceTarget, max_packets: usize) -> Tracer {
+        Tracer {
+            id: "1".to_string(),
+            options: TraceOptions {
+                target,
+                file: None,
+                duration: 60,
+                max_packets,
+            },
+            expires_at: Instant::now() + Duration::from_secs(60),
+            packets: AtomicUsize::new(0),
+            file: None,
+        }
+    }
+
+    fn publish(topic: &str) -> Packet {
+        Packet::Publish(Publish {
+            dup: false,
+            qos: Qos::AtMostOnce,
+            retain: false,
+            topic: topic.into(),
+            packet_id: None,
+            properties: PublishProperties::default(),
+            payload: Bytes::from_static(b"secret"),
+        })
+    }
+
+    #[test]
+    fn test_tracer() {
+        let tracer_a = tracer(TraceTarget::ClientId("a".to_string()), 2);
+        assert!(tracer_a.matches(Some("a"), &Packet::PingReq));
+        assert!(!tracer_a.matches(Some("b"), &Packet::PingReq));
+        assert!(!tracer_a.matches(Some("a"), &publish("$SYS/trace/1")));
+
+        assert!(tracer_a.take_slot());
+        assert!(tracer_a.take_slot());
+        assert!(!tracer_a.take_slot());
+        assert!(!tracer_a.is_active());
+
+        let tracer_topic = tracer(TraceTarget::Topic("a/+".to_string()), 10);
+        assert!(tracer_topic.matches(None, &publish("a/b")));
+        assert!(!tracer_topic.matches(None, &publish("b")));
+        assert!(!tracer_topic.matches(Some("a"), &Packet::PingReq));
+    }
+
+    #[tokio::test]
+    async fn test_trace_file() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_simple().to_string());
+        std::fs::create_dir(&dir).unwrap();
+        let options = |file: &str| TraceOptions {
+            target: TraceTarget::Topic("a/+".to_string()),
+            file: Some(file.to_string()),
+            duration: 60,
+            max_packets: 10,
+        };
+
+        let state = ServiceState::new(ServiceConfig::default(), Vec::new()).unwrap();
+        assert!(state.start_trace(options("trace.log")).is_err());
+
+        let state = ServiceState::new(
+            ServiceConfig {
+                trace_dir: Some(dir.to_string_lossy().into_owned()),
+                ..ServiceConfig::default()
+            },
+            Vec::new(),
+        )
+        .unwrap();
+        for name in ["../trace.log", "a/trace.log", "/tmp/trace.log", "..", ""].iter() {
+            assert!(state.start_trace(options(name)).is_err());
+        }
+
+        state.start_trace(options("trace.log")).unwrap();
+        state.trace_packet(Some("a"), Direction::Received, &publish("a/b"));
+        let content = std::fs::read_to_string(dir.join("trace.log")).unwrap();
+        assert!(content.contains("topic=a/b"));
+        assert!(!content.contains("secret"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -151,9 +151,12 @@ impl Trie {
         matched.into_iter()
     }
 
+    /// Returns the members of the share groups matching the topic, `choose` returns the index
+    /// of the member of a group that receives the message.
     pub fn matches_shared(
         &self,
         topic: impl AsRef<str>,
+        mut choose: impl FnMut(&str, &[&str]) -> usize,
     ) -> impl Iterator<Item = (&str, Vec<&FilterItem>)> {
         let segments = topic.as_ref().split('/').collect::<Vec<_>>();
         assert!(!segments.is_empty());
@@ -161,7 +164,7 @@ impl Trie {
         let mut nodes = Vec::new();
         let mut matched: HashMap<&str, Vec<&FilterItem>> = HashMap::new();
 
-        for node in self.share_subscriptions.values() {
+        for (share_name, node) in &self.share_subscriptions {
             let mut share_matches: IndexMap<&str, Vec<&FilterItem>> = IndexMap::new();
 
             nodes.clear();
@@ -171,8 +174,9 @@ impl Trie {
             }
 
             if !share_matches.is_empty() {
+                let members = share_matches.keys().copied().collect::<Vec<_>>();
                 let (k, items) = share_matches
-                    .swap_remove_index(fastrand::usize(0..share_matches.len()))
+                    .swap_remove_index(choose(share_name, &members))
                     .unwrap();
                 matched.entry(k).or_default().extend(items);
             }
@@ -277,12 +281,61 @@ impl Trie {
         remove_count
     }
 
-    pub fn unsubscribe_all(&mut self, client_id: &str) {
+    /// Removes all subscriptions of the client and returns their number.
+    pub fn unsubscribe_all(&mut self, client_id: &str) -> usize {
         let mut count = Self::internal_unsubscribe_all(&mut self.root, client_id);
         for node in self.share_subscriptions.values_mut() {
             count += Self::internal_unsubscribe_all(node, client_id);
         }
         self.subscribers_count -= count;
+        count
+    }
+
+    fn internal_subscriptions(
+        parent_node: &Node,
+        prefix: Option<&str>,
+        client_id: &str,
+        res: &mut Vec<(String, FilterItem)>,
+    ) {
+        let children = parent_node
+            .hash_child
+            .iter()
+            .map(|node| ("#", &**node))
+            .chain(parent_node.plus_child.iter().map(|node| ("+", &**node)))
+            .chain(
+                parent_node
+                    .named_children
+                    .iter()
+                    .map(|(name, node)| (name.as_str(), node)),
+            );
+
+        for (segment, node) in children {
+            let path = match prefix {
+                Some(prefix) => format!("{}/{}", prefix, segment),
+                None => segment.to_string(),
+            };
+            if let Some(item) = node.data.get(client_id) {
+                res.push((path.clone(), *item));
+            }
+            Self::internal_subscriptions(node, Some(&path), client_id, res);
+        }
+    }
+
+    /// Returns the filters subscribed by the client, shared subscriptions have the `$share/{name}/`
+    /// prefix.
+    pub fn subscriptions(&self, client_id: &str) -> Vec<(String, FilterItem)> {
+        let mut res = Vec::new();
+        Self::internal_subscriptions(&self.root, None, client_id, &mut res);
+        for (share_name, node) in &self.share_subscriptions {
+            let mut shared = Vec::new();
+            Self::internal_subscriptions(node, None, client_id, &mut shared);
+            res.extend(
+                shared
+                    .into_iter()
+                    .map(|(path, item)| (format!("$share/{}/{}", share_name, path), item)),
+            );
+        }
+        res
     }
 
     fn internal_matches_retained_messages_all<'a>(
@@ -392,6 +445,10 @@ impl Trie {
                 self.retained_messages_count -= 1;
                 self.retained_messages_bytes -= dropped_msg.payload().len();
             }
+            (Some(replaced_msg), true) => {
+                self.retained_messages_bytes -= replaced_msg.payload().len();
+                self.retained_messages_bytes += msg_size;
+            }
             _ => {}
         }
         res
@@ -557,6 +614,35 @@ mod tests {
         assert!(tree.root.is_empty());
     }
 
+    #[test]
+    fn test_subscriptions() {
+        let mut tree = Trie::default();
+
+        tree.subscribe(parse_filter("a/b/c").unwrap(), "1", item!(1));
+        tree.subscribe(parse_filter("a/+/c").unwrap(), "1", item!(2));
+        tree.subscribe(parse_filter("a/#").unwrap(), "1", item!(3));
+        tree.subscribe(parse_filter("/a").unwrap(), "1", item!(4));
+        tree.subscribe(parse_filter("$share/g/d/+").unwrap(), "1", item!(5));
+        tree.subscribe(parse_filter("a/b").unwrap(), "2", item!(6));
+
+        let mut subscriptions = tree
+            .subscriptions("1")
+            .into_iter()
+            .map(|(filter, item)| (filter, item.id.unwrap().get()))
+            .collect::<Vec<_>>();
+        subscriptions.sort();
+        assert_eq!(
+            subscriptions,
+            vec![
+                ("$share/g/d/+".to_string(), 5),
+                ("/a".to_string(), 4),
+                ("a/#".to_string(), 3),
+                ("a/+/c".to_string(), 2),
+                ("a/b/c".to_string(), 1),
+            ]
+        );
+    }
+
     #[test]
     fn test_retained_messages() {
         let mut tree = Trie::default();
@@ -572,6 +658,11 @@ mod tests {
         tree.set_retained_message("a/b", Some(Message::new("b", Qos::AtMostOnce, &b"123"[..])));
         tree.set_retained_message("b/1", Some(Message::new("c", Qos::AtMostOnce, &b"123"[..])));
         assert_eq!(tree.retained_messages_count(), 4);
+        assert_eq!(tree.retained_messages_bytes(), 12);
+
+        tree.set_retained_message("b/1", Some(Message::new("c", Qos::AtMostOnce, &b"1"[..])));
+        assert_eq!(tree.retained_messages_count(), 4);
+        assert_eq!(tree.retained_messages_bytes(), 10);
 
         assert_eq!(
             do_matche_retained_messages!(tree, "a/#"),
@@ -599,6 +690,7 @@ mod tests {
 
         tree.set_retained_message("a/k/c", None);
         assert_eq!(tree.retained_messages_count(), 0);
+        assert_eq!(tree.retained_messages_bytes(), 0);
 
         assert!(tree.root.is_empty());
     }
@@ -0,0 +1,523 @@
+#![forbid(unsafe_code)]
+#![warn(clippy::default_trait_access)]
+
+use std::collections::HashSet;
+use std::num::{NonZeroU16, NonZeroUsize};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use service::codec::{LastWill, Publish, Qos, RetainHandling};
+use service::{
+    Filter, Message, QueueLimit, QuotaUsage, SessionChange, SessionSnapshot,
+    SharedSubscriptionStrategy, Storage, StorageFactory, StorageMemory, StorageMetrics,
+};
+use sled::Batch;
+use tokio::sync::Notify;
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    /// The directory of the database.
+    path: String,
+
+    /// How often sled writes the changes to the disk in the background, 500 milliseconds if not
+    /// specified.
+    flush_every_ms: Option<u64>,
+
+    /// Writes the changes to the disk before the calls of the storage return, so a message is on
+    /// the disk before it is acknowledged with PUBACK or PUBREC.
+    #[serde(default)]
+    sync: bool,
+}
+
+pub struct StorageSledFactory;
+
+#[async_trait::async_trait]
+impl StorageFactory for StorageSledFactory {
+    fn name(&self) -> &'static str {
+        "sled"
+    }
+
+    async fn create(&self, config: Value) -> Result<Arc<dyn Storage>> {
+        let config: Config = serde_yaml::from_value(config)?;
+        let mut db_config = sled::Config::new().path(&config.path);
+        if let Some(flush_every_ms) = config.flush_every_ms {
+            db_config = db_config.flush_every_ms(Some(flush_every_ms));
+        }
+        Ok(Arc::new(
+            StorageSled::new(db_config.open()?)?.with_sync(config.sync),
+        ))
+    }
+}
+
+/// A session as it is stored in the database, its queued messages and inflight packets are
+/// stored separately.
+#[derive(Serialize, Deserialize)]
+struct SessionRecord {
+    snapshot: SessionSnapshot,
+    /// The session expires after `session_expiry_interval` seconds since it was saved, unless
+    /// it was connected.
+    connected: bool,
+    saved_at: SystemTime,
+}
+
+/// The prefix of the keys of the queued messages and inflight packets of a session.
+///
+/// A client identifier can't contain the null character [MQTT-1.5.4-2], so it ends the prefix.
+fn entry_prefix(client_id: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(client_id.len() + 9);
+    key.extend_from_slice(client_id.as_bytes());
+    key.push(0);
+    key
+}
+
+/// The key of a queued message or inflight packet, they are ordered by the sequence number.
+fn entry_key(client_id: &str, seq: u64) -> Vec<u8> {
+    let mut key = entry_prefix(client_id);
+    key.extend_from_slice(&seq.to_be_bytes());
+    key
+}
+
+/// Reads the queued messages or inflight packets of a session in their order.
+fn load_entries<T: DeserializeOwned>(tree: &sled::Tree, client_id: &str) -> Result<Vec<T>> {
+    tree.scan_prefix(entry_prefix(client_id))
+        .values()
+        .map(|data| Ok(serde_yaml::from_slice(&data?)?))
+        .collect()
+}
+
+/// The writes to the trees of the database.
+#[derive(Default)]
+struct Batches {
+    sessions: Batch,
+    queues: Batch,
+    inflight_pub_packets: Batch,
+}
+
+/// Persists the sessions and retained messages of a [`StorageMemory`] to a sled database, they
+/// are restored when the storage is opened again.
+///
+/// Only the changes recorded by the journal of the memory storage are written, a queued
+/// message or inflight packet is a key of its own. The changes are written by sled to the disk
+/// every `flush_every_ms` milliseconds, so a crash loses the changes of that interval,
+/// including messages that have already been acknowledged to the publisher. With
+/// [`StorageSled::with_sync`] they are flushed before each call returns instead, at the cost of
+/// a disk write per call.
+pub struct StorageSled {
+    memory: StorageMemory,
+    db: sled::Db,
+    sessions: sled::Tree,
+    queues: sled::Tree,
+    inflight_pub_packets: sled::Tree,
+    retained_messages: sled::Tree,
+    connected: Mutex<HashSet<String>>,
+    /// Keeps the changes taken by concurrent calls in order.
+    write_lock: Mutex<()>,
+    sync: bool,
+}
+
+impl StorageSled {
+    /// Opens the storage and restores the sessions and retained messages of the database, the
+    /// restored sessions are disconnected.
+    pub fn new(db: sled::Db) -> Result<Self> {
+        let storage = Self {
+            memory: StorageMemory::with_journal(),
+            sessions: db.open_tree("sessions")?,
+            queues: db.open_tree("queues")?,
+            inflight_pub_packets: db.open_tree("inflight_pub_packets")?,
+            retained_messages: db.open_tree("retained_messages")?,
+            db,
+            connected: Mutex::new(HashSet::new()),
+            write_lock: Mutex::new(()),
+            sync: false,
+        };
+
+        for item in storage.retained_messages.iter() {
+            let (_, data) = item?;
+            let msg: Message = serde_yaml::from_slice(&data)?;
+            storage.memory.update_retained_message(msg);
+        }
+
+        let now = SystemTime::now();
+        for item in storage.sessions.iter() {
+            let (client_id, data) = item?;
+            let client_id = std::str::from_utf8(&client_id)?;
+            let SessionRecord {
+                mut snapshot,
+                connected,
+                saved_at,
+            } = serde_yaml::from_slice(&data)?;
+            if !connected {
+                let elapsed = now.duration_since(saved_at).unwrap_or_default().as_secs();
+                snapshot.session_expiry_interval = snapshot
+                    .session_expiry_interval
+                    .saturating_sub(elapsed.min(u32::MAX as u64) as u32);
+            }
+            snapshot.queue = load_entries(&storage.queues, client_id)?;
+            snapshot.inflight_pub_packets = load_entries(&storage.inflight_pub_packets, client_id)?;
+            storage.memory.import_session(client_id, snapshot);
+        }
+
+        // the restored entries are numbered again, each tree is replaced in a single batch
+        let mut batches = Batches::default();
+        for key in storage.queues.iter().keys() {
+            batches.queues.remove(key?);
+        }
+        for key in storage.inflight_pub_packets.iter().keys() {
+            batches.inflight_pub_packets.remove(key?);
+        }
+        storage.write_changes(batches, storage.memory.take_changes())?;
+
+        Ok(storage)
+    }
+
+    /// Flushes the changes to the disk before the calls that make them return.
+    #[inline]
+    pub fn with_sync(self, sync: bool) -> Self {
+        Self { sync, ..self }
+    }
+
+    /// Writes the changes made to the memory storage by the last call.
+    fn save_changes(&self) {
+        let _write_guard = self.write_lock.lock();
+        let changes = self.memory.take_changes();
+        if changes.is_empty() {
+            return;
+        }
+        if let Err(err) = self.write_changes(Batches::default(), changes) {
+            tracing::error!(error = %err, "failed to save sessions");
+        }
+    }
+
+    fn write_changes(&self, mut batches: Batches, changes: Vec<SessionChange>) -> Result<()> {
+        for change in changes {
+            match change {
+                SessionChange::Updated(client_id) => {
+                    if let Some(snapshot) = self.memory.export_session_settings(&client_id) {
+                        let record = SessionRecord {
+                            snapshot,
+                            connected: self.connected.lock().contains(&client_id),
+                            saved_at: SystemTime::now(),
+                        };
+                        batches
+                            .sessions
+                            .insert(client_id.as_bytes(), serde_yaml::to_vec(&record)?);
+                    }
+                }
+                SessionChange::Removed(client_id) => {
+                    // the keys of the session are scanned, so the pending writes are applied
+                    // first
+                    self.apply_batches(std::mem::take(&mut batches))?;
+                    batches.sessions.remove(client_id.as_bytes());
+                    for (tree, batch) in [
+                        (&self.queues, &mut batches.queues),
+                        (
+                            &self.inflight_pub_packets,
+                            &mut batches.inflight_pub_packets,
+                        ),
+                    ]
+                    .iter_mut()
+                    {
+                        for key in tree.scan_prefix(entry_prefix(&client_id)).keys() {
+                            batch.remove(key?);
+                        }
+                    }
+                }
+                SessionChange::Queued {
+                    client_id,
+                    seq,
+                    msg,
+                } => batches
+                    .queues
+                    .insert(entry_key(&client_id, seq), serde_yaml::to_vec(&msg)?),
+                SessionChange::Dequeued { client_id, seq } => {
+                    batches.queues.remove(entry_key(&client_id, seq))
+                }
+                SessionChange::InflightAdded {
+                    client_id,
+                    seq,
+                    publish,
+                } => batches
+                    .inflight_pub_packets
+                    .insert(entry_key(&client_id, seq), serde_yaml::to_vec(&publish)?),
+                SessionChange::InflightRemoved { client_id, seq } => batches
+                    .inflight_pub_packets
+                    .remove(entry_key(&client_id, seq)),
+            }
+        }
+        self.apply_batches(batches)?;
+
+        if self.sync {
+            self.db.flush()?;
+        }
+        Ok(())
+    }
+
+    fn apply_batches(&self, batches: Batches) -> Result<()> {
+        self.sessions.apply_batch(batches.sessions)?;
+        self.queues.apply_batch(batches.queues)?;
+        self.inflight_pub_packets
+            .apply_batch(batches.inflight_pub_packets)?;
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+impl Storage for StorageSled {
+    fn update_retained_message(&self, msg: Message) {
+        let topic = msg.topic().clone();
+        let res = if msg.is_empty() {
+            self.retained_messages
+                .remove(&*topic)
+                .map(|_| ())
+                .map_err(anyhow::Error::from)
+        } else {
+            serde_yaml::to_vec(&msg)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| Ok(self.retained_messages.insert(&*topic, data).map(|_| ())?))
+        }
+        .and_then(|_| {
+            if self.sync {
+                self.db.flush()?;
+            }
+            Ok(())
+        });
+        if let Err(err) = res {
+            tracing::error!(
+                topic = %topic,
+                error = %err,
+                "failed to save retained message",
+            );
+        }
+        self.memory.update_retained_message(msg);
+    }
+
+    fn create_session(
+        &self,
+        client_id: &str,
+        clean_start: bool,
+        session_expiry_interval: u32,
+        last_will: Option<LastWill>,
+        quota: Option<Arc<QuotaUsage>>,
+        queue_limit: QueueLimit,
+    ) -> (bool, Arc<Notify>) {
+        let res = self.memory.create_session(
+            client_id,
+            clean_start,
+            session_expiry_interval,
+            last_will,
+            quota,
+            queue_limit,
+        );
+        self.connected.lock().insert(client_id.to_string());
+        self.save_changes();
+        res
+    }
+
+    fn disconnect_session(
+        &self,
+        client_id: &str,
+        session_expiry_interval: u32,
+        last_will: Option<LastWill>,
+    ) {
+        self.memory
+            .disconnect_session(client_id, session_expiry_interval, last_will);
+        self.connected.lock().remove(client_id);
+        self.save_changes();
+    }
+
+    fn update_sessions(&self) {
+        self.memory.update_sessions();
+        self.save_changes();
+    }
+
+    fn take_queue_overflow(&self, client_id: &str) -> bool {
+        self.memory.take_queue_overflow(client_id)
+    }
+
+    fn remove_expired_messages(&self) -> usize {
+        // the restored messages keep their creation time and expire after the restart as well
+        let count = self.memory.remove_expired_messages();
+        self.save_changes();
+        count
+    }
+
+    fn set_shared_subscription_strategy(&self, strategy: SharedSubscriptionStrategy) {
+        self.memory.set_shared_subscription_strategy(strategy);
+    }
+
+    fn subscribe(
+        &self,
+        client_id: &str,
+        filter: Filter<'_>,
+        qos: Qos,
+        no_local: bool,
+        retain_as_published: bool,
+        retain_handling: RetainHandling,
+        id: Option<NonZeroUsize>,
+        max_subscriptions: Option<usize>,
+    ) -> bool {
+        let res = self.memory.subscribe(
+            client_id,
+            filter,
+            qos,
+            no_local,
+            retain_as_published,
+            retain_handling,
+            id,
+            max_subscriptions,
+        );
+        if res {
+            self.save_changes();
+        }
+        res
+    }
+
+    fn unsubscribe(&self, client_id: &str, filter: Filter<'_>) -> bool {
+        let res = self.memory.unsubscribe(client_id, filter);
+        if res {
+            self.save_changes();
+        }
+        res
+    }
+
+    fn next_messages(&self, client_id: &str, limit: Option<usize>) -> Vec<Message> {
+        let msgs = self.memory.next_messages(client_id, limit);
+        if !msgs.is_empty() {
+            self.save_changes();
+        }
+        msgs
+    }
+
+    fn deliver(&self, msgs: Vec<Message>) -> usize {
+        let count = self.memory.deliver(msgs);
+        self.save_changes();
+        count
+    }
+
+    fn count_matches(&self, msg: &Message) -> usize {
+        self.memory.count_matches(msg)
+    }
+
+    fn add_inflight_pub_packet(&self, client_id: &str, publish: Publish) {
+        self.memory.add_inflight_pub_packet(client_id, publish);
+        self.save_changes();
+    }
+
+    fn get_inflight_pub_packets(
+        &self,
+        client_id: &str,
+        packet_id: NonZeroU16,
+        remove: bool,
+    ) -> Option<Publish> {
+        let res = self
+            .memory
+            .get_inflight_pub_packets(client_id, packet_id, remove);
+        if res.is_some() && remove {
+            self.save_changes();
+        }
+        res
+    }
+
+    fn get_all_inflight_pub_packets(&self, client_id: &str) -> Vec<Publish> {
+        self.memory.get_all_inflight_pub_packets(client_id)
+    }
+
+    fn retained_messages(&self, filter: Filter<'_>) -> Vec<Message> {
+        self.memory.retained_messages(filter)
+    }
+
+    fn export_session(&self, client_id: &str) -> Option<SessionSnapshot> {
+        self.memory.export_session(client_id)
+    }
+
+    fn import_session(&self, client_id: &str, snapshot: SessionSnapshot) {
+        self.memory.import_session(client_id, snapshot);
+        self.connected.lock().remove(client_id);
+        self.save_changes();
+    }
+
+    fn metrics(&self) -> StorageMetrics {
+        self.memory.metrics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore() {
+        // the storages share the database, reopening it could find the lock of the previous one
+        // still held since sled releases it asynchronously
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let open = || StorageSled::new(db.clone()).unwrap();
+        {
+            let storage = open();
+            storage.update_retained_message(
+                Message::new("r", Qos::AtMostOnce, &b"1"[..]).with_retain(true),
+            );
+            storage.create_session("a", true, 60, None, None, QueueLimit::default());
+            storage.subscribe(
+                "a",
+                service::parse_filter("a/+").unwrap(),
+                Qos::AtLeastOnce,
+                false,
+                false,
+                RetainHandling::Never,
+                None,
+                None,
+            );
+            storage.deliver(vec![
+                Message::new("a/1", Qos::AtLeastOnce, &b"1"[..]),
+                Message::new("a/2", Qos::AtLeastOnce, &b"2"[..]),
+            ]);
+            let msg = storage.next_messages("a", Some(1)).remove(0);
+            let mut publish = msg.to_publish();
+            publish.packet_id = NonZeroU16::new(1);
+            storage.add_inflight_pub_packet("a", publish);
+
+            storage.create_session("b", true, 0, None, None, QueueLimit::default());
+        }
+
+        // the connected sessions are restored with the expiry interval of their connection
+        let storage = open();
+        storage.update_sessions();
+        assert_eq!(storage.metrics().session_count, 1);
+        assert!(
+            storage
+                .create_session("a", false, 60, None, None, QueueLimit::default())
+                .0
+        );
+        assert_eq!(storage.get_all_inflight_pub_packets("a").len(), 1);
+        let msgs = storage.next_messages("a", None);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(&**msgs[0].topic(), "a/2");
+        assert_eq!(
+            storage
+                .retained_messages(service::parse_filter("#").unwrap())
+                .len(),
+            1
+        );
+        assert!(storage
+            .get_inflight_pub_packets("a", NonZeroU16::new(1).unwrap(), true)
+            .is_some());
+        storage.deliver(vec![Message::new("a/3", Qos::AtLeastOnce, &b"3"[..])]);
+        drop(storage);
+
+        // only the changes since the restore are written
+        let storage = open();
+        storage.create_session("a", false, 60, None, None, QueueLimit::default());
+        assert!(storage.get_all_inflight_pub_packets("a").is_empty());
+        let msgs = storage.next_messages("a", None);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(&**msgs[0].topic(), "a/3");
+        storage.create_session("a", true, 60, None, None, QueueLimit::default());
+        assert!(storage.queues.is_empty());
+        assert_eq!(storage.sessions.len(), 1);
+    }
+}
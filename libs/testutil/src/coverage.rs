@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Statement {
+    pub id: String,
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SuiteSpec {
+    #[serde(default)]
+    spec: Vec<String>,
+}
+
+/// Which normative statements of the catalog are verified by the suites.
+#[derive(Debug)]
+pub struct Coverage {
+    pub statements: Vec<Statement>,
+    pub covered: BTreeMap<String, Vec<PathBuf>>,
+    /// Statements referenced by the suites that are not in the catalog.
+    pub unknown: BTreeMap<String, Vec<PathBuf>>,
+}
+
+impl Coverage {
+    pub fn uncovered(&self) -> impl Iterator<Item = &Statement> {
+        self.statements
+            .iter()
+            .filter(move |statement| !self.covered.contains_key(&statement.id))
+    }
+}
+
+impl Display for Coverage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let total = self.statements.len();
+        let covered = self.covered.len();
+        writeln!(
+            f,
+            "covered {}/{} statements ({:.1}%)",
+            covered,
+            total,
+            if total > 0 {
+                covered as f64 * 100.0 / total as f64
+            } else {
+                0.0
+            }
+        )?;
+        for (id, paths) in &self.covered {
+            writeln!(f, "  [x] {} ({} suites)", id, paths.len())?;
+        }
+        for statement in self.uncovered() {
+            writeln!(f, "  [ ] {} {}", statement.id, statement.text)?;
+        }
+        for (id, paths) in &self.unknown {
+            for path in paths {
+                writeln!(f, "  unknown statement {} in {}", id, path.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects the `spec` tags of all suites in `suites_dir` and matches them with the statements
+/// of the `catalog` file.
+pub fn spec_coverage(catalog: &Path, suites_dir: &Path) -> Coverage {
+    let statements: Vec<Statement> =
+        serde_yaml::from_str(&std::fs::read_to_string(catalog).unwrap()).unwrap();
+    let mut coverage = Coverage {
+        statements,
+        covered: BTreeMap::new(),
+        unknown: BTreeMap::new(),
+    };
+
+    let mut paths = Vec::new();
+    find_suites(suites_dir, &mut paths);
+    paths.sort();
+
+    for path in paths {
+        let suite: SuiteSpec =
+            serde_yaml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        for id in suite.spec {
+            let known = coverage
+                .statements
+                .iter()
+                .any(|statement| statement.id == id);
+            let map = if known {
+                &mut coverage.covered
+            } else {
+                &mut coverage.unknown
+            };
+            map.entry(id).or_default().push(path.clone());
+        }
+    }
+
+    coverage
+}
+
+fn find_suites(dir: &Path, paths: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_suites(&path, paths);
+        } else if path.extension().and_then(|ext| ext.to_str()) == Some("yaml") {
+            paths.push(path);
+        }
+    }
+}
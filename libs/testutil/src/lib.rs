@@ -1,28 +1,65 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::default_trait_access)]
 
+mod coverage;
 mod runner;
 mod suite;
 
-pub use runner::run;
-pub use suite::Suite;
+pub use coverage::{spec_coverage, Coverage, Statement};
+pub use runner::{run, run_with_broker, Endpoint};
+pub use suite::{Listener, Suite};
 
 use std::future::Future;
 use std::path::Path;
 use std::sync::Arc;
 
+use client::Transport;
 use serde_yaml::Value;
 
 use service::plugin::Plugin;
+use service::{ServiceState, Storage};
 
-pub async fn run_yaml_file<T, F>(path: &Path, create_plugins: T)
-where
+/// The environment variable with the address of an external broker to run the conformance
+/// suites against, the suites that are not tagged with `spec` are skipped.
+pub const BROKER_ENV: &str = "RSMQTT_TEST_BROKER";
+
+/// Runs the suite of the YAML file with the plugins and the storage backend created by the
+/// factories, so the same suites check the broker semantics with every backend.
+pub async fn run_yaml_file<T, F, S, SF, L, LF>(
+    path: &Path,
+    create_plugins: T,
+    create_storage: S,
+    listen: L,
+) where
     T: FnOnce(Vec<Value>) -> F,
     F: Future<Output = Vec<(&'static str, Arc<dyn Plugin>)>>,
+    S: FnOnce() -> SF,
+    SF: Future<Output = Arc<dyn Storage>>,
+    L: FnOnce(Arc<ServiceState>, Listener) -> LF,
+    LF: Future<Output = Endpoint>,
 {
     let suite: Suite = serde_yaml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
     if suite.disable {
         return;
     }
-    run(suite, create_plugins).await;
+    match std::env::var(BROKER_ENV) {
+        Ok(broker) => {
+            if suite.spec.is_empty() {
+                return;
+            }
+            let addrs = tokio::net::lookup_host(&broker)
+                .await
+                .unwrap_or_else(|err| panic!("resolve '{}': {}", broker, err))
+                .collect();
+            run_with_broker(
+                suite,
+                Endpoint {
+                    addrs,
+                    transport: Transport::Tcp,
+                },
+            )
+            .await;
+        }
+        Err(_) => run(suite, create_plugins, create_storage, listen).await,
+    }
 }
@@ -1,33 +1,90 @@
 use std::collections::HashMap;
 use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use bytestring::ByteString;
-use codec::{Codec, Packet};
+use client::Transport;
+use codec::{Codec, Packet, ProtocolLevel};
 use futures_util::future::BoxFuture;
 use serde_yaml::Value;
 use service::plugin::Plugin;
-use service::{client_loop, RemoteAddr, ServiceState};
-use tokio::io::{DuplexStream, ReadHalf, WriteHalf};
+use service::{client_loop_with_settings, RemoteAddr, ServiceState, Storage};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::Mutex;
+use tokio::time::Instant;
 
-use crate::suite::{Step, Suite};
+use crate::suite::{Listener, Step, Suite};
+
+type ClientCodec = Codec<Box<dyn AsyncRead + Send + Unpin>, Box<dyn AsyncWrite + Send + Unpin>>;
+
+/// The addresses and the transport that the connections of a suite are opened with.
+pub struct Endpoint {
+    pub addrs: Vec<SocketAddr>,
+    pub transport: Transport,
+}
 
 struct RunnerContext {
-    state: Arc<ServiceState>,
-    clients: HashMap<ByteString, Codec<ReadHalf<DuplexStream>, WriteHalf<DuplexStream>>>,
+    state: Option<Arc<ServiceState>>,
+    endpoint: Option<Endpoint>,
+    protocol: Option<ProtocolLevel>,
+    clients: HashMap<ByteString, ClientCodec>,
 }
 
-pub async fn run<T, F>(suite: Suite, create_plugins: T)
+impl RunnerContext {
+    fn state(&self) -> Arc<ServiceState> {
+        self.state
+            .clone()
+            .expect("the suite runs against an external broker")
+    }
+}
+
+/// Runs the suite with an in-process service, `listen` starts the listener of the suite if it
+/// is not `memory` and returns the endpoint to connect to.
+pub async fn run<T, F, S, SF, L, LF>(suite: Suite, create_plugins: T, create_storage: S, listen: L)
 where
     T: FnOnce(Vec<Value>) -> F,
     F: Future<Output = Vec<(&'static str, Arc<dyn Plugin>)>>,
+    S: FnOnce() -> SF,
+    SF: Future<Output = Arc<dyn Storage>>,
+    L: FnOnce(Arc<ServiceState>, Listener) -> LF,
+    LF: Future<Output = Endpoint>,
 {
+    // the paused clock would advance while waiting for the sockets
+    assert!(
+        !suite.pause_time || suite.listener == Listener::Memory,
+        "`pause_time` requires the memory listener"
+    );
+    if suite.pause_time {
+        tokio::time::pause();
+    }
+
     let plugins = create_plugins(suite.plugins).await;
-    let state = ServiceState::new(suite.config, plugins).unwrap();
+    let storage = create_storage().await;
+    let state = ServiceState::with_storage(suite.config, plugins, storage).unwrap();
+    let endpoint = match suite.listener {
+        Listener::Memory => None,
+        listener => Some(listen(state.clone(), listener).await),
+    };
+    let ctx = Arc::new(Mutex::new(RunnerContext {
+        state: Some(state),
+        endpoint,
+        protocol: suite.protocol.map(Into::into),
+        clients: HashMap::new(),
+    }));
+
+    execute_step(ctx.clone(), suite.step, None, None).await;
+    ctx.lock().await.clients.clear();
+}
+
+/// Runs the suite against an external broker, the config and the plugins of the suite are
+/// ignored.
+pub async fn run_with_broker(suite: Suite, broker: Endpoint) {
     let ctx = Arc::new(Mutex::new(RunnerContext {
-        state,
+        state: None,
+        endpoint: Some(broker),
+        protocol: suite.protocol.map(Into::into),
         clients: HashMap::new(),
     }));
 
@@ -43,32 +100,56 @@ fn execute_step(
 ) -> BoxFuture<'static, ()> {
     let fut = async move {
         match step {
-            Step::Connect { remote_addr } => {
-                let id = id.expect("expect id");
+            Step::Connect {
+                conn,
+                remote_addr,
+                settings,
+            } => {
+                let id = conn.or(id).expect("expect id");
                 // println!("[CONNECT] id={}", id);
                 let mut ctx = ctx.lock().await;
-                let (client, server) = tokio::io::duplex(4096);
-                let (server_reader, server_writer) = tokio::io::split(server);
-                let (client_reader, client_writer) = tokio::io::split(client);
-                let codec = Codec::new(client_reader, client_writer);
-                let remote_addr = remote_addr.unwrap_or_else(|| RemoteAddr {
-                    protocol: "memory".into(),
-                    addr: Some(format!("{}", id).into()),
-                });
-                tokio::spawn(client_loop(
-                    ctx.state.clone(),
-                    server_reader,
-                    server_writer,
-                    remote_addr,
-                ));
+                let codec = match &ctx.endpoint {
+                    Some(endpoint) => {
+                        let (client_reader, client_writer) = endpoint
+                            .transport
+                            .connect(&endpoint.addrs)
+                            .await
+                            .unwrap_or_else(|err| {
+                                panic!("connect to {:?}: {}", endpoint.addrs, err)
+                            });
+                        ClientCodec::new(client_reader, client_writer)
+                    }
+                    None => {
+                        let (client, server) = tokio::io::duplex(4096);
+                        let (server_reader, server_writer) = tokio::io::split(server);
+                        let (client_reader, client_writer) = tokio::io::split(client);
+                        let remote_addr = remote_addr.unwrap_or_else(|| RemoteAddr {
+                            protocol: "memory".into(),
+                            addr: Some(format!("{}", id).into()),
+                            client_cert: None,
+                            listener: None,
+                        });
+                        tokio::spawn(client_loop_with_settings(
+                            ctx.state(),
+                            server_reader,
+                            server_writer,
+                            remote_addr,
+                            Arc::new(settings.map(|settings| *settings).unwrap_or_default()),
+                        ));
+                        // starts the connection now, so its timers are not delayed until the next
+                        // step yields
+                        let () = tokio::task::yield_now().await;
+                        ClientCodec::new(Box::new(client_reader), Box::new(client_writer))
+                    }
+                };
                 assert!(
                     ctx.clients.insert(id.clone(), codec).is_none(),
                     "connection id '{}' exists",
                     id
                 );
             }
-            Step::Disconnect => {
-                let id = id.expect("expect id");
+            Step::Disconnect { conn } => {
+                let id = conn.or(id).expect("expect id");
                 // println!("[DISCONNECT] id={}", id);
 
                 let mut ctx = ctx.lock().await;
@@ -78,21 +159,45 @@ fn execute_step(
                     id
                 );
             }
-            Step::Send { mut packet } => {
-                let id = id.clone().expect("expect id");
+            Step::Send { conn, mut packet } => {
+                // the client id of the sequence only applies to its own connection
+                let client_id = if conn.is_some() { None } else { client_id };
+                let id = conn.or(id).expect("expect id");
                 // println!("[SEND] id={} packet={:?}", id, packet);
-                if let Packet::Connect(connect) = &mut packet {
+                let mut ctx = ctx.lock().await;
+                if let Packet::Connect(connect) = &mut *packet {
                     connect.client_id = client_id.unwrap_or_else(|| id.clone());
+                    if let Some(protocol) = ctx.protocol {
+                        connect.level = protocol;
+                    }
                 }
-                let mut ctx = ctx.lock().await;
                 let codec = ctx
                     .clients
                     .get_mut(&id)
                     .unwrap_or_else(|| panic!("connection id '{}' not exists", id));
                 codec.encode(&packet).await.unwrap();
             }
-            Step::Receive { packet, after } => {
-                let id = id.expect("expect id");
+            Step::SendRaw { conn, data, level } => {
+                let id = conn.or(id).expect("expect id");
+                // println!("[SEND_RAW] id={} data={}", id, data);
+                let data = decode_hex(&data);
+                let mut ctx = ctx.lock().await;
+                let codec = ctx
+                    .clients
+                    .get_mut(&id)
+                    .unwrap_or_else(|| panic!("connection id '{}' not exists", id));
+                if let Some(level) = level {
+                    codec.set_protocol_level(level);
+                }
+                codec.write_raw(&data).await.unwrap();
+            }
+            Step::Receive {
+                conn,
+                packet,
+                partial,
+                after,
+            } => {
+                let id = conn.or(id).expect("expect id");
                 // println!("[RECEIVE] id={} packet={:?}", id, packet);
                 let mut ctx = ctx.lock().await;
                 let codec = ctx
@@ -121,10 +226,21 @@ fn execute_step(
                             .expect("unexpected eof");
                     recv_packet
                 };
-                assert_eq!(packet, recv_packet);
+                if partial {
+                    let recv_value = serde_yaml::to_value(&recv_packet).unwrap();
+                    assert!(
+                        value_contains(&packet, &recv_value),
+                        "expected packet matching {:?}, received {:?}",
+                        packet,
+                        recv_packet
+                    );
+                } else {
+                    let packet: Packet = serde_yaml::from_value(packet).unwrap();
+                    assert_eq!(packet, recv_packet);
+                }
             }
-            Step::Eof => {
-                let id = id.expect("expect id");
+            Step::Eof { conn } => {
+                let id = conn.or(id).expect("expect id");
                 // println!("[EOF] id={}", id);
                 let mut ctx = ctx.lock().await;
                 let codec = ctx
@@ -142,6 +258,41 @@ fn execute_step(
                 // println!("[DELAY] duration={}", duration);
                 tokio::time::sleep(Duration::from_secs(duration)).await
             }
+            Step::AdvanceTime { duration } => {
+                // println!("[ADVANCE_TIME] duration={}", duration);
+                tokio::time::advance(Duration::from_millis(duration)).await;
+                // let the timers that fired run before the next step
+                let () = tokio::task::yield_now().await;
+            }
+            Step::UpdateMetrics => {
+                // println!("[UPDATE_METRICS]");
+                let state = ctx.lock().await.state();
+                state.update_metrics().await;
+                state.update_sys_topics();
+            }
+            Step::Redirect {
+                redirect,
+                disconnect_clients,
+            } => {
+                // println!("[REDIRECT] redirect={:?}", redirect);
+                let state = ctx.lock().await.state();
+                state.set_redirect(redirect.clone());
+                if let (Some(redirect), true) = (&redirect, disconnect_clients) {
+                    state.redirect_clients(redirect).await;
+                }
+            }
+            Step::Metrics { values } => {
+                // println!("[METRICS] values={:?}", values);
+                let state = ctx.lock().await.state();
+                state.update_metrics().await;
+                let metrics = serde_yaml::to_value(state.metrics()).unwrap();
+                for (name, expected) in values {
+                    let value = metrics
+                        .get(name.as_str())
+                        .unwrap_or_else(|| panic!("unknown metric '{}'", name));
+                    assert_eq!(value, &expected, "metric '{}'", name);
+                }
+            }
             Step::Parallel { steps } => {
                 let mut futs = Vec::new();
                 for step in steps {
@@ -173,3 +324,49 @@ fn execute_step(
     };
     Box::pin(fut)
 }
+
+fn decode_hex(s: &str) -> Vec<u8> {
+    let digits = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| {
+            c.to_digit(16)
+                .unwrap_or_else(|| panic!("invalid hex data: {}", s)) as u8
+        })
+        .collect::<Vec<_>>();
+    assert!(digits.len() % 2 == 0, "invalid hex data: {}", s);
+    digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect()
+}
+
+/// Checks that every field given in `expected` has the same value in `actual`, strings
+/// match the byte sequences of `Bytes` fields.
+fn value_contains(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Mapping(expected), Value::Mapping(actual)) => {
+            expected.iter().all(|(key, value)| {
+                actual
+                    .get(key)
+                    .map(|actual| value_contains(value, actual))
+                    .unwrap_or(false)
+            })
+        }
+        (Value::Sequence(expected), Value::Sequence(actual)) => {
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(actual)
+                    .all(|(expected, actual)| value_contains(expected, actual))
+        }
+        (Value::String(expected), Value::Sequence(actual)) => {
+            let bytes = actual
+                .iter()
+                .map(|value| value.as_u64().map(|b| b as u8))
+                .collect::<Option<Vec<_>>>();
+            bytes.as_deref() == Some(expected.as_bytes())
+        }
+        _ => expected == actual,
+    }
+}
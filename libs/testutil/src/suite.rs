@@ -1,29 +1,71 @@
-use codec::Packet;
+use std::collections::BTreeMap;
+
+use codec::{Packet, ProtocolLevel};
 use serde::Deserialize;
 
 use bytestring::ByteString;
 use serde_yaml::Value;
-use service::{RemoteAddr, ServiceConfig};
+use service::{ListenerSettings, Redirect, RemoteAddr, ServiceConfig};
 
 #[derive(Debug, Deserialize)]
-#[serde(tag = "type", rename_all = "lowercase")]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Step {
     Connect {
+        conn: Option<ByteString>,
         remote_addr: Option<RemoteAddr>,
+        /// The settings of the listener, only used without a network listener.
+        settings: Option<Box<ListenerSettings>>,
+    },
+    Disconnect {
+        conn: Option<ByteString>,
     },
-    Disconnect,
     Send {
-        packet: Packet,
+        conn: Option<ByteString>,
+        packet: Box<Packet>,
+    },
+    /// Writes hex encoded bytes, whitespace between the bytes is ignored.
+    ///
+    /// `level` sets the protocol level of the following packets, which is otherwise taken
+    /// from the CONNECT packets sent with `send`.
+    SendRaw {
+        conn: Option<ByteString>,
+        data: String,
+        level: Option<ProtocolLevel>,
     },
+    /// Receives a packet and compares it with `packet`, with `partial` only the fields given
+    /// in `packet` are compared.
     #[serde(rename = "recv")]
     Receive {
-        packet: Packet,
+        conn: Option<ByteString>,
+        packet: Value,
+        #[serde(default)]
+        partial: bool,
         after: Option<u64>,
     },
-    Eof,
+    Eof {
+        conn: Option<ByteString>,
+    },
     Delay {
         duration: u64,
     },
+    /// Moves the paused clock forward by `duration` milliseconds, requires `pause_time`.
+    AdvanceTime {
+        duration: u64,
+    },
+    /// Recalculates the metrics and publishes them to the `$SYS` topics.
+    UpdateMetrics,
+    /// Recalculates the metrics and checks the values of the given fields of
+    /// [`service::Metrics`].
+    Metrics {
+        values: BTreeMap<String, Value>,
+    },
+    /// Redirects the new connections, or accepts them again without `redirect`, and with
+    /// `disconnect_clients` disconnects the connected clients with the redirect.
+    Redirect {
+        redirect: Option<Redirect>,
+        #[serde(default)]
+        disconnect_clients: bool,
+    },
     Parallel {
         steps: Vec<Step>,
     },
@@ -34,6 +76,36 @@ pub enum Step {
     },
 }
 
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    V3,
+    V4,
+    V5,
+}
+
+impl From<Protocol> for ProtocolLevel {
+    fn from(protocol: Protocol) -> Self {
+        match protocol {
+            Protocol::V3 => ProtocolLevel::V3,
+            Protocol::V4 => ProtocolLevel::V4,
+            Protocol::V5 => ProtocolLevel::V5,
+        }
+    }
+}
+
+/// The listener of the broker that the connections of a suite are opened through.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Listener {
+    #[default]
+    Memory,
+    Tcp,
+    Tls,
+    Ws,
+    Wss,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Suite {
     #[serde(default)]
@@ -43,4 +115,17 @@ pub struct Suite {
     pub step: Step,
     #[serde(default)]
     pub disable: bool,
+    /// Pauses the clock, so that it only moves when the runtime is idle or with `advance_time`
+    /// steps and time-dependent behavior is tested without real sleeps.
+    #[serde(default)]
+    pub pause_time: bool,
+    /// Overrides the protocol level of all CONNECT packets sent by the suite.
+    #[serde(default)]
+    pub protocol: Option<Protocol>,
+    #[serde(default)]
+    pub listener: Listener,
+    /// The normative statements of the specification (e.g. `MQTT-3.1.0-1`) that the suite
+    /// verifies.
+    #[serde(default)]
+    pub spec: Vec<String>,
 }